@@ -85,6 +85,21 @@ contract SetupDEX is Script {
         dex.createPair(ETH, address(sol));
         console.log("Created ETH/SOL pair");
 
+        // Fund the deployer's DEX balances, which the orders below lock their escrow from
+        console.log("\n=== Depositing ===");
+
+        dex.deposit{value: 20 ether}(ETH, 20 ether);
+        console.log("Deposited 20 ETH");
+
+        dex.deposit(address(usdc), 28_850 * 10 ** 6);
+        console.log("Deposited 28,850 USDC");
+
+        dex.deposit(address(base), 4_440 * 10 ** 18);
+        console.log("Deposited 4,440 BASE");
+
+        dex.deposit(address(sol), 735 * 10 ** 8);
+        console.log("Deposited 73.5 SOL");
+
         // Place limit orders
         // Price ratios: 1 ETH = 3000 USDC, 1 BASE = 2 USDC (equiv in ETH), 1 SOL = 120 USDC (equiv in ETH)
         // That means: 1 ETH = 1500 BASE, 1 ETH = 25 SOL
@@ -156,7 +171,7 @@ contract SetupDEX is Script {
         console.log("\nPlacing ETH/USDC sell orders (selling ETH for USDC):");
 
         // Sell order at 3050 USDC per ETH (ask above market)
-        dex.placeLimitOrder{value: 2 ether}(
+        dex.placeLimitOrder(
             ETH, // tokenIn (paying with ETH)
             usdc, // tokenOut (receiving USDC)
             false, // isSell
@@ -167,7 +182,7 @@ contract SetupDEX is Script {
         console.log("  - Sell 2 ETH at 3050 USDC/ETH");
 
         // Sell order at 3100 USDC per ETH
-        dex.placeLimitOrder{value: 3 ether}(
+        dex.placeLimitOrder(
             ETH,
             usdc,
             false,
@@ -178,7 +193,7 @@ contract SetupDEX is Script {
         console.log("  - Sell 3 ETH at 3100 USDC/ETH");
 
         // Sell order at 3150 USDC per ETH
-        dex.placeLimitOrder{value: 5 ether}(
+        dex.placeLimitOrder(
             ETH,
             usdc,
             false,
@@ -196,7 +211,7 @@ contract SetupDEX is Script {
         console.log("\nPlacing ETH/BASE orders (1 ETH = 1500 BASE):");
 
         // Sell ETH for BASE at 1520 BASE per ETH (slightly above 1500)
-        dex.placeLimitOrder{value: 2 ether}(
+        dex.placeLimitOrder(
             ETH,
             base_token,
             false,
@@ -218,7 +233,7 @@ contract SetupDEX is Script {
         console.log("  - Buy 3 ETH at 1480 BASE/ETH");
 
         // Sell ETH for BASE at 1550 BASE per ETH
-        dex.placeLimitOrder{value: 3 ether}(
+        dex.placeLimitOrder(
             ETH,
             base_token,
             false,
@@ -233,7 +248,7 @@ contract SetupDEX is Script {
         console.log("\nPlacing ETH/SOL orders (1 ETH = 25 SOL):");
 
         // Sell ETH for SOL at 25.5 SOL per ETH (slightly above 25)
-        dex.placeLimitOrder{value: 2 ether}(
+        dex.placeLimitOrder(
             ETH,
             sol_token,
             false,
@@ -255,7 +270,7 @@ contract SetupDEX is Script {
         console.log("  - Buy 3 ETH at 24.5 SOL/ETH");
 
         // Sell ETH for SOL at 26 SOL per ETH
-        dex.placeLimitOrder{value: 3 ether}(
+        dex.placeLimitOrder(
             ETH,
             sol_token,
             false,
@@ -4,14 +4,75 @@ pragma solidity ^0.8.0;
 /// @title EnshrinedDEX
 /// @notice System predeploy contract for the enshrined DEX at address 0x4200000000000000000000000000000000000042
 /// @dev This is an interface-only contract. The actual logic is implemented in the L2 state transition function
-///      All state is maintained in the protocol layer, not in EVM storage
+///      All state is maintained in the protocol layer, not in EVM storage. Orders and swaps lock and settle
+///      against balances held by the DEX, so tokens only move on deposit and withdrawal
 interface IEnshrinedDEX {
+    /// @notice How long an order stays on the book
+    /// @dev GTC rests any remainder, IOC cancels it, FOK fills completely or reverts,
+    ///      POST_ONLY rests without taking liquidity or reverts if it would cross
+    enum TimeInForce {
+        GTC,
+        IOC,
+        FOK,
+        POST_ONLY
+    }
+
+    /// @notice What happens when an order would match a resting order of the same trader
+    /// @dev MARKET_DEFAULT uses the pair's mode. CANCEL_NEWEST cancels the incoming order,
+    ///      CANCEL_OLDEST the resting one and CANCEL_BOTH both. DECREMENT_AND_CANCEL reduces
+    ///      both by the smaller remaining amount and cancels the order that runs out
+    enum SelfTradePrevention {
+        MARKET_DEFAULT,
+        CANCEL_NEWEST,
+        CANCEL_OLDEST,
+        CANCEL_BOTH,
+        DECREMENT_AND_CANCEL
+    }
+
+    /// @notice How a pair matches its orders
+    /// @dev CONTINUOUS matches orders as they arrive in price-time priority. BATCH_AUCTION
+    ///      collects a block's orders and clears them at the start of the next block, ahead of
+    ///      its other transactions, at the single price that matches the most volume. Batch
+    ///      auction pairs only take GTC and IOC limit orders, and aren't routed through by swaps
+    enum MarketMode {
+        CONTINUOUS,
+        BATCH_AUCTION
+    }
+
+    /// @notice Expected execution of one hop of a quoted swap
+    /// @dev Fees are charged in the pair's quote token. Prices are quote per base, before fees
+    struct QuoteHop {
+        bytes32 pairId;
+        address tokenIn;
+        address tokenOut;
+        uint256 amountIn;
+        uint256 amountOut;
+        uint256 fee;
+        uint256 avgPriceNum;
+        uint256 avgPriceDenom;
+        uint256 worstPriceNum;
+        uint256 worstPriceDenom;
+        uint256 levelsConsumed;
+        uint256 ordersConsumed;
+        bool liquidityExhausted;
+    }
+
     // Events
     event PairCreated(
         address indexed token0,
         address indexed token1,
         bytes32 indexed pairId
     );
+    event MarketConfigured(
+        bytes32 indexed pairId,
+        uint256 tickNum,
+        uint256 tickDenom,
+        uint256 lotSize,
+        uint256 minNotional,
+        bool overrideFee,
+        uint256 feeBps,
+        int256 makerFeeBps
+    );
     event LimitOrderPlaced(
         bytes32 indexed orderId,
         address indexed trader,
@@ -22,12 +83,89 @@ interface IEnshrinedDEX {
         uint256 priceNum,
         uint256 priceDenom
     );
+    event StopOrderPlaced(
+        bytes32 indexed orderId,
+        address indexed trader,
+        address indexed tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 stopNum,
+        uint256 stopDenom,
+        uint256 limitNum,
+        uint256 limitDenom
+    );
+    event StopOrderTriggered(
+        bytes32 indexed orderId,
+        uint256 priceNum,
+        uint256 priceDenom
+    );
+    event OcoOrderPlaced(
+        bytes32 indexed limitOrderId,
+        bytes32 indexed stopOrderId,
+        address indexed trader
+    );
+    event BracketOrderPlaced(
+        bytes32 indexed entryOrderId,
+        address indexed trader,
+        uint256 takeProfitNum,
+        uint256 stopLossNum,
+        uint256 stopLossLimitNum,
+        uint256 priceDenom
+    );
+    event BracketActivated(
+        bytes32 indexed entryOrderId,
+        bytes32 indexed takeProfitOrderId,
+        bytes32 indexed stopLossOrderId,
+        uint256 amount
+    );
+    event RangeOrderPlaced(
+        bytes32 indexed orderId,
+        uint256 flipPriceNum,
+        uint256 priceDenom
+    );
+    event RangeOrderFlipped(
+        bytes32 indexed orderId,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom
+    );
     event OrderCancelled(bytes32 indexed orderId, address indexed trader);
+    event OrderAmended(
+        bytes32 indexed orderId,
+        address indexed trader,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        bool keptPriority
+    );
     event OrderFilled(
         bytes32 indexed makerOrderId,
         bytes32 indexed takerOrderId,
         uint256 amount
     );
+    event SelfTradePrevented(
+        bytes32 indexed makerOrderId,
+        bytes32 indexed takerOrderId,
+        uint256 makerReduced,
+        uint256 takerReduced
+    );
+    event SelfTradePreventionSet(bytes32 indexed pairId, SelfTradePrevention mode);
+    event MarketModeSet(bytes32 indexed pairId, MarketMode mode);
+    event BatchCleared(
+        bytes32 indexed pairId,
+        uint256 priceNum,
+        uint256 priceDenom,
+        uint256 volume
+    );
+    event TradeFees(
+        bytes32 indexed makerOrderId,
+        bytes32 indexed takerOrderId,
+        address indexed token,
+        uint256 takerFee,
+        int256 makerFee
+    );
     event Swap(
         address indexed trader,
         address indexed tokenIn,
@@ -36,6 +174,9 @@ interface IEnshrinedDEX {
         uint256 amountOut,
         bytes32[] route
     );
+    event FeesWithdrawn(address indexed token, address indexed to, uint256 amount);
+    event Deposited(address indexed account, address indexed token, uint256 amount);
+    event Withdrawn(address indexed account, address indexed token, uint256 amount);
     event LiquidityAdded(
         address indexed provider,
         address indexed token0,
@@ -72,6 +213,47 @@ interface IEnshrinedDEX {
     /// @param token1 Second token address
     function createPair(address token0, address token1) external;
 
+    /// @notice Create a new trading pair with market parameters (RESTRICTED: only the DEX admin, unless every parameter is left at its default)
+    /// @dev Limit orders must be priced on a tick multiple, sized in whole lots and worth at least minNotional
+    /// @param token0 First token address (use address(0) for ETH)
+    /// @param token1 Second token address
+    /// @param tickNum Tick size numerator (0 for no tick constraint)
+    /// @param tickDenom Tick size denominator
+    /// @param lotSize Order size increment in base token units
+    /// @param minNotional Minimum order value in quote token units
+    /// @param overrideFee Whether feeBps and makerFeeBps replace the protocol default fees for this pair
+    /// @param feeBps Taker fee in basis points (ignored unless overrideFee is set)
+    /// @param makerFeeBps Maker fee in basis points, negative for a rebate no larger than feeBps
+    function createPairWithParams(
+        address token0,
+        address token1,
+        uint256 tickNum,
+        uint256 tickDenom,
+        uint256 lotSize,
+        uint256 minNotional,
+        bool overrideFee,
+        uint256 feeBps,
+        int256 makerFeeBps
+    ) external;
+
+    /// @notice Deposit tokens into the caller's DEX balance, which orders and swaps trade from
+    /// @dev ETH deposits send amount as value, token deposits are pulled with protocolTransfer
+    /// @param token Token to deposit (use address(0) for ETH)
+    /// @param amount Amount to deposit
+    function deposit(address token, uint256 amount) external payable;
+
+    /// @notice Withdraw tokens from the caller's DEX balance
+    /// @dev Only the balance not locked in open orders can be withdrawn
+    /// @param token Token to withdraw (use address(0) for ETH)
+    /// @param amount Amount to withdraw
+    function withdraw(address token, uint256 amount) external;
+
+    /// @notice Get an account's DEX balance of a token, not counting what open orders lock
+    /// @param account Account address
+    /// @param token Token address (use address(0) for ETH)
+    /// @return amount Balance available to trade and withdraw
+    function getBalance(address account, address token) external view returns (uint256 amount);
+
     /// @notice Place a limit order
     /// @param tokenIn Token to sell
     /// @param tokenOut Token to buy
@@ -87,13 +269,180 @@ interface IEnshrinedDEX {
         uint256 amount,
         uint256 priceNum,
         uint256 priceDenom
-    ) external payable returns (bytes32 orderId);
+    ) external returns (bytes32 orderId);
 
-    /// @notice Cancel an existing order
+    /// @notice Place a limit order with a time in force
+    /// @param tokenIn Token to sell
+    /// @param tokenOut Token to buy
+    /// @param isBuy True for buy order, false for sell order
+    /// @param amount Amount of tokenIn to sell
+    /// @param priceNum Price numerator
+    /// @param priceDenom Price denominator
+    /// @param timeInForce What happens to the part of the order that does not fill immediately
+    /// @param selfTradePrevention What happens if the order would match the caller's own orders
+    /// @return orderId The unique identifier for the placed order
+    function placeOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        TimeInForce timeInForce,
+        SelfTradePrevention selfTradePrevention
+    ) external returns (bytes32 orderId);
+
+    /// @notice Place an iceberg order: a limit order that only shows part of its size on the book
+    /// @dev The rest is a hidden reserve. Each time the displayed clip is filled, the next clip
+    ///      is shown and queues behind the orders already at its price
+    /// @param tokenIn Token to sell
+    /// @param tokenOut Token to buy
+    /// @param isBuy True for buy order, false for sell order
+    /// @param amount Amount of tokenIn to sell
+    /// @param priceNum Price numerator
+    /// @param priceDenom Price denominator
+    /// @param displayAmount Size of each displayed clip, in tokenIn like amount
+    /// @return orderId The unique identifier for the placed order
+    function placeIcebergOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        uint256 displayAmount
+    ) external returns (bytes32 orderId);
+
+    /// @notice Place a stop order that is activated when the last trade price reaches the stop price
+    /// @dev Buy stops trigger when the price rises to the stop price, sell stops when it falls to it.
+    ///      Once triggered the order becomes a limit order at the limit price, or a market order
+    ///      if limitNum is 0, and is matched in the same block. Buy stops need a limit price
+    ///      to bound their escrow
+    /// @param tokenIn Token to sell
+    /// @param tokenOut Token to buy
+    /// @param isBuy True for buy order, false for sell order
+    /// @param amount Amount of tokenIn to sell
+    /// @param stopNum Stop price numerator
+    /// @param stopDenom Stop price denominator
+    /// @param limitNum Limit price numerator (0 for a stop market order)
+    /// @param limitDenom Limit price denominator
+    /// @return orderId The unique identifier for the placed order
+    function placeStopOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 stopNum,
+        uint256 stopDenom,
+        uint256 limitNum,
+        uint256 limitDenom
+    ) external returns (bytes32 orderId);
+
+    /// @notice Place a one-cancels-other pair: a limit order and a stop order for the same amount
+    /// @dev As soon as either order fills, even partially, or is cancelled, the other one is
+    ///      cancelled. Both orders share one escrow of amount. The limit order must not cross the
+    ///      book. The stop order becomes a limit order at the stop limit price when triggered, or
+    ///      a market order if stopLimitNum is 0; buy pairs need a stop limit price to bound their
+    ///      escrow. Linked orders cannot be amended
+    /// @param tokenIn Token to sell
+    /// @param tokenOut Token to buy
+    /// @param isBuy True for buy orders, false for sell orders
+    /// @param amount Amount of tokenIn to sell
+    /// @param priceNum Limit price numerator
+    /// @param stopNum Stop price numerator
+    /// @param stopLimitNum Stop limit price numerator (0 for a stop market order)
+    /// @param priceDenom Denominator shared by all prices
+    /// @return limitOrderId The unique identifier for the limit order
+    /// @return stopOrderId The unique identifier for the stop order
+    function placeOcoOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 stopNum,
+        uint256 stopLimitNum,
+        uint256 priceDenom
+    ) external returns (bytes32 limitOrderId, bytes32 stopOrderId);
+
+    /// @notice Place a bracket order: a limit buy entry whose fills are sold by a take-profit
+    ///         limit order and a stop-loss order, linked one-cancels-other
+    /// @dev The exits are placed once the entry is filled, or its remainder is cancelled by
+    ///      matching, and are sized to the tokenOut it bought, which stays escrowed for them.
+    ///      The take-profit must be above the entry price and the stop-loss below it. A triggered
+    ///      stop-loss sells at the stop-loss limit price, or at market if stopLossLimitNum is 0.
+    ///      Cancelling the entry drops the exits and pays out what it bought so far. Brackets
+    ///      are long only, as a short bracket would have to escrow the cost of its stop-loss
+    /// @param tokenIn Token to buy with
+    /// @param tokenOut Token to buy
+    /// @param amount Amount of tokenIn to spend
+    /// @param priceNum Entry price numerator
+    /// @param takeProfitNum Take-profit price numerator
+    /// @param stopLossNum Stop-loss price numerator
+    /// @param stopLossLimitNum Stop-loss limit price numerator (0 for a stop market order)
+    /// @param priceDenom Denominator shared by all prices
+    /// @return entryOrderId The unique identifier for the entry order; the exits' follow in BracketActivated
+    function placeBracketOrder(
+        address tokenIn,
+        address tokenOut,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 takeProfitNum,
+        uint256 stopLossNum,
+        uint256 stopLossLimitNum,
+        uint256 priceDenom
+    ) external returns (bytes32 entryOrderId);
+
+    /// @notice Place a range order: a post-only limit order that re-posts what it received on the
+    ///         other side of the book each time it is completely filled
+    /// @dev A sell flips to a buy at the flip price, which must be below the order price, and a
+    ///      buy flips to a sell above it. The re-posted order keeps the order ID and swaps the two
+    ///      prices, so the order keeps trading back and forth across the spread. Proceeds are held
+    ///      until the order flips; whatever the re-posted order doesn't need is paid out then, and
+    ///      cancelling pays out what is held. Once the proceeds are too small for an order, or the
+    ///      flip would cross the book, they are paid out and the order ends. Range orders cannot
+    ///      be amended or placed in batch auction markets
+    /// @param tokenIn Token to sell
+    /// @param tokenOut Token to buy
+    /// @param isBuy True for buy order, false for sell order
+    /// @param amount Amount of tokenIn to sell
+    /// @param priceNum Price numerator
+    /// @param flipPriceNum Flip price numerator
+    /// @param priceDenom Denominator shared by both prices
+    /// @return orderId The unique identifier for the placed order; flips follow in RangeOrderFlipped
+    function placeRangeOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 flipPriceNum,
+        uint256 priceDenom
+    ) external returns (bytes32 orderId);
+
+    /// @notice Cancel an existing order, including a stop order that has not been triggered
+    /// @dev Cancelling one order of a one-cancels-other pair cancels the other one too
     /// @param orderId The order ID to cancel
     function cancelOrder(bytes32 orderId) external;
 
+    /// @notice Change the price and unfilled size of a resting order
+    /// @dev A smaller size at the same price keeps the order's time priority, a price change
+    ///      or larger size loses it. The escrow difference is refunded to or collected from the
+    ///      trader's DEX balance
+    /// @param orderId The order ID to amend
+    /// @param newAmount New unfilled amount, in tokenIn like placeOrder
+    /// @param newPriceNum New price numerator
+    /// @param newPriceDenom New price denominator
+    function amendOrder(
+        bytes32 orderId,
+        uint256 newAmount,
+        uint256 newPriceNum,
+        uint256 newPriceDenom
+    ) external;
+
     /// @notice Execute a swap with slippage protection
+    /// @dev The input can be split across routes that share no pair when that pays out more, with one
+    ///      Swap event for each route taken. getQuote reports the route taking the largest share.
     /// @param tokenIn Input token address
     /// @param tokenOut Output token address
     /// @param amountIn Amount of input tokens
@@ -104,7 +453,7 @@ interface IEnshrinedDEX {
         address tokenOut,
         uint256 amountIn,
         uint256 minAmountOut
-    ) external payable returns (uint256 amountOut);
+    ) external returns (uint256 amountOut);
 
     /// @notice Get a quote for a potential swap
     /// @param tokenIn Input token address
@@ -118,6 +467,103 @@ interface IEnshrinedDEX {
         uint256 amountIn
     ) external view returns (uint256 amountOut, bytes32[] memory route);
 
+    /// @notice Get a quote for a potential swap with the expected execution of each hop
+    /// @dev When the input would be split between routes, the hops run route after route
+    /// @param tokenIn Input token address
+    /// @param tokenOut Output token address
+    /// @param amountIn Amount of input tokens
+    /// @return amountOut Expected output amount
+    /// @return priceImpact Shortfall against the mid prices along the route, in basis points
+    /// @return hops Expected execution of each hop
+    function getQuoteDetail(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountIn
+    ) external view returns (uint256 amountOut, uint256 priceImpact, QuoteHop[] memory hops);
+
+    /// @notice Execute a swap that receives an exact output amount
+    /// @dev Only the input the swap spends leaves the caller's balance; the rest of maxAmountIn is
+    ///      refunded in the same transaction. A route ending in a sell can deliver a few units more
+    ///      than amountOut through rounding, never less.
+    /// @param tokenIn Input token address
+    /// @param tokenOut Output token address
+    /// @param amountOut Amount of output tokens to receive
+    /// @param maxAmountIn Maximum input amount (slippage protection)
+    /// @return amountIn Actual amount spent
+    function swapExactOut(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountOut,
+        uint256 maxAmountIn
+    ) external returns (uint256 amountIn);
+
+    /// @notice Get a quote for a potential exact-output swap
+    /// @param tokenIn Input token address
+    /// @param tokenOut Output token address
+    /// @param amountOut Amount of output tokens to receive
+    /// @return amountIn Expected input amount
+    /// @return route The route that would be used (array of pair IDs)
+    function getQuoteExactOut(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountOut
+    ) external view returns (uint256 amountIn, bytes32[] memory route);
+
+    /// @notice Set the self-trade prevention mode of a pair (RESTRICTED: only the DEX admin)
+    /// @param token0 First token address
+    /// @param token1 Second token address
+    /// @param mode Mode for orders that don't set their own, MARKET_DEFAULT for the protocol default
+    function setSelfTradePrevention(
+        address token0,
+        address token1,
+        SelfTradePrevention mode
+    ) external;
+
+    /// @notice Set how a pair matches its orders (RESTRICTED: only the DEX admin)
+    /// @dev Reverts while a batch auction waits to clear or stop and linked orders are pending.
+    ///      A batch auction fill emits OrderFilled with the buy order as the maker
+    /// @param token0 First token address
+    /// @param token1 Second token address
+    /// @param mode Continuous matching or a batch auction per block
+    function setMarketMode(address token0, address token1, MarketMode mode) external;
+
+    /// @notice Withdraw all protocol fees collected in a token (RESTRICTED: only the DEX admin)
+    /// @param token Token to withdraw fees in (use address(0) for ETH)
+    /// @param to Recipient of the fees
+    /// @return amount Amount of fees withdrawn
+    function withdrawFees(address token, address to) external returns (uint256 amount);
+
+    /// @notice Add liquidity to a pair's constant-product pool, which trades alongside its orderbook
+    /// @dev Tokens come from the caller's DEX balance. The first deposit sets the pool price and
+    ///      locks 1000 shares for good; later deposits take both tokens in the pool's ratio, up to
+    ///      amount0 and amount1. Takers fill against the pool or the book, whichever is better,
+    ///      and a pool fill emits OrderFilled with a zero maker order ID. The pool fee stays in the
+    ///      reserves. Batch auction pairs have no pool, and a pair with one cannot change mode
+    /// @param token0 First token address
+    /// @param token1 Second token address
+    /// @param amount0 Most of token0 to deposit
+    /// @param amount1 Most of token1 to deposit
+    /// @return shares Pool shares minted to the caller
+    function addLiquidity(
+        address token0,
+        address token1,
+        uint256 amount0,
+        uint256 amount1
+    ) external returns (uint256 shares);
+
+    /// @notice Burn pool shares for their part of a pair's pool reserves
+    /// @dev The tokens are credited to the caller's DEX balance
+    /// @param token0 First token address
+    /// @param token1 Second token address
+    /// @param shares Pool shares to burn
+    /// @return amount0 Amount of token0 paid out
+    /// @return amount1 Amount of token1 paid out
+    function removeLiquidity(
+        address token0,
+        address token1,
+        uint256 shares
+    ) external returns (uint256 amount0, uint256 amount1);
+
     /// @notice Get orderbook depth for a trading pair
     /// @param token0 First token
     /// @param token1 Second token
@@ -218,6 +664,39 @@ contract EnshrinedDEX is IEnshrinedDEX {
         revert("Not implemented in EVM");
     }
 
+    function createPairWithParams(
+        address token0,
+        address token1,
+        uint256 tickNum,
+        uint256 tickDenom,
+        uint256 lotSize,
+        uint256 minNotional,
+        bool overrideFee,
+        uint256 feeBps,
+        int256 makerFeeBps
+    ) external override {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function deposit(address token, uint256 amount) external payable override {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function withdraw(address token, uint256 amount) external override {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function getBalance(
+        address account,
+        address token
+    ) external view override returns (uint256 amount) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
     function placeLimitOrder(
         address tokenIn,
         address tokenOut,
@@ -225,7 +704,89 @@ contract EnshrinedDEX is IEnshrinedDEX {
         uint256 amount,
         uint256 priceNum,
         uint256 priceDenom
-    ) external payable override returns (bytes32 orderId) {
+    ) external override returns (bytes32 orderId) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function placeOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        TimeInForce timeInForce,
+        SelfTradePrevention selfTradePrevention
+    ) external override returns (bytes32 orderId) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function placeIcebergOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        uint256 displayAmount
+    ) external override returns (bytes32 orderId) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function placeStopOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 stopNum,
+        uint256 stopDenom,
+        uint256 limitNum,
+        uint256 limitDenom
+    ) external override returns (bytes32 orderId) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function placeOcoOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 stopNum,
+        uint256 stopLimitNum,
+        uint256 priceDenom
+    ) external override returns (bytes32 limitOrderId, bytes32 stopOrderId) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function placeBracketOrder(
+        address tokenIn,
+        address tokenOut,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 takeProfitNum,
+        uint256 stopLossNum,
+        uint256 stopLossLimitNum,
+        uint256 priceDenom
+    ) external override returns (bytes32 entryOrderId) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function placeRangeOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 flipPriceNum,
+        uint256 priceDenom
+    ) external override returns (bytes32 orderId) {
         // Intercepted by protocol layer
         revert("Not implemented in EVM");
     }
@@ -235,12 +796,22 @@ contract EnshrinedDEX is IEnshrinedDEX {
         revert("Not implemented in EVM");
     }
 
+    function amendOrder(
+        bytes32 orderId,
+        uint256 newAmount,
+        uint256 newPriceNum,
+        uint256 newPriceDenom
+    ) external override {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
     function swap(
         address tokenIn,
         address tokenOut,
         uint256 amountIn,
         uint256 minAmountOut
-    ) external payable override returns (uint256 amountOut) {
+    ) external override returns (uint256 amountOut) {
         // Intercepted by protocol layer
         revert("Not implemented in EVM");
     }
@@ -259,6 +830,82 @@ contract EnshrinedDEX is IEnshrinedDEX {
         revert("Not implemented in EVM");
     }
 
+    function getQuoteDetail(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountIn
+    )
+        external
+        view
+        override
+        returns (uint256 amountOut, uint256 priceImpact, QuoteHop[] memory hops)
+    {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function swapExactOut(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountOut,
+        uint256 maxAmountIn
+    ) external override returns (uint256 amountIn) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function getQuoteExactOut(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountOut
+    )
+        external
+        view
+        override
+        returns (uint256 amountIn, bytes32[] memory route)
+    {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function setSelfTradePrevention(
+        address token0,
+        address token1,
+        SelfTradePrevention mode
+    ) external override {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function setMarketMode(address token0, address token1, MarketMode mode) external override {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function withdrawFees(address token, address to) external override returns (uint256 amount) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function addLiquidity(
+        address token0,
+        address token1,
+        uint256 amount0,
+        uint256 amount1
+    ) external override returns (uint256 shares) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
+    function removeLiquidity(
+        address token0,
+        address token1,
+        uint256 shares
+    ) external override returns (uint256 amount0, uint256 amount1) {
+        // Intercepted by protocol layer
+        revert("Not implemented in EVM");
+    }
+
     function getOrderbookDepth(
         address token0,
         address token1,
@@ -11,6 +11,12 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
     mapping(bytes32 => bool) public pairs;
     mapping(bytes32 => Order) public orders;
     mapping(address => bytes32[]) public userOrders;
+    mapping(address => uint256) public accruedFees;
+    mapping(bytes32 => bytes32) public linkedOrders;
+    mapping(address => mapping(address => uint256)) public balances;
+    mapping(bytes32 => mapping(address => Liquidity)) public liquidity;
+
+    address public immutable admin = msg.sender;
 
     uint256 private orderNonce;
 
@@ -25,6 +31,12 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         uint8 status; // 0=open, 1=filled, 2=cancelled
     }
 
+    struct Liquidity {
+        uint256 amount0;
+        uint256 amount1;
+        uint256 shares;
+    }
+
     // Helper to get pair ID
     function getPairId(address token0, address token1) internal pure returns (bytes32) {
         // Ensure consistent ordering
@@ -35,6 +47,10 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
     }
 
     function createPair(address token0, address token1) external override {
+        _createPair(token0, token1);
+    }
+
+    function _createPair(address token0, address token1) internal returns (bytes32 pairId) {
         if (token0 == address(0) && token1 == address(0)) {
             revert InvalidTokenAddress(token0);
         }
@@ -42,7 +58,7 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
             revert InvalidTokenAddress(token0);
         }
 
-        bytes32 pairId = getPairId(token0, token1);
+        pairId = getPairId(token0, token1);
         if (pairs[pairId]) {
             revert PairAlreadyExists(token0, token1, pairId);
         }
@@ -51,6 +67,61 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         emit PairCreated(token0, token1, pairId);
     }
 
+    function createPairWithParams(
+        address token0,
+        address token1,
+        uint256 tickNum,
+        uint256 tickDenom,
+        uint256 lotSize,
+        uint256 minNotional,
+        bool overrideFee,
+        uint256 feeBps,
+        int256 makerFeeBps
+    ) external override {
+        require(tickNum == 0 || tickDenom > 0, "Invalid tick size");
+        require(lotSize > 0, "Invalid lot size");
+        require(!overrideFee || feeBps <= 10000, "Invalid fee");
+        require(!overrideFee || makerFeeBps >= 0 || uint256(-makerFeeBps) <= feeBps, "Unfunded rebate");
+
+        bytes32 pairId = _createPair(token0, token1);
+
+        emit MarketConfigured(
+            pairId, tickNum, tickDenom, lotSize, minNotional, overrideFee, feeBps, makerFeeBps
+        );
+    }
+
+    function deposit(address token, uint256 amount) external payable override {
+        require(amount > 0, "Invalid amount");
+        require(msg.value == (token == address(0) ? amount : 0), "Invalid value");
+
+        // Mock: token deposits are credited without pulling the tokens
+        balances[msg.sender][token] += amount;
+
+        emit Deposited(msg.sender, token, amount);
+    }
+
+    function withdraw(address token, uint256 amount) external override {
+        require(amount > 0, "Invalid amount");
+        uint256 available = balances[msg.sender][token];
+        if (available < amount) {
+            revert InsufficientBalance(msg.sender, amount, available);
+        }
+
+        balances[msg.sender][token] = available - amount;
+        if (token == address(0)) {
+            payable(msg.sender).transfer(amount);
+        }
+
+        emit Withdrawn(msg.sender, token, amount);
+    }
+
+    function getBalance(
+        address account,
+        address token
+    ) external view override returns (uint256 amount) {
+        return balances[account][token];
+    }
+
     function placeLimitOrder(
         address tokenIn,
         address tokenOut,
@@ -58,7 +129,52 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         uint256 amount,
         uint256 priceNum,
         uint256 priceDenom
-    ) external payable override returns (bytes32 orderId) {
+    ) external override returns (bytes32 orderId) {
+        return _placeLimitOrder(tokenIn, tokenOut, isBuy, amount, priceNum, priceDenom);
+    }
+
+    function placeOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        TimeInForce timeInForce,
+        SelfTradePrevention selfTradePrevention
+    ) external override returns (bytes32 orderId) {
+        orderId = _placeLimitOrder(tokenIn, tokenOut, isBuy, amount, priceNum, priceDenom);
+
+        // Mock: nothing ever matches, so immediate orders are cancelled right away
+        if (timeInForce == TimeInForce.IOC) {
+            orders[orderId].status = 2; // cancelled
+            emit OrderCancelled(orderId, msg.sender);
+        } else if (timeInForce == TimeInForce.FOK) {
+            revert("Fill or kill not filled");
+        }
+    }
+
+    function placeIcebergOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom,
+        uint256 displayAmount
+    ) external override returns (bytes32 orderId) {
+        require(displayAmount > 0, "Invalid display amount");
+        return _placeLimitOrder(tokenIn, tokenOut, isBuy, amount, priceNum, priceDenom);
+    }
+
+    function _placeLimitOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 priceDenom
+    ) internal returns (bytes32 orderId) {
         require(amount > 0, "Invalid amount");
         require(priceNum > 0 && priceDenom > 0, "Invalid price");
 
@@ -98,6 +214,146 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         return orderId;
     }
 
+    function placeStopOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 stopNum,
+        uint256 stopDenom,
+        uint256 limitNum,
+        uint256 limitDenom
+    ) external override returns (bytes32 orderId) {
+        require(amount > 0, "Invalid amount");
+        require(stopNum > 0 && stopDenom > 0, "Invalid stop price");
+        require(limitNum == 0 || limitDenom > 0, "Invalid limit price");
+        require(!isBuy || limitNum > 0, "Buy stops need a limit price");
+
+        bytes32 pairId = getPairId(tokenIn, tokenOut);
+        require(pairs[pairId], "Pair does not exist");
+
+        orderId = keccak256(abi.encodePacked(msg.sender, orderNonce++, block.timestamp));
+
+        // Mock: nothing ever trades, so the stop is never triggered and stays open
+        orders[orderId] = Order({
+            trader: msg.sender,
+            tokenIn: tokenIn,
+            tokenOut: tokenOut,
+            isBuy: isBuy,
+            amount: amount,
+            priceNum: limitNum,
+            priceDenom: limitDenom,
+            status: 0 // open
+        });
+        userOrders[msg.sender].push(orderId);
+
+        emit StopOrderPlaced(
+            orderId,
+            msg.sender,
+            tokenIn,
+            tokenOut,
+            isBuy,
+            amount,
+            stopNum,
+            stopDenom,
+            limitNum,
+            limitDenom
+        );
+    }
+
+    function placeOcoOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 stopNum,
+        uint256 stopLimitNum,
+        uint256 priceDenom
+    ) external override returns (bytes32 limitOrderId, bytes32 stopOrderId) {
+        require(stopNum > 0, "Invalid stop price");
+        require(!isBuy || stopLimitNum > 0, "Buy stops need a limit price");
+
+        limitOrderId = _placeLimitOrder(tokenIn, tokenOut, isBuy, amount, priceNum, priceDenom);
+
+        // Mock: nothing ever trades, so both orders stay open until one is cancelled
+        stopOrderId = keccak256(abi.encodePacked(msg.sender, orderNonce++, block.timestamp));
+        orders[stopOrderId] = Order({
+            trader: msg.sender,
+            tokenIn: tokenIn,
+            tokenOut: tokenOut,
+            isBuy: isBuy,
+            amount: amount,
+            priceNum: stopLimitNum,
+            priceDenom: priceDenom,
+            status: 0 // open
+        });
+        userOrders[msg.sender].push(stopOrderId);
+        linkedOrders[limitOrderId] = stopOrderId;
+        linkedOrders[stopOrderId] = limitOrderId;
+
+        emit StopOrderPlaced(
+            stopOrderId,
+            msg.sender,
+            tokenIn,
+            tokenOut,
+            isBuy,
+            amount,
+            stopNum,
+            priceDenom,
+            stopLimitNum,
+            priceDenom
+        );
+        emit OcoOrderPlaced(limitOrderId, stopOrderId, msg.sender);
+    }
+
+    function placeBracketOrder(
+        address tokenIn,
+        address tokenOut,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 takeProfitNum,
+        uint256 stopLossNum,
+        uint256 stopLossLimitNum,
+        uint256 priceDenom
+    ) external override returns (bytes32 entryOrderId) {
+        require(takeProfitNum > priceNum, "Take-profit must be above the entry price");
+        require(stopLossNum > 0 && stopLossNum < priceNum, "Stop-loss must be below the entry price");
+
+        // Mock: nothing ever trades, so the entry never fills and the exits are never placed
+        entryOrderId = _placeLimitOrder(tokenIn, tokenOut, true, amount, priceNum, priceDenom);
+
+        emit BracketOrderPlaced(
+            entryOrderId,
+            msg.sender,
+            takeProfitNum,
+            stopLossNum,
+            stopLossLimitNum,
+            priceDenom
+        );
+    }
+
+    function placeRangeOrder(
+        address tokenIn,
+        address tokenOut,
+        bool isBuy,
+        uint256 amount,
+        uint256 priceNum,
+        uint256 flipPriceNum,
+        uint256 priceDenom
+    ) external override returns (bytes32 orderId) {
+        if (isBuy) {
+            require(flipPriceNum > priceNum, "Flip price must be above the buy price");
+        } else {
+            require(flipPriceNum > 0 && flipPriceNum < priceNum, "Flip price must be below the sell price");
+        }
+
+        // Mock: nothing ever trades, so the order never flips
+        orderId = _placeLimitOrder(tokenIn, tokenOut, isBuy, amount, priceNum, priceDenom);
+
+        emit RangeOrderPlaced(orderId, flipPriceNum, priceDenom);
+    }
+
     function cancelOrder(bytes32 orderId) external override {
         Order storage order = orders[orderId];
         require(order.trader != address(0), "Order not found");
@@ -107,6 +363,41 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         order.status = 2; // cancelled
 
         emit OrderCancelled(orderId, msg.sender);
+
+        // The other order of a one-cancels-other pair goes with it
+        bytes32 linkedOrderId = linkedOrders[orderId];
+        if (linkedOrderId != bytes32(0)) {
+            delete linkedOrders[orderId];
+            delete linkedOrders[linkedOrderId];
+            if (orders[linkedOrderId].status == 0) {
+                orders[linkedOrderId].status = 2; // cancelled
+                emit OrderCancelled(linkedOrderId, msg.sender);
+            }
+        }
+    }
+
+    function amendOrder(
+        bytes32 orderId,
+        uint256 newAmount,
+        uint256 newPriceNum,
+        uint256 newPriceDenom
+    ) external override {
+        Order storage order = orders[orderId];
+        require(order.trader != address(0), "Order not found");
+        require(order.trader == msg.sender, "Not your order");
+        require(order.status == 0, "Order not open");
+        require(linkedOrders[orderId] == bytes32(0), "Order is linked");
+        require(newAmount > 0, "Invalid amount");
+        require(newPriceNum > 0 && newPriceDenom > 0, "Invalid price");
+
+        bool keptPriority = newAmount <= order.amount &&
+            newPriceNum * order.priceDenom == order.priceNum * newPriceDenom;
+
+        order.amount = newAmount;
+        order.priceNum = newPriceNum;
+        order.priceDenom = newPriceDenom;
+
+        emit OrderAmended(orderId, msg.sender, newAmount, newPriceNum, newPriceDenom, keptPriority);
     }
 
     function swap(
@@ -114,7 +405,7 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         address tokenOut,
         uint256 amountIn,
         uint256 minAmountOut
-    ) external payable override returns (uint256 amountOut) {
+    ) external override returns (uint256 amountOut) {
         require(amountIn > 0, "Invalid amount");
 
         bytes32 pairId = getPairId(tokenIn, tokenOut);
@@ -149,6 +440,173 @@ contract MockEnshrinedDEX is IEnshrinedDEX {
         return (amountOut, route);
     }
 
+    function getQuoteDetail(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountIn
+    )
+        external
+        view
+        override
+        returns (uint256 amountOut, uint256 priceImpact, QuoteHop[] memory hops)
+    {
+        bytes32 pairId = getPairId(tokenIn, tokenOut);
+        require(pairs[pairId], "Pair does not exist");
+
+        // Mock: return 90% of input in one fill without impact
+        amountOut = (amountIn * 90) / 100;
+
+        hops = new QuoteHop[](1);
+        hops[0] = QuoteHop({
+            pairId: pairId,
+            tokenIn: tokenIn,
+            tokenOut: tokenOut,
+            amountIn: amountIn,
+            amountOut: amountOut,
+            fee: 0,
+            avgPriceNum: 90,
+            avgPriceDenom: 100,
+            worstPriceNum: 90,
+            worstPriceDenom: 100,
+            levelsConsumed: 1,
+            ordersConsumed: 1,
+            liquidityExhausted: false
+        });
+
+        return (amountOut, 0, hops);
+    }
+
+    function swapExactOut(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountOut,
+        uint256 maxAmountIn
+    ) external override returns (uint256 amountIn) {
+        require(amountOut > 0, "Invalid amount");
+
+        bytes32 pairId = getPairId(tokenIn, tokenOut);
+        require(pairs[pairId], "Pair does not exist");
+
+        // Mock: the input that returns amountOut at 90%, rounded up
+        amountIn = (amountOut * 100 + 89) / 90;
+        require(amountIn <= maxAmountIn, "Slippage exceeded");
+
+        bytes32[] memory route = new bytes32[](1);
+        route[0] = pairId;
+
+        emit Swap(msg.sender, tokenIn, tokenOut, amountIn, amountOut, route);
+
+        return amountIn;
+    }
+
+    function getQuoteExactOut(
+        address tokenIn,
+        address tokenOut,
+        uint256 amountOut
+    ) external view override returns (uint256 amountIn, bytes32[] memory route) {
+        bytes32 pairId = getPairId(tokenIn, tokenOut);
+        require(pairs[pairId], "Pair does not exist");
+
+        // Mock: the input that returns amountOut at 90%, rounded up
+        amountIn = (amountOut * 100 + 89) / 90;
+
+        route = new bytes32[](1);
+        route[0] = pairId;
+
+        return (amountIn, route);
+    }
+
+    function setSelfTradePrevention(
+        address token0,
+        address token1,
+        SelfTradePrevention mode
+    ) external override {
+        if (msg.sender != admin) {
+            revert Unauthorized(msg.sender);
+        }
+
+        bytes32 pairId = getPairId(token0, token1);
+        require(pairs[pairId], "Pair does not exist");
+
+        emit SelfTradePreventionSet(pairId, mode);
+    }
+
+    function setMarketMode(address token0, address token1, MarketMode mode) external override {
+        if (msg.sender != admin) {
+            revert Unauthorized(msg.sender);
+        }
+
+        bytes32 pairId = getPairId(token0, token1);
+        require(pairs[pairId], "Pair does not exist");
+
+        emit MarketModeSet(pairId, mode);
+    }
+
+    function withdrawFees(address token, address to) external override returns (uint256 amount) {
+        if (msg.sender != admin) {
+            revert Unauthorized(msg.sender);
+        }
+
+        amount = accruedFees[token];
+        require(amount > 0, "No fees to withdraw");
+        accruedFees[token] = 0;
+
+        emit FeesWithdrawn(token, to, amount);
+    }
+
+    function addLiquidity(
+        address token0,
+        address token1,
+        uint256 amount0,
+        uint256 amount1
+    ) external override returns (uint256 shares) {
+        require(amount0 > 0 && amount1 > 0, "Invalid amount");
+
+        bytes32 pairId = getPairId(token0, token1);
+        require(pairs[pairId], "Pair does not exist");
+
+        for (uint256 i = 0; i < 2; i++) {
+            (address token, uint256 amount) = i == 0 ? (token0, amount0) : (token1, amount1);
+            uint256 available = balances[msg.sender][token];
+            if (available < amount) {
+                revert InsufficientBalance(msg.sender, amount, available);
+            }
+            balances[msg.sender][token] = available - amount;
+        }
+
+        // Mock: takes both amounts in full and mints one share per unit deposited
+        shares = amount0 + amount1;
+        Liquidity storage position = liquidity[pairId][msg.sender];
+        position.amount0 += amount0;
+        position.amount1 += amount1;
+        position.shares += shares;
+
+        emit LiquidityAdded(msg.sender, token0, token1, amount0, amount1);
+    }
+
+    function removeLiquidity(
+        address token0,
+        address token1,
+        uint256 shares
+    ) external override returns (uint256 amount0, uint256 amount1) {
+        require(shares > 0, "Invalid amount");
+
+        bytes32 pairId = getPairId(token0, token1);
+        Liquidity storage position = liquidity[pairId][msg.sender];
+        require(position.shares >= shares, "Insufficient shares");
+
+        // Mock: pays back the provider's own deposits pro rata, given in the same token order
+        amount0 = (position.amount0 * shares) / position.shares;
+        amount1 = (position.amount1 * shares) / position.shares;
+        position.amount0 -= amount0;
+        position.amount1 -= amount1;
+        position.shares -= shares;
+        balances[msg.sender][token0] += amount0;
+        balances[msg.sender][token1] += amount1;
+
+        emit LiquidityRemoved(msg.sender, token0, token1, amount0, amount1);
+    }
+
     function getOrderbookDepth(
         address token0,
         address token1,
@@ -0,0 +1,186 @@
+//! Routing benchmarks on synthetic markets.
+//!
+//! Each market has a handful of hub tokens paired with each other and with
+//! every other token, plus random pairs between the remaining tokens, all with
+//! a bid and an ask on the book.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dex::router::Router;
+use dex::{
+    Address, BlockContext, DexConfig, Order, OrderBook, OrderId, OrderSide, Pair, PairId,
+    PoolManager, Price, U256,
+};
+use std::collections::{HashMap, HashSet};
+use std::hint::black_box;
+
+const HUBS: usize = 4;
+const MAX_HOPS: usize = 3;
+const SIZES: [usize; 3] = [50, 200, 800];
+
+/// Deterministic generator so every run sees the same market.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize % bound
+    }
+}
+
+fn token(index: usize) -> Address {
+    Address::left_padding_from(&(index as u64 + 1).to_be_bytes())
+}
+
+/// Pairs of a synthetic market with `tokens` tokens and a mid price for each.
+fn synthetic_pairs(tokens: usize) -> Vec<(Pair, u128)> {
+    let mut rng = Lcg(tokens as u64);
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::new();
+    let mut add = |a: usize, b: usize, rng: &mut Lcg| {
+        if a != b && seen.insert((a.min(b), a.max(b))) {
+            pairs.push((
+                Pair::new(token(a.max(b)), token(a.min(b))),
+                100 + rng.next(900) as u128,
+            ));
+        }
+    };
+
+    for a in 0..HUBS {
+        for b in a + 1..HUBS {
+            add(a, b, &mut rng);
+        }
+    }
+    for leaf in HUBS..tokens {
+        for _ in 0..2 {
+            let hub = rng.next(HUBS);
+            add(leaf, hub, &mut rng);
+        }
+        for _ in 0..2 {
+            let other = HUBS + rng.next(tokens - HUBS);
+            add(leaf, other, &mut rng);
+        }
+    }
+
+    pairs
+}
+
+fn limit_order(id: u64, side: OrderSide, price: u128) -> Order {
+    Order::new_limit(
+        OrderId(id),
+        Address::repeat_byte(0xAA),
+        side,
+        Price::from_u128(price, 1),
+        U256::from(1_000_000u64),
+        BlockContext::default(),
+    )
+}
+
+fn synthetic_router(tokens: usize) -> (Router, HashMap<PairId, OrderBook>) {
+    let config = DexConfig::default();
+    let mut router = Router::new();
+    let mut orderbooks = HashMap::new();
+
+    for (i, (pair, mid)) in synthetic_pairs(tokens).into_iter().enumerate() {
+        let mut book = OrderBook::new(pair);
+        let id = 2 * i as u64;
+        book.place_order(limit_order(id, OrderSide::Buy, mid - 1), &config)
+            .unwrap();
+        book.place_order(limit_order(id + 1, OrderSide::Sell, mid + 1), &config)
+            .unwrap();
+        router.add_pair(pair);
+        orderbooks.insert(pair.id(), book);
+    }
+
+    (router, orderbooks)
+}
+
+fn synthetic_pool_manager(tokens: usize) -> PoolManager {
+    let mut pm = PoolManager::new();
+    let maker = Address::repeat_byte(0xAA);
+    let amount = U256::from(1_000_000u64);
+
+    for (pair, mid) in synthetic_pairs(tokens) {
+        pm.create_pair(pair.base, pair.quote).unwrap();
+        pm.place_limit_order(
+            pair.base,
+            pair.quote,
+            maker,
+            OrderSide::Buy,
+            Price::from_u128(mid - 1, 1),
+            amount,
+        )
+        .unwrap();
+        pm.place_limit_order(
+            pair.base,
+            pair.quote,
+            maker,
+            OrderSide::Sell,
+            Price::from_u128(mid + 1, 1),
+            amount,
+        )
+        .unwrap();
+    }
+
+    pm
+}
+
+fn bench_find_routes(c: &mut Criterion) {
+    let candidates = DexConfig::default().max_route_candidates;
+    let mut group = c.benchmark_group("find_routes");
+
+    for tokens in SIZES {
+        let (router, orderbooks) = synthetic_router(tokens);
+        let (token_in, token_out) = (token(HUBS), token(tokens - 1));
+
+        group.bench_with_input(BenchmarkId::new("exhaustive", tokens), &tokens, |b, _| {
+            b.iter(|| {
+                router.find_routes(
+                    black_box(token_in),
+                    black_box(token_out),
+                    MAX_HOPS,
+                    &orderbooks,
+                )
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("candidates", tokens), &tokens, |b, _| {
+            b.iter(|| {
+                router.find_candidate_routes(
+                    black_box(token_in),
+                    black_box(token_out),
+                    MAX_HOPS,
+                    candidates,
+                    &orderbooks,
+                )
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_get_quote(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_quote");
+
+    for tokens in SIZES {
+        let pm = synthetic_pool_manager(tokens);
+        let (token_in, token_out) = (token(HUBS), token(tokens - 1));
+
+        group.bench_with_input(BenchmarkId::from_parameter(tokens), &tokens, |b, _| {
+            b.iter(|| {
+                pm.get_quote(
+                    black_box(token_in),
+                    black_box(token_out),
+                    U256::from(10_000u64),
+                )
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_routes, bench_get_quote);
+criterion_main!(benches);
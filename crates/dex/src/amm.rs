@@ -0,0 +1,561 @@
+//! Constant-product liquidity pools trading alongside the orderbook.
+//!
+//! Each pair has a pool that liquidity providers deposit both tokens into in
+//! exchange for shares of its reserves. The pool quotes a price from its
+//! reserves, `quote / base`, and trades along the curve `base * quote = k`.
+//! Its fee is charged on the token paid in and stays in the reserves, so it
+//! accrues to the providers.
+
+use crate::types::{Address, Amount, Price, U256};
+use alloy::primitives::Uint;
+use std::collections::HashMap;
+
+/// Shares locked for good by the first deposit, so the pool can never be
+/// emptied back to zero and its share price can't be inflated from nothing.
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+/// Basis points in one whole.
+const BPS: u64 = 10_000;
+
+/// Wide enough for the products and square roots of the curve math.
+type U1024 = Uint<1024, 16>;
+
+/// Token reserves of a pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reserves {
+    /// Base token held.
+    pub base: Amount,
+    /// Quote token held.
+    pub quote: Amount,
+}
+
+impl Reserves {
+    /// Whether the pool can't trade: it holds nothing of one of its tokens.
+    pub fn is_empty(&self) -> bool {
+        self.base.is_zero() || self.quote.is_zero()
+    }
+
+    /// Price the next unit of base bought from the pool costs, fee included.
+    pub fn ask_price(&self, fee_bps: u32) -> Option<Price> {
+        if self.is_empty() {
+            return None;
+        }
+        let numerator = self.quote.checked_mul(U256::from(BPS))?;
+        let denominator = self.base.checked_mul(U256::from(kept_bps(fee_bps)))?;
+        Some(Price::new(numerator, denominator))
+    }
+
+    /// Price the next unit of base sold to the pool fetches, fee included.
+    pub fn bid_price(&self, fee_bps: u32) -> Option<Price> {
+        if self.is_empty() {
+            return None;
+        }
+        let numerator = self.quote.checked_mul(U256::from(kept_bps(fee_bps)))?;
+        let denominator = self.base.checked_mul(U256::from(BPS))?;
+        Some(Price::new(numerator, denominator))
+    }
+
+    /// Base the pool sells before its ask rises to `price`.
+    ///
+    /// The fee paid in grows the quote reserve beyond the curve, so the base
+    /// reserve `x` left once the ask reaches `n / d` solves
+    /// `n·κ²·x² + d·B·q·(B - κ)·x - d·B²·k = 0`, where `B` is one whole in
+    /// basis points and `κ` the share of it kept after the fee. The pool
+    /// always keeps some base.
+    pub fn base_out_to(&self, price: Price, fee_bps: u32) -> Amount {
+        // Nothing to sell at a price the pool is already past
+        if self.ask_price(fee_bps).is_none_or(|ask| ask >= price) {
+            return U256::ZERO;
+        }
+        let (bps, kept) = (wide(U256::from(BPS)), wide(U256::from(kept_bps(fee_bps))));
+        let (n, d) = (wide(price.numerator), wide(price.denominator));
+        let k = wide(self.base) * wide(self.quote);
+
+        let target = (|| {
+            let linear = d * bps * wide(self.quote) * (bps - kept);
+            let constant = (U1024::from(4u64) * n * kept * kept * d * bps * bps).checked_mul(k)?;
+            let discriminant = linear.checked_mul(linear)?.checked_add(constant)?;
+            let root = ceil_sqrt(discriminant);
+            Some((root - linear).div_ceil(U1024::from(2u64) * n * kept * kept))
+        })();
+
+        match target {
+            // Unless the price is too far off to work out
+            Some(target) if target < wide(self.base) => {
+                narrow(wide(self.base) - target.max(U1024::from(1u64)))
+            }
+            _ => U256::ZERO,
+        }
+    }
+
+    /// Base the pool buys before its bid falls to `price`.
+    ///
+    /// The fee paid in is added to the base reserve without moving the curve,
+    /// so the base `y` sold once the bid reaches `n / d` solves
+    /// `n·κ·y² + n·b·(B + κ)·y + n·B·b² - d·κ·k = 0`. Returns `U256::MAX`
+    /// when the price is too low to work out, as the pool would then buy
+    /// anything offered.
+    pub fn base_in_to(&self, price: Price, fee_bps: u32) -> Amount {
+        if self.bid_price(fee_bps).is_none_or(|bid| bid <= price) {
+            return U256::ZERO;
+        }
+        if price.numerator.is_zero() {
+            return U256::MAX;
+        }
+        let (bps, kept) = (wide(U256::from(BPS)), wide(U256::from(kept_bps(fee_bps))));
+        let (n, d) = (wide(price.numerator), wide(price.denominator));
+        let (base, k) = (wide(self.base), wide(self.base) * wide(self.quote));
+
+        let root = (|| {
+            let linear = n * base * (bps - kept);
+            let constant = (U1024::from(4u64) * n * kept * kept * d).checked_mul(k)?;
+            let discriminant = linear.checked_mul(linear)?.checked_add(constant)?;
+            Some(discriminant.root(2))
+        })();
+
+        let Some(root) = root else {
+            return U256::MAX;
+        };
+        let offset = n * base * (bps + kept);
+        if root <= offset {
+            return U256::ZERO;
+        }
+        U256::saturating_from((root - offset) / (U1024::from(2u64) * n * kept))
+    }
+
+    /// Quote it costs to buy `base_out` from the pool, fee included, rounded
+    /// up. `None` if the pool doesn't hold that much base.
+    pub fn quote_for_base_out(&self, base_out: Amount, fee_bps: u32) -> Option<Amount> {
+        if self.is_empty() || base_out >= self.base {
+            return None;
+        }
+        let numerator = wide(self.quote) * wide(base_out) * wide(U256::from(BPS));
+        let denominator = wide(self.base - base_out) * wide(U256::from(kept_bps(fee_bps)));
+        checked_narrow(numerator.div_ceil(denominator))
+    }
+
+    /// Quote selling `base_in` to the pool fetches after its fee, rounded down.
+    pub fn quote_for_base_in(&self, base_in: Amount, fee_bps: u32) -> Amount {
+        if self.is_empty() {
+            return U256::ZERO;
+        }
+        let effective = wide(base_in) * wide(U256::from(kept_bps(fee_bps)));
+        let numerator = wide(self.quote) * effective;
+        let denominator = wide(self.base) * wide(U256::from(BPS)) + effective;
+        narrow(numerator / denominator)
+    }
+
+    /// Base `quote_in` buys from the pool after its fee, rounded down.
+    pub fn base_for_quote_in(&self, quote_in: Amount, fee_bps: u32) -> Amount {
+        if self.is_empty() {
+            return U256::ZERO;
+        }
+        let effective = wide(quote_in) * wide(U256::from(kept_bps(fee_bps)));
+        let numerator = wide(self.base) * effective;
+        let denominator = wide(self.quote) * wide(U256::from(BPS)) + effective;
+        narrow(numerator / denominator)
+    }
+
+    /// Base it takes to fetch `quote_out` from the pool after its fee, rounded
+    /// up. `None` if the pool doesn't hold that much quote.
+    pub fn base_for_quote_out(&self, quote_out: Amount, fee_bps: u32) -> Option<Amount> {
+        if self.is_empty() || quote_out >= self.quote {
+            return None;
+        }
+        let numerator = wide(self.base) * wide(quote_out) * wide(U256::from(BPS));
+        let denominator = wide(self.quote - quote_out) * wide(U256::from(kept_bps(fee_bps)));
+        checked_narrow(numerator.div_ceil(denominator))
+    }
+
+    /// The reserves after the pool sold `base_out` for `quote_in`.
+    pub fn after_buy(&self, base_out: Amount, quote_in: Amount) -> Self {
+        Self {
+            base: self.base - base_out,
+            quote: self.quote.saturating_add(quote_in),
+        }
+    }
+
+    /// The reserves after the pool bought `base_in` for `quote_out`.
+    pub fn after_sell(&self, base_in: Amount, quote_out: Amount) -> Self {
+        Self {
+            base: self.base.saturating_add(base_in),
+            quote: self.quote - quote_out,
+        }
+    }
+}
+
+/// A deposit into or withdrawal from a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiquidityChange {
+    /// Shares minted for a deposit or burned for a withdrawal.
+    pub shares: Amount,
+    /// Base token moved into or out of the reserves.
+    pub base: Amount,
+    /// Quote token moved into or out of the reserves.
+    pub quote: Amount,
+}
+
+/// A pair's liquidity pool and the shares its providers hold.
+#[derive(Debug, Clone, Default)]
+pub struct LiquidityPool {
+    /// Tokens in the pool, fees earned included.
+    reserves: Reserves,
+    /// Shares issued, the locked minimum included.
+    total_shares: Amount,
+    /// Shares held per provider.
+    shares: HashMap<Address, Amount>,
+}
+
+impl LiquidityPool {
+    /// Create an empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the pool's reserves.
+    pub fn reserves(&self) -> Reserves {
+        self.reserves
+    }
+
+    /// Replace the reserves once a trade against the pool executes.
+    pub fn set_reserves(&mut self, reserves: Reserves) {
+        self.reserves = reserves;
+    }
+
+    /// Whether the pool holds no liquidity.
+    pub fn is_empty(&self) -> bool {
+        self.total_shares.is_zero()
+    }
+
+    /// Total shares issued, the locked minimum included.
+    pub fn total_shares(&self) -> Amount {
+        self.total_shares
+    }
+
+    /// Shares held by a provider.
+    pub fn shares_of(&self, provider: Address) -> Amount {
+        self.shares.get(&provider).copied().unwrap_or(U256::ZERO)
+    }
+
+    /// Deposit up to `base` and `quote` into the pool.
+    ///
+    /// The first deposit sets the pool's price and mints the geometric mean of
+    /// the amounts in shares, less the locked minimum. Later deposits go in at
+    /// the pool's ratio: the provider gets the shares the scarcer token pays
+    /// for and only hands over the tokens those shares are worth, rounded up.
+    pub fn add_liquidity(
+        &mut self,
+        provider: Address,
+        base: Amount,
+        quote: Amount,
+    ) -> Result<LiquidityChange, LiquidityError> {
+        if base.is_zero() || quote.is_zero() {
+            return Err(LiquidityError::InvalidAmount);
+        }
+
+        let change = if self.is_empty() {
+            let minimum = U256::from(MINIMUM_LIQUIDITY);
+            let shares = narrow((wide(base) * wide(quote)).root(2))
+                .checked_sub(minimum)
+                .filter(|shares| !shares.is_zero())
+                .ok_or(LiquidityError::BelowMinimumLiquidity)?;
+            self.total_shares = minimum;
+            LiquidityChange {
+                shares,
+                base,
+                quote,
+            }
+        } else {
+            let total = wide(self.total_shares);
+            let shares = narrow(
+                (wide(base) * total / wide(self.reserves.base))
+                    .min(wide(quote) * total / wide(self.reserves.quote)),
+            );
+            if shares.is_zero() {
+                return Err(LiquidityError::InvalidAmount);
+            }
+            LiquidityChange {
+                shares,
+                base: narrow((wide(shares) * wide(self.reserves.base)).div_ceil(total)),
+                quote: narrow((wide(shares) * wide(self.reserves.quote)).div_ceil(total)),
+            }
+        };
+
+        self.reserves = Reserves {
+            base: self.reserves.base.saturating_add(change.base),
+            quote: self.reserves.quote.saturating_add(change.quote),
+        };
+        self.total_shares = self.total_shares.saturating_add(change.shares);
+        let held = self.shares.entry(provider).or_default();
+        *held = held.saturating_add(change.shares);
+        Ok(change)
+    }
+
+    /// Burn `shares` of a provider for their part of the reserves, rounded down.
+    pub fn remove_liquidity(
+        &mut self,
+        provider: Address,
+        shares: Amount,
+    ) -> Result<LiquidityChange, LiquidityError> {
+        if shares.is_zero() {
+            return Err(LiquidityError::InvalidAmount);
+        }
+        let held = self.shares_of(provider);
+        if held < shares {
+            return Err(LiquidityError::InsufficientShares {
+                provider,
+                required: shares,
+                available: held,
+            });
+        }
+
+        let total = wide(self.total_shares);
+        let change = LiquidityChange {
+            shares,
+            base: narrow(wide(shares) * wide(self.reserves.base) / total),
+            quote: narrow(wide(shares) * wide(self.reserves.quote) / total),
+        };
+
+        self.reserves = Reserves {
+            base: self.reserves.base - change.base,
+            quote: self.reserves.quote - change.quote,
+        };
+        self.total_shares -= shares;
+        if held == shares {
+            self.shares.remove(&provider);
+        } else {
+            self.shares.insert(provider, held - shares);
+        }
+        Ok(change)
+    }
+}
+
+/// Share of a whole in basis points kept after a fee.
+fn kept_bps(fee_bps: u32) -> u64 {
+    BPS.saturating_sub(fee_bps as u64)
+}
+
+fn wide(value: U256) -> U1024 {
+    U1024::from(value)
+}
+
+fn narrow(value: U1024) -> U256 {
+    U256::saturating_from(value)
+}
+
+fn checked_narrow(value: U1024) -> Option<U256> {
+    (value <= wide(U256::MAX)).then(|| narrow(value))
+}
+
+/// Square root rounded up.
+fn ceil_sqrt(value: U1024) -> U1024 {
+    let root = value.root(2);
+    if root * root < value {
+        root + U1024::from(1u64)
+    } else {
+        root
+    }
+}
+
+/// Errors that can occur when adding or removing liquidity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiquidityError {
+    /// Amounts are zero or too small to mint any shares.
+    InvalidAmount,
+    /// The first deposit doesn't cover the locked minimum of shares.
+    BelowMinimumLiquidity,
+    /// The provider holds fewer shares than it wants to burn.
+    InsufficientShares {
+        provider: Address,
+        required: Amount,
+        available: Amount,
+    },
+    /// Batch auction markets clear without the pool, so it takes no liquidity.
+    BatchAuctionMarket,
+}
+
+impl std::fmt::Display for LiquidityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LiquidityError::InvalidAmount => write!(f, "invalid liquidity amount"),
+            LiquidityError::BelowMinimumLiquidity => {
+                write!(f, "first deposit is below the minimum liquidity")
+            }
+            LiquidityError::InsufficientShares {
+                provider,
+                required,
+                available,
+            } => write!(
+                f,
+                "insufficient shares: provider={:?}, required={}, available={}",
+                provider, required, available
+            ),
+            LiquidityError::BatchAuctionMarket => {
+                write!(f, "batch auction markets take no pool liquidity")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LiquidityError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEE_BPS: u32 = 30;
+
+    fn provider(n: u8) -> Address {
+        Address::repeat_byte(n)
+    }
+
+    /// A pool holding 1,000,000 base at a price of 2000.
+    fn pool() -> LiquidityPool {
+        let mut pool = LiquidityPool::new();
+        pool.add_liquidity(
+            provider(1),
+            U256::from(1_000_000),
+            U256::from(2_000_000_000u64),
+        )
+        .unwrap();
+        pool
+    }
+
+    #[test]
+    fn test_liquidity_shares() {
+        let mut pool = pool();
+        // sqrt(1e6 * 2e9) = 44,721,359, less the locked minimum
+        assert_eq!(pool.shares_of(provider(1)), U256::from(44_720_359));
+        assert_eq!(pool.total_shares(), U256::from(44_721_359));
+
+        // A later deposit goes in at the pool's ratio, paying for the shares it
+        // gets rounded up; the excess quote stays out
+        let change = pool
+            .add_liquidity(provider(2), U256::from(100_000), U256::from(500_000_000))
+            .unwrap();
+        assert_eq!(change.shares, U256::from(4_472_135));
+        assert_eq!(change.base, U256::from(100_000));
+        assert_eq!(change.quote, U256::from(199_999_960));
+        assert_eq!(
+            pool.reserves(),
+            Reserves {
+                base: U256::from(1_100_000),
+                quote: U256::from(2_199_999_960u64),
+            }
+        );
+
+        // Withdrawing rounds down, so the shares left are never underfunded
+        let change = pool
+            .remove_liquidity(provider(2), U256::from(4_472_135))
+            .unwrap();
+        assert_eq!(change.base, U256::from(99_999));
+        assert_eq!(change.quote, U256::from(199_999_959));
+        assert_eq!(pool.shares_of(provider(2)), U256::ZERO);
+
+        assert_eq!(
+            pool.remove_liquidity(provider(2), U256::from(1)),
+            Err(LiquidityError::InsufficientShares {
+                provider: provider(2),
+                required: U256::from(1),
+                available: U256::ZERO,
+            })
+        );
+        assert_eq!(
+            pool.add_liquidity(provider(2), U256::ZERO, U256::from(1)),
+            Err(LiquidityError::InvalidAmount)
+        );
+        assert_eq!(
+            LiquidityPool::new().add_liquidity(provider(1), U256::from(1_000), U256::from(1_000)),
+            Err(LiquidityError::BelowMinimumLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_buy_stops_at_price() {
+        let reserves = pool().reserves();
+        let ask = reserves.ask_price(FEE_BPS).unwrap();
+        assert!(ask > Price::from_u128(2006, 1) && ask < Price::from_u128(2007, 1));
+
+        let bound = Price::from_u128(2100, 1);
+        let base = reserves.base_out_to(bound, FEE_BPS);
+        let cost = |base| reserves.quote_for_base_out(base, FEE_BPS).unwrap();
+        let ask_after = |base| {
+            reserves
+                .after_buy(base, cost(base))
+                .ask_price(FEE_BPS)
+                .unwrap()
+        };
+        // The fee kept in the reserves moves the ask too, so the pool sells up
+        // to the price and not a unit past it
+        assert!(ask_after(base) <= bound);
+        assert!(ask_after(base + U256::from(2)) > bound);
+
+        // Nothing is sold at a price the pool is already past
+        assert_eq!(
+            reserves.base_out_to(Price::from_u128(2000, 1), FEE_BPS),
+            U256::ZERO
+        );
+        // and the pool never sells its last unit
+        assert_eq!(
+            reserves.base_out_to(Price::new(U256::MAX, U256::from(1)), FEE_BPS),
+            U256::from(999_999)
+        );
+    }
+
+    #[test]
+    fn test_sell_stops_at_price() {
+        let reserves = pool().reserves();
+        let bid = reserves.bid_price(FEE_BPS).unwrap();
+        assert_eq!(bid, Price::from_u128(1994, 1));
+
+        let bound = Price::from_u128(1900, 1);
+        let base = reserves.base_in_to(bound, FEE_BPS);
+        let bid_after = |base| {
+            let proceeds = reserves.quote_for_base_in(base, FEE_BPS);
+            reserves
+                .after_sell(base, proceeds)
+                .bid_price(FEE_BPS)
+                .unwrap()
+        };
+        assert!(bid_after(base) >= bound);
+        assert!(bid_after(base + U256::from(2)) < bound);
+
+        assert_eq!(
+            reserves.base_in_to(Price::from_u128(2000, 1), FEE_BPS),
+            U256::ZERO
+        );
+        // A market sell's price leaves the pool buying far more than exists
+        assert!(
+            reserves.base_in_to(Price::new(U256::from(1), U256::MAX), FEE_BPS)
+                > U256::from(u128::MAX)
+        );
+    }
+
+    #[test]
+    fn test_amounts_round_in_the_pools_favor() {
+        let reserves = pool().reserves();
+        let k = reserves.base * reserves.quote;
+
+        // Buying: the quote paid keeps the curve whole even before the fee
+        let quote = reserves
+            .quote_for_base_out(U256::from(1_000), FEE_BPS)
+            .unwrap();
+        assert!(
+            reserves.after_buy(U256::from(1_000), quote).base
+                * reserves.after_buy(U256::from(1_000), quote).quote
+                > k
+        );
+        assert_eq!(
+            reserves.base_for_quote_in(quote, FEE_BPS),
+            U256::from(1_000)
+        );
+
+        // Selling: the quote received does too
+        let quote = reserves.quote_for_base_in(U256::from(1_000), FEE_BPS);
+        let after = reserves.after_sell(U256::from(1_000), quote);
+        assert!(after.base * after.quote > k);
+        let base = reserves.base_for_quote_out(quote, FEE_BPS).unwrap();
+        assert!(base <= U256::from(1_000));
+        assert!(reserves.quote_for_base_in(base, FEE_BPS) >= quote);
+    }
+}
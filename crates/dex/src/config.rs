@@ -1,30 +1,64 @@
 //! DEX configuration parameters.
 
+use crate::order::SelfTradePrevention;
+use crate::types::{Amount, Price, I256, U256};
+use alloy::primitives::U512;
+
+/// Most routes a swap's input may be split across, since every part of the
+/// input is tried on each of them.
+pub const MAX_SPLIT_ROUTES: usize = 4;
+
 /// Configuration for the DEX.
 #[derive(Debug, Clone)]
 pub struct DexConfig {
-    /// Fee charged per trade in basis points (1 bp = 0.01%).
+    /// Taker fee charged per trade in basis points (1 bp = 0.01%).
     /// For example, 30 = 0.30% fee.
     pub fee_bps: u32,
 
+    /// Maker fee charged per trade in basis points.
+    /// Negative values are rebates paid to the maker out of the taker fee,
+    /// so a rebate may not exceed `fee_bps`.
+    pub maker_fee_bps: i32,
+
     /// Maximum number of hops allowed when routing through multiple pairs.
     pub max_routing_hops: usize,
 
+    /// Maximum number of routes a swap's input may be split across, at most
+    /// [`MAX_SPLIT_ROUTES`].
+    /// A value of 1 sends every swap down a single route.
+    pub max_split_routes: usize,
+
+    /// Maximum number of candidate routes the router keeps per token while
+    /// searching, and returns for a quote to choose between.
+    pub max_route_candidates: usize,
+
     /// Minimum order size in the smallest token unit.
     /// Orders below this size will be rejected.
     pub min_order_size: u128,
 
-    /// Whether to allow self-trading (same address on both sides).
-    pub allow_self_trade: bool,
+    /// What happens when an order would match the same trader's resting order,
+    /// unless the market or the order itself says otherwise.
+    pub self_trade_prevention: SelfTradePrevention,
+
+    /// Number of recent trades kept per pair.
+    pub trade_history_len: usize,
+
+    /// Number of recent candles kept per pair and candle interval.
+    pub candle_history_len: usize,
 }
 
 impl Default for DexConfig {
     fn default() -> Self {
         Self {
-            fee_bps: 30,           // 0.30% default fee
-            max_routing_hops: 3,   // Max 3 hops (4 tokens in path)
-            min_order_size: 1,     // Minimum 1 unit
-            allow_self_trade: false,
+            fee_bps: 30,             // 0.30% default fee
+            maker_fee_bps: 0,        // Makers trade for free
+            max_routing_hops: 3,     // Max 3 hops (4 tokens in path)
+            max_split_routes: 3,     // Split across up to 3 routes
+            max_route_candidates: 8, // Weigh up to 8 routes per quote
+            min_order_size: 1,       // Minimum 1 unit
+            self_trade_prevention: SelfTradePrevention::CancelNewest,
+            trade_history_len: 1_000,  // Last 1000 trades per pair
+            candle_history_len: 1_000, // Last 1000 candles per interval
         }
     }
 }
@@ -36,29 +70,76 @@ impl DexConfig {
         self
     }
 
+    /// Create a new configuration with custom maker fee (negative for a rebate).
+    pub fn with_maker_fee_bps(mut self, maker_fee_bps: i32) -> Self {
+        self.maker_fee_bps = maker_fee_bps;
+        self
+    }
+
     /// Create a new configuration with custom max routing hops.
     pub fn with_max_routing_hops(mut self, max_hops: usize) -> Self {
         self.max_routing_hops = max_hops;
         self
     }
 
+    /// Create a new configuration with a custom maximum number of split routes.
+    /// Panics above [`MAX_SPLIT_ROUTES`].
+    pub fn with_max_split_routes(mut self, max_routes: usize) -> Self {
+        assert!(
+            max_routes <= MAX_SPLIT_ROUTES,
+            "at most {MAX_SPLIT_ROUTES} split routes"
+        );
+        self.max_split_routes = max_routes;
+        self
+    }
+
+    /// Create a new configuration with a custom number of candidate routes.
+    pub fn with_max_route_candidates(mut self, max_candidates: usize) -> Self {
+        self.max_route_candidates = max_candidates;
+        self
+    }
+
     /// Create a new configuration with custom minimum order size.
     pub fn with_min_order_size(mut self, min_size: u128) -> Self {
         self.min_order_size = min_size;
         self
     }
 
-    /// Create a new configuration allowing self-trading.
-    pub fn with_self_trade(mut self, allow: bool) -> Self {
-        self.allow_self_trade = allow;
+    /// Create a new configuration with a custom self-trade prevention mode.
+    pub fn with_self_trade_prevention(mut self, mode: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = mode;
+        self
+    }
+
+    /// Create a new configuration keeping a custom number of trades per pair.
+    pub fn with_trade_history_len(mut self, len: usize) -> Self {
+        self.trade_history_len = len;
+        self
+    }
+
+    /// Create a new configuration keeping a custom number of candles per interval.
+    pub fn with_candle_history_len(mut self, len: usize) -> Self {
+        self.candle_history_len = len;
         self
     }
 
     /// Calculate the fee amount for a given trade amount.
     /// Returns the fee amount (to be subtracted from the output).
     pub fn calculate_fee(&self, amount: u128) -> u128 {
-        // fee = amount * fee_bps / 10000
-        amount.saturating_mul(self.fee_bps as u128) / 10000
+        fee_for_bps(amount, self.fee_bps)
+    }
+
+    /// Calculate the signed maker fee for a given trade amount.
+    /// Negative results are rebates owed to the maker.
+    pub fn calculate_maker_fee(&self, amount: u128) -> I256 {
+        maker_fee_for_bps(amount, self.maker_fee_bps)
+    }
+
+    /// Whether maker rebates are covered by the taker fee and swaps split
+    /// across no more than [`MAX_SPLIT_ROUTES`] routes.
+    pub fn is_valid(&self) -> bool {
+        rebate_is_funded(self.fee_bps, self.maker_fee_bps)
+            && self.max_split_routes <= MAX_SPLIT_ROUTES
     }
 
     /// Calculate the amount after fee deduction.
@@ -67,6 +148,212 @@ impl DexConfig {
     }
 }
 
+/// Calculate the fee for a trade amount at the given rate in basis points.
+pub fn fee_for_bps(amount: u128, fee_bps: u32) -> u128 {
+    // fee = amount * fee_bps / 10000
+    amount.saturating_mul(fee_bps as u128) / 10000
+}
+
+/// Calculate the signed maker fee for a trade amount.
+/// Rebates are rounded towards zero so they never exceed the quoted rate.
+pub fn maker_fee_for_bps(amount: u128, maker_fee_bps: i32) -> I256 {
+    let fee = I256::from_raw(U256::from(fee_for_bps(
+        amount,
+        maker_fee_bps.unsigned_abs(),
+    )));
+    if maker_fee_bps < 0 {
+        -fee
+    } else {
+        fee
+    }
+}
+
+/// A maker rebate is paid out of the taker fee, so it may not exceed it.
+fn rebate_is_funded(taker_fee_bps: u32, maker_fee_bps: i32) -> bool {
+    maker_fee_bps >= 0 || maker_fee_bps.unsigned_abs() <= taker_fee_bps
+}
+
+/// How a market matches its orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarketMode {
+    /// Orders match as they arrive, in price-time priority.
+    #[default]
+    Continuous,
+    /// Orders arriving in a block are collected and cleared together once the
+    /// block is over, at a single price that maximizes the matched volume.
+    BatchAuction,
+}
+
+/// Trading parameters for a single market (pair).
+///
+/// The defaults impose no constraints beyond the global [`DexConfig`]:
+/// any price, a lot size of one unit, no minimum notional and continuous matching,
+/// with a 0.30% fee on the market's liquidity pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketConfig {
+    /// Minimum price increment. Limit prices must be a whole multiple of it.
+    /// `None` allows any price.
+    pub tick_size: Option<Price>,
+
+    /// Minimum size increment in base token units.
+    /// Limit order amounts must be a whole multiple of it.
+    pub lot_size: Amount,
+
+    /// Minimum value of a limit order in quote token units.
+    pub min_notional: Amount,
+
+    /// Taker fee override for this market in basis points.
+    /// `None` uses the global [`DexConfig::fee_bps`].
+    pub fee_bps: Option<u32>,
+
+    /// Maker fee override for this market in basis points (negative for a rebate).
+    /// `None` uses the global [`DexConfig::maker_fee_bps`].
+    pub maker_fee_bps: Option<i32>,
+
+    /// Self-trade prevention override for this market.
+    /// `None` uses the global [`DexConfig::self_trade_prevention`].
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+
+    /// Whether orders match continuously or in a batch auction per block.
+    pub mode: MarketMode,
+
+    /// Fee of the market's liquidity pool in basis points, charged on what a
+    /// taker pays the pool and left in its reserves for the providers.
+    pub pool_fee_bps: u32,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self {
+            tick_size: None,
+            lot_size: U256::from(1),
+            min_notional: U256::ZERO,
+            fee_bps: None,
+            maker_fee_bps: None,
+            self_trade_prevention: None,
+            mode: MarketMode::Continuous,
+            pool_fee_bps: 30, // 0.30%, like the default taker fee
+        }
+    }
+}
+
+impl MarketConfig {
+    /// Create a new market configuration with a tick size.
+    pub fn with_tick_size(mut self, tick_size: Price) -> Self {
+        self.tick_size = Some(tick_size);
+        self
+    }
+
+    /// Create a new market configuration with a lot size.
+    pub fn with_lot_size(mut self, lot_size: Amount) -> Self {
+        self.lot_size = lot_size;
+        self
+    }
+
+    /// Create a new market configuration with a minimum notional.
+    pub fn with_min_notional(mut self, min_notional: Amount) -> Self {
+        self.min_notional = min_notional;
+        self
+    }
+
+    /// Create a new market configuration with a taker fee override.
+    pub fn with_fee_bps(mut self, fee_bps: u32) -> Self {
+        self.fee_bps = Some(fee_bps);
+        self
+    }
+
+    /// Create a new market configuration with a maker fee override.
+    pub fn with_maker_fee_bps(mut self, maker_fee_bps: i32) -> Self {
+        self.maker_fee_bps = Some(maker_fee_bps);
+        self
+    }
+
+    /// Create a new market configuration with a self-trade prevention override.
+    pub fn with_self_trade_prevention(mut self, mode: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = Some(mode);
+        self
+    }
+
+    /// Create a new market configuration with a matching mode.
+    pub fn with_mode(mut self, mode: MarketMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Create a new market configuration with a liquidity pool fee.
+    pub fn with_pool_fee_bps(mut self, pool_fee_bps: u32) -> Self {
+        self.pool_fee_bps = pool_fee_bps;
+        self
+    }
+
+    /// Whether orders are cleared in a batch auction per block.
+    pub fn is_batch_auction(&self) -> bool {
+        self.mode == MarketMode::BatchAuction
+    }
+
+    /// Check that the parameters are usable: a non-zero tick and lot size,
+    /// a maker rebate covered by the taker fee and a pool fee below 100%.
+    pub fn is_valid(&self, config: &DexConfig) -> bool {
+        let tick_ok = self
+            .tick_size
+            .is_none_or(|tick| !tick.numerator.is_zero() && !tick.denominator.is_zero());
+        tick_ok
+            && !self.lot_size.is_zero()
+            && rebate_is_funded(self.fee_bps(config), self.maker_fee_bps(config))
+            && self.pool_fee_bps < 10_000
+    }
+
+    /// Whether a price is a whole multiple of the tick size.
+    pub fn is_on_tick(&self, price: Price) -> bool {
+        let Some(tick) = self.tick_size else {
+            return true;
+        };
+        // price / tick = (p.num * t.den) / (p.den * t.num) must be an integer
+        let num: U512 = price.numerator.widening_mul(tick.denominator);
+        let den: U512 = price.denominator.widening_mul(tick.numerator);
+        !den.is_zero() && (num % den).is_zero()
+    }
+
+    /// Whether an amount is a whole multiple of the lot size.
+    pub fn is_on_lot(&self, amount: Amount) -> bool {
+        !self.lot_size.is_zero() && (amount % self.lot_size).is_zero()
+    }
+
+    /// Round an amount down to a whole number of lots.
+    pub fn round_down_to_lot(&self, amount: Amount) -> Amount {
+        if self.lot_size.is_zero() {
+            return amount;
+        }
+        amount - amount % self.lot_size
+    }
+
+    /// Taker fee rate for this market, falling back to the global configuration.
+    pub fn fee_bps(&self, config: &DexConfig) -> u32 {
+        self.fee_bps.unwrap_or(config.fee_bps)
+    }
+
+    /// Maker fee rate for this market, falling back to the global configuration.
+    pub fn maker_fee_bps(&self, config: &DexConfig) -> i32 {
+        self.maker_fee_bps.unwrap_or(config.maker_fee_bps)
+    }
+
+    /// Self-trade prevention mode for this market, falling back to the global configuration.
+    pub fn self_trade_prevention(&self, config: &DexConfig) -> SelfTradePrevention {
+        self.self_trade_prevention
+            .unwrap_or(config.self_trade_prevention)
+    }
+
+    /// Calculate the taker fee for a trade amount in this market.
+    pub fn calculate_fee(&self, amount: u128, config: &DexConfig) -> u128 {
+        fee_for_bps(amount, self.fee_bps(config))
+    }
+
+    /// Calculate the signed maker fee for a trade amount in this market.
+    pub fn calculate_maker_fee(&self, amount: u128, config: &DexConfig) -> I256 {
+        maker_fee_for_bps(amount, self.maker_fee_bps(config))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,4 +376,78 @@ mod tests {
         assert_eq!(config.calculate_fee(10000), 100);
         assert_eq!(config.amount_after_fee(10000), 9900);
     }
+
+    #[test]
+    fn test_market_tick_size() {
+        let market =
+            MarketConfig::default().with_tick_size(Price::new(U256::from(1), U256::from(100)));
+
+        // 1.25 = 125 ticks of 0.01
+        assert!(market.is_on_tick(Price::new(U256::from(5), U256::from(4))));
+        // 1/3 is not a multiple of 0.01
+        assert!(!market.is_on_tick(Price::new(U256::from(1), U256::from(3))));
+        // No tick size accepts any price
+        assert!(MarketConfig::default().is_on_tick(Price::new(U256::from(1), U256::from(3))));
+    }
+
+    #[test]
+    fn test_market_lot_size() {
+        let market = MarketConfig::default().with_lot_size(U256::from(100));
+
+        assert!(market.is_on_lot(U256::from(300)));
+        assert!(!market.is_on_lot(U256::from(250)));
+        assert_eq!(market.round_down_to_lot(U256::from(250)), U256::from(200));
+        assert!(!MarketConfig::default()
+            .with_lot_size(U256::ZERO)
+            .is_valid(&DexConfig::default()));
+    }
+
+    #[test]
+    fn test_market_fee_override() {
+        let config = DexConfig::default();
+
+        assert_eq!(MarketConfig::default().calculate_fee(10000, &config), 30);
+        assert_eq!(
+            MarketConfig::default()
+                .with_fee_bps(5)
+                .calculate_fee(10000, &config),
+            5
+        );
+    }
+
+    #[test]
+    fn test_maker_fee_and_rebate() {
+        let config = DexConfig::default().with_maker_fee_bps(-10);
+
+        assert_eq!(
+            config.calculate_maker_fee(10000),
+            I256::try_from(-10).unwrap()
+        );
+        assert_eq!(
+            DexConfig::default()
+                .with_maker_fee_bps(5)
+                .calculate_maker_fee(10000),
+            I256::try_from(5).unwrap()
+        );
+        // Rebates round towards zero
+        assert_eq!(config.calculate_maker_fee(999), I256::ZERO);
+
+        // A rebate larger than the taker fee cannot be funded
+        assert!(config.is_valid());
+        assert!(!config.clone().with_maker_fee_bps(-31).is_valid());
+        assert!(!MarketConfig::default().with_fee_bps(5).is_valid(&config));
+    }
+
+    #[test]
+    fn test_max_split_routes_capped() {
+        let mut config = DexConfig::default().with_max_split_routes(MAX_SPLIT_ROUTES);
+        assert!(config.is_valid());
+
+        config.max_split_routes = MAX_SPLIT_ROUTES + 1;
+        assert!(!config.is_valid());
+        assert!(std::panic::catch_unwind(|| {
+            DexConfig::default().with_max_split_routes(MAX_SPLIT_ROUTES + 1)
+        })
+        .is_err());
+    }
 }
@@ -0,0 +1,338 @@
+//! Trade history and OHLCV candles of a pair.
+//!
+//! Every fill an orderbook executes is recorded as a trade, stamped with the
+//! chain position of the transaction that executed it. The most recent trades
+//! are kept as a tape, and each trade is folded into the candle of every
+//! interval it falls in. Both are bounded, dropping the oldest entries first.
+
+use crate::config::DexConfig;
+use crate::order::{OrderId, OrderSide};
+use crate::orderbook::Fill;
+use crate::types::{Amount, BlockContext, Price};
+use std::collections::VecDeque;
+
+/// Seconds in a minute.
+const MINUTE: u64 = 60;
+/// Seconds in an hour.
+const HOUR: u64 = 60 * MINUTE;
+/// Seconds in a day.
+const DAY: u64 = 24 * HOUR;
+
+/// A fill executed on a pair.
+#[derive(Debug, Clone)]
+pub struct Trade {
+    /// Position of the trade in the pair's history, counting from 0.
+    pub sequence: u64,
+    /// The taker order ID. In a batch auction the sell order is the taker.
+    pub taker_order_id: OrderId,
+    /// Side of the taker, which tells whether the trade lifted an ask or hit a bid.
+    pub taker_side: OrderSide,
+    /// The fill, including its maker and fees.
+    pub fill: Fill,
+    /// Chain position of the transaction that executed the trade.
+    pub executed_at: BlockContext,
+}
+
+/// Length of the period a candle covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandleInterval {
+    /// One candle per block.
+    Block,
+    /// One minute of block time.
+    Minute,
+    /// One hour of block time.
+    Hour,
+    /// One day of block time.
+    Day,
+}
+
+impl CandleInterval {
+    /// All intervals, shortest first.
+    pub const ALL: [CandleInterval; 4] = [
+        CandleInterval::Block,
+        CandleInterval::Minute,
+        CandleInterval::Hour,
+        CandleInterval::Day,
+    ];
+
+    /// Start of the candle that a trade at `at` belongs to: the block number
+    /// for block candles, otherwise the block timestamp rounded down to the
+    /// interval.
+    pub fn start(self, at: BlockContext) -> u64 {
+        match self {
+            CandleInterval::Block => at.block_number,
+            CandleInterval::Minute => at.timestamp - at.timestamp % MINUTE,
+            CandleInterval::Hour => at.timestamp - at.timestamp % HOUR,
+            CandleInterval::Day => at.timestamp - at.timestamp % DAY,
+        }
+    }
+
+    /// Position of the interval in `ALL`.
+    fn index(self) -> usize {
+        match self {
+            CandleInterval::Block => 0,
+            CandleInterval::Minute => 1,
+            CandleInterval::Hour => 2,
+            CandleInterval::Day => 3,
+        }
+    }
+}
+
+/// Open, high, low and close prices and the volume traded over one interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candle {
+    /// Block number or timestamp the candle starts at, see `CandleInterval::start`.
+    pub start: u64,
+    /// Price of the first trade.
+    pub open: Price,
+    /// Highest trade price.
+    pub high: Price,
+    /// Lowest trade price.
+    pub low: Price,
+    /// Price of the last trade.
+    pub close: Price,
+    /// Base token traded.
+    pub volume: Amount,
+    /// Quote token traded.
+    pub quote_volume: Amount,
+    /// Number of trades.
+    pub trade_count: u64,
+}
+
+impl Candle {
+    /// A candle opened by a single fill.
+    fn open(start: u64, fill: &Fill) -> Self {
+        Self {
+            start,
+            open: fill.price,
+            high: fill.price,
+            low: fill.price,
+            close: fill.price,
+            volume: fill.base_amount,
+            quote_volume: fill.quote_amount,
+            trade_count: 1,
+        }
+    }
+
+    /// Fold a later fill into the candle.
+    fn update(&mut self, fill: &Fill) {
+        self.high = self.high.max(fill.price);
+        self.low = self.low.min(fill.price);
+        self.close = fill.price;
+        self.volume = self.volume.saturating_add(fill.base_amount);
+        self.quote_volume = self.quote_volume.saturating_add(fill.quote_amount);
+        self.trade_count += 1;
+    }
+}
+
+/// Recent trades of a pair and the candles built from them.
+///
+/// Intervals without trades have no candle, so consecutive candles need not
+/// be adjacent.
+#[derive(Debug, Clone, Default)]
+pub struct TradeHistory {
+    /// Most recent trades, oldest first.
+    trades: VecDeque<Trade>,
+    /// Most recent candles of each interval, oldest first, indexed like
+    /// `CandleInterval::ALL`.
+    candles: [VecDeque<Candle>; 4],
+    /// Number of trades ever recorded.
+    trade_count: u64,
+}
+
+impl TradeHistory {
+    /// Create an empty history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a fill executed at `executed_at`, keeping no more trades and
+    /// candles per interval than `config` allows.
+    pub(crate) fn record(
+        &mut self,
+        taker_order_id: OrderId,
+        taker_side: OrderSide,
+        fill: &Fill,
+        executed_at: BlockContext,
+        config: &DexConfig,
+    ) {
+        for interval in CandleInterval::ALL {
+            let start = interval.start(executed_at);
+            let candles = &mut self.candles[interval.index()];
+            // Chain time doesn't run backwards, but a trade stamped before the
+            // last candle still belongs in it rather than in a new one
+            match candles.back_mut() {
+                Some(candle) if candle.start >= start => candle.update(fill),
+                _ => candles.push_back(Candle::open(start, fill)),
+            }
+            truncate_front(candles, config.candle_history_len);
+        }
+
+        self.trades.push_back(Trade {
+            sequence: self.trade_count,
+            taker_order_id,
+            taker_side,
+            fill: fill.clone(),
+            executed_at,
+        });
+        truncate_front(&mut self.trades, config.trade_history_len);
+        self.trade_count += 1;
+    }
+
+    /// Most recent trades, oldest first.
+    pub fn trades(&self) -> &VecDeque<Trade> {
+        &self.trades
+    }
+
+    /// The latest trade.
+    pub fn last_trade(&self) -> Option<&Trade> {
+        self.trades.back()
+    }
+
+    /// Number of trades ever recorded, including those dropped from the tape.
+    pub fn trade_count(&self) -> u64 {
+        self.trade_count
+    }
+
+    /// Most recent candles of an interval, oldest first. The last one is still
+    /// open while trades keep falling in its interval.
+    pub fn candles(&self, interval: CandleInterval) -> &VecDeque<Candle> {
+        &self.candles[interval.index()]
+    }
+}
+
+/// Drop the oldest entries of `entries` beyond `len`.
+fn truncate_front<T>(entries: &mut VecDeque<T>, len: usize) {
+    let excess = entries.len().saturating_sub(len);
+    entries.drain(..excess);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Address, I256, U256};
+
+    fn fill(price: u128, base_amount: u64) -> Fill {
+        let price = Price::from_u128(price, 1);
+        Fill {
+            maker_order_id: OrderId(1),
+            maker: Address::repeat_byte(1),
+            base_amount: U256::from(base_amount),
+            quote_amount: price.quote_amount(U256::from(base_amount)).unwrap(),
+            price,
+            taker_fee: U256::ZERO,
+            maker_fee: I256::ZERO,
+            from_pool: false,
+        }
+    }
+
+    fn record(history: &mut TradeHistory, fill: Fill, at: BlockContext, config: &DexConfig) {
+        history.record(OrderId(2), OrderSide::Buy, &fill, at, config);
+    }
+
+    #[test]
+    fn test_candle_interval_start() {
+        let at = BlockContext::new(42, 0, 1_700_003_725);
+
+        assert_eq!(CandleInterval::Block.start(at), 42);
+        assert_eq!(CandleInterval::Minute.start(at), 1_700_003_700);
+        assert_eq!(CandleInterval::Hour.start(at), 1_700_002_800);
+        assert_eq!(CandleInterval::Day.start(at), 1_699_920_000);
+    }
+
+    #[test]
+    fn test_candles_aggregate_trades() {
+        let config = DexConfig::default();
+        let mut history = TradeHistory::new();
+
+        // Two blocks in the same minute, then one a minute later
+        record(
+            &mut history,
+            fill(100, 5),
+            BlockContext::new(1, 0, 60),
+            &config,
+        );
+        record(
+            &mut history,
+            fill(104, 1),
+            BlockContext::new(1, 1, 60),
+            &config,
+        );
+        record(
+            &mut history,
+            fill(98, 2),
+            BlockContext::new(2, 0, 72),
+            &config,
+        );
+        record(
+            &mut history,
+            fill(101, 3),
+            BlockContext::new(3, 0, 120),
+            &config,
+        );
+
+        let blocks = history.candles(CandleInterval::Block);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].start, 1);
+        assert_eq!(blocks[0].open, Price::from_u128(100, 1));
+        assert_eq!(blocks[0].high, Price::from_u128(104, 1));
+        assert_eq!(blocks[0].close, Price::from_u128(104, 1));
+        assert_eq!(blocks[0].trade_count, 2);
+
+        let minutes = history.candles(CandleInterval::Minute);
+        assert_eq!(minutes.len(), 2);
+        let first = minutes[0];
+        assert_eq!(first.start, 60);
+        assert_eq!(first.open, Price::from_u128(100, 1));
+        assert_eq!(first.high, Price::from_u128(104, 1));
+        assert_eq!(first.low, Price::from_u128(98, 1));
+        assert_eq!(first.close, Price::from_u128(98, 1));
+        assert_eq!(first.volume, U256::from(8));
+        assert_eq!(first.quote_volume, U256::from(500 + 104 + 196));
+        assert_eq!(first.trade_count, 3);
+        assert_eq!(minutes[1].start, 120);
+        assert_eq!(minutes[1].open, Price::from_u128(101, 1));
+
+        let days = history.candles(CandleInterval::Day);
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].volume, U256::from(11));
+        assert_eq!(days[0].trade_count, 4);
+
+        assert_eq!(history.trade_count(), 4);
+        let last = history.last_trade().unwrap();
+        assert_eq!(last.sequence, 3);
+        assert_eq!(last.fill.price, Price::from_u128(101, 1));
+        assert_eq!(last.executed_at, BlockContext::new(3, 0, 120));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let config = DexConfig::default()
+            .with_trade_history_len(2)
+            .with_candle_history_len(3);
+        let mut history = TradeHistory::new();
+
+        for block in 0..5 {
+            let at = BlockContext::new(block, 0, block * MINUTE);
+            record(&mut history, fill(100 + block as u128, 1), at, &config);
+        }
+
+        // The oldest trades and candles are dropped first
+        let sequences: Vec<u64> = history.trades().iter().map(|t| t.sequence).collect();
+        assert_eq!(sequences, vec![3, 4]);
+        assert_eq!(history.trade_count(), 5);
+
+        let starts: Vec<u64> = history
+            .candles(CandleInterval::Block)
+            .iter()
+            .map(|c| c.start)
+            .collect();
+        assert_eq!(starts, vec![2, 3, 4]);
+        assert_eq!(history.candles(CandleInterval::Minute).len(), 3);
+        assert_eq!(history.candles(CandleInterval::Hour).len(), 1);
+        assert_eq!(
+            history.candles(CandleInterval::Hour)[0].volume,
+            U256::from(5)
+        );
+    }
+}
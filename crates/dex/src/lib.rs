@@ -2,11 +2,16 @@
 //!
 //! This library provides an efficient orderbook implementation with:
 //! - Limit and market order support
+//! - Constant-product liquidity pools trading alongside each orderbook
+//! - Continuous matching or per-block batch auctions per market
+//! - Trade history and OHLCV candles per pair
 //! - Multi-pair management
 //! - Quote generation with automatic multi-hop routing
 //! - Configurable fee structure
 
+pub mod amm;
 pub mod config;
+pub mod history;
 pub mod order;
 pub mod orderbook;
 pub mod pair;
@@ -14,10 +19,14 @@ pub mod pool_manager;
 pub mod router;
 pub mod types;
 
-pub use config::DexConfig;
-pub use order::{Order, OrderId, OrderSide, OrderStatus, OrderType};
-pub use orderbook::{OrderBook, OrderError};
+pub use amm::{LiquidityChange, LiquidityError, LiquidityPool, Reserves};
+pub use config::{DexConfig, MarketConfig, MarketMode};
+pub use history::{Candle, CandleInterval, Trade, TradeHistory};
+pub use order::{
+    Order, OrderId, OrderSide, OrderStatus, OrderType, SelfTradePrevention, TimeInForce,
+};
+pub use orderbook::{MarketSimulation, OrderBook, OrderError};
 pub use pair::{Pair, PairId};
-pub use pool_manager::{PoolManager, PoolError};
-pub use router::{Quote, Route, RouteHop};
-pub use types::{Address, Amount, Price, TokenId, U256, ETH_TOKEN};
+pub use pool_manager::{OrderGroup, OrderGroupKind, PoolError, PoolManager};
+pub use router::{Quote, Route, RouteHop, RouteSplit};
+pub use types::{Address, Amount, BlockContext, Price, TokenId, ETH_TOKEN, I256, U256};
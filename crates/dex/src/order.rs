@@ -1,10 +1,9 @@
 //! Order types and management.
 
-use crate::types::{Address, Amount, Price, U256};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::types::{Address, Amount, BlockContext, Price, U256};
 
 /// Unique identifier for an order.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct OrderId(pub u64);
 
 impl OrderId {
@@ -22,6 +21,16 @@ pub enum OrderSide {
     Sell,
 }
 
+impl OrderSide {
+    /// The other side of the book.
+    pub fn opposite(self) -> Self {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
 /// Type of order.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderType {
@@ -31,6 +40,51 @@ pub enum OrderType {
     Market,
 }
 
+/// How long an order stays on the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeInForce {
+    /// Good till cancelled: any unfilled remainder rests on the book.
+    #[default]
+    GoodTillCancel,
+    /// Immediate or cancel: fill what is possible now and cancel the remainder.
+    ImmediateOrCancel,
+    /// Fill or kill: fill the whole order now or reject it without any fill.
+    FillOrKill,
+    /// Post only: rest on the book as a maker; rejected if it would cross.
+    PostOnly,
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder rests on the book.
+    pub fn rests(&self) -> bool {
+        matches!(self, TimeInForce::GoodTillCancel | TimeInForce::PostOnly)
+    }
+}
+
+/// What happens when an incoming order would match a resting order of the
+/// same trader. Whichever orders are cancelled leave the book, so an incoming
+/// order never rests crossed against its own orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelfTradePrevention {
+    /// Cancel the rest of the incoming order and keep the resting order.
+    #[default]
+    CancelNewest,
+    /// Cancel the resting order and keep matching the incoming order.
+    CancelOldest,
+    /// Cancel both orders.
+    CancelBoth,
+    /// Reduce both orders by the smaller remaining amount: the smaller order is
+    /// cancelled and the larger one shrinks. An incoming order with some amount
+    /// left keeps matching.
+    DecrementAndCancel,
+    /// Leave the resting order alone and keep matching the incoming order past
+    /// it. Swaps trade this way; it is only safe for orders that never rest,
+    /// as a resting remainder could sit crossed against the skipped order.
+    Skip,
+    /// Let the orders trade with each other.
+    Allow,
+}
+
 /// Status of an order.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderStatus {
@@ -55,16 +109,42 @@ pub struct Order {
     pub side: OrderSide,
     /// Limit or market order.
     pub order_type: OrderType,
+    /// How long the order stays on the book.
+    pub time_in_force: TimeInForce,
     /// Price per unit (for limit orders).
     pub price: Price,
+    /// Self-trade prevention mode for this order, overriding the market's.
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// Trigger price for stop orders. A stop order waits off the book until the
+    /// last trade price reaches it, then becomes a regular limit or market order.
+    pub stop_price: Option<Price>,
     /// Original amount of base token.
     pub original_amount: Amount,
     /// Remaining amount of base token to be filled.
     pub remaining_amount: Amount,
+    /// Clip size of an iceberg order: only this much of the remaining amount is
+    /// shown on the book at a time, the rest is a hidden reserve.
+    pub display_amount: Option<Amount>,
+    /// Part of the remaining amount shown on the book. Equal to the remaining
+    /// amount unless the order is an iceberg.
+    pub visible_amount: Amount,
+    /// Price a range order is re-posted at on the opposite side once it is
+    /// completely filled. The order then flips between its two prices, earning
+    /// the spread between them each round trip.
+    pub flip_price: Option<Price>,
+    /// What a range order received from its fills since it was last posted,
+    /// net of maker fees: quote while it sells, base while it buys. It funds
+    /// the order's next side.
+    pub range_proceeds: Amount,
+    /// Quote left to spend, fees included, for a market buy sized in quote.
+    /// Such an order buys as much base as the budget affords instead of a
+    /// fixed base amount.
+    pub quote_budget: Option<Amount>,
     /// Current status of the order.
     pub status: OrderStatus,
-    /// Timestamp when the order was created (unix millis).
-    pub timestamp: u64,
+    /// Chain position of the transaction that placed the order.
+    /// Orders at the same price are matched in this order (time priority).
+    pub placed_at: BlockContext,
 }
 
 impl Order {
@@ -75,25 +155,40 @@ impl Order {
         side: OrderSide,
         price: Price,
         amount: Amount,
+        placed_at: BlockContext,
     ) -> Self {
         Self {
             id,
             trader,
             side,
             order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            self_trade_prevention: None,
             price,
+            stop_price: None,
             original_amount: amount,
             remaining_amount: amount,
+            display_amount: None,
+            visible_amount: amount,
+            flip_price: None,
+            range_proceeds: U256::ZERO,
+            quote_budget: None,
             status: OrderStatus::Open,
-            timestamp: current_timestamp(),
+            placed_at,
         }
     }
 
     /// Create a new market order.
-    pub fn new_market(id: OrderId, trader: Address, side: OrderSide, amount: Amount) -> Self {
+    pub fn new_market(
+        id: OrderId,
+        trader: Address,
+        side: OrderSide,
+        amount: Amount,
+        placed_at: BlockContext,
+    ) -> Self {
         // Market orders use a placeholder price; they match at the best available price.
         let price = match side {
-            OrderSide::Buy => Price::new(U256::MAX, U256::from(1)),  // Willing to pay any price
+            OrderSide::Buy => Price::new(U256::MAX, U256::from(1)), // Willing to pay any price
             OrderSide::Sell => Price::new(U256::from(1), U256::MAX), // Willing to accept any price
         };
         Self {
@@ -101,22 +196,139 @@ impl Order {
             trader,
             side,
             order_type: OrderType::Market,
+            time_in_force: TimeInForce::ImmediateOrCancel,
+            self_trade_prevention: None,
             price,
+            stop_price: None,
             original_amount: amount,
             remaining_amount: amount,
+            display_amount: None,
+            visible_amount: amount,
+            flip_price: None,
+            range_proceeds: U256::ZERO,
+            quote_budget: None,
             status: OrderStatus::Open,
-            timestamp: current_timestamp(),
+            placed_at,
+        }
+    }
+
+    /// Set the time in force.
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    /// Set the self-trade prevention mode, overriding the market's.
+    pub fn with_self_trade_prevention(mut self, mode: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = Some(mode);
+        self
+    }
+
+    /// Turn the order into a stop order triggered at `stop_price`.
+    /// A stop limit order is a limit order with a stop price, a stop market order
+    /// is a market order with one.
+    pub fn with_stop_price(mut self, stop_price: Price) -> Self {
+        self.stop_price = Some(stop_price);
+        self
+    }
+
+    /// Turn the order into an iceberg order showing `display_amount` at a time.
+    pub fn with_display_amount(mut self, display_amount: Amount) -> Self {
+        self.display_amount = Some(display_amount);
+        self.visible_amount = display_amount.min(self.remaining_amount);
+        self
+    }
+
+    /// Turn the order into a range order that flips to `flip_price` on the
+    /// opposite side each time it is completely filled.
+    pub fn with_flip_price(mut self, flip_price: Price) -> Self {
+        self.flip_price = Some(flip_price);
+        self
+    }
+
+    /// Size a market buy by the quote it may spend, fees included, rather than
+    /// by base. The base amount is left open until the budget runs out.
+    pub fn with_quote_budget(mut self, quote_budget: Amount) -> Self {
+        self.original_amount = U256::MAX;
+        self.remaining_amount = U256::MAX;
+        self.visible_amount = U256::MAX;
+        self.quote_budget = Some(quote_budget);
+        self
+    }
+
+    /// Settle a quote-sized order once its budget is spent or the book runs dry:
+    /// the order ends filled for the base it bought, or cancelled if it bought
+    /// nothing.
+    pub fn close_quote_budget(&mut self) {
+        self.resize(U256::ZERO);
+        if self.original_amount.is_zero() {
+            self.cancel();
+        } else {
+            self.status = OrderStatus::Filled;
         }
     }
 
+    /// Show the next clip of an iceberg order from its hidden reserve.
+    /// Returns false for orders that are not icebergs or have nothing left.
+    pub fn refresh_clip(&mut self) -> bool {
+        match self.display_amount {
+            Some(display_amount) if !self.remaining_amount.is_zero() => {
+                self.visible_amount = display_amount.min(self.remaining_amount);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Re-post a completely filled range order on the opposite side for
+    /// `amount` of base: it swaps its price and flip price, takes its time
+    /// priority from `placed_at` and starts over without proceeds.
+    /// Returns false for orders that are not range orders.
+    pub fn flip(&mut self, amount: Amount, placed_at: BlockContext) -> bool {
+        let Some(flip_price) = self.flip_price else {
+            return false;
+        };
+        self.side = self.side.opposite();
+        self.flip_price = Some(self.price);
+        self.price = flip_price;
+        self.original_amount = amount;
+        self.remaining_amount = amount;
+        self.visible_amount = amount;
+        self.range_proceeds = U256::ZERO;
+        self.status = OrderStatus::Open;
+        self.placed_at = placed_at;
+        true
+    }
+
+    /// Check if a trade at `last_price` triggers this stop order.
+    /// Buy stops trigger when the price rises to the stop price, sell stops when
+    /// it falls to it. Orders without a stop price are never triggered.
+    pub fn is_triggered_by(&self, last_price: Price) -> bool {
+        match (self.stop_price, self.side) {
+            (Some(stop_price), OrderSide::Buy) => last_price >= stop_price,
+            (Some(stop_price), OrderSide::Sell) => last_price <= stop_price,
+            (None, _) => false,
+        }
+    }
+
+    /// Sort key for time priority within a price level.
+    /// Earlier chain position wins; the order ID breaks ties within a transaction.
+    pub fn priority(&self) -> (BlockContext, u64) {
+        (self.placed_at, self.id.0)
+    }
+
     /// Check if the order is still active (can be matched).
     pub fn is_active(&self) -> bool {
-        matches!(self.status, OrderStatus::Open | OrderStatus::PartiallyFilled)
+        matches!(
+            self.status,
+            OrderStatus::Open | OrderStatus::PartiallyFilled
+        )
     }
 
     /// Fill some amount of the order.
     pub fn fill(&mut self, amount: Amount) {
         self.remaining_amount = self.remaining_amount.saturating_sub(amount);
+        self.visible_amount = self.visible_amount.saturating_sub(amount);
         if self.remaining_amount.is_zero() {
             self.status = OrderStatus::Filled;
         } else {
@@ -129,11 +341,52 @@ impl Order {
         self.status = OrderStatus::Cancelled;
     }
 
+    /// Shrink the order by some amount without filling it.
+    /// An order reduced to nothing is cancelled.
+    pub fn reduce(&mut self, amount: Amount) {
+        self.original_amount = self.original_amount.saturating_sub(amount);
+        self.remaining_amount = self.remaining_amount.saturating_sub(amount);
+        self.visible_amount = self.visible_amount.min(self.remaining_amount);
+        if self.remaining_amount.is_zero() {
+            self.cancel();
+        }
+    }
+
+    /// Set the unfilled amount of the order, keeping the amount already filled.
+    pub fn resize(&mut self, remaining_amount: Amount) {
+        self.original_amount = self.filled_amount().saturating_add(remaining_amount);
+        self.remaining_amount = remaining_amount;
+        self.visible_amount = match self.display_amount {
+            Some(_) => self.visible_amount.min(remaining_amount),
+            None => remaining_amount,
+        };
+    }
+
     /// Get the filled amount.
     pub fn filled_amount(&self) -> Amount {
         self.original_amount.saturating_sub(self.remaining_amount)
     }
 
+    /// Get the collateral still held in escrow for the unfilled part of the order.
+    /// Sell orders escrow base token, buy orders escrow quote token at the limit price.
+    pub fn remaining_escrow(&self) -> Amount {
+        self.escrow_for(self.remaining_amount)
+    }
+
+    /// Get the collateral escrowed for the whole order when it was placed.
+    pub fn original_escrow(&self) -> Amount {
+        self.escrow_for(self.original_amount)
+    }
+
+    /// Collateral backing `amount` of base: the base itself for sell orders, its
+    /// quote value at the limit price for buy orders.
+    fn escrow_for(&self, amount: Amount) -> Amount {
+        match self.side {
+            OrderSide::Sell => amount,
+            OrderSide::Buy => self.price.quote_amount(amount).unwrap_or(U256::ZERO),
+        }
+    }
+
     /// Check if this order can match with another order.
     /// For a buy order to match a sell order: buy_price >= sell_price
     /// For a sell order to match a buy order: sell_price <= buy_price
@@ -149,13 +402,6 @@ impl Order {
     }
 }
 
-fn current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis() as u64)
-        .unwrap_or(0)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,6 +418,7 @@ mod tests {
             OrderSide::Buy,
             Price::from_u128(100, 1),
             U256::from(1000),
+            BlockContext::default(),
         );
 
         assert_eq!(order.status, OrderStatus::Open);
@@ -187,6 +434,7 @@ mod tests {
             OrderSide::Buy,
             Price::from_u128(100, 1),
             U256::from(1000),
+            BlockContext::default(),
         );
 
         order.fill(U256::from(400));
@@ -200,6 +448,85 @@ mod tests {
         assert!(!order.is_active());
     }
 
+    #[test]
+    fn test_remaining_escrow() {
+        let mut buy_order = Order::new_limit(
+            OrderId(1),
+            test_address(),
+            OrderSide::Buy,
+            Price::from_u128(100, 1),
+            U256::from(1000),
+            BlockContext::default(),
+        );
+        buy_order.fill(U256::from(400));
+        // 600 base left at 100 quote each
+        assert_eq!(buy_order.remaining_escrow(), U256::from(60_000));
+
+        let mut sell_order = Order::new_limit(
+            OrderId(2),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(100, 1),
+            U256::from(1000),
+            BlockContext::default(),
+        );
+        sell_order.fill(U256::from(250));
+        assert_eq!(sell_order.remaining_escrow(), U256::from(750));
+    }
+
+    #[test]
+    fn test_order_priority_follows_chain_position() {
+        let earlier = Order::new_limit(
+            OrderId(7),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(100, 1),
+            U256::from(1000),
+            BlockContext::new(5, 3, 1_000),
+        );
+        let later = Order::new_limit(
+            OrderId(2),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(100, 1),
+            U256::from(1000),
+            BlockContext::new(6, 0, 1_002),
+        );
+
+        // Chain position decides priority, not the order ID
+        assert!(earlier.priority() < later.priority());
+        assert_eq!(earlier.placed_at.timestamp, 1_000);
+    }
+
+    #[test]
+    fn test_stop_trigger_direction() {
+        let buy_stop = Order::new_market(
+            OrderId(1),
+            test_address(),
+            OrderSide::Buy,
+            U256::from(1000),
+            BlockContext::default(),
+        )
+        .with_stop_price(Price::from_u128(110, 1));
+        let sell_stop = Order::new_limit(
+            OrderId(2),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(85, 1),
+            U256::from(1000),
+            BlockContext::default(),
+        )
+        .with_stop_price(Price::from_u128(90, 1));
+
+        // Buy stops trigger on the way up, sell stops on the way down
+        assert!(!buy_stop.is_triggered_by(Price::from_u128(109, 1)));
+        assert!(buy_stop.is_triggered_by(Price::from_u128(110, 1)));
+        assert!(buy_stop.is_triggered_by(Price::from_u128(120, 1)));
+        assert!(!sell_stop.is_triggered_by(Price::from_u128(91, 1)));
+        assert!(sell_stop.is_triggered_by(Price::from_u128(90, 1)));
+        assert!(sell_stop.is_triggered_by(Price::from_u128(80, 1)));
+    }
+
     #[test]
     fn test_order_matching() {
         let buy_order = Order::new_limit(
@@ -208,6 +535,7 @@ mod tests {
             OrderSide::Buy,
             Price::from_u128(100, 1),
             U256::from(1000),
+            BlockContext::default(),
         );
 
         let sell_order_good = Order::new_limit(
@@ -216,6 +544,7 @@ mod tests {
             OrderSide::Sell,
             Price::from_u128(95, 1),
             U256::from(1000),
+            BlockContext::default(),
         );
 
         let sell_order_bad = Order::new_limit(
@@ -224,9 +553,77 @@ mod tests {
             OrderSide::Sell,
             Price::from_u128(105, 1),
             U256::from(1000),
+            BlockContext::default(),
         );
 
         assert!(buy_order.can_match(&sell_order_good)); // 100 >= 95
         assert!(!buy_order.can_match(&sell_order_bad)); // 100 < 105
     }
+
+    #[test]
+    fn test_iceberg_clip() {
+        let mut order = Order::new_limit(
+            OrderId(1),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(100, 1),
+            U256::from(250),
+            BlockContext::default(),
+        )
+        .with_display_amount(U256::from(100));
+        assert_eq!(order.visible_amount, U256::from(100));
+
+        order.fill(U256::from(100));
+        assert_eq!(order.visible_amount, U256::ZERO);
+        assert!(order.refresh_clip());
+        assert_eq!(order.visible_amount, U256::from(100));
+
+        // The last clip is whatever is left of the reserve
+        order.fill(U256::from(100));
+        assert!(order.refresh_clip());
+        assert_eq!(order.visible_amount, U256::from(50));
+
+        order.fill(U256::from(50));
+        assert!(!order.refresh_clip());
+        assert_eq!(order.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn test_range_order_flip() {
+        let mut order = Order::new_limit(
+            OrderId(1),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(101, 1),
+            U256::from(100),
+            BlockContext::default(),
+        )
+        .with_flip_price(Price::from_u128(99, 1));
+        order.fill(U256::from(100));
+        order.range_proceeds = U256::from(10_100);
+
+        let placed_at = BlockContext::new(2, 0, 0);
+        assert!(order.flip(U256::from(102), placed_at));
+        assert_eq!(order.side, OrderSide::Buy);
+        assert_eq!(order.price, Price::from_u128(99, 1));
+        assert_eq!(order.flip_price, Some(Price::from_u128(101, 1)));
+        assert_eq!(order.remaining_amount, U256::from(102));
+        assert_eq!(order.filled_amount(), U256::ZERO);
+        assert_eq!(order.range_proceeds, U256::ZERO);
+        assert_eq!(order.status, OrderStatus::Open);
+        assert_eq!(order.placed_at, placed_at);
+
+        // Plain orders don't flip
+        let mut plain = Order::new_limit(
+            OrderId(2),
+            test_address(),
+            OrderSide::Sell,
+            Price::from_u128(101, 1),
+            U256::from(100),
+            BlockContext::default(),
+        );
+        plain.fill(U256::from(100));
+        assert!(!plain.flip(U256::from(100), placed_at));
+        assert_eq!(plain.side, OrderSide::Sell);
+    }
 }
@@ -1,9 +1,13 @@
 //! Orderbook implementation with efficient order matching.
 
-use crate::config::DexConfig;
-use crate::order::{Order, OrderId, OrderSide};
+use crate::amm::{LiquidityChange, LiquidityError, LiquidityPool, Reserves};
+use crate::config::{DexConfig, MarketConfig};
+use crate::history::TradeHistory;
+use crate::order::{
+    Order, OrderId, OrderSide, OrderStatus, OrderType, SelfTradePrevention, TimeInForce,
+};
 use crate::pair::{Pair, PairStats};
-use crate::types::{Address, Amount, Price, U256};
+use crate::types::{Address, Amount, BlockContext, Price, I256, U256};
 use std::collections::{BTreeMap, HashMap};
 
 /// Result of executing a trade.
@@ -17,9 +21,130 @@ pub struct TradeResult {
     pub remaining_amount: Amount,
     /// Whether the order was fully filled.
     pub fully_filled: bool,
+    /// Matches against the taker's own resting orders stopped by self-trade prevention.
+    pub prevented: Vec<PreventedTrade>,
+    /// Stop orders activated by this trade, in activation order.
+    pub triggered: Vec<TriggeredOrder>,
+    /// Orders cancelled because the order linked to them one-cancels-other was
+    /// filled or cancelled.
+    pub linked_cancels: Vec<LinkedCancel>,
+    /// Brackets whose exit orders were placed, in activation order.
+    pub brackets: Vec<ActivatedBracket>,
+    /// Range orders completely filled by this trade, in the order they filled.
+    pub flipped: Vec<FlippedOrder>,
+}
+
+/// A match between two orders of the same trader stopped by self-trade prevention.
+///
+/// Cancelled orders keep their remaining amount, so their unfilled escrow can be
+/// refunded. Reductions shrink an order that stays active.
+#[derive(Debug, Clone)]
+pub struct PreventedTrade {
+    /// The mode that was applied.
+    pub mode: SelfTradePrevention,
+    /// The resting order afterwards. Its status is `Cancelled` if it was cancelled.
+    pub maker: Order,
+    /// Base amount the resting order was reduced by.
+    pub maker_reduced: Amount,
+    /// Base amount the incoming order was reduced by.
+    pub taker_reduced: Amount,
+}
+
+/// A stop order activated by a trade and matched right away.
+#[derive(Debug, Clone)]
+pub struct TriggeredOrder {
+    /// The activated order after matching. Its status is `Cancelled` if an
+    /// unfilled remainder was dropped instead of resting on the book.
+    pub order: Order,
+    /// Last trade price that activated the order.
+    pub trigger_price: Price,
+    /// Fills of the activated order, which trades as the taker.
+    pub fills: Vec<Fill>,
+    /// Matches of the activated order stopped by self-trade prevention.
+    pub prevented: Vec<PreventedTrade>,
+}
+
+/// Result of amending a resting order.
+#[derive(Debug, Clone)]
+pub struct AmendResult {
+    /// The order before the amendment.
+    pub previous: Order,
+    /// The order after the amendment.
+    pub order: Order,
+    /// Whether the order kept its time priority.
+    pub kept_priority: bool,
+}
+
+/// An order cancelled because the order linked to it one-cancels-other was
+/// filled or cancelled.
+#[derive(Debug, Clone)]
+pub struct LinkedCancel {
+    /// The cancelled order.
+    pub order: Order,
+    /// The linked order, as it was when its fill or cancellation cancelled this one.
+    pub cancelled_by: Order,
+    /// Whether the cancelled order was a stop order still waiting for its trigger.
+    pub pending_stop: bool,
+}
+
+/// Exit orders of a bracket, placed once its entry order has finished trading.
+///
+/// Both exits are on the opposite side of the entry and sized to its filled
+/// amount: a take-profit limit order and a stop-loss stop order, linked
+/// one-cancels-other. Their IDs are assigned up front by the caller.
+#[derive(Debug, Clone)]
+pub struct BracketExits {
+    /// ID of the take-profit order.
+    pub take_profit_id: OrderId,
+    /// Limit price of the take-profit order.
+    pub take_profit: Price,
+    /// ID of the stop-loss order.
+    pub stop_loss_id: OrderId,
+    /// Stop price of the stop-loss order.
+    pub stop_loss: Price,
+    /// Limit price of the triggered stop-loss, or `None` for a stop market order.
+    pub stop_loss_limit: Option<Price>,
+}
+
+/// Exit orders placed for a bracket whose entry order finished trading.
+#[derive(Debug, Clone)]
+pub struct ActivatedBracket {
+    /// ID of the entry order.
+    pub entry_id: OrderId,
+    /// The take-profit order after matching. It trades as a taker right away
+    /// if its price crosses the book.
+    pub take_profit: Order,
+    /// Fills of the take-profit order.
+    pub take_profit_fills: Vec<Fill>,
+    /// Matches of the take-profit order stopped by self-trade prevention.
+    pub take_profit_prevented: Vec<PreventedTrade>,
+    /// The stop-loss order as it was put in the trigger index.
+    pub stop_loss: Order,
+}
+
+/// A range order that was completely filled and flipped to the opposite side.
+#[derive(Debug, Clone)]
+pub struct FlippedOrder {
+    /// The order as it was filled, with the proceeds of its fills.
+    pub filled: Order,
+    /// The order re-posted at its flip price under the same ID, or `None` if
+    /// the proceeds were too little to re-post or it would have crossed the
+    /// book, which ends the range order.
+    pub reposted: Option<Order>,
+    /// Proceeds the re-posted order doesn't need, in the token the filled
+    /// order received. They are owed back to the trader.
+    pub refund: Amount,
 }
 
 /// A single fill (partial or complete match between two orders).
+///
+/// Fees are denominated in the quote token. The taker fee is always charged,
+/// while the maker fee is signed: a negative maker fee is a rebate funded out
+/// of the taker fee of the same fill.
+///
+/// A fill against the pair's liquidity pool has no maker order: its maker
+/// order ID and address are zero, it pays no maker fee, and its price is the
+/// average the pool charged, its own fee included.
 #[derive(Debug, Clone)]
 pub struct Fill {
     /// The maker order ID.
@@ -34,8 +159,122 @@ pub struct Fill {
     pub price: Price,
     /// Fee paid by the taker.
     pub taker_fee: Amount,
-    /// Fee paid by the maker.
-    pub maker_fee: Amount,
+    /// Fee paid by the maker, negative for a rebate paid to the maker.
+    pub maker_fee: I256,
+    /// Whether the taker traded against the liquidity pool instead of an order.
+    pub from_pool: bool,
+}
+
+impl TradeResult {
+    /// Result of an order put aside without being matched.
+    fn unmatched(order: &Order) -> Self {
+        Self {
+            taker_order_id: order.id,
+            fills: Vec::new(),
+            remaining_amount: order.remaining_amount,
+            fully_filled: false,
+            prevented: Vec::new(),
+            triggered: Vec::new(),
+            linked_cancels: Vec::new(),
+            brackets: Vec::new(),
+            flipped: Vec::new(),
+        }
+    }
+}
+
+impl Fill {
+    /// Net fee kept by the protocol: the taker fee less any maker rebate.
+    pub fn protocol_fee(&self) -> Amount {
+        let taker_fee = I256::from_raw(self.taker_fee);
+        taker_fee
+            .saturating_add(self.maker_fee)
+            .max(I256::ZERO)
+            .into_raw()
+    }
+
+    /// What the maker receives for the fill, net of its fee: quote if it
+    /// sold, base if it bought. A rebate to a maker that bought is paid in
+    /// quote and is not included.
+    pub fn maker_proceeds(&self, maker_side: OrderSide) -> Amount {
+        let fee = self.maker_fee.unsigned_abs();
+        match maker_side {
+            OrderSide::Sell if self.maker_fee.is_negative() => {
+                self.quote_amount.saturating_add(fee)
+            }
+            OrderSide::Sell => self.quote_amount.saturating_sub(fee),
+            OrderSide::Buy => self.base_amount.saturating_sub(self.maker_fee_in_base()),
+        }
+    }
+
+    /// A positive maker fee converted to base at the fill price.
+    /// Used when the maker is the buyer and is paid in base; zero for rebates.
+    pub fn maker_fee_in_base(&self) -> Amount {
+        if !self.maker_fee.is_positive() {
+            return U256::ZERO;
+        }
+        self.base_amount
+            .saturating_mul(self.maker_fee.into_raw())
+            .checked_div(self.quote_amount)
+            .unwrap_or(U256::ZERO)
+    }
+}
+
+/// What a market order would do against the book, without placing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketSimulation {
+    /// Amount spent: the base sold, or the quote paid for a buy with its fees.
+    pub amount_in: Amount,
+    /// Amount received: the quote a sell gets after fees, or the base bought.
+    pub amount_out: Amount,
+    /// Taker fee charged on the fills, in quote.
+    pub fee: Amount,
+    /// Average fill price before fees.
+    pub avg_price: Price,
+    /// Price of the least favorable fill.
+    pub worst_price: Price,
+    /// Number of price levels traded against.
+    pub levels_consumed: usize,
+    /// Number of resting orders traded against.
+    pub orders_consumed: usize,
+    /// Whether the book ran out before the order was filled.
+    pub liquidity_exhausted: bool,
+}
+
+/// A fill made when a batch auction clears.
+///
+/// Both orders of an auction fill rest on the book, so the buy order is taken
+/// as the maker and the sell order as the taker: each side pays its fee out of
+/// what it receives, as in continuous matching.
+#[derive(Debug, Clone)]
+pub struct BatchFill {
+    /// The sell order ID.
+    pub sell_order_id: OrderId,
+    /// The seller's address.
+    pub seller: Address,
+    /// Limit price of the buy order, which its escrow was locked at.
+    pub buy_price: Price,
+    /// The fill, at the clearing price, with the buy order as the maker.
+    pub fill: Fill,
+}
+
+/// Result of clearing a batch auction.
+#[derive(Debug, Clone, Default)]
+pub struct BatchClearing {
+    /// The uniform price every fill traded at, `None` if the book wasn't crossed.
+    pub price: Option<Price>,
+    /// Base amount matched.
+    pub volume: Amount,
+    /// Fills, pairing buy and sell orders in price-time priority.
+    pub fills: Vec<BatchFill>,
+    /// Immediate-or-cancel orders cancelled with their remainder unfilled.
+    pub cancelled: Vec<Order>,
+}
+
+impl BatchClearing {
+    /// Whether clearing neither filled nor cancelled anything.
+    pub fn is_empty(&self) -> bool {
+        self.fills.is_empty() && self.cancelled.is_empty()
+    }
 }
 
 /// An orderbook for a single trading pair.
@@ -54,10 +293,37 @@ pub struct OrderBook {
     asks: BTreeMap<PriceKey, Vec<Order>>,
     /// All orders by ID for quick lookup.
     orders: HashMap<OrderId, OrderLocation>,
-    /// Next order ID.
-    next_order_id: u64,
     /// Total traded volume.
     total_volume: Amount,
+    /// Tick size, lot size and fee parameters for this market.
+    market: MarketConfig,
+    /// Pending buy stop orders indexed by stop price, each level in time priority.
+    /// They trigger when the last trade price rises to or above the stop price.
+    buy_stops: BTreeMap<Price, Vec<Order>>,
+    /// Pending sell stop orders indexed by stop price, each level in time priority.
+    /// They trigger when the last trade price falls to or below the stop price.
+    sell_stops: BTreeMap<Price, Vec<Order>>,
+    /// Side and stop price of each pending stop order.
+    stop_index: HashMap<OrderId, (OrderSide, Price)>,
+    /// Price of the most recent fill.
+    last_trade_price: Option<Price>,
+    /// Recent trades and the candles built from them.
+    history: TradeHistory,
+    /// One-cancels-other links, stored in both directions.
+    oco_links: HashMap<OrderId, OrderId>,
+    /// Brackets by entry order ID, until their exit orders are placed.
+    brackets: BTreeMap<OrderId, Bracket>,
+    /// Liquidity pool trading alongside the resting orders.
+    pool: LiquidityPool,
+}
+
+/// A bracket waiting for its entry order to finish trading.
+#[derive(Debug, Clone)]
+struct Bracket {
+    /// Exit orders to place.
+    exits: BracketExits,
+    /// The entry order once it has finished trading with something filled.
+    entry: Option<Order>,
 }
 
 /// Location of an order in the book.
@@ -99,91 +365,659 @@ impl Ord for PriceKey {
 impl OrderBook {
     /// Create a new orderbook for a pair.
     pub fn new(pair: Pair) -> Self {
+        Self::with_market_config(pair, MarketConfig::default())
+    }
+
+    /// Create a new orderbook for a pair with custom market parameters.
+    pub fn with_market_config(pair: Pair, market: MarketConfig) -> Self {
         Self {
             pair,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             orders: HashMap::new(),
-            next_order_id: 1,
             total_volume: U256::ZERO,
+            market,
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            stop_index: HashMap::new(),
+            last_trade_price: None,
+            history: TradeHistory::new(),
+            oco_links: HashMap::new(),
+            brackets: BTreeMap::new(),
+            pool: LiquidityPool::new(),
+        }
+    }
+
+    /// Get the market parameters.
+    pub fn market_config(&self) -> &MarketConfig {
+        &self.market
+    }
+
+    /// Update the market parameters.
+    /// Orders already resting on the book are not revalidated.
+    pub fn set_market_config(&mut self, market: MarketConfig) {
+        self.market = market;
+    }
+
+    /// Get the price of the most recent fill.
+    pub fn last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
+    }
+
+    /// Get the recent trades and candles of the pair.
+    pub fn history(&self) -> &TradeHistory {
+        &self.history
+    }
+
+    /// Get the liquidity pool trading alongside the book.
+    pub fn pool(&self) -> &LiquidityPool {
+        &self.pool
+    }
+
+    /// Deposit liquidity into the pool. Batch auction markets clear without
+    /// the pool, so they take none.
+    pub fn add_liquidity(
+        &mut self,
+        provider: Address,
+        base_amount: Amount,
+        quote_amount: Amount,
+    ) -> Result<LiquidityChange, LiquidityError> {
+        if self.market.is_batch_auction() {
+            return Err(LiquidityError::BatchAuctionMarket);
         }
+        self.pool.add_liquidity(provider, base_amount, quote_amount)
     }
 
-    /// Generate a new order ID.
-    fn generate_order_id(&mut self) -> OrderId {
-        let id = OrderId(self.next_order_id);
-        self.next_order_id += 1;
-        id
+    /// Withdraw liquidity from the pool by burning a provider's shares.
+    pub fn remove_liquidity(
+        &mut self,
+        provider: Address,
+        shares: Amount,
+    ) -> Result<LiquidityChange, LiquidityError> {
+        self.pool.remove_liquidity(provider, shares)
     }
 
-    /// Place a limit order.
-    /// Returns the order ID and any immediate fills if the order crosses the spread.
-    pub fn place_limit_order(
+    /// Place an order on the book.
+    /// The order is built by the caller, which assigns its globally unique ID and
+    /// stamps it with the chain position it was placed at.
+    ///
+    /// Stop orders are parked in the trigger index instead of being matched. They
+    /// are activated once a fill moves the last trade price through their stop
+    /// price, and must not be triggered by the current last trade price already.
+    ///
+    /// The order is matched immediately against the opposite side and the
+    /// liquidity pool, whichever offers the better price. What happens
+    /// to an unfilled remainder depends on the order's time in force: it rests on
+    /// the book for good-till-cancel and post-only limit orders and is cancelled
+    /// otherwise. Market orders never rest.
+    ///
+    /// Post-only orders that would cross and fill-or-kill orders that cannot be
+    /// filled completely are rejected before anything is matched.
+    ///
+    /// Limit orders must respect the market's tick size, lot size and minimum
+    /// notional. Market orders only have to meet the global minimum size, since
+    /// swap amounts are chosen by the router rather than the trader.
+    ///
+    /// Range orders must be post-only, so they only ever trade as makers. Each
+    /// time one is completely filled, matching re-posts it on the opposite side.
+    ///
+    /// In a batch auction market nothing is matched on placement: see
+    /// `clear_batch`.
+    pub fn place_order(
         &mut self,
-        trader: Address,
-        side: OrderSide,
-        price: Price,
-        amount: Amount,
+        mut order: Order,
         config: &DexConfig,
-    ) -> Result<(OrderId, TradeResult), OrderError> {
-        if amount < U256::from(config.min_order_size) {
+    ) -> Result<TradeResult, OrderError> {
+        if order.remaining_amount < U256::from(config.min_order_size) {
             return Err(OrderError::BelowMinimumSize);
         }
 
-        let order_id = self.generate_order_id();
-        let mut order = Order::new_limit(order_id, trader, side, price, amount);
+        // Equal ratios must land on the same price level
+        order.price = order.price.normalized();
+        order.stop_price = order.stop_price.map(|price| price.normalized());
+        order.flip_price = order.flip_price.map(|price| price.normalized());
+
+        if order.order_type == OrderType::Limit {
+            self.validate_limit_order(&order)?;
+        }
+        if order.flip_price.is_some() {
+            self.validate_range_order(&order)?;
+        }
+
+        if self.market.is_batch_auction() {
+            return self.add_batch_order(order);
+        }
+
+        if order.stop_price.is_some() {
+            let trade_result = TradeResult::unmatched(&order);
+            self.add_stop_order(order)?;
+            return Ok(trade_result);
+        }
+
+        self.check_time_in_force(&order, config)?;
 
         // Try to match immediately against existing orders
-        let trade_result = self.match_order(&mut order, config);
+        let mut trade_result = self.match_order(&mut order, config);
+        self.rest_remainder(&mut order);
 
-        // If there's remaining amount, add to the book
-        if !order.remaining_amount.is_zero() && order.is_active() {
-            self.add_order_to_book(order);
+        if !order.is_active() {
+            finish_bracket_entry(&mut self.brackets, &order);
         }
+        self.activate_brackets(order.placed_at, config, &mut trade_result);
 
-        Ok((order_id, trade_result))
+        Ok(trade_result)
     }
 
-    /// Place a market order.
-    /// Market orders execute immediately at the best available price.
-    pub fn place_market_order(
+    /// Place two orders of the same trader and side as a one-cancels-other pair:
+    /// a limit order resting on the book and a stop order waiting for its trigger.
+    /// As soon as either one is filled, even partially, or cancelled, the other
+    /// one is cancelled.
+    ///
+    /// The limit order is placed post-only, so neither leg trades before both are
+    /// in place. If the stop order is rejected, the limit order is removed again.
+    /// Batch auction markets take neither leg.
+    pub fn place_oco_order(
         &mut self,
-        trader: Address,
-        side: OrderSide,
-        amount: Amount,
+        limit_order: Order,
+        stop_order: Order,
+        config: &DexConfig,
+    ) -> Result<(), OrderError> {
+        if limit_order.trader != stop_order.trader
+            || limit_order.side != stop_order.side
+            || limit_order.order_type != OrderType::Limit
+            || limit_order.stop_price.is_some()
+            || stop_order.stop_price.is_none()
+        {
+            return Err(OrderError::InvalidOrderGroup);
+        }
+
+        let (limit_id, stop_id) = (limit_order.id, stop_order.id);
+        self.place_order(
+            limit_order.with_time_in_force(TimeInForce::PostOnly),
+            config,
+        )?;
+        if let Err(err) = self.place_order(stop_order, config) {
+            self.remove_order(limit_id)?;
+            return Err(err);
+        }
+        self.link_orders(limit_id, stop_id);
+
+        Ok(())
+    }
+
+    /// Place a bracket: an entry order that places two exit orders once it has
+    /// finished trading, a take-profit limit order and a stop-loss stop order on
+    /// the opposite side, linked one-cancels-other and sized to the entry's fills.
+    ///
+    /// The entry has finished trading once it is completely filled, or once
+    /// matching cancels its remainder, as for an immediate-or-cancel entry. An
+    /// entry that finishes without any fill drops the bracket, and so does
+    /// cancelling the entry, even after a partial fill.
+    ///
+    /// The take-profit must be better than the entry price and the stop-loss
+    /// worse. Exits only have to respect the tick size: their amount follows
+    /// from the entry's fills. A take-profit crossing the book when it is placed
+    /// trades right away, and a stop-loss already reached is triggered.
+    /// Batch auction markets don't take brackets.
+    pub fn place_bracket_order(
+        &mut self,
+        entry: Order,
+        exits: BracketExits,
         config: &DexConfig,
     ) -> Result<TradeResult, OrderError> {
-        if amount < U256::from(config.min_order_size) {
-            return Err(OrderError::BelowMinimumSize);
+        let exits = BracketExits {
+            take_profit: exits.take_profit.normalized(),
+            stop_loss: exits.stop_loss.normalized(),
+            stop_loss_limit: exits.stop_loss_limit.map(|price| price.normalized()),
+            ..exits
+        };
+        if self.market.is_batch_auction() {
+            return Err(OrderError::NotInBatchAuction);
+        }
+        if entry.order_type != OrderType::Limit || entry.stop_price.is_some() {
+            return Err(OrderError::InvalidOrderGroup);
+        }
+        let entry_price = entry.price.normalized();
+        let ordered = match entry.side {
+            OrderSide::Buy => exits.take_profit > entry_price && exits.stop_loss < entry_price,
+            OrderSide::Sell => exits.take_profit < entry_price && exits.stop_loss > entry_price,
+        };
+        if !ordered {
+            return Err(OrderError::InvalidOrderGroup);
+        }
+        let exit_prices = [Some(exits.take_profit), exits.stop_loss_limit];
+        if !exit_prices
+            .into_iter()
+            .flatten()
+            .all(|price| self.market.is_on_tick(price))
+        {
+            return Err(OrderError::PriceNotOnTick);
+        }
+
+        let entry_id = entry.id;
+        self.brackets
+            .insert(entry_id, Bracket { exits, entry: None });
+        let trade_result = self.place_order(entry, config);
+        if trade_result.is_err() {
+            self.brackets.remove(&entry_id);
+        }
+        trade_result
+    }
+
+    /// Place the exit orders of brackets whose entry has finished trading.
+    ///
+    /// The take-profit trades as a taker if it crosses the book, and the
+    /// stop-loss is activated right away if the last trade price already reached
+    /// it. Their fills can finish further entries, so activation repeats until no
+    /// bracket is left waiting to be placed.
+    fn activate_brackets(
+        &mut self,
+        placed_at: BlockContext,
+        config: &DexConfig,
+        trade_result: &mut TradeResult,
+    ) {
+        while let Some(entry_id) = self
+            .brackets
+            .iter()
+            .find(|(_, bracket)| bracket.entry.is_some())
+            .map(|(entry_id, _)| *entry_id)
+        {
+            let Some(Bracket {
+                exits,
+                entry: Some(entry),
+            }) = self.brackets.remove(&entry_id)
+            else {
+                break;
+            };
+            let side = entry.side.opposite();
+            let amount = entry.filled_amount();
+
+            let stop_loss = match exits.stop_loss_limit {
+                Some(limit) => Order::new_limit(
+                    exits.stop_loss_id,
+                    entry.trader,
+                    side,
+                    limit,
+                    amount,
+                    placed_at,
+                ),
+                None => {
+                    Order::new_market(exits.stop_loss_id, entry.trader, side, amount, placed_at)
+                }
+            }
+            .with_stop_price(exits.stop_loss);
+            self.insert_stop_order(stop_loss.clone(), exits.stop_loss);
+            self.link_orders(exits.take_profit_id, exits.stop_loss_id);
+
+            let mut take_profit = Order::new_limit(
+                exits.take_profit_id,
+                entry.trader,
+                side,
+                exits.take_profit,
+                amount,
+                placed_at,
+            );
+            let result = self.match_against_book(&mut take_profit, config);
+            self.rest_remainder(&mut take_profit);
+
+            trade_result.linked_cancels.extend(result.linked_cancels);
+            trade_result.flipped.extend(result.flipped);
+            trade_result.brackets.push(ActivatedBracket {
+                entry_id,
+                take_profit,
+                take_profit_fills: result.fills,
+                take_profit_prevented: result.prevented,
+                stop_loss,
+            });
+
+            self.activate_stops(placed_at, config, trade_result);
         }
+    }
+
+    /// Link two orders one-cancels-other.
+    fn link_orders(&mut self, order_id: OrderId, other_id: OrderId) {
+        self.oco_links.insert(order_id, other_id);
+        self.oco_links.insert(other_id, order_id);
+    }
 
-        let order_id = self.generate_order_id();
-        let mut order = Order::new_market(order_id, trader, side, amount);
+    /// Cancel the order linked one-cancels-other to `order`, which was just
+    /// filled or cancelled.
+    fn cancel_linked(&mut self, order: &Order) -> Option<LinkedCancel> {
+        let linked_id = take_link(&mut self.oco_links, order.id)?;
+        let pending_stop = self.is_pending_stop(linked_id);
+        let linked = self.remove_order(linked_id).ok()?;
+        Some(LinkedCancel {
+            order: linked,
+            cancelled_by: order.clone(),
+            pending_stop,
+        })
+    }
 
-        // Execute immediately
-        let trade_result = self.match_order(&mut order, config);
+    /// Reject post-only orders that would cross and fill-or-kill orders that
+    /// cannot be filled completely.
+    fn check_time_in_force(&self, order: &Order, config: &DexConfig) -> Result<(), OrderError> {
+        match order.time_in_force {
+            TimeInForce::PostOnly if self.would_cross(order) => Err(OrderError::PostOnlyWouldCross),
+            TimeInForce::FillOrKill
+                if self.fillable_amount(order, config) < order.remaining_amount =>
+            {
+                Err(OrderError::FillOrKillNotFilled)
+            }
+            _ => Ok(()),
+        }
+    }
 
-        // Market orders don't rest on the book - any unfilled portion is cancelled
+    /// Put an order on the book of a batch auction market without matching it.
+    ///
+    /// Only plain good-till-cancel and immediate-or-cancel limit orders take
+    /// part in an auction; icebergs are fine. The book may be left crossed
+    /// until the block's auction clears it.
+    fn add_batch_order(&mut self, order: Order) -> Result<TradeResult, OrderError> {
+        let supported = order.order_type == OrderType::Limit
+            && order.stop_price.is_none()
+            && order.flip_price.is_none()
+            && matches!(
+                order.time_in_force,
+                TimeInForce::GoodTillCancel | TimeInForce::ImmediateOrCancel
+            );
+        if !supported {
+            return Err(OrderError::NotInBatchAuction);
+        }
 
+        let trade_result = TradeResult::unmatched(&order);
+        self.add_order_to_book(order);
         Ok(trade_result)
     }
 
-    /// Match an incoming order against the book.
+    /// Check a range order: a post-only limit order showing its full size,
+    /// whose flip price is on the tick and across the spread from its price,
+    /// below it for a sell and above it for a buy.
+    fn validate_range_order(&self, order: &Order) -> Result<(), OrderError> {
+        let Some(flip_price) = order.flip_price else {
+            return Ok(());
+        };
+        let across = match order.side {
+            OrderSide::Sell => flip_price < order.price && !flip_price.numerator.is_zero(),
+            OrderSide::Buy => flip_price > order.price,
+        };
+        if order.order_type != OrderType::Limit
+            || order.time_in_force != TimeInForce::PostOnly
+            || order.stop_price.is_some()
+            || order.display_amount.is_some()
+            || !across
+        {
+            return Err(OrderError::InvalidRangeOrder);
+        }
+        if !self.market.is_on_tick(flip_price) {
+            return Err(OrderError::PriceNotOnTick);
+        }
+        Ok(())
+    }
+
+    /// Re-post a completely filled range order on the opposite side at its
+    /// flip price, for as much as its proceeds buy in whole lots, and work out
+    /// the proceeds left over.
+    ///
+    /// The re-posted order keeps the order's ID and takes its time priority
+    /// from `placed_at`. It has to meet the market's minimums like any limit
+    /// order and may not cross the book; if it can't, the range order is done
+    /// and all of its proceeds are left over.
+    fn flip_range_order(
+        &mut self,
+        filled: Order,
+        placed_at: BlockContext,
+        config: &DexConfig,
+    ) -> FlippedOrder {
+        let proceeds = filled.range_proceeds;
+        let amount = match (filled.side, filled.flip_price) {
+            (OrderSide::Sell, Some(flip_price)) => flip_price.base_amount(proceeds),
+            _ => Some(proceeds),
+        };
+        let amount = self.market.round_down_to_lot(amount.unwrap_or(U256::ZERO));
+
+        let mut order = filled.clone();
+        let reposted = amount >= U256::from(config.min_order_size.max(1))
+            && order.flip(amount, placed_at)
+            && self.validate_limit_order(&order).is_ok()
+            && !self.would_cross(&order);
+        if !reposted {
+            return FlippedOrder {
+                filled,
+                reposted: None,
+                refund: proceeds,
+            };
+        }
+
+        let refund = proceeds.saturating_sub(order.remaining_escrow());
+        self.add_order_to_book(order.clone());
+        FlippedOrder {
+            filled,
+            reposted: Some(order),
+            refund,
+        }
+    }
+
+    /// Put the unfilled remainder of a matched order on the book if it rests.
+    /// Market and immediate orders don't rest - any unfilled portion is cancelled.
+    /// Returns whether the order now rests on the book.
+    fn rest_remainder(&mut self, order: &mut Order) -> bool {
+        if order.remaining_amount.is_zero() || !order.is_active() {
+            return false;
+        }
+        if order.order_type == OrderType::Limit && order.time_in_force.rests() {
+            // An iceberg rests with a full clip, whatever its own fills consumed
+            order.refresh_clip();
+            self.add_order_to_book(order.clone());
+            return true;
+        }
+        order.cancel();
+        false
+    }
+
+    /// Check a limit order against the market parameters.
+    fn validate_limit_order(&self, order: &Order) -> Result<(), OrderError> {
+        if !self.market.is_on_tick(order.price) {
+            return Err(OrderError::PriceNotOnTick);
+        }
+        if !self.market.is_on_lot(order.remaining_amount) {
+            return Err(OrderError::AmountNotOnLot);
+        }
+        let notional = order
+            .price
+            .quote_amount(order.remaining_amount)
+            .ok_or(OrderError::InvalidPrice)?;
+        if notional < self.market.min_notional {
+            return Err(OrderError::BelowMinimumNotional);
+        }
+        if let Some(display_amount) = order.display_amount {
+            if display_amount.is_zero() || !self.market.is_on_lot(display_amount) {
+                return Err(OrderError::InvalidDisplayAmount);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an order would match against the best price on the opposite
+    /// side, the liquidity pool's included.
+    fn would_cross(&self, order: &Order) -> bool {
+        match order.side {
+            OrderSide::Buy => self
+                .best_ask_with_pool()
+                .is_some_and(|ask| order.price >= ask),
+            OrderSide::Sell => self
+                .best_bid_with_pool()
+                .is_some_and(|bid| order.price <= bid),
+        }
+    }
+
+    /// How much of an order could be filled right now, without matching it.
+    /// A dry run of `match_order`, so self-trade prevention and the liquidity
+    /// pool count as they would.
+    fn fillable_amount(&self, order: &Order, config: &DexConfig) -> Amount {
+        let plan = self.plan_match(&mut order.clone(), config);
+        plan.fills
+            .iter()
+            .fold(U256::ZERO, |acc, fill| acc.saturating_add(fill.base_amount))
+    }
+
+    /// Self-trade prevention mode for an order: its own, or the market's.
+    fn self_trade_prevention(&self, order: &Order, config: &DexConfig) -> SelfTradePrevention {
+        order
+            .self_trade_prevention
+            .unwrap_or_else(|| self.market.self_trade_prevention(config))
+    }
+
+    /// Match an incoming order against the book, then activate the stop orders
+    /// its fills triggered. Range orders the matching fills completely are
+    /// re-posted on the opposite side as part of each match.
     fn match_order(&mut self, taker_order: &mut Order, config: &DexConfig) -> TradeResult {
-        let mut fills = Vec::new();
+        let mut trade_result = self.match_against_book(taker_order, config);
+        if !trade_result.fills.is_empty() {
+            self.activate_stops(taker_order.placed_at, config, &mut trade_result);
+        }
+        trade_result
+    }
+
+    /// Activate the stop orders triggered by the last trade price and add them
+    /// to `trade_result`.
+    ///
+    /// Each activated order trades as a taker straight away and takes its time
+    /// priority from `placed_at`, the chain position of the trade that triggered
+    /// it. Its own fills move the last trade price and can trigger further stops,
+    /// so activation repeats until no pending stop is triggered.
+    fn activate_stops(
+        &mut self,
+        placed_at: BlockContext,
+        config: &DexConfig,
+        trade_result: &mut TradeResult,
+    ) {
+        while let Some(trigger_price) = self.last_trade_price {
+            let Some(mut order) = self.take_triggered_stop(trigger_price) else {
+                break;
+            };
+            order.placed_at = placed_at;
+
+            let (fills, prevented) = match self.check_time_in_force(&order, config) {
+                Ok(()) => {
+                    let result = self.match_against_book(&mut order, config);
+                    trade_result.linked_cancels.extend(result.linked_cancels);
+                    trade_result.flipped.extend(result.flipped);
+                    (result.fills, result.prevented)
+                }
+                Err(_) => (Vec::new(), Vec::new()),
+            };
+            self.rest_remainder(&mut order);
+
+            // A stop dropped without resting cancels the order linked to it
+            if order.status == OrderStatus::Cancelled {
+                trade_result
+                    .linked_cancels
+                    .extend(self.cancel_linked(&order));
+            }
+
+            trade_result.triggered.push(TriggeredOrder {
+                order,
+                trigger_price,
+                fills,
+                prevented,
+            });
+        }
+    }
+
+    /// Remove the next stop order triggered by `last_price` from the trigger index.
+    /// Triggered buy and sell stops are taken in time priority.
+    fn take_triggered_stop(&mut self, last_price: Price) -> Option<Order> {
+        // The lowest buy stop and the highest sell stop are the first to trigger
+        let buy = self
+            .buy_stops
+            .first_key_value()
+            .filter(|(_, orders)| orders[0].is_triggered_by(last_price))
+            .map(|(stop_price, orders)| (*stop_price, orders[0].priority()));
+        let sell = self
+            .sell_stops
+            .last_key_value()
+            .filter(|(_, orders)| orders[0].is_triggered_by(last_price))
+            .map(|(stop_price, orders)| (*stop_price, orders[0].priority()));
+
+        let (side, stop_price) = match (buy, sell) {
+            (Some(buy), Some(sell)) if sell.1 < buy.1 => (OrderSide::Sell, sell.0),
+            (Some(buy), _) => (OrderSide::Buy, buy.0),
+            (None, Some(sell)) => (OrderSide::Sell, sell.0),
+            (None, None) => return None,
+        };
+
+        let stops = match side {
+            OrderSide::Buy => &mut self.buy_stops,
+            OrderSide::Sell => &mut self.sell_stops,
+        };
+        let order_id = stops.get(&stop_price)?.first()?.id;
+        self.stop_index.remove(&order_id);
+        take_order(stops, &stop_price, order_id)
+    }
+
+    /// Park a stop order in the trigger index.
+    fn add_stop_order(&mut self, order: Order) -> Result<(), OrderError> {
+        let stop_price = order.stop_price.ok_or(OrderError::InvalidPrice)?;
+        if self
+            .last_trade_price
+            .is_some_and(|last_price| order.is_triggered_by(last_price))
+        {
+            return Err(OrderError::StopPriceAlreadyReached);
+        }
+
+        self.insert_stop_order(order, stop_price);
+        Ok(())
+    }
+
+    /// Insert a stop order into the trigger index at `stop_price`, whether or not
+    /// the last trade price triggers it already.
+    fn insert_stop_order(&mut self, order: Order, stop_price: Price) {
+        let stops = match order.side {
+            OrderSide::Buy => &mut self.buy_stops,
+            OrderSide::Sell => &mut self.sell_stops,
+        };
+
+        let orders = stops.entry(stop_price).or_default();
+        let position = orders.partition_point(|o| o.priority() <= order.priority());
+        self.stop_index.insert(order.id, (order.side, stop_price));
+        orders.insert(position, order);
+    }
+
+    /// Match an order against the opposite side of the book.
+    fn match_against_book(&mut self, taker_order: &mut Order, config: &DexConfig) -> TradeResult {
+        let plan = self.plan_match(taker_order, config);
+        self.commit_match(taker_order, plan, config)
+    }
+
+    /// Walk the opposite side of the book for an incoming order and work out
+    /// what matching it does, without changing the book.
+    ///
+    /// This is the matching core shared by execution and simulation: placing
+    /// an order commits the plan, while quotes run the same walk as a dry run
+    /// and only read its fills. The walk borrows the levels it reaches and
+    /// records the orders it changes by their index on the level, copying
+    /// only those; `taker_order` itself is updated as it would be on the book.
+    ///
+    /// The liquidity pool is interleaved with the levels: ahead of each level
+    /// the taker trades with the pool until the pool's price reaches the
+    /// level's, and once the book is done with the taker, until it reaches
+    /// the taker's limit.
+    fn plan_match(&self, taker_order: &mut Order, config: &DexConfig) -> MatchPlan {
+        let mut plan = MatchPlan::default();
+        let stp_mode = self.self_trade_prevention(taker_order, config);
+        let fee_bps = self.market.fee_bps(config);
+        let mut reserves = self.pool.reserves();
 
         // Get the opposite side's orders
         let opposite_book = match taker_order.side {
-            OrderSide::Buy => &mut self.asks,
-            OrderSide::Sell => &mut self.bids,
+            OrderSide::Buy => &self.asks,
+            OrderSide::Sell => &self.bids,
         };
 
-        // Collect price levels to remove after matching
-        let mut empty_levels: Vec<PriceKey> = Vec::new();
-
         // Iterate through price levels in order
-        for (price_key, orders) in opposite_book.iter_mut() {
-            if taker_order.remaining_amount.is_zero() {
+        for (price_key, level) in opposite_book {
+            // Stop once the taker is filled or cancelled by self-trade prevention
+            if !taker_order.is_active() {
                 break;
             }
 
@@ -197,26 +1031,82 @@ impl OrderBook {
                 break; // No more matches possible due to price ordering
             }
 
-            // Match against orders at this price level
-            let mut i = 0;
-            while i < orders.len() && !taker_order.remaining_amount.is_zero() {
-                let maker_order = &mut orders[i];
+            // The pool goes first for as long as it beats this level
+            self.fill_from_pool(
+                taker_order,
+                price_key.price,
+                &mut reserves,
+                &mut plan,
+                config,
+            );
+            if !taker_order.is_active() {
+                break;
+            }
 
-                if !maker_order.is_active() {
-                    i += 1;
+            // Match against orders at this price level, copying only the
+            // orders the walk changes
+            let mut walked = LevelChanges::new(*price_key);
+            let mut step = 0;
+            while taker_order.is_active() {
+                let Some(index) = walked.index_at(step, level.len()) else {
+                    break;
+                };
+                step += 1;
+
+                // A swap walks past the trader's own orders without touching them
+                let maker_order = walked.order(level, index);
+                if !maker_order.is_active()
+                    || (stp_mode == SelfTradePrevention::Skip
+                        && taker_order.trader == maker_order.trader)
+                {
+                    continue;
+                }
+                let maker_order = walked.order_mut(level, index);
+
+                // An order linked to a maker that already traded is cancelled in place
+                if let Some(position) = plan
+                    .unlinked
+                    .iter()
+                    .position(|(id, _)| *id == maker_order.id)
+                {
+                    let (_, cancelled_by) = plan.unlinked.swap_remove(position);
+                    maker_order.cancel();
+                    plan.linked_cancels.push(LinkedCancel {
+                        order: maker_order.clone(),
+                        cancelled_by,
+                        pending_stop: false,
+                    });
                     continue;
                 }
 
                 // Check self-trade
-                if !config.allow_self_trade && taker_order.trader == maker_order.trader {
-                    i += 1;
+                if taker_order.trader == maker_order.trader
+                    && stp_mode != SelfTradePrevention::Allow
+                {
+                    plan.prevented
+                        .push(prevent_self_trade(stp_mode, taker_order, maker_order));
+                    if !maker_order.is_active() {
+                        plan.finished.push(maker_order.clone());
+                        if let Some(linked_id) = plan.take_link(&self.oco_links, maker_order.id) {
+                            plan.unlinked.push((linked_id, maker_order.clone()));
+                        }
+                    }
                     continue;
                 }
 
-                // Calculate fill amount
-                let fill_base_amount = taker_order
-                    .remaining_amount
-                    .min(maker_order.remaining_amount);
+                // Calculate fill amount, up to the displayed clip of an iceberg
+                let mut fill_base_amount =
+                    taker_order.remaining_amount.min(maker_order.visible_amount);
+
+                // A quote-sized taker buys no more than its remaining budget affords
+                if let Some(budget) = taker_order.quote_budget {
+                    fill_base_amount =
+                        fill_base_amount.min(affordable_base(maker_order.price, budget, fee_bps));
+                    if fill_base_amount.is_zero() {
+                        taker_order.close_quote_budget();
+                        break;
+                    }
+                }
 
                 // Calculate quote amount using maker's price (price-time priority)
                 let fill_quote_amount = maker_order
@@ -224,17 +1114,16 @@ impl OrderBook {
                     .quote_amount(fill_base_amount)
                     .unwrap_or(U256::ZERO);
 
-                // Calculate fees
-                let taker_fee = U256::from(
-                    config.calculate_fee(fill_quote_amount.try_into().unwrap_or(u128::MAX)),
-                );
-                let maker_fee = U256::ZERO; // Makers typically don't pay fees
+                let (taker_fee, maker_fee) = self.fill_fees(fill_quote_amount, config);
 
                 // Execute the fill
                 taker_order.fill(fill_base_amount);
                 maker_order.fill(fill_base_amount);
+                if let Some(budget) = taker_order.quote_budget.as_mut() {
+                    *budget = budget.saturating_sub(fill_quote_amount.saturating_add(taker_fee));
+                }
 
-                fills.push(Fill {
+                let fill = Fill {
                     maker_order_id: maker_order.id,
                     maker: maker_order.trader,
                     base_amount: fill_base_amount,
@@ -242,72 +1131,304 @@ impl OrderBook {
                     price: maker_order.price,
                     taker_fee,
                     maker_fee,
-                });
+                    from_pool: false,
+                };
+                // A range order holds on to what it receives to fund its next side
+                if maker_order.flip_price.is_some() {
+                    let proceeds = fill.maker_proceeds(maker_order.side);
+                    maker_order.range_proceeds =
+                        maker_order.range_proceeds.saturating_add(proceeds);
+                }
+                plan.fills.push(fill);
 
-                // Update volume
-                self.total_volume = self.total_volume.saturating_add(fill_base_amount);
+                // The first fill of a linked maker cancels the order linked to it
+                if let Some(linked_id) = plan.take_link(&self.oco_links, maker_order.id) {
+                    plan.unlinked.push((linked_id, maker_order.clone()));
+                }
 
-                // Remove filled orders from location map
                 if !maker_order.is_active() {
-                    self.orders.remove(&maker_order.id);
+                    plan.finished.push(maker_order.clone());
                 }
 
-                i += 1;
+                // A consumed iceberg clip is refreshed from the reserve and loses
+                // time priority: it moves to the back of its level, where the
+                // taker can still reach it after the orders queued before it
+                if maker_order.visible_amount.is_zero() && maker_order.refresh_clip() {
+                    maker_order.placed_at = taker_order.placed_at;
+                    walked.requeued.push(index);
+                }
             }
 
-            // Remove fully filled orders
-            orders.retain(|o| o.is_active());
-
-            if orders.is_empty() {
-                empty_levels.push(*price_key);
+            if !walked.updated.is_empty() {
+                plan.levels.push(walked);
             }
         }
 
-        // Remove empty price levels
-        for level in empty_levels {
-            match taker_order.side {
-                OrderSide::Buy => self.asks.remove(&level),
-                OrderSide::Sell => self.bids.remove(&level),
-            };
+        let limit_price = taker_order.price;
+        self.fill_from_pool(taker_order, limit_price, &mut reserves, &mut plan, config);
+        if reserves != self.pool.reserves() {
+            plan.reserves = Some(reserves);
         }
 
-        let remaining = taker_order.remaining_amount;
-        let fully_filled = remaining.is_zero();
+        plan.exhausted = taker_order.is_active()
+            && !taker_order
+                .quote_budget
+                .is_some_and(|budget| budget.is_zero());
 
-        TradeResult {
-            taker_order_id: taker_order.id,
-            fills,
-            remaining_amount: remaining,
-            fully_filled,
+        // A quote-sized taker is done once the book runs dry, with or without budget left
+        if taker_order.quote_budget.is_some() && taker_order.is_active() {
+            taker_order.close_quote_budget();
+        }
+
+        // A linked taker cancels the order linked to it once it trades or is cancelled
+        if !plan.fills.is_empty() || taker_order.status == OrderStatus::Cancelled {
+            if let Some(linked_id) = plan.take_link(&self.oco_links, taker_order.id) {
+                plan.unlinked.push((linked_id, taker_order.clone()));
+            }
         }
+
+        plan
     }
 
-    /// Add an order to the book (after matching).
-    fn add_order_to_book(&mut self, order: Order) {
-        let price_key = PriceKey {
-            price: order.price,
-            is_bid: order.side == OrderSide::Buy,
+    /// Fill an incoming order from the liquidity pool for as long as the pool's
+    /// price, its fee included, doesn't pass `bound`. `reserves` are the
+    /// pool's as the walk has left them so far.
+    ///
+    /// The pool trades in whole lots, and only at an average price within the
+    /// taker's limit. It pays no maker fee, while the taker pays the market's
+    /// taker fee on top of the pool's fee.
+    fn fill_from_pool(
+        &self,
+        taker_order: &mut Order,
+        bound: Price,
+        reserves: &mut Reserves,
+        plan: &mut MatchPlan,
+        config: &DexConfig,
+    ) {
+        if !taker_order.is_active() || reserves.is_empty() {
+            return;
+        }
+        let pool_fee_bps = self.market.pool_fee_bps;
+
+        let mut base_amount = match taker_order.side {
+            OrderSide::Buy => reserves.base_out_to(bound, pool_fee_bps),
+            OrderSide::Sell => reserves.base_in_to(bound, pool_fee_bps),
+        }
+        .min(taker_order.remaining_amount);
+
+        // A quote-sized taker buys no more than its remaining budget affords
+        if let Some(budget) = taker_order.quote_budget {
+            let quote_amount = affordable_quote(budget, self.market.fee_bps(config));
+            base_amount = base_amount.min(reserves.base_for_quote_in(quote_amount, pool_fee_bps));
+        }
+
+        let base_amount = self.market.round_down_to_lot(base_amount);
+        if base_amount.is_zero() {
+            return;
+        }
+        let quote_amount = match taker_order.side {
+            OrderSide::Buy => reserves.quote_for_base_out(base_amount, pool_fee_bps),
+            OrderSide::Sell => Some(reserves.quote_for_base_in(base_amount, pool_fee_bps)),
+        };
+        let Some(quote_amount) = quote_amount.filter(|quote| !quote.is_zero()) else {
+            return;
         };
 
-        let book = match order.side {
-            OrderSide::Buy => &mut self.bids,
-            OrderSide::Sell => &mut self.asks,
+        // Rounding must not take the fill past the taker's limit
+        let price = Price::new(quote_amount, base_amount);
+        let within_limit = match taker_order.side {
+            OrderSide::Buy => price <= taker_order.price,
+            OrderSide::Sell => price >= taker_order.price,
         };
+        if !within_limit {
+            return;
+        }
 
-        let orders = book.entry(price_key).or_insert_with(Vec::new);
-        orders.push(order.clone());
+        let (taker_fee, _) = self.fill_fees(quote_amount, config);
+        taker_order.fill(base_amount);
+        if let Some(budget) = taker_order.quote_budget.as_mut() {
+            *budget = budget.saturating_sub(quote_amount.saturating_add(taker_fee));
+        }
+        *reserves = match taker_order.side {
+            OrderSide::Buy => reserves.after_buy(base_amount, quote_amount),
+            OrderSide::Sell => reserves.after_sell(base_amount, quote_amount),
+        };
 
-        self.orders.insert(
-            order.id,
-            OrderLocation {
-                side: order.side,
-                price_key,
+        plan.fills.push(Fill {
+            maker_order_id: OrderId(0),
+            maker: Address::ZERO,
+            base_amount,
+            quote_amount,
+            price,
+            taker_fee,
+            maker_fee: I256::ZERO,
+            from_pool: true,
+        });
+    }
+
+    /// Taker and maker fees of a fill, charged on its quote value.
+    fn fill_fees(&self, quote_amount: Amount, config: &DexConfig) -> (Amount, I256) {
+        let fee_basis = quote_amount.try_into().unwrap_or(u128::MAX);
+        let taker_fee = U256::from(self.market.calculate_fee(fee_basis, config));
+        // A rebate can never exceed the taker fee that funds it
+        let maker_fee = self
+            .market
+            .calculate_maker_fee(fee_basis, config)
+            .max(-I256::from_raw(taker_fee));
+        (taker_fee, maker_fee)
+    }
+
+    /// Apply the matching worked out by `plan_match` to the book, re-posting
+    /// the range orders it filled completely.
+    fn commit_match(
+        &mut self,
+        taker_order: &Order,
+        plan: MatchPlan,
+        config: &DexConfig,
+    ) -> TradeResult {
+        let MatchPlan {
+            fills,
+            prevented,
+            mut linked_cancels,
+            unlinked,
+            taken_links,
+            finished,
+            levels,
+            reserves,
+            exhausted: _,
+        } = plan;
+
+        if let Some(reserves) = reserves {
+            self.pool.set_reserves(reserves);
+        }
+
+        // Apply the changes to the levels walked, without the orders that left them
+        let opposite_book = match taker_order.side {
+            OrderSide::Buy => &mut self.asks,
+            OrderSide::Sell => &mut self.bids,
+        };
+        for changes in levels {
+            let price_key = changes.price_key;
+            let Some(orders) = opposite_book.get_mut(&price_key) else {
+                continue;
+            };
+            changes.apply(orders);
+            if orders.is_empty() {
+                opposite_book.remove(&price_key);
+            }
+        }
+
+        for maker_order in &finished {
+            self.orders.remove(&maker_order.id);
+            finish_bracket_entry(&mut self.brackets, maker_order);
+        }
+        for linked_cancel in &linked_cancels {
+            self.orders.remove(&linked_cancel.order.id);
+        }
+        for order_id in taken_links {
+            self.oco_links.remove(&order_id);
+        }
+
+        // Update volume, the last trade price and the trade history
+        for fill in &fills {
+            self.total_volume = self.total_volume.saturating_add(fill.base_amount);
+            self.last_trade_price = Some(fill.price);
+            self.history.record(
+                taker_order.id,
+                taker_order.side,
+                fill,
+                taker_order.placed_at,
+                config,
+            );
+        }
+
+        for (linked_id, cancelled_by) in unlinked {
+            let pending_stop = self.is_pending_stop(linked_id);
+            if let Ok(order) = self.remove_order(linked_id) {
+                linked_cancels.push(LinkedCancel {
+                    order,
+                    cancelled_by,
+                    pending_stop,
+                });
+            }
+        }
+
+        let flipped = finished
+            .into_iter()
+            .filter(|o| o.status == OrderStatus::Filled && o.flip_price.is_some())
+            .map(|o| self.flip_range_order(o, taker_order.placed_at, config))
+            .collect();
+
+        let remaining = taker_order.remaining_amount;
+        let fully_filled = remaining.is_zero();
+
+        TradeResult {
+            taker_order_id: taker_order.id,
+            fills,
+            remaining_amount: remaining,
+            fully_filled,
+            prevented,
+            triggered: Vec::new(),
+            linked_cancels,
+            brackets: Vec::new(),
+            flipped,
+        }
+    }
+
+    /// Add an order to the book (after matching).
+    fn add_order_to_book(&mut self, order: Order) {
+        let price_key = PriceKey {
+            price: order.price,
+            is_bid: order.side == OrderSide::Buy,
+        };
+
+        let book = match order.side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        // Keep each level sorted by time priority (chain position)
+        let orders = book.entry(price_key).or_default();
+        let position = orders.partition_point(|o| o.priority() <= order.priority());
+        orders.insert(position, order.clone());
+
+        self.orders.insert(
+            order.id,
+            OrderLocation {
+                side: order.side,
+                price_key,
             },
         );
     }
 
-    /// Cancel an order by ID.
-    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<Order, OrderError> {
+    /// Cancel an order by ID, including a pending stop order.
+    ///
+    /// Cancelling an order linked one-cancels-other cancels the linked order
+    /// too, which is returned alongside. Cancelling a bracket entry drops the
+    /// bracket, even if the entry was partially filled.
+    pub fn cancel_order(
+        &mut self,
+        order_id: OrderId,
+    ) -> Result<(Order, Option<LinkedCancel>), OrderError> {
+        let order = self.remove_order(order_id)?;
+        self.brackets.remove(&order_id);
+        let linked_cancel = self.cancel_linked(&order);
+        Ok((order, linked_cancel))
+    }
+
+    /// Take an order off the book or out of the trigger index and mark it cancelled.
+    fn remove_order(&mut self, order_id: OrderId) -> Result<Order, OrderError> {
+        if let Some((side, stop_price)) = self.stop_index.remove(&order_id) {
+            let stops = match side {
+                OrderSide::Buy => &mut self.buy_stops,
+                OrderSide::Sell => &mut self.sell_stops,
+            };
+            let mut order =
+                take_order(stops, &stop_price, order_id).ok_or(OrderError::OrderNotFound)?;
+            order.cancel();
+            return Ok(order);
+        }
+
         let location = self
             .orders
             .remove(&order_id)
@@ -339,8 +1460,323 @@ impl OrderBook {
         Ok(order)
     }
 
-    /// Get an order by ID.
+    /// Amend the price and unfilled amount of a resting order.
+    ///
+    /// Reducing the amount at the same price keeps the order's place in the
+    /// queue. A price change or size increase loses it: the order moves to the
+    /// back of its new price level as if placed at `placed_at`.
+    ///
+    /// The amended order must respect the market parameters and may not cross
+    /// the book; taking liquidity needs a new order. In a batch auction market
+    /// it may, and trades when the block's auction clears. Pending stop
+    /// orders, orders linked one-cancels-other and range orders cannot be
+    /// amended.
+    pub fn amend_order(
+        &mut self,
+        order_id: OrderId,
+        amount: Amount,
+        price: Price,
+        placed_at: BlockContext,
+        config: &DexConfig,
+    ) -> Result<AmendResult, OrderError> {
+        let location = self
+            .orders
+            .get(&order_id)
+            .cloned()
+            .ok_or(OrderError::OrderNotFound)?;
+        let book = match location.side {
+            OrderSide::Buy => &self.bids,
+            OrderSide::Sell => &self.asks,
+        };
+        let previous = book
+            .get(&location.price_key)
+            .and_then(|orders| orders.iter().find(|o| o.id == order_id))
+            .cloned()
+            .ok_or(OrderError::OrderNotFound)?;
+        if self.oco_links.contains_key(&order_id) {
+            return Err(OrderError::LinkedOrder);
+        }
+        if previous.flip_price.is_some() {
+            return Err(OrderError::RangeOrder);
+        }
+
+        if amount < U256::from(config.min_order_size) {
+            return Err(OrderError::BelowMinimumSize);
+        }
+
+        let mut order = previous.clone();
+        order.price = price.normalized();
+        order.resize(amount);
+        self.validate_limit_order(&order)?;
+
+        let kept_priority = order.price == previous.price && amount <= previous.remaining_amount;
+        if kept_priority {
+            let book = match location.side {
+                OrderSide::Buy => &mut self.bids,
+                OrderSide::Sell => &mut self.asks,
+            };
+            if let Some(resting) = book
+                .get_mut(&location.price_key)
+                .and_then(|orders| orders.iter_mut().find(|o| o.id == order_id))
+            {
+                *resting = order.clone();
+            }
+        } else {
+            if !self.market.is_batch_auction() && self.would_cross(&order) {
+                return Err(OrderError::AmendWouldCross);
+            }
+            let book = match location.side {
+                OrderSide::Buy => &mut self.bids,
+                OrderSide::Sell => &mut self.asks,
+            };
+            take_order(book, &location.price_key, order_id);
+            order.placed_at = placed_at;
+            self.add_order_to_book(order.clone());
+        }
+
+        Ok(AmendResult {
+            previous,
+            order,
+            kept_priority,
+        })
+    }
+
+    /// Clear the batch auction of a batch auction market once the block
+    /// collecting its orders is over.
+    ///
+    /// Every order on the book takes part, icebergs with their hidden reserve.
+    /// The clearing price is the level price that matches the most base. Ties
+    /// go to the price leaving the least unmatched on either side, then to the
+    /// middle one of those still tied. Orders priced better than the clearing
+    /// price fill completely, and those at it fill in time priority as far as
+    /// the matched volume goes. Every fill trades at the clearing price.
+    ///
+    /// Self-trade prevention doesn't apply: a trader's buy and sell orders
+    /// meeting in an auction trade with each other. Immediate-or-cancel orders
+    /// left with a remainder are cancelled afterwards, so the book is uncrossed
+    /// once the auction clears.
+    ///
+    /// The fills are recorded in the trade history as executed at `executed_at`,
+    /// with the sell orders as takers.
+    pub fn clear_batch(&mut self, config: &DexConfig, executed_at: BlockContext) -> BatchClearing {
+        let mut clearing = BatchClearing::default();
+
+        if let Some((price, volume)) = self.clearing_price() {
+            let buys = batch_allocations(&self.bids, volume);
+            let sells = batch_allocations(&self.asks, volume);
+
+            // Pair the allocations up in priority order on both sides
+            let (mut buy_iter, mut sell_iter) = (buys.iter(), sells.iter());
+            let (mut buy, mut sell) = (buy_iter.next().cloned(), sell_iter.next().cloned());
+            while let (Some((buy_order, buy_left)), Some((sell_order, sell_left))) =
+                (buy.as_mut(), sell.as_mut())
+            {
+                let base_amount = (*buy_left).min(*sell_left);
+                let quote_amount = price.quote_amount(base_amount).unwrap_or(U256::ZERO);
+                let (taker_fee, maker_fee) = self.fill_fees(quote_amount, config);
+                clearing.fills.push(BatchFill {
+                    sell_order_id: sell_order.id,
+                    seller: sell_order.trader,
+                    buy_price: buy_order.price,
+                    fill: Fill {
+                        maker_order_id: buy_order.id,
+                        maker: buy_order.trader,
+                        base_amount,
+                        quote_amount,
+                        price,
+                        taker_fee,
+                        maker_fee,
+                        from_pool: false,
+                    },
+                });
+
+                *buy_left -= base_amount;
+                *sell_left -= base_amount;
+                if buy_left.is_zero() {
+                    buy = buy_iter.next().cloned();
+                }
+                if sell_left.is_zero() {
+                    sell = sell_iter.next().cloned();
+                }
+            }
+
+            let filled = |allocations: &[(Order, Amount)]| {
+                allocations
+                    .iter()
+                    .map(|(order, amount)| (order.id, *amount))
+                    .collect::<HashMap<_, _>>()
+            };
+            self.fill_batch_orders(OrderSide::Buy, &filled(&buys), executed_at);
+            self.fill_batch_orders(OrderSide::Sell, &filled(&sells), executed_at);
+
+            self.total_volume = self.total_volume.saturating_add(volume);
+            self.last_trade_price = Some(price);
+            for batch_fill in &clearing.fills {
+                self.history.record(
+                    batch_fill.sell_order_id,
+                    OrderSide::Sell,
+                    &batch_fill.fill,
+                    executed_at,
+                    config,
+                );
+            }
+            clearing.price = Some(price);
+            clearing.volume = volume;
+        }
+
+        let unfilled: Vec<OrderId> = self
+            .bids
+            .values()
+            .chain(self.asks.values())
+            .flatten()
+            .filter(|o| o.time_in_force == TimeInForce::ImmediateOrCancel)
+            .map(|o| o.id)
+            .collect();
+        clearing.cancelled = unfilled
+            .into_iter()
+            .filter_map(|order_id| self.remove_order(order_id).ok())
+            .collect();
+
+        clearing
+    }
+
+    /// Whether a batch auction market has an auction waiting to clear: its
+    /// book is crossed, or an immediate-or-cancel order waits for the block
+    /// to end.
+    pub fn has_pending_batch(&self) -> bool {
+        let crossed = self.spread().is_some_and(|(bid, ask)| bid >= ask);
+        crossed
+            || self
+                .bids
+                .values()
+                .chain(self.asks.values())
+                .flatten()
+                .any(|o| o.time_in_force == TimeInForce::ImmediateOrCancel)
+    }
+
+    /// Whether the market can switch between continuous matching and batch
+    /// auctions. It can't while an auction waits to clear, nor while stop
+    /// orders, one-cancels-other links, brackets, range orders or pool
+    /// liquidity are pending, which batch auctions don't have.
+    pub fn can_switch_mode(&self) -> bool {
+        !self.has_pending_batch()
+            && self.stop_index.is_empty()
+            && self.oco_links.is_empty()
+            && self.brackets.is_empty()
+            && self.pool.is_empty()
+            && !self
+                .bids
+                .values()
+                .chain(self.asks.values())
+                .flatten()
+                .any(|o| o.flip_price.is_some())
+    }
+
+    /// The uniform price a batch auction clears at and the base it matches,
+    /// or `None` if the book isn't crossed.
+    fn clearing_price(&self) -> Option<(Price, Amount)> {
+        let (best_bid, best_ask) = self.spread()?;
+        if best_bid < best_ask {
+            return None;
+        }
+
+        // Only level prices within the crossed range can match anything
+        let mut candidates: Vec<Price> = self
+            .bids
+            .keys()
+            .map(|k| k.price)
+            .take_while(|price| *price >= best_ask)
+            .chain(
+                self.asks
+                    .keys()
+                    .map(|k| k.price)
+                    .take_while(|price| *price <= best_bid),
+            )
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        let mut best = (U256::ZERO, U256::MAX);
+        let mut tied = Vec::new();
+        for price in candidates {
+            let demand = depth_through(&self.bids, |level| level >= price);
+            let supply = depth_through(&self.asks, |level| level <= price);
+            let matched = demand.min(supply);
+            let imbalance = demand.max(supply) - matched;
+
+            let better = matched > best.0 || (matched == best.0 && imbalance < best.1);
+            if better {
+                best = (matched, imbalance);
+                tied.clear();
+            }
+            if better || (matched, imbalance) == best {
+                tied.push(price);
+            }
+        }
+
+        let price = *tied.get((tied.len().checked_sub(1)?) / 2)?;
+        Some((price, best.0))
+    }
+
+    /// Apply the base amounts a batch auction filled to the orders on one side
+    /// of the book, taking filled orders off it.
+    ///
+    /// An iceberg whose clip the auction consumed is refreshed as in continuous
+    /// matching: it takes its time priority from `executed_at` and moves to the
+    /// back of its level.
+    fn fill_batch_orders(
+        &mut self,
+        side: OrderSide,
+        filled: &HashMap<OrderId, Amount>,
+        executed_at: BlockContext,
+    ) {
+        let book = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+        let mut finished = Vec::new();
+        book.retain(|_, orders| {
+            let mut refreshed = Vec::new();
+            orders.retain_mut(|order| {
+                let Some(amount) = filled.get(&order.id) else {
+                    return order.is_active();
+                };
+                order.fill(*amount);
+                if !order.is_active() {
+                    finished.push(order.id);
+                    return false;
+                }
+                if order.visible_amount.is_zero() && order.refresh_clip() {
+                    order.placed_at = executed_at;
+                    refreshed.push(order.clone());
+                    return false;
+                }
+                true
+            });
+            orders.extend(refreshed);
+            !orders.is_empty()
+        });
+
+        for order_id in finished {
+            self.orders.remove(&order_id);
+        }
+    }
+
+    /// Whether an order is a stop order still waiting in the trigger index.
+    pub fn is_pending_stop(&self, order_id: OrderId) -> bool {
+        self.stop_index.contains_key(&order_id)
+    }
+
+    /// Get an order by ID, including a pending stop order.
     pub fn get_order(&self, order_id: OrderId) -> Option<&Order> {
+        if let Some((side, stop_price)) = self.stop_index.get(&order_id) {
+            let stops = match side {
+                OrderSide::Buy => &self.buy_stops,
+                OrderSide::Sell => &self.sell_stops,
+            };
+            return stops.get(stop_price)?.iter().find(|o| o.id == order_id);
+        }
+
         let location = self.orders.get(&order_id)?;
         let book = match location.side {
             OrderSide::Buy => &self.bids,
@@ -360,15 +1796,33 @@ impl OrderBook {
         self.asks.first_key_value().map(|(k, _)| k.price)
     }
 
+    /// Get the best price a sell gets right now: the best bid or the price the
+    /// liquidity pool pays for the next unit of base, whichever is higher.
+    pub fn best_bid_with_pool(&self) -> Option<Price> {
+        let pool_bid = self.pool.reserves().bid_price(self.market.pool_fee_bps);
+        self.best_bid().max(pool_bid)
+    }
+
+    /// Get the best price a buy pays right now: the best ask or the price the
+    /// liquidity pool charges for the next unit of base, whichever is lower.
+    pub fn best_ask_with_pool(&self) -> Option<Price> {
+        let pool_ask = self.pool.reserves().ask_price(self.market.pool_fee_bps);
+        match (self.best_ask(), pool_ask) {
+            (Some(ask), Some(pool_ask)) => Some(ask.min(pool_ask)),
+            (ask, pool_ask) => ask.or(pool_ask),
+        }
+    }
+
     /// Get the spread (difference between best ask and best bid).
     pub fn spread(&self) -> Option<(Price, Price)> {
         Some((self.best_bid()?, self.best_ask()?))
     }
 
-    /// Get the total liquidity available at a price level.
+    /// Get the displayed liquidity at a price level.
+    /// Hidden iceberg reserves are not included.
     pub fn liquidity_at_price(&self, side: OrderSide, price: Price) -> Amount {
         let price_key = PriceKey {
-            price,
+            price: price.normalized(),
             is_bid: side == OrderSide::Buy,
         };
 
@@ -382,12 +1836,12 @@ impl OrderBook {
                 orders
                     .iter()
                     .filter(|o| o.is_active())
-                    .fold(U256::ZERO, |acc, o| acc.saturating_add(o.remaining_amount))
+                    .fold(U256::ZERO, |acc, o| acc.saturating_add(o.visible_amount))
             })
             .unwrap_or(U256::ZERO)
     }
 
-    /// Get total bid liquidity up to a certain depth.
+    /// Get displayed bid liquidity up to a certain depth.
     pub fn bid_liquidity(&self, depth: usize) -> Vec<(Price, Amount)> {
         self.bids
             .iter()
@@ -396,13 +1850,13 @@ impl OrderBook {
                 let total = orders
                     .iter()
                     .filter(|o| o.is_active())
-                    .fold(U256::ZERO, |acc, o| acc.saturating_add(o.remaining_amount));
+                    .fold(U256::ZERO, |acc, o| acc.saturating_add(o.visible_amount));
                 (k.price, total)
             })
             .collect()
     }
 
-    /// Get total ask liquidity up to a certain depth.
+    /// Get displayed ask liquidity up to a certain depth.
     pub fn ask_liquidity(&self, depth: usize) -> Vec<(Price, Amount)> {
         self.asks
             .iter()
@@ -411,7 +1865,7 @@ impl OrderBook {
                 let total = orders
                     .iter()
                     .filter(|o| o.is_active())
-                    .fold(U256::ZERO, |acc, o| acc.saturating_add(o.remaining_amount));
+                    .fold(U256::ZERO, |acc, o| acc.saturating_add(o.visible_amount));
                 (k.price, total)
             })
             .collect()
@@ -435,170 +1889,640 @@ impl OrderBook {
     }
 
     /// Simulate a market buy to get expected output.
-    /// Returns (output_amount, average_price) if there's enough liquidity.
+    /// Returns what the buy would do if there's any liquidity; its output is the
+    /// base received.
+    ///
+    /// The input is a quote budget that pays for the fills and their taker
+    /// fees. The buy is a dry run of a quote-sized market buy, so its output is
+    /// the base such an order receives.
     pub fn simulate_market_buy(
         &self,
         input_quote_amount: Amount,
         config: &DexConfig,
-    ) -> Option<(Amount, Price)> {
-        let mut remaining_quote = input_quote_amount;
-        let mut total_base = U256::ZERO;
+    ) -> Option<MarketSimulation> {
+        let taker_order =
+            dry_run_order(OrderSide::Buy, U256::ZERO).with_quote_budget(input_quote_amount);
+        self.simulate_market_order(taker_order, config)
+    }
+
+    /// Simulate a market sell to get expected output.
+    /// Returns what the sell would do if there's any liquidity; its output is
+    /// the quote received after fees.
+    pub fn simulate_market_sell(
+        &self,
+        input_base_amount: Amount,
+        config: &DexConfig,
+    ) -> Option<MarketSimulation> {
+        self.simulate_market_order(dry_run_order(OrderSide::Sell, input_base_amount), config)
+    }
+
+    /// Simulate a market buy of exactly `output_base_amount`.
+    /// Returns what the buy would do if there's enough liquidity, its input
+    /// being the quote the fills cost with their taker fees.
+    pub fn simulate_market_buy_exact_out(
+        &self,
+        output_base_amount: Amount,
+        config: &DexConfig,
+    ) -> Option<MarketSimulation> {
+        self.simulate_market_order(dry_run_order(OrderSide::Buy, output_base_amount), config)
+            .filter(|simulation| !simulation.liquidity_exhausted)
+    }
+
+    /// Simulate a market sell that receives at least `output_quote_amount` after
+    /// the taker fee.
+    /// Returns what the sell would do if there's enough liquidity, its input
+    /// being the base to sell. Rounding can leave the output a few units over
+    /// the amount asked for, never under.
+    pub fn simulate_market_sell_exact_out(
+        &self,
+        output_quote_amount: Amount,
+        config: &DexConfig,
+    ) -> Option<MarketSimulation> {
+        if !self.pool.is_empty() {
+            return self.search_market_sell_exact_out(output_quote_amount, config);
+        }
 
-        for (_price_key, orders) in &self.asks {
+        let fee_bps = self.market.fee_bps(config);
+        let mut remaining_quote = output_quote_amount;
+        let mut input_base_amount = U256::ZERO;
+
+        // Estimate the base to sell from the prices of the resting orders
+        for order in self.bids.values().flatten().filter(|o| o.is_active()) {
             if remaining_quote.is_zero() {
                 break;
             }
 
-            for order in orders {
-                if !order.is_active() || remaining_quote.is_zero() {
-                    continue;
-                }
-
-                // How much quote do we need to buy all of this order's base?
-                let order_quote_value = order.price.quote_amount(order.remaining_amount)?;
-
-                if remaining_quote >= order_quote_value {
-                    // We can fill this entire order
-                    total_base = total_base.saturating_add(order.remaining_amount);
-                    remaining_quote = remaining_quote.saturating_sub(order_quote_value);
-                } else {
-                    // Partial fill
-                    let base_we_can_buy = order.price.base_amount(remaining_quote)?;
-                    total_base = total_base.saturating_add(base_we_can_buy);
-                    remaining_quote = U256::ZERO;
-                }
-            }
+            // Sell this entire order, or just enough of it for what's left
+            let base = order.remaining_amount.min(base_for_net_quote(
+                order.price,
+                remaining_quote,
+                fee_bps,
+            )?);
+            let quote = order.price.quote_amount(base)?;
+            let fee = U256::from(
+                self.market
+                    .calculate_fee(quote.try_into().unwrap_or(u128::MAX), config),
+            );
+            input_base_amount = input_base_amount.saturating_add(base);
+            remaining_quote = remaining_quote.saturating_sub(quote.saturating_sub(fee));
         }
 
-        if total_base.is_zero() {
+        if !remaining_quote.is_zero() {
             return None;
         }
 
-        // Apply fee to output
-        let fee = U256::from(config.calculate_fee(total_base.try_into().unwrap_or(u128::MAX)));
-        let output_after_fee = total_base.saturating_sub(fee);
-
-        // Calculate average price
-        let spent = input_quote_amount.saturating_sub(remaining_quote);
-        let avg_price = Price::new(spent, total_base);
-
-        Some((output_after_fee, avg_price))
+        // Iceberg clips are rounded one at a time and linked orders drop out
+        // as the sell executes, so the estimate can fall short. Dry-run it and
+        // top it up at the worst price reached until it doesn't.
+        loop {
+            let simulation = self.simulate_market_sell(input_base_amount, config)?;
+            if simulation.amount_out >= output_quote_amount {
+                return Some(simulation);
+            }
+            if simulation.liquidity_exhausted {
+                return None;
+            }
+            let shortfall = output_quote_amount - simulation.amount_out;
+            input_base_amount = input_base_amount.checked_add(base_for_net_quote(
+                simulation.worst_price,
+                shortfall,
+                fee_bps,
+            )?)?;
+        }
     }
 
-    /// Simulate a market sell to get expected output.
-    /// Returns (output_amount, average_price) if there's enough liquidity.
-    pub fn simulate_market_sell(
+    /// Find the least base a market sell receives `output_quote_amount` for
+    /// when the liquidity pool trades alongside the book.
+    ///
+    /// The pool fills the sell ahead of worse levels, so the book's prices
+    /// can't tell how much to sell. The output only grows with the input,
+    /// though: starting from what the best price would take, double the input
+    /// until it's enough, then bisect between the last two tries.
+    fn search_market_sell_exact_out(
         &self,
-        input_base_amount: Amount,
+        output_quote_amount: Amount,
         config: &DexConfig,
-    ) -> Option<(Amount, Price)> {
-        let mut remaining_base = input_base_amount;
-        let mut total_quote = U256::ZERO;
+    ) -> Option<MarketSimulation> {
+        let enough = |input_base_amount| {
+            self.simulate_market_sell(input_base_amount, config)
+                .filter(|simulation| simulation.amount_out >= output_quote_amount)
+        };
 
-        for (_price_key, orders) in &self.bids {
-            if remaining_base.is_zero() {
-                break;
+        let best_bid = self.best_bid_with_pool()?;
+        let fee_bps = self.market.fee_bps(config);
+        let mut high =
+            base_for_net_quote(best_bid, output_quote_amount, fee_bps)?.max(U256::from(1));
+        let mut low = U256::ZERO;
+        let mut best = loop {
+            if let Some(simulation) = enough(high) {
+                break simulation;
             }
+            low = high;
+            high = high.checked_mul(U256::from(2))?;
+        };
 
-            for order in orders {
-                if !order.is_active() || remaining_base.is_zero() {
-                    continue;
-                }
-
-                if remaining_base >= order.remaining_amount {
-                    // We can fill this entire order
-                    let quote_value = order.price.quote_amount(order.remaining_amount)?;
-                    total_quote = total_quote.saturating_add(quote_value);
-                    remaining_base = remaining_base.saturating_sub(order.remaining_amount);
-                } else {
-                    // Partial fill
-                    let quote_value = order.price.quote_amount(remaining_base)?;
-                    total_quote = total_quote.saturating_add(quote_value);
-                    remaining_base = U256::ZERO;
+        while high - low > U256::from(1) {
+            let middle = low + (high - low) / U256::from(2);
+            match enough(middle) {
+                Some(simulation) => {
+                    high = middle;
+                    best = simulation;
                 }
+                None => low = middle,
             }
         }
+        Some(best)
+    }
 
-        if total_quote.is_zero() {
-            return None;
+    /// Dry-run placing a market order: the checks [`OrderBook::place_order`]
+    /// makes of it and the fills it would get, without changing the book.
+    /// Stop orders its fills would trigger don't change those fills, so they
+    /// aren't run.
+    pub fn simulate_market_fills(
+        &self,
+        order: &Order,
+        config: &DexConfig,
+    ) -> Result<Vec<Fill>, OrderError> {
+        if order.remaining_amount < U256::from(config.min_order_size) {
+            return Err(OrderError::BelowMinimumSize);
         }
+        if self.market.is_batch_auction() {
+            return Err(OrderError::NotInBatchAuction);
+        }
+        self.check_time_in_force(order, config)?;
 
-        // Apply fee to output
-        let fee = U256::from(config.calculate_fee(total_quote.try_into().unwrap_or(u128::MAX)));
-        let output_after_fee = total_quote.saturating_sub(fee);
-
-        // Calculate average price
-        let sold = input_base_amount.saturating_sub(remaining_base);
-        let avg_price = Price::new(total_quote, sold);
+        let mut taker_order = order.clone();
+        Ok(self.plan_match(&mut taker_order, config).fills)
+    }
 
-        Some((output_after_fee, avg_price))
+    /// Dry-run a market order through the matching core and sum up its fills.
+    /// Returns what the order would do if it fills anything. Batch auction
+    /// markets take no market orders, so they never do.
+    fn simulate_market_order(
+        &self,
+        mut taker_order: Order,
+        config: &DexConfig,
+    ) -> Option<MarketSimulation> {
+        if self.market.is_batch_auction() {
+            return None;
+        }
+        let plan = self.plan_match(&mut taker_order, config);
+        let mut fills = SimulatedFills::default();
+        for fill in &plan.fills {
+            fills.add(
+                fill.price,
+                fill.base_amount,
+                fill.quote_amount,
+                fill.taker_fee,
+            );
+        }
+        fills.finish(taker_order.side, plan.exhausted)
     }
 }
 
-/// Errors that can occur when working with orders.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum OrderError {
-    /// Order amount is below the minimum size.
-    BelowMinimumSize,
-    /// Order was not found.
-    OrderNotFound,
-    /// Insufficient liquidity for the trade.
-    InsufficientLiquidity,
-    /// Invalid price.
-    InvalidPrice,
+/// What matching an incoming order against the book does, worked out by
+/// `OrderBook::plan_match` before anything on the book changes.
+#[derive(Debug, Default)]
+struct MatchPlan {
+    /// Fills of the incoming order, in matching order.
+    fills: Vec<Fill>,
+    /// Matches stopped by self-trade prevention.
+    prevented: Vec<PreventedTrade>,
+    /// Orders cancelled in place because the order linked to them traded
+    /// earlier in the walk.
+    linked_cancels: Vec<LinkedCancel>,
+    /// Orders still to cancel because the order linked to them was filled or
+    /// cancelled, with that order.
+    unlinked: Vec<(OrderId, Order)>,
+    /// Both ends of the one-cancels-other links used up by the walk.
+    taken_links: Vec<OrderId>,
+    /// Resting orders that finished trading, filled or cancelled by self-trade
+    /// prevention.
+    finished: Vec<Order>,
+    /// The changes to the price levels walked.
+    levels: Vec<LevelChanges>,
+    /// The pool's reserves after the walk, if it traded with the pool.
+    reserves: Option<Reserves>,
+    /// Whether the incoming order wanted more than the book could match: it
+    /// was still active when the walk ended, with budget left if quote-sized.
+    exhausted: bool,
 }
 
-impl std::fmt::Display for OrderError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            OrderError::BelowMinimumSize => write!(f, "order amount is below minimum size"),
-            OrderError::OrderNotFound => write!(f, "order not found"),
-            OrderError::InsufficientLiquidity => write!(f, "insufficient liquidity"),
-            OrderError::InvalidPrice => write!(f, "invalid price"),
+impl MatchPlan {
+    /// Use up the one-cancels-other link of an order, unless the walk already
+    /// did. Returns the ID of the order it was linked to.
+    fn take_link(
+        &mut self,
+        links: &HashMap<OrderId, OrderId>,
+        order_id: OrderId,
+    ) -> Option<OrderId> {
+        if self.taken_links.contains(&order_id) {
+            return None;
         }
+        let linked_id = *links.get(&order_id)?;
+        self.taken_links.extend([order_id, linked_id]);
+        Some(linked_id)
     }
 }
 
-impl std::error::Error for OrderError {}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::types::ETH_TOKEN;
+/// What a match changes on one price level, recorded against the level as it
+/// is on the book by the index of each order.
+#[derive(Debug)]
+struct LevelChanges {
+    /// The level changed.
+    price_key: PriceKey,
+    /// The orders the walk changed, as it leaves them.
+    updated: BTreeMap<usize, Order>,
+    /// Iceberg orders whose clip was refreshed, in the order they moved to
+    /// the back of the level.
+    requeued: Vec<usize>,
+}
 
-    fn setup() -> (OrderBook, DexConfig) {
-        let eth = ETH_TOKEN;
-        let usdc = Address::repeat_byte(0x01);
-        let pair = Pair::new(eth, usdc);
-        let book = OrderBook::new(pair);
-        let config = DexConfig::default();
-        (book, config)
+impl LevelChanges {
+    fn new(price_key: PriceKey) -> Self {
+        Self {
+            price_key,
+            updated: BTreeMap::new(),
+            requeued: Vec::new(),
+        }
     }
 
-    fn test_trader(n: u8) -> Address {
-        Address::repeat_byte(n)
+    /// The index of the order at `step` of the walk through a level of `len`
+    /// orders: the level in book order, then the orders moved to its back.
+    fn index_at(&self, step: usize, len: usize) -> Option<usize> {
+        match step.checked_sub(len) {
+            None => Some(step),
+            Some(requeued) => self.requeued.get(requeued).copied(),
+        }
     }
 
-    #[test]
-    fn test_place_limit_order() {
-        let (mut book, config) = setup();
-        let trader = test_trader(1);
+    /// The order at `index` of `level` as the walk has left it.
+    fn order<'a>(&'a self, level: &'a [Order], index: usize) -> &'a Order {
+        self.updated.get(&index).unwrap_or(&level[index])
+    }
 
-        let (order_id, result) = book
-            .place_limit_order(
-                trader,
-                OrderSide::Buy,
-                Price::from_u128(100, 1),
-                U256::from(1000),
-                &config,
-            )
-            .unwrap();
+    /// The order at `index` of `level` to change, copied on first use.
+    fn order_mut(&mut self, level: &[Order], index: usize) -> &mut Order {
+        self.updated
+            .entry(index)
+            .or_insert_with(|| level[index].clone())
+    }
 
-        assert_eq!(result.fills.len(), 0);
-        assert_eq!(result.remaining_amount, U256::from(1000));
-        assert!(!result.fully_filled);
+    /// Apply the changes to the level's orders, moving refreshed icebergs to
+    /// the back and taking off the orders that left the level.
+    fn apply(self, orders: &mut Vec<Order>) {
+        let mut slots: Vec<Option<Order>> = std::mem::take(orders).into_iter().map(Some).collect();
+        for (index, order) in self.updated {
+            slots[index] = Some(order);
+        }
 
-        let order = book.get_order(order_id).unwrap();
+        // An order refreshed more than once ends up where it moved last
+        let mut moved = Vec::new();
+        for (n, index) in self.requeued.iter().enumerate() {
+            if !self.requeued[n + 1..].contains(index) {
+                moved.extend(slots[*index].take());
+            }
+        }
+
+        *orders = slots
+            .into_iter()
+            .flatten()
+            .chain(moved)
+            .filter(|o| o.is_active())
+            .collect();
+    }
+}
+
+/// Running totals of the fills of a simulated market order.
+#[derive(Debug, Default)]
+struct SimulatedFills {
+    base: Amount,
+    quote: Amount,
+    fee: Amount,
+    worst_price: Option<Price>,
+    levels: usize,
+    orders: usize,
+}
+
+impl SimulatedFills {
+    /// Add a fill against a resting order at `price`. Orders are walked best
+    /// price first, so a new price is a new level.
+    fn add(&mut self, price: Price, base: Amount, quote: Amount, fee: Amount) {
+        if self.worst_price != Some(price) {
+            self.levels += 1;
+            self.worst_price = Some(price);
+        }
+        self.orders += 1;
+        self.base = self.base.saturating_add(base);
+        self.quote = self.quote.saturating_add(quote);
+        self.fee = self.fee.saturating_add(fee);
+    }
+
+    /// The simulation of a taker on `side` that made these fills, `None`
+    /// without any.
+    fn finish(self, side: OrderSide, liquidity_exhausted: bool) -> Option<MarketSimulation> {
+        let (amount_in, amount_out) = match side {
+            OrderSide::Buy => (self.quote.saturating_add(self.fee), self.base),
+            OrderSide::Sell => (self.base, self.quote.saturating_sub(self.fee)),
+        };
+        Some(MarketSimulation {
+            amount_in,
+            amount_out,
+            fee: self.fee,
+            worst_price: self.worst_price?,
+            avg_price: Price::new(self.quote, self.base),
+            levels_consumed: self.levels,
+            orders_consumed: self.orders,
+            liquidity_exhausted,
+        })
+    }
+}
+
+/// A market order to dry-run against the book. It belongs to no trader, so
+/// simulations see the fills of a trader without resting orders on the book.
+fn dry_run_order(side: OrderSide, amount: Amount) -> Order {
+    Order::new_market(
+        OrderId(0),
+        Address::ZERO,
+        side,
+        amount,
+        BlockContext::default(),
+    )
+}
+
+/// Base a quote budget buys at `price` with the taker fee paid on top.
+fn affordable_base(price: Price, budget: Amount, fee_bps: u32) -> Amount {
+    price
+        .base_amount(affordable_quote(budget, fee_bps))
+        .unwrap_or(U256::ZERO)
+}
+
+/// The largest quote a budget pays for with the taker fee on top.
+fn affordable_quote(budget: Amount, fee_bps: u32) -> Amount {
+    // Split so the scaling can't overflow
+    let bps = U256::from(10_000);
+    let scale = bps + U256::from(fee_bps);
+    budget / scale * bps + budget % scale * bps / scale
+}
+
+/// Base to sell at `price` to receive at least `net_quote` once the taker fee
+/// is taken out.
+fn base_for_net_quote(price: Price, net_quote: Amount, fee_bps: u32) -> Option<Amount> {
+    let bps = U256::from(10_000);
+    let kept = bps
+        .checked_sub(U256::from(fee_bps))
+        .filter(|kept| !kept.is_zero())?;
+    let quote = net_quote.checked_mul(bps)?.div_ceil(kept);
+    Some(
+        quote
+            .checked_mul(price.denominator)?
+            .div_ceil(price.numerator),
+    )
+}
+
+/// Stop a taker from matching its own resting order.
+fn prevent_self_trade(
+    mode: SelfTradePrevention,
+    taker: &mut Order,
+    maker: &mut Order,
+) -> PreventedTrade {
+    let mut maker_reduced = U256::ZERO;
+    let mut taker_reduced = U256::ZERO;
+
+    match mode {
+        SelfTradePrevention::CancelNewest => taker.cancel(),
+        SelfTradePrevention::CancelOldest => maker.cancel(),
+        SelfTradePrevention::CancelBoth => {
+            taker.cancel();
+            maker.cancel();
+        }
+        SelfTradePrevention::DecrementAndCancel => {
+            // The smaller order is cancelled and the larger one shrinks by its size
+            match taker.remaining_amount.cmp(&maker.remaining_amount) {
+                std::cmp::Ordering::Less => {
+                    maker_reduced = taker.remaining_amount;
+                    maker.reduce(maker_reduced);
+                    taker.cancel();
+                }
+                std::cmp::Ordering::Greater => {
+                    taker_reduced = maker.remaining_amount;
+                    taker.reduce(taker_reduced);
+                    maker.cancel();
+                }
+                std::cmp::Ordering::Equal => {
+                    taker.cancel();
+                    maker.cancel();
+                }
+            }
+        }
+        // The caller skips or matches these orders instead
+        SelfTradePrevention::Skip | SelfTradePrevention::Allow => {}
+    }
+
+    PreventedTrade {
+        mode,
+        maker: maker.clone(),
+        maker_reduced,
+        taker_reduced,
+    }
+}
+
+/// Total remaining amount of the active orders on the levels of one side of the
+/// book whose price `reaches` accepts. Levels are walked best price first, so
+/// the walk stops at the first one it rejects.
+fn depth_through(
+    levels: &BTreeMap<PriceKey, Vec<Order>>,
+    reaches: impl Fn(Price) -> bool,
+) -> Amount {
+    levels
+        .iter()
+        .take_while(|(price_key, _)| reaches(price_key.price))
+        .flat_map(|(_, orders)| orders)
+        .filter(|o| o.is_active())
+        .fold(U256::ZERO, |acc, o| acc.saturating_add(o.remaining_amount))
+}
+
+/// The orders on one side of a batch auction that fill `volume` between them,
+/// with the base each one fills: best price first, and in time priority within
+/// a level.
+fn batch_allocations(
+    levels: &BTreeMap<PriceKey, Vec<Order>>,
+    volume: Amount,
+) -> Vec<(Order, Amount)> {
+    let mut left = volume;
+    let mut allocations = Vec::new();
+    for order in levels.values().flatten().filter(|o| o.is_active()) {
+        if left.is_zero() {
+            break;
+        }
+        let amount = order.remaining_amount.min(left);
+        left -= amount;
+        allocations.push((order.clone(), amount));
+    }
+    allocations
+}
+
+/// Mark a bracket entry that finished trading as ready to place its exits, or
+/// drop the bracket if the entry finished without any fill.
+fn finish_bracket_entry(brackets: &mut BTreeMap<OrderId, Bracket>, entry: &Order) {
+    if entry.filled_amount().is_zero() {
+        brackets.remove(&entry.id);
+    } else if let Some(bracket) = brackets.get_mut(&entry.id) {
+        bracket.entry = Some(entry.clone());
+    }
+}
+
+/// Remove the one-cancels-other link of an order in both directions.
+/// Returns the ID of the order it was linked to.
+fn take_link(links: &mut HashMap<OrderId, OrderId>, order_id: OrderId) -> Option<OrderId> {
+    let linked_id = links.remove(&order_id)?;
+    links.remove(&linked_id);
+    Some(linked_id)
+}
+
+/// Remove an order from a price level, dropping the level once it is empty.
+fn take_order<K: Ord>(
+    levels: &mut BTreeMap<K, Vec<Order>>,
+    key: &K,
+    order_id: OrderId,
+) -> Option<Order> {
+    let orders = levels.get_mut(key)?;
+    let position = orders.iter().position(|o| o.id == order_id)?;
+    let order = orders.remove(position);
+    if orders.is_empty() {
+        levels.remove(key);
+    }
+    Some(order)
+}
+
+/// Errors that can occur when working with orders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    /// Order amount is below the minimum size.
+    BelowMinimumSize,
+    /// Order was not found.
+    OrderNotFound,
+    /// Insufficient liquidity for the trade.
+    InsufficientLiquidity,
+    /// Invalid price.
+    InvalidPrice,
+    /// Price is not a multiple of the market's tick size.
+    PriceNotOnTick,
+    /// Amount is not a multiple of the market's lot size.
+    AmountNotOnLot,
+    /// Order value is below the market's minimum notional.
+    BelowMinimumNotional,
+    /// Post-only order would have matched against the book.
+    PostOnlyWouldCross,
+    /// Fill-or-kill order could not be filled completely.
+    FillOrKillNotFilled,
+    /// Stop order would be triggered by the last trade price right away.
+    StopPriceAlreadyReached,
+    /// Amended order would have matched against the book.
+    AmendWouldCross,
+    /// Iceberg clip is zero or not a multiple of the lot size.
+    InvalidDisplayAmount,
+    /// Orders linked one-cancels-other cannot be amended.
+    LinkedOrder,
+    /// Orders don't form a valid one-cancels-other pair or bracket.
+    InvalidOrderGroup,
+    /// Range order isn't post-only, is an iceberg or stop order, or its flip
+    /// price isn't across the spread from its price.
+    InvalidRangeOrder,
+    /// Range orders cannot be amended.
+    RangeOrder,
+    /// Batch auction markets only take good-till-cancel and
+    /// immediate-or-cancel limit orders.
+    NotInBatchAuction,
+}
+
+impl std::fmt::Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderError::BelowMinimumSize => write!(f, "order amount is below minimum size"),
+            OrderError::OrderNotFound => write!(f, "order not found"),
+            OrderError::InsufficientLiquidity => write!(f, "insufficient liquidity"),
+            OrderError::InvalidPrice => write!(f, "invalid price"),
+            OrderError::PriceNotOnTick => write!(f, "price is not a multiple of the tick size"),
+            OrderError::AmountNotOnLot => write!(f, "amount is not a multiple of the lot size"),
+            OrderError::BelowMinimumNotional => write!(f, "order value is below minimum notional"),
+            OrderError::PostOnlyWouldCross => write!(f, "post-only order would cross the book"),
+            OrderError::FillOrKillNotFilled => {
+                write!(f, "fill-or-kill order cannot be filled completely")
+            }
+            OrderError::StopPriceAlreadyReached => {
+                write!(f, "stop price already reached by the last trade")
+            }
+            OrderError::AmendWouldCross => write!(f, "amended order would cross the book"),
+            OrderError::InvalidDisplayAmount => write!(f, "invalid iceberg display amount"),
+            OrderError::LinkedOrder => write!(f, "order is linked one-cancels-other"),
+            OrderError::InvalidOrderGroup => write!(f, "invalid order group"),
+            OrderError::InvalidRangeOrder => write!(f, "invalid range order"),
+            OrderError::RangeOrder => write!(f, "order is a range order"),
+            OrderError::NotInBatchAuction => {
+                write!(f, "order type not supported in a batch auction")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MarketMode;
+    use crate::types::{BlockContext, ETH_TOKEN};
+
+    fn setup() -> (OrderBook, DexConfig) {
+        let eth = ETH_TOKEN;
+        let usdc = Address::repeat_byte(0x01);
+        let pair = Pair::new(eth, usdc);
+        let book = OrderBook::new(pair);
+        let config = DexConfig::default();
+        (book, config)
+    }
+
+    fn test_trader(n: u8) -> Address {
+        Address::repeat_byte(n)
+    }
+
+    fn limit_order(id: u64, trader: Address, side: OrderSide, price: Price, amount: u64) -> Order {
+        Order::new_limit(
+            OrderId(id),
+            trader,
+            side,
+            price,
+            U256::from(amount),
+            BlockContext::default(),
+        )
+    }
+
+    fn market_order(id: u64, trader: Address, side: OrderSide, amount: u64) -> Order {
+        Order::new_market(
+            OrderId(id),
+            trader,
+            side,
+            U256::from(amount),
+            BlockContext::default(),
+        )
+    }
+
+    #[test]
+    fn test_place_limit_order() {
+        let (mut book, config) = setup();
+        let trader = test_trader(1);
+        let price = Price::from_u128(100, 1);
+
+        let result = book
+            .place_order(limit_order(1, trader, OrderSide::Buy, price, 1000), &config)
+            .unwrap();
+
+        assert_eq!(result.fills.len(), 0);
+        assert_eq!(result.remaining_amount, U256::from(1000));
+        assert!(!result.fully_filled);
+
+        let order = book.get_order(OrderId(1)).unwrap();
         assert_eq!(order.remaining_amount, U256::from(1000));
     }
 
@@ -607,26 +2531,18 @@ mod tests {
         let (mut book, config) = setup();
         let buyer = test_trader(1);
         let seller = test_trader(2);
+        let price = Price::from_u128(100, 1);
 
         // Place a sell order first
-        book.place_limit_order(
-            seller,
-            OrderSide::Sell,
-            Price::from_u128(100, 1),
-            U256::from(1000),
+        book.place_order(
+            limit_order(1, seller, OrderSide::Sell, price, 1000),
             &config,
         )
         .unwrap();
 
         // Place a buy order that crosses the spread
-        let (_, result) = book
-            .place_limit_order(
-                buyer,
-                OrderSide::Buy,
-                Price::from_u128(100, 1),
-                U256::from(500),
-                &config,
-            )
+        let result = book
+            .place_order(limit_order(2, buyer, OrderSide::Buy, price, 500), &config)
             .unwrap();
 
         assert_eq!(result.fills.len(), 1);
@@ -641,42 +2557,200 @@ mod tests {
         let taker = test_trader(2);
 
         // Add liquidity
-        book.place_limit_order(
-            maker,
-            OrderSide::Sell,
-            Price::from_u128(100, 1),
-            U256::from(1000),
+        book.place_order(
+            limit_order(1, maker, OrderSide::Sell, Price::from_u128(100, 1), 1000),
             &config,
         )
         .unwrap();
 
         // Market buy
         let result = book
-            .place_market_order(taker, OrderSide::Buy, U256::from(500), &config)
+            .place_order(market_order(2, taker, OrderSide::Buy, 500), &config)
             .unwrap();
 
         assert_eq!(result.fills.len(), 1);
         assert_eq!(result.fills[0].base_amount, U256::from(500));
+
+        // Market orders never rest on the book
+        assert!(book.get_order(OrderId(2)).is_none());
     }
 
     #[test]
-    fn test_cancel_order() {
+    fn test_market_buy_sized_in_quote() {
         let (mut book, config) = setup();
-        let trader = test_trader(1);
+        let maker = test_trader(1);
+        let taker = test_trader(2);
 
-        let (order_id, _) = book
-            .place_limit_order(
-                trader,
-                OrderSide::Buy,
-                Price::from_u128(100, 1),
-                U256::from(1000),
+        book.place_order(
+            limit_order(1, maker, OrderSide::Sell, Price::from_u128(100, 1), 1000),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(2, maker, OrderSide::Sell, Price::from_u128(110, 1), 1000),
+            &config,
+        )
+        .unwrap();
+
+        let budget = U256::from(200_000);
+        let simulation = book.simulate_market_buy(budget, &config).unwrap();
+
+        let order = market_order(3, taker, OrderSide::Buy, 0).with_quote_budget(budget);
+        let result = book.place_order(order, &config).unwrap();
+
+        // The first level costs 100,000 plus a 300 fee; the 99,700 left buys 903
+        // at 110 for 99,330 plus a 297 fee, and the last 73 can't buy another unit
+        assert_eq!(result.fills.len(), 2);
+        assert_eq!(result.fills[0].base_amount, U256::from(1000));
+        assert_eq!(result.fills[1].base_amount, U256::from(903));
+        assert_eq!(result.fills[1].quote_amount, U256::from(99_330));
+        assert_eq!(result.fills[1].taker_fee, U256::from(297));
+        assert_eq!(simulation.amount_out, U256::from(1903));
+        assert!(result.fully_filled);
+
+        // The rest of the second level stays on the book
+        let maker_order = book.get_order(OrderId(2)).unwrap();
+        assert_eq!(maker_order.remaining_amount, U256::from(97));
+        assert!(book.get_order(OrderId(3)).is_none());
+    }
+
+    #[test]
+    fn test_simulate_market_sell_detail() {
+        let (mut book, config) = setup();
+        let maker = test_trader(1);
+
+        for (id, price, amount) in [(1, 100, 1000), (2, 100, 500), (3, 90, 1000)] {
+            book.place_order(
+                limit_order(
+                    id,
+                    maker,
+                    OrderSide::Buy,
+                    Price::from_u128(price, 1),
+                    amount,
+                ),
+                &config,
+            )
+            .unwrap();
+        }
+
+        // Both orders at 100 and half the one at 90, with a 0.30% fee on each
+        let simulation = book
+            .simulate_market_sell(U256::from(2000), &config)
+            .unwrap();
+        assert_eq!(simulation.amount_in, U256::from(2000));
+        assert_eq!(simulation.fee, U256::from(300 + 150 + 135));
+        assert_eq!(simulation.amount_out, U256::from(195_000 - 585));
+        assert_eq!(simulation.avg_price, Price::from_u128(195, 2));
+        assert_eq!(simulation.worst_price, Price::from_u128(90, 1));
+        assert_eq!(simulation.levels_consumed, 2);
+        assert_eq!(simulation.orders_consumed, 3);
+        assert!(!simulation.liquidity_exhausted);
+
+        // Selling more than the bids hold sells what it can
+        let simulation = book
+            .simulate_market_sell(U256::from(3000), &config)
+            .unwrap();
+        assert_eq!(simulation.amount_in, U256::from(2500));
+        assert!(simulation.liquidity_exhausted);
+    }
+
+    #[test]
+    fn test_simulate_exact_out() {
+        let (mut book, config) = setup();
+        let maker = test_trader(1);
+        let taker = test_trader(2);
+
+        for (id, side, price) in [
+            (1, OrderSide::Sell, 100),
+            (2, OrderSide::Sell, 110),
+            (3, OrderSide::Buy, 95),
+            (4, OrderSide::Buy, 90),
+        ] {
+            book.place_order(
+                limit_order(id, maker, side, Price::from_u128(price, 1), 1000),
                 &config,
             )
             .unwrap();
+        }
+
+        // Buying exactly 1,500 costs 100,000 + 55,000 plus 300 + 165 in fees
+        let simulation = book
+            .simulate_market_buy_exact_out(U256::from(1500), &config)
+            .unwrap();
+        assert_eq!(simulation.amount_in, U256::from(155_465));
+
+        // 120,000 after fees takes the whole first bid (94,715 after its fee) and
+        // enough of the second for the 25,285 left
+        let simulation = book
+            .simulate_market_sell_exact_out(U256::from(120_000), &config)
+            .unwrap();
+        assert_eq!(simulation.amount_in, U256::from(1282));
+
+        // Selling that much receives at least the amount asked for
+        let result = book
+            .place_order(market_order(5, taker, OrderSide::Sell, 1282), &config)
+            .unwrap();
+        let received = result
+            .fills
+            .iter()
+            .fold(U256::ZERO, |acc, f| acc + f.quote_amount - f.taker_fee);
+        assert_eq!(received, U256::from(120_019));
+
+        // More than the book holds can't be quoted
+        assert!(book
+            .simulate_market_buy_exact_out(U256::from(2001), &config)
+            .is_none());
+    }
+
+    #[test]
+    fn test_simulation_walks_iceberg_clips() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(3, 1);
+
+        let iceberg = limit_order(1, test_trader(1), OrderSide::Sell, price, 1_000_000)
+            .with_display_amount(U256::from(777));
+        book.place_order(iceberg, &config).unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, price, 500),
+            &config,
+        )
+        .unwrap();
+
+        // The budget is spent one clip at a time, each fill's fee rounded on its own
+        let budget = U256::from(100_000);
+        let simulation = book.simulate_market_buy(budget, &config).unwrap();
+        assert_eq!(book.ask_liquidity(1), vec![(price, U256::from(1277))]);
+
+        let order = market_order(3, test_trader(3), OrderSide::Buy, 0).with_quote_budget(budget);
+        let result = book.place_order(order, &config).unwrap();
+        let bought = result
+            .fills
+            .iter()
+            .fold(U256::ZERO, |acc, f| acc + f.base_amount);
+        let spent = result
+            .fills
+            .iter()
+            .fold(U256::ZERO, |acc, f| acc + f.quote_amount + f.taker_fee);
+        assert_eq!(simulation.amount_out, bought);
+        assert_eq!(simulation.amount_in, spent);
+        assert_eq!(simulation.orders_consumed, result.fills.len());
+        assert_eq!(simulation.levels_consumed, 1);
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let (mut book, config) = setup();
+        let trader = test_trader(1);
+
+        book.place_order(
+            limit_order(1, trader, OrderSide::Buy, Price::from_u128(100, 1), 1000),
+            &config,
+        )
+        .unwrap();
 
-        let cancelled = book.cancel_order(order_id).unwrap();
+        let (cancelled, _) = book.cancel_order(OrderId(1)).unwrap();
         assert_eq!(cancelled.status, crate::order::OrderStatus::Cancelled);
-        assert!(book.get_order(order_id).is_none());
+        assert!(book.get_order(OrderId(1)).is_none());
     }
 
     #[test]
@@ -685,38 +2759,1524 @@ mod tests {
         let maker1 = test_trader(1);
         let maker2 = test_trader(2);
         let taker = test_trader(3);
+        let price = Price::from_u128(100, 1);
 
         // Place two sell orders at same price - first one should match first
-        let (order1, _) = book
-            .place_limit_order(
-                maker1,
-                OrderSide::Sell,
-                Price::from_u128(100, 1),
-                U256::from(500),
-                &config,
-            )
+        book.place_order(limit_order(1, maker1, OrderSide::Sell, price, 500), &config)
             .unwrap();
-
-        let (order2, _) = book
-            .place_limit_order(
-                maker2,
-                OrderSide::Sell,
-                Price::from_u128(100, 1),
-                U256::from(500),
-                &config,
-            )
+        book.place_order(limit_order(2, maker2, OrderSide::Sell, price, 500), &config)
             .unwrap();
 
         // Buy 500 - should match first order only
         let result = book
-            .place_market_order(taker, OrderSide::Buy, U256::from(500), &config)
+            .place_order(market_order(3, taker, OrderSide::Buy, 500), &config)
             .unwrap();
 
         assert_eq!(result.fills.len(), 1);
-        assert_eq!(result.fills[0].maker_order_id, order1);
+        assert_eq!(result.fills[0].maker_order_id, OrderId(1));
 
         // First order should be filled, second should remain
-        assert!(book.get_order(order1).is_none()); // Removed when filled
-        assert!(book.get_order(order2).is_some());
+        assert!(book.get_order(OrderId(1)).is_none()); // Removed when filled
+        assert!(book.get_order(OrderId(2)).is_some());
+    }
+
+    #[test]
+    fn test_equivalent_prices_share_level() {
+        let (mut book, config) = setup();
+
+        // 1/2, 2/4 and a raw 3/6 are the same price
+        book.place_order(
+            limit_order(
+                1,
+                test_trader(1),
+                OrderSide::Buy,
+                Price::from_u128(1, 2),
+                100,
+            ),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(
+                2,
+                test_trader(2),
+                OrderSide::Buy,
+                Price::from_u128(2, 4),
+                200,
+            ),
+            &config,
+        )
+        .unwrap();
+        let raw = Price {
+            numerator: U256::from(3),
+            denominator: U256::from(6),
+        };
+        book.place_order(
+            limit_order(3, test_trader(3), OrderSide::Buy, raw, 300),
+            &config,
+        )
+        .unwrap();
+
+        let bids = book.bid_liquidity(10);
+        assert_eq!(bids.len(), 1);
+        assert_eq!(bids[0].0, Price::from_u128(1, 2));
+        assert_eq!(bids[0].1, U256::from(600));
+        assert_eq!(
+            book.liquidity_at_price(OrderSide::Buy, Price::from_u128(4, 8)),
+            U256::from(600)
+        );
+        assert_eq!(
+            book.liquidity_at_price(OrderSide::Buy, raw),
+            U256::from(600)
+        );
+
+        // Cancelling the middle order leaves the level intact
+        book.cancel_order(OrderId(2)).unwrap();
+        assert_eq!(
+            book.liquidity_at_price(OrderSide::Buy, Price::from_u128(1, 2)),
+            U256::from(400)
+        );
+
+        // A seller at 5/10 matches the whole level in time priority
+        let result = book
+            .place_order(
+                limit_order(
+                    4,
+                    test_trader(4),
+                    OrderSide::Sell,
+                    Price::from_u128(5, 10),
+                    400,
+                ),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.fills.len(), 2);
+        assert_eq!(result.fills[0].maker_order_id, OrderId(1));
+        assert_eq!(result.fills[1].maker_order_id, OrderId(3));
+        assert!(book.bid_liquidity(10).is_empty());
+    }
+
+    #[test]
+    fn test_time_priority_by_chain_position() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        // An order from a later block arrives first, e.g. when replaying out of order
+        let mut later = limit_order(1, test_trader(1), OrderSide::Sell, price, 500);
+        later.placed_at = BlockContext::new(2, 0, 1_002);
+        let mut earlier = limit_order(2, test_trader(2), OrderSide::Sell, price, 500);
+        earlier.placed_at = BlockContext::new(1, 4, 1_000);
+
+        book.place_order(later, &config).unwrap();
+        book.place_order(earlier, &config).unwrap();
+
+        let result = book
+            .place_order(
+                market_order(3, test_trader(3), OrderSide::Buy, 500),
+                &config,
+            )
+            .unwrap();
+
+        // The order placed earlier on chain has priority
+        assert_eq!(result.fills[0].maker_order_id, OrderId(2));
+    }
+
+    #[test]
+    fn test_market_config_constraints() {
+        let (_, config) = setup();
+        let pair = Pair::new(ETH_TOKEN, Address::repeat_byte(0x01));
+        let market = MarketConfig::default()
+            .with_tick_size(Price::from_u128(1, 2))
+            .with_lot_size(U256::from(10))
+            .with_min_notional(U256::from(1000));
+        let mut book = OrderBook::with_market_config(pair, market);
+        let trader = test_trader(1);
+
+        // 3/4 is not a multiple of the 1/2 tick
+        let result = book.place_order(
+            limit_order(1, trader, OrderSide::Buy, Price::from_u128(3, 4), 2000),
+            &config,
+        );
+        assert_eq!(result.unwrap_err(), OrderError::PriceNotOnTick);
+
+        // 2005 is not a multiple of the 10 unit lot
+        let result = book.place_order(
+            limit_order(2, trader, OrderSide::Buy, Price::from_u128(3, 2), 2005),
+            &config,
+        );
+        assert_eq!(result.unwrap_err(), OrderError::AmountNotOnLot);
+
+        // 1.5 * 600 = 900 quote, below the 1000 minimum
+        let result = book.place_order(
+            limit_order(3, trader, OrderSide::Buy, Price::from_u128(3, 2), 600),
+            &config,
+        );
+        assert_eq!(result.unwrap_err(), OrderError::BelowMinimumNotional);
+
+        // 1.5 * 700 = 1050 quote, on tick and lot
+        book.place_order(
+            limit_order(4, trader, OrderSide::Buy, Price::from_u128(3, 2), 700),
+            &config,
+        )
+        .unwrap();
+        assert!(book.get_order(OrderId(4)).is_some());
+    }
+
+    #[test]
+    fn test_market_fee_override() {
+        let (_, config) = setup();
+        let pair = Pair::new(ETH_TOKEN, Address::repeat_byte(0x01));
+        let market = MarketConfig::default().with_fee_bps(100);
+        let mut book = OrderBook::with_market_config(pair, market);
+        let price = Price::from_u128(1, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 10000),
+            &config,
+        )
+        .unwrap();
+        let result = book
+            .place_order(
+                market_order(2, test_trader(2), OrderSide::Buy, 10000),
+                &config,
+            )
+            .unwrap();
+
+        // 1% of 10000 quote instead of the global 0.30%
+        assert_eq!(result.fills[0].taker_fee, U256::from(100));
+    }
+
+    #[test]
+    fn test_maker_rebate() {
+        let config = DexConfig::default().with_maker_fee_bps(-10);
+        let (mut book, _) = setup();
+        let price = Price::from_u128(1, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 10000),
+            &config,
+        )
+        .unwrap();
+        let result = book
+            .place_order(
+                market_order(2, test_trader(2), OrderSide::Buy, 10000),
+                &config,
+            )
+            .unwrap();
+
+        let fill = &result.fills[0];
+        assert_eq!(fill.taker_fee, U256::from(30));
+        assert_eq!(fill.maker_fee, I256::try_from(-10).unwrap());
+        assert_eq!(fill.protocol_fee(), U256::from(20));
+    }
+
+    #[test]
+    fn test_maker_rebate_capped_by_taker_fee() {
+        // A global config change may leave a market's taker override below the rebate
+        let config = DexConfig::default().with_maker_fee_bps(-20);
+        let pair = Pair::new(ETH_TOKEN, Address::repeat_byte(0x01));
+        let mut book = OrderBook::with_market_config(pair, MarketConfig::default().with_fee_bps(5));
+        let price = Price::from_u128(1, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Buy, price, 10000),
+            &config,
+        )
+        .unwrap();
+        let result = book
+            .place_order(
+                market_order(2, test_trader(2), OrderSide::Sell, 10000),
+                &config,
+            )
+            .unwrap();
+
+        let fill = &result.fills[0];
+        assert_eq!(fill.taker_fee, U256::from(5));
+        assert_eq!(fill.maker_fee, I256::try_from(-5).unwrap());
+        assert_eq!(fill.protocol_fee(), U256::ZERO);
+    }
+
+    #[test]
+    fn test_post_only_rejected_if_crossing() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 500),
+            &config,
+        )
+        .unwrap();
+
+        let crossing = limit_order(2, test_trader(2), OrderSide::Buy, price, 500)
+            .with_time_in_force(TimeInForce::PostOnly);
+        assert_eq!(
+            book.place_order(crossing, &config).unwrap_err(),
+            OrderError::PostOnlyWouldCross
+        );
+
+        let passive = limit_order(
+            3,
+            test_trader(2),
+            OrderSide::Buy,
+            Price::from_u128(99, 1),
+            500,
+        )
+        .with_time_in_force(TimeInForce::PostOnly);
+        book.place_order(passive, &config).unwrap();
+        assert!(book.get_order(OrderId(3)).is_some());
+        assert!(book.get_order(OrderId(1)).is_some());
+    }
+
+    #[test]
+    fn test_immediate_or_cancel_discards_remainder() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 300),
+            &config,
+        )
+        .unwrap();
+
+        let order = limit_order(2, test_trader(2), OrderSide::Buy, price, 500)
+            .with_time_in_force(TimeInForce::ImmediateOrCancel);
+        let result = book.place_order(order, &config).unwrap();
+
+        assert_eq!(result.fills.len(), 1);
+        assert_eq!(result.remaining_amount, U256::from(200));
+        assert!(book.get_order(OrderId(2)).is_none());
+        assert!(book.bid_liquidity(10).is_empty());
+    }
+
+    #[test]
+    fn test_fill_or_kill_checked_before_matching() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 300),
+            &config,
+        )
+        .unwrap();
+        // The trader's own order is skipped when matching, so it doesn't count
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, price, 300),
+            &config,
+        )
+        .unwrap();
+
+        let order = limit_order(3, test_trader(2), OrderSide::Buy, price, 500)
+            .with_time_in_force(TimeInForce::FillOrKill);
+        assert_eq!(
+            book.place_order(order, &config).unwrap_err(),
+            OrderError::FillOrKillNotFilled
+        );
+        // Nothing was filled
+        assert_eq!(
+            book.liquidity_at_price(OrderSide::Sell, price),
+            U256::from(600)
+        );
+
+        let order = limit_order(4, test_trader(3), OrderSide::Buy, price, 500)
+            .with_time_in_force(TimeInForce::FillOrKill);
+        let result = book.place_order(order, &config).unwrap();
+        assert!(result.fully_filled);
+    }
+
+    #[test]
+    fn test_stop_market_triggered_by_trade() {
+        let (mut book, config) = setup();
+
+        book.place_order(
+            limit_order(
+                1,
+                test_trader(1),
+                OrderSide::Buy,
+                Price::from_u128(100, 1),
+                500,
+            ),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(
+                2,
+                test_trader(2),
+                OrderSide::Buy,
+                Price::from_u128(90, 1),
+                1000,
+            ),
+            &config,
+        )
+        .unwrap();
+
+        // Parked in the trigger index, not matched
+        let stop = market_order(3, test_trader(3), OrderSide::Sell, 300)
+            .with_stop_price(Price::from_u128(95, 1));
+        let result = book.place_order(stop, &config).unwrap();
+        assert!(result.fills.is_empty());
+        assert!(book.get_order(OrderId(3)).is_some());
+
+        // A trade at 100 doesn't reach the stop price
+        let result = book
+            .place_order(
+                market_order(4, test_trader(4), OrderSide::Sell, 500),
+                &config,
+            )
+            .unwrap();
+        assert!(result.triggered.is_empty());
+
+        // A trade at 90 falls through it, and the stop sells into the same level
+        let result = book
+            .place_order(
+                market_order(5, test_trader(4), OrderSide::Sell, 200),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.triggered.len(), 1);
+        let triggered = &result.triggered[0];
+        assert_eq!(triggered.order.id, OrderId(3));
+        assert_eq!(triggered.trigger_price, Price::from_u128(90, 1));
+        assert_eq!(triggered.order.status, OrderStatus::Filled);
+        assert_eq!(triggered.fills.len(), 1);
+        assert_eq!(triggered.fills[0].maker_order_id, OrderId(2));
+        assert_eq!(triggered.fills[0].base_amount, U256::from(300));
+
+        assert!(book.get_order(OrderId(3)).is_none());
+        assert_eq!(
+            book.liquidity_at_price(OrderSide::Buy, Price::from_u128(90, 1)),
+            U256::from(500)
+        );
+    }
+
+    #[test]
+    fn test_stop_limit_rests_after_trigger() {
+        let (mut book, config) = setup();
+
+        book.place_order(
+            limit_order(
+                1,
+                test_trader(1),
+                OrderSide::Sell,
+                Price::from_u128(100, 1),
+                200,
+            ),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(
+                2,
+                test_trader(1),
+                OrderSide::Sell,
+                Price::from_u128(110, 1),
+                200,
+            ),
+            &config,
+        )
+        .unwrap();
+
+        let stop = limit_order(
+            3,
+            test_trader(2),
+            OrderSide::Buy,
+            Price::from_u128(105, 1),
+            300,
+        )
+        .with_stop_price(Price::from_u128(100, 1));
+        book.place_order(stop, &config).unwrap();
+
+        let result = book
+            .place_order(
+                market_order(4, test_trader(3), OrderSide::Buy, 100),
+                &config,
+            )
+            .unwrap();
+
+        // The stop takes the rest of the 100 level and its remainder rests at 105
+        let triggered = &result.triggered[0];
+        assert_eq!(triggered.fills.len(), 1);
+        assert_eq!(triggered.fills[0].base_amount, U256::from(100));
+        assert_eq!(triggered.order.status, OrderStatus::PartiallyFilled);
+
+        let resting = book.get_order(OrderId(3)).unwrap();
+        assert_eq!(resting.remaining_amount, U256::from(200));
+        assert_eq!(book.best_bid(), Some(Price::from_u128(105, 1)));
+    }
+
+    #[test]
+    fn test_stop_order_placement_and_cancel() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 100),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Buy, price, 100),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(book.last_trade_price(), Some(price));
+
+        // Stops the last trade already reached are rejected
+        let stop = market_order(3, test_trader(3), OrderSide::Buy, 100).with_stop_price(price);
+        assert_eq!(
+            book.place_order(stop, &config).unwrap_err(),
+            OrderError::StopPriceAlreadyReached
+        );
+
+        let stop = market_order(4, test_trader(3), OrderSide::Sell, 100)
+            .with_stop_price(Price::from_u128(95, 1));
+        book.place_order(stop, &config).unwrap();
+
+        let (cancelled, _) = book.cancel_order(OrderId(4)).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert!(book.get_order(OrderId(4)).is_none());
+        assert_eq!(
+            book.cancel_order(OrderId(4)).unwrap_err(),
+            OrderError::OrderNotFound
+        );
+    }
+
+    #[test]
+    fn test_self_trade_cancel_newest_and_oldest() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+        let alice = test_trader(1);
+
+        book.place_order(limit_order(1, alice, OrderSide::Sell, price, 300), &config)
+            .unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, price, 300),
+            &config,
+        )
+        .unwrap();
+
+        // The default cancels the incoming order instead of resting it crossed
+        let result = book
+            .place_order(limit_order(3, alice, OrderSide::Buy, price, 500), &config)
+            .unwrap();
+        assert!(result.fills.is_empty());
+        assert_eq!(result.prevented.len(), 1);
+        assert_eq!(result.prevented[0].maker.id, OrderId(1));
+        assert!(result.prevented[0].maker.is_active());
+        assert!(book.get_order(OrderId(3)).is_none());
+        assert_eq!(book.best_bid(), None);
+
+        // Cancelling the oldest removes the resting order and matches past it
+        let order = limit_order(4, alice, OrderSide::Buy, price, 500)
+            .with_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        let result = book.place_order(order, &config).unwrap();
+        assert_eq!(result.prevented[0].maker.status, OrderStatus::Cancelled);
+        assert_eq!(result.fills.len(), 1);
+        assert_eq!(result.fills[0].maker_order_id, OrderId(2));
+        assert!(book.get_order(OrderId(1)).is_none());
+        assert_eq!(
+            book.get_order(OrderId(4)).unwrap().remaining_amount,
+            U256::from(200)
+        );
+        assert_eq!(book.best_ask(), None);
+    }
+
+    #[test]
+    fn test_self_trade_cancel_both() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+        let alice = test_trader(1);
+        book.set_market_config(
+            MarketConfig::default().with_self_trade_prevention(SelfTradePrevention::CancelBoth),
+        );
+
+        book.place_order(limit_order(1, alice, OrderSide::Sell, price, 300), &config)
+            .unwrap();
+        let result = book
+            .place_order(limit_order(2, alice, OrderSide::Buy, price, 500), &config)
+            .unwrap();
+
+        assert_eq!(result.prevented[0].mode, SelfTradePrevention::CancelBoth);
+        assert_eq!(result.prevented[0].maker.status, OrderStatus::Cancelled);
+        assert!(book.get_order(OrderId(1)).is_none());
+        assert!(book.get_order(OrderId(2)).is_none());
+        assert_eq!(book.spread(), None);
+    }
+
+    #[test]
+    fn test_self_trade_decrement_and_cancel() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+        let alice = test_trader(1);
+        let bob = test_trader(2);
+        let config = config.with_self_trade_prevention(SelfTradePrevention::DecrementAndCancel);
+
+        book.place_order(limit_order(1, alice, OrderSide::Sell, price, 300), &config)
+            .unwrap();
+        book.place_order(limit_order(2, bob, OrderSide::Sell, price, 300), &config)
+            .unwrap();
+
+        // The larger taker shrinks by the resting size and keeps matching
+        let result = book
+            .place_order(limit_order(3, alice, OrderSide::Buy, price, 500), &config)
+            .unwrap();
+        let prevented = &result.prevented[0];
+        assert_eq!(prevented.maker.status, OrderStatus::Cancelled);
+        assert_eq!(prevented.taker_reduced, U256::from(300));
+        assert_eq!(prevented.maker_reduced, U256::ZERO);
+        assert!(result.fully_filled);
+        assert_eq!(result.fills[0].base_amount, U256::from(200));
+
+        // The smaller taker is cancelled and the resting order shrinks
+        let result = book
+            .place_order(limit_order(4, bob, OrderSide::Buy, price, 40), &config)
+            .unwrap();
+        assert_eq!(result.prevented[0].maker_reduced, U256::from(40));
+        assert!(book.get_order(OrderId(4)).is_none());
+        let resting = book.get_order(OrderId(2)).unwrap();
+        assert_eq!(resting.remaining_amount, U256::from(60));
+        assert_eq!(resting.original_amount, U256::from(260));
+    }
+
+    #[test]
+    fn test_amend_size_decrease_keeps_priority() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, price, 500),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, price, 500),
+            &config,
+        )
+        .unwrap();
+
+        let later = BlockContext::new(1, 0, 0);
+        let amended = book
+            .amend_order(OrderId(1), U256::from(300), price, later, &config)
+            .unwrap();
+        assert!(amended.kept_priority);
+        assert_eq!(amended.previous.remaining_amount, U256::from(500));
+        assert_eq!(amended.order.remaining_amount, U256::from(300));
+        assert_eq!(amended.order.placed_at, BlockContext::default());
+
+        // Order 1 is still first in line
+        let result = book
+            .place_order(
+                market_order(3, test_trader(3), OrderSide::Buy, 300),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.fills.len(), 1);
+        assert_eq!(result.fills[0].maker_order_id, OrderId(1));
+    }
+
+    #[test]
+    fn test_amend_price_change_or_increase_loses_priority() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+        let later = BlockContext::new(1, 0, 0);
+
+        for id in 1..=3 {
+            book.place_order(
+                limit_order(id, test_trader(id as u8), OrderSide::Sell, price, 100),
+                &config,
+            )
+            .unwrap();
+        }
+
+        // A size increase moves order 1 behind order 3
+        let amended = book
+            .amend_order(OrderId(1), U256::from(200), price, later, &config)
+            .unwrap();
+        assert!(!amended.kept_priority);
+        assert_eq!(amended.order.placed_at, later);
+
+        // A price change moves order 2 to its new level, even back to the old one
+        book.amend_order(
+            OrderId(2),
+            U256::from(100),
+            Price::from_u128(101, 1),
+            later,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(book.best_ask(), Some(price));
+        book.amend_order(OrderId(2), U256::from(100), price, later, &config)
+            .unwrap();
+
+        let result = book
+            .place_order(
+                market_order(4, test_trader(4), OrderSide::Buy, 400),
+                &config,
+            )
+            .unwrap();
+        let makers: Vec<_> = result.fills.iter().map(|f| f.maker_order_id).collect();
+        assert_eq!(makers, vec![OrderId(3), OrderId(1), OrderId(2)]);
+    }
+
+    #[test]
+    fn test_amend_rejects_invalid_changes() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+        let later = BlockContext::new(1, 0, 0);
+
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Buy, price, 100),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(
+                2,
+                test_trader(2),
+                OrderSide::Sell,
+                Price::from_u128(110, 1),
+                100,
+            ),
+            &config,
+        )
+        .unwrap();
+
+        // Crossing the book needs a new order
+        assert_eq!(
+            book.amend_order(
+                OrderId(1),
+                U256::from(100),
+                Price::from_u128(110, 1),
+                later,
+                &config
+            )
+            .unwrap_err(),
+            OrderError::AmendWouldCross
+        );
+        assert_eq!(
+            book.amend_order(OrderId(1), U256::ZERO, price, later, &config)
+                .unwrap_err(),
+            OrderError::BelowMinimumSize
+        );
+        assert_eq!(
+            book.amend_order(OrderId(9), U256::from(100), price, later, &config)
+                .unwrap_err(),
+            OrderError::OrderNotFound
+        );
+
+        // Rejected amendments leave the order untouched
+        let order = book.get_order(OrderId(1)).unwrap();
+        assert_eq!(order.price, price);
+        assert_eq!(order.remaining_amount, U256::from(100));
+    }
+
+    #[test]
+    fn test_iceberg_shows_clip_and_refreshes_at_back() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        let iceberg = limit_order(1, test_trader(1), OrderSide::Sell, price, 300)
+            .with_display_amount(U256::from(100));
+        book.place_order(iceberg, &config).unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, price, 100),
+            &config,
+        )
+        .unwrap();
+
+        // Only the clip is visible in the depth
+        assert_eq!(book.ask_liquidity(1), vec![(price, U256::from(200))]);
+        assert_eq!(
+            book.liquidity_at_price(OrderSide::Sell, price),
+            U256::from(200)
+        );
+
+        // The consumed clip is refreshed behind order 2, and the taker reaches it again
+        let later = BlockContext::new(1, 0, 0);
+        let mut taker = market_order(3, test_trader(3), OrderSide::Buy, 250);
+        taker.placed_at = later;
+        let result = book.place_order(taker, &config).unwrap();
+        let fills: Vec<_> = result
+            .fills
+            .iter()
+            .map(|f| (f.maker_order_id, f.base_amount))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (OrderId(1), U256::from(100)),
+                (OrderId(2), U256::from(100)),
+                (OrderId(1), U256::from(50)),
+            ]
+        );
+
+        let order = book.get_order(OrderId(1)).unwrap();
+        assert_eq!(order.remaining_amount, U256::from(150));
+        assert_eq!(order.visible_amount, U256::from(50));
+        assert_eq!(order.placed_at, later);
+        assert_eq!(book.ask_liquidity(1), vec![(price, U256::from(50))]);
+
+        // Zero clips are rejected
+        let invalid = limit_order(4, test_trader(1), OrderSide::Sell, price, 300)
+            .with_display_amount(U256::ZERO);
+        assert_eq!(
+            book.place_order(invalid, &config).unwrap_err(),
+            OrderError::InvalidDisplayAmount
+        );
+    }
+
+    #[test]
+    fn test_oco_fill_cancels_linked_order() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+
+        let limit = limit_order(1, alice, OrderSide::Sell, Price::from_u128(110, 1), 100);
+        let stop =
+            market_order(2, alice, OrderSide::Sell, 100).with_stop_price(Price::from_u128(90, 1));
+        book.place_oco_order(limit, stop, &config).unwrap();
+        assert!(book.get_order(OrderId(2)).is_some());
+
+        // Linked orders can't be amended
+        assert_eq!(
+            book.amend_order(
+                OrderId(1),
+                U256::from(50),
+                Price::from_u128(110, 1),
+                BlockContext::default(),
+                &config,
+            )
+            .unwrap_err(),
+            OrderError::LinkedOrder
+        );
+
+        // A partial fill of the limit order cancels the stop order
+        let result = book
+            .place_order(
+                limit_order(
+                    3,
+                    test_trader(2),
+                    OrderSide::Buy,
+                    Price::from_u128(110, 1),
+                    40,
+                ),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.linked_cancels.len(), 1);
+        let linked = &result.linked_cancels[0];
+        assert_eq!(linked.order.id, OrderId(2));
+        assert_eq!(linked.order.status, OrderStatus::Cancelled);
+        assert_eq!(linked.cancelled_by.id, OrderId(1));
+        assert!(linked.pending_stop);
+        assert!(book.get_order(OrderId(2)).is_none());
+
+        // The limit order keeps resting on its own
+        assert_eq!(
+            book.get_order(OrderId(1)).unwrap().remaining_amount,
+            U256::from(60)
+        );
+    }
+
+    #[test]
+    fn test_oco_triggered_stop_cancels_limit_order() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+
+        let limit = limit_order(1, alice, OrderSide::Sell, Price::from_u128(110, 1), 100);
+        let stop =
+            market_order(2, alice, OrderSide::Sell, 100).with_stop_price(Price::from_u128(90, 1));
+        book.place_oco_order(limit, stop, &config).unwrap();
+        book.place_order(
+            limit_order(
+                3,
+                test_trader(2),
+                OrderSide::Buy,
+                Price::from_u128(90, 1),
+                200,
+            ),
+            &config,
+        )
+        .unwrap();
+
+        // A trade at 90 triggers the stop order, whose fill cancels the limit order
+        let result = book
+            .place_order(
+                market_order(4, test_trader(3), OrderSide::Sell, 10),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.triggered.len(), 1);
+        assert_eq!(result.triggered[0].order.status, OrderStatus::Filled);
+        assert_eq!(result.linked_cancels.len(), 1);
+        assert_eq!(result.linked_cancels[0].order.id, OrderId(1));
+        assert_eq!(result.linked_cancels[0].cancelled_by.id, OrderId(2));
+        assert!(!result.linked_cancels[0].pending_stop);
+        assert!(book.get_order(OrderId(1)).is_none());
+        assert_eq!(book.best_ask(), None);
+    }
+
+    #[test]
+    fn test_oco_cancel_and_rejected_leg() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+
+        let limit = limit_order(1, alice, OrderSide::Sell, Price::from_u128(110, 1), 100);
+        let stop =
+            market_order(2, alice, OrderSide::Sell, 100).with_stop_price(Price::from_u128(90, 1));
+        book.place_oco_order(limit, stop, &config).unwrap();
+
+        // Cancelling one leg cancels the other
+        let (cancelled, linked) = book.cancel_order(OrderId(2)).unwrap();
+        assert_eq!(cancelled.id, OrderId(2));
+        let linked = linked.unwrap();
+        assert_eq!(linked.order.id, OrderId(1));
+        assert_eq!(linked.order.status, OrderStatus::Cancelled);
+        assert!(book.get_order(OrderId(1)).is_none());
+
+        // Legs must share trader and side
+        let limit = limit_order(3, alice, OrderSide::Sell, Price::from_u128(110, 1), 100);
+        let stop =
+            market_order(4, alice, OrderSide::Buy, 100).with_stop_price(Price::from_u128(120, 1));
+        assert_eq!(
+            book.place_oco_order(limit, stop, &config).unwrap_err(),
+            OrderError::InvalidOrderGroup
+        );
+
+        // A rejected stop order takes the limit order off the book again
+        book.place_order(
+            limit_order(
+                5,
+                test_trader(2),
+                OrderSide::Buy,
+                Price::from_u128(95, 1),
+                10,
+            ),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            market_order(6, test_trader(3), OrderSide::Sell, 10),
+            &config,
+        )
+        .unwrap();
+        let limit = limit_order(7, alice, OrderSide::Sell, Price::from_u128(110, 1), 100);
+        let stop =
+            market_order(8, alice, OrderSide::Sell, 100).with_stop_price(Price::from_u128(100, 1));
+        assert_eq!(
+            book.place_oco_order(limit, stop, &config).unwrap_err(),
+            OrderError::StopPriceAlreadyReached
+        );
+        assert!(book.get_order(OrderId(7)).is_none());
+        assert_eq!(book.best_ask(), None);
+    }
+
+    #[test]
+    fn test_bracket_places_exits_once_entry_filled() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+        let exits = BracketExits {
+            take_profit_id: OrderId(2),
+            take_profit: Price::from_u128(120, 1),
+            stop_loss_id: OrderId(3),
+            stop_loss: Price::from_u128(90, 1),
+            stop_loss_limit: None,
+        };
+
+        let entry = limit_order(1, alice, OrderSide::Buy, Price::from_u128(100, 1), 100);
+        let result = book.place_bracket_order(entry, exits, &config).unwrap();
+        assert!(result.brackets.is_empty());
+
+        // A partial fill doesn't place the exits yet
+        let result = book
+            .place_order(
+                market_order(4, test_trader(2), OrderSide::Sell, 60),
+                &config,
+            )
+            .unwrap();
+        assert!(result.brackets.is_empty());
+        assert!(book.get_order(OrderId(2)).is_none());
+
+        // Filling the entry does, sized to the entry on the opposite side
+        let result = book
+            .place_order(
+                market_order(5, test_trader(2), OrderSide::Sell, 40),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.brackets.len(), 1);
+        let bracket = &result.brackets[0];
+        assert_eq!(bracket.entry_id, OrderId(1));
+        assert!(bracket.take_profit_fills.is_empty());
+        let take_profit = book.get_order(OrderId(2)).unwrap();
+        assert_eq!(take_profit.side, OrderSide::Sell);
+        assert_eq!(take_profit.remaining_amount, U256::from(100));
+        let stop_loss = book.get_order(OrderId(3)).unwrap();
+        assert_eq!(stop_loss.stop_price, Some(Price::from_u128(90, 1)));
+        assert_eq!(book.best_ask(), Some(Price::from_u128(120, 1)));
+
+        // Taking profit cancels the stop-loss
+        let result = book
+            .place_order(market_order(6, test_trader(3), OrderSide::Buy, 30), &config)
+            .unwrap();
+        assert_eq!(result.linked_cancels.len(), 1);
+        assert_eq!(result.linked_cancels[0].order.id, OrderId(3));
+        assert!(book.get_order(OrderId(3)).is_none());
+    }
+
+    #[test]
+    fn test_bracket_exits_trade_when_placed() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+
+        // The entry's own fill at 95 already reaches the stop-loss, which is
+        // triggered as soon as it is placed and cancels the take-profit
+        book.place_order(
+            limit_order(
+                1,
+                test_trader(2),
+                OrderSide::Sell,
+                Price::from_u128(95, 1),
+                100,
+            ),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(
+                2,
+                test_trader(3),
+                OrderSide::Buy,
+                Price::from_u128(90, 1),
+                500,
+            ),
+            &config,
+        )
+        .unwrap();
+        let exits = BracketExits {
+            take_profit_id: OrderId(4),
+            take_profit: Price::from_u128(105, 1),
+            stop_loss_id: OrderId(5),
+            stop_loss: Price::from_u128(97, 1),
+            stop_loss_limit: Some(Price::from_u128(90, 1)),
+        };
+        let entry = limit_order(3, alice, OrderSide::Buy, Price::from_u128(100, 1), 100)
+            .with_time_in_force(TimeInForce::ImmediateOrCancel);
+        let result = book.place_bracket_order(entry, exits, &config).unwrap();
+
+        assert_eq!(result.fills.len(), 1);
+        assert_eq!(result.brackets.len(), 1);
+        assert_eq!(result.brackets[0].take_profit.status, OrderStatus::Open);
+        assert_eq!(result.triggered.len(), 1);
+        assert_eq!(result.triggered[0].order.id, OrderId(5));
+        assert_eq!(result.triggered[0].fills[0].maker_order_id, OrderId(2));
+        assert_eq!(result.linked_cancels.len(), 1);
+        assert_eq!(result.linked_cancels[0].order.id, OrderId(4));
+        assert!(book.get_order(OrderId(4)).is_none());
+
+        // Exits on the wrong side of the entry price are rejected
+        let exits = BracketExits {
+            take_profit_id: OrderId(7),
+            take_profit: Price::from_u128(95, 1),
+            stop_loss_id: OrderId(8),
+            stop_loss: Price::from_u128(90, 1),
+            stop_loss_limit: None,
+        };
+        let entry = limit_order(6, alice, OrderSide::Buy, Price::from_u128(100, 1), 100);
+        assert_eq!(
+            book.place_bracket_order(entry, exits, &config).unwrap_err(),
+            OrderError::InvalidOrderGroup
+        );
+    }
+
+    #[test]
+    fn test_cancelled_bracket_entry_drops_exits() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+        let exits = BracketExits {
+            take_profit_id: OrderId(2),
+            take_profit: Price::from_u128(120, 1),
+            stop_loss_id: OrderId(3),
+            stop_loss: Price::from_u128(90, 1),
+            stop_loss_limit: None,
+        };
+
+        let entry = limit_order(1, alice, OrderSide::Buy, Price::from_u128(100, 1), 100);
+        book.place_bracket_order(entry, exits, &config).unwrap();
+        book.place_order(
+            market_order(4, test_trader(2), OrderSide::Sell, 60),
+            &config,
+        )
+        .unwrap();
+
+        let (cancelled, linked) = book.cancel_order(OrderId(1)).unwrap();
+        assert_eq!(cancelled.filled_amount(), U256::from(60));
+        assert!(linked.is_none());
+
+        // Nothing is placed later on
+        let result = book
+            .place_order(
+                limit_order(
+                    5,
+                    test_trader(3),
+                    OrderSide::Buy,
+                    Price::from_u128(100, 1),
+                    10,
+                ),
+                &config,
+            )
+            .unwrap();
+        assert!(result.brackets.is_empty());
+        assert!(book.get_order(OrderId(2)).is_none());
+        assert!(book.get_order(OrderId(3)).is_none());
+    }
+
+    fn batch_setup() -> (OrderBook, DexConfig) {
+        let (mut book, config) = setup();
+        book.set_market_config(MarketConfig::default().with_mode(MarketMode::BatchAuction));
+        (book, config)
+    }
+
+    #[test]
+    fn test_iceberg_refreshed_repeatedly_in_one_match() {
+        let (mut book, config) = setup();
+        let price = Price::from_u128(100, 1);
+
+        let iceberg = limit_order(1, test_trader(1), OrderSide::Sell, price, 300)
+            .with_display_amount(U256::from(100));
+        book.place_order(iceberg, &config).unwrap();
+
+        // The taker goes through two clips and part of a third
+        let result = book
+            .place_order(
+                market_order(2, test_trader(2), OrderSide::Buy, 250),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.fills.len(), 3);
+        assert!(result.fully_filled);
+
+        // The iceberg is still on the book once, showing what's left of its clip
+        assert_eq!(book.ask_liquidity(1), vec![(price, U256::from(50))]);
+        assert_eq!(book.asks.values().flatten().count(), 1);
+    }
+
+    #[test]
+    fn test_batch_auction_clears_at_uniform_price() {
+        let (mut book, config) = batch_setup();
+        let orders = [
+            (1, OrderSide::Buy, 105, 10),
+            (2, OrderSide::Buy, 100, 10),
+            (3, OrderSide::Sell, 95, 5),
+            (4, OrderSide::Sell, 100, 10),
+        ];
+        for (id, side, price, amount) in orders {
+            let result = book
+                .place_order(
+                    limit_order(
+                        id,
+                        test_trader(id as u8),
+                        side,
+                        Price::from_u128(price, 1),
+                        amount,
+                    ),
+                    &config,
+                )
+                .unwrap();
+            assert!(result.fills.is_empty());
+        }
+
+        // Nothing trades until the auction clears
+        assert!(book.has_pending_batch());
+        assert!(!book.can_switch_mode());
+
+        // 100 matches 15: all of the sells against the first buy and half the second
+        let clearing = book.clear_batch(&config, BlockContext::default());
+        assert_eq!(clearing.price, Some(Price::from_u128(100, 1)));
+        assert_eq!(clearing.volume, U256::from(15));
+        let pairs: Vec<_> = clearing
+            .fills
+            .iter()
+            .map(|f| {
+                (
+                    f.fill.maker_order_id.0,
+                    f.sell_order_id.0,
+                    f.fill.base_amount,
+                )
+            })
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (1, 3, U256::from(5)),
+                (1, 4, U256::from(5)),
+                (2, 4, U256::from(5)),
+            ]
+        );
+        assert!(clearing
+            .fills
+            .iter()
+            .all(|f| f.fill.price == Price::from_u128(100, 1)
+                && f.fill.quote_amount == U256::from(500)));
+        assert_eq!(clearing.fills[0].buy_price, Price::from_u128(105, 1));
+
+        assert_eq!(
+            book.get_order(OrderId(2)).unwrap().remaining_amount,
+            U256::from(5)
+        );
+        assert!(book.get_order(OrderId(1)).is_none());
+        assert!(book.best_ask().is_none());
+        assert_eq!(book.last_trade_price(), Some(Price::from_u128(100, 1)));
+        assert_eq!(book.stats().total_volume, U256::from(15));
+        assert!(book.can_switch_mode());
+    }
+
+    #[test]
+    fn test_batch_auction_refreshes_iceberg_at_back() {
+        let (mut book, config) = batch_setup();
+        let price = Price::from_u128(100, 1);
+
+        let iceberg = limit_order(1, test_trader(1), OrderSide::Sell, price, 300)
+            .with_display_amount(U256::from(100));
+        book.place_order(iceberg, &config).unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, price, 100),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(3, test_trader(3), OrderSide::Buy, price, 120),
+            &config,
+        )
+        .unwrap();
+
+        // The auction consumes the iceberg's clip, which is refreshed behind order 2
+        let cleared_at = BlockContext::new(2, 0, 1_002);
+        let clearing = book.clear_batch(&config, cleared_at);
+        assert_eq!(clearing.fills.len(), 1);
+        assert_eq!(clearing.fills[0].sell_order_id, OrderId(1));
+        let order = book.get_order(OrderId(1)).unwrap();
+        assert_eq!(order.remaining_amount, U256::from(180));
+        assert_eq!(order.visible_amount, U256::from(100));
+        assert_eq!(order.placed_at, cleared_at);
+
+        // So the next auction fills order 2 first
+        book.place_order(
+            limit_order(4, test_trader(3), OrderSide::Buy, price, 100),
+            &config,
+        )
+        .unwrap();
+        let clearing = book.clear_batch(&config, BlockContext::new(3, 0, 1_004));
+        let sells: Vec<_> = clearing.fills.iter().map(|f| f.sell_order_id).collect();
+        assert_eq!(sells, vec![OrderId(2)]);
+    }
+
+    #[test]
+    fn test_batch_auction_order_types() {
+        let (mut book, config) = batch_setup();
+        let price = Price::from_u128(100, 1);
+
+        let rejected = [
+            market_order(1, test_trader(1), OrderSide::Buy, 10),
+            limit_order(2, test_trader(1), OrderSide::Buy, price, 10)
+                .with_time_in_force(TimeInForce::PostOnly),
+            limit_order(3, test_trader(1), OrderSide::Buy, price, 10)
+                .with_time_in_force(TimeInForce::FillOrKill),
+            limit_order(4, test_trader(1), OrderSide::Buy, price, 10)
+                .with_stop_price(Price::from_u128(110, 1)),
+        ];
+        for order in rejected {
+            assert_eq!(
+                book.place_order(order, &config).unwrap_err(),
+                OrderError::NotInBatchAuction
+            );
+        }
+        assert!(book.simulate_market_sell(U256::from(10), &config).is_none());
+
+        // An immediate-or-cancel order waits for the auction, then drops its remainder
+        book.place_order(
+            limit_order(5, test_trader(1), OrderSide::Buy, price, 10)
+                .with_time_in_force(TimeInForce::ImmediateOrCancel),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(6, test_trader(2), OrderSide::Sell, price, 4),
+            &config,
+        )
+        .unwrap();
+        assert!(book.has_pending_batch());
+
+        let clearing = book.clear_batch(&config, BlockContext::default());
+        assert_eq!(clearing.volume, U256::from(4));
+        assert_eq!(clearing.cancelled.len(), 1);
+        assert_eq!(clearing.cancelled[0].id, OrderId(5));
+        assert_eq!(clearing.cancelled[0].remaining_amount, U256::from(6));
+        assert!(book.get_order(OrderId(5)).is_none());
+        assert!(!book.has_pending_batch());
+
+        // An uncrossed book with nothing to cancel clears nothing
+        assert!(book
+            .clear_batch(&config, BlockContext::default())
+            .is_empty());
+    }
+
+    /// A book whose pool holds 1,000,000 base at a price of 2000.
+    fn pool_setup() -> (OrderBook, DexConfig) {
+        let (mut book, config) = setup();
+        book.add_liquidity(
+            test_trader(9),
+            U256::from(1_000_000),
+            U256::from(2_000_000_000u64),
+        )
+        .unwrap();
+        (book, config)
+    }
+
+    #[test]
+    fn test_pool_interleaves_with_book() {
+        let (mut book, config) = pool_setup();
+        let (near, far) = (Price::from_u128(2010, 1), Price::from_u128(2100, 1));
+        book.place_order(
+            limit_order(1, test_trader(1), OrderSide::Sell, near, 1_000),
+            &config,
+        )
+        .unwrap();
+        book.place_order(
+            limit_order(2, test_trader(2), OrderSide::Sell, far, 1_000),
+            &config,
+        )
+        .unwrap();
+
+        let simulation = book
+            .simulate_market_buy_exact_out(U256::from(60_000), &config)
+            .unwrap();
+        let result = book
+            .place_order(
+                market_order(3, test_trader(3), OrderSide::Buy, 60_000),
+                &config,
+            )
+            .unwrap();
+        assert!(result.fully_filled);
+
+        // The pool fills ahead of each level until its price reaches the
+        // level's, then takes the rest
+        let sources: Vec<bool> = result.fills.iter().map(|f| f.from_pool).collect();
+        assert_eq!(sources, [true, false, true, false, true]);
+        assert!(result.fills[0].price <= near);
+        assert!(result.fills[2].price > near && result.fills[2].price <= far);
+        assert!(result.fills[4].price > far);
+        for fill in result.fills.iter().filter(|f| f.from_pool) {
+            assert_eq!(fill.maker_order_id, OrderId(0));
+            assert_eq!(fill.maker_fee, I256::ZERO);
+        }
+
+        // The pool paid out what the fills took and kept what they paid
+        let pool_fills = result.fills.iter().filter(|f| f.from_pool);
+        let (base, quote) = pool_fills.fold((U256::ZERO, U256::ZERO), |(b, q), f| {
+            (b + f.base_amount, q + f.quote_amount)
+        });
+        assert_eq!(base, U256::from(58_000));
+        assert_eq!(
+            book.pool().reserves(),
+            Reserves {
+                base: U256::from(942_000),
+                quote: U256::from(2_000_000_000u64) + quote,
+            }
+        );
+
+        // The quote promised exactly what the order did
+        let (paid, fees) = result
+            .fills
+            .iter()
+            .fold((U256::ZERO, U256::ZERO), |(p, f), fill| {
+                (p + fill.quote_amount, f + fill.taker_fee)
+            });
+        assert_eq!(simulation.amount_in, paid + fees);
+        assert_eq!(simulation.fee, fees);
+        assert_eq!(simulation.orders_consumed, 5);
+    }
+
+    #[test]
+    fn test_pool_with_limit_orders() {
+        let (mut book, config) = pool_setup();
+        let price = Price::from_u128(2050, 1);
+
+        // A limit buy takes from the pool up to its price and rests the remainder
+        let result = book
+            .place_order(
+                limit_order(1, test_trader(1), OrderSide::Buy, price, 100_000),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.fills.len(), 1);
+        assert!(result.fills[0].price <= price);
+        assert_eq!(book.best_bid(), Some(price));
+        assert!(book.best_ask().is_none());
+        let pool_ask = book.best_ask_with_pool().unwrap();
+        assert!(pool_ask <= price && pool_ask > Price::from_u128(2049, 1));
+
+        // Post-only orders can't cross the pool either
+        let post_only = limit_order(
+            2,
+            test_trader(2),
+            OrderSide::Buy,
+            Price::from_u128(2060, 1),
+            10,
+        )
+        .with_time_in_force(TimeInForce::PostOnly);
+        assert_eq!(
+            book.place_order(post_only, &config).unwrap_err(),
+            OrderError::PostOnlyWouldCross
+        );
+
+        // Fill-or-kill counts what the pool can fill
+        let fill_or_kill = limit_order(
+            3,
+            test_trader(2),
+            OrderSide::Sell,
+            Price::from_u128(1500, 1),
+            200_000,
+        )
+        .with_time_in_force(TimeInForce::FillOrKill);
+        let result = book.place_order(fill_or_kill, &config).unwrap();
+        assert!(result.fully_filled);
+        assert!(!result.fills[0].from_pool);
+        assert!(result.fills[1..].iter().all(|f| f.from_pool));
+
+        // The pool holds out of batch auctions, so a market with liquidity
+        // can't switch to them
+        assert!(!book.can_switch_mode());
+    }
+
+    fn range_order(id: u64, trader: Address, side: OrderSide, price: u128, flip: u128) -> Order {
+        limit_order(id, trader, side, Price::from_u128(price, 1), 100)
+            .with_time_in_force(TimeInForce::PostOnly)
+            .with_flip_price(Price::from_u128(flip, 1))
+    }
+
+    #[test]
+    fn test_range_order_flips_when_filled() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+        book.place_order(range_order(1, alice, OrderSide::Sell, 101, 99), &config)
+            .unwrap();
+
+        // A partial fill holds on to the proceeds without flipping
+        let result = book
+            .place_order(market_order(2, test_trader(2), OrderSide::Buy, 40), &config)
+            .unwrap();
+        assert!(result.flipped.is_empty());
+        let order = book.get_order(OrderId(1)).unwrap();
+        assert_eq!(order.range_proceeds, U256::from(4_040));
+
+        // Once filled, the 10100 quote received buys 102 at 99, with 2 left over
+        let result = book
+            .place_order(market_order(3, test_trader(2), OrderSide::Buy, 60), &config)
+            .unwrap();
+        assert_eq!(result.flipped.len(), 1);
+        let flipped = &result.flipped[0];
+        assert_eq!(flipped.filled.range_proceeds, U256::from(10_100));
+        assert_eq!(flipped.refund, U256::from(2));
+        let order = book.get_order(OrderId(1)).unwrap();
+        assert_eq!(order.side, OrderSide::Buy);
+        assert_eq!(order.price, Price::from_u128(99, 1));
+        assert_eq!(order.remaining_amount, U256::from(102));
+        assert_eq!(book.best_bid(), Some(Price::from_u128(99, 1)));
+        assert_eq!(book.best_ask(), None);
+
+        // Selling into it flips it back with all the base it bought
+        let result = book
+            .place_order(
+                market_order(4, test_trader(3), OrderSide::Sell, 102),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.flipped.len(), 1);
+        assert_eq!(result.flipped[0].refund, U256::ZERO);
+        let order = book.get_order(OrderId(1)).unwrap();
+        assert_eq!(order.side, OrderSide::Sell);
+        assert_eq!(order.price, Price::from_u128(101, 1));
+        assert_eq!(order.remaining_amount, U256::from(102));
+        assert_eq!(book.best_ask(), Some(Price::from_u128(101, 1)));
+    }
+
+    #[test]
+    fn test_range_order_proceeds_net_of_maker_fee() {
+        let (mut book, _) = setup();
+        let config = DexConfig::default().with_maker_fee_bps(10);
+        book.place_order(
+            range_order(1, test_trader(1), OrderSide::Sell, 101, 99),
+            &config,
+        )
+        .unwrap();
+
+        // 10100 less a 10 maker fee buys 101 at 99, leaving 91
+        let result = book
+            .place_order(
+                market_order(2, test_trader(2), OrderSide::Buy, 100),
+                &config,
+            )
+            .unwrap();
+        let flipped = &result.flipped[0];
+        assert_eq!(flipped.refund, U256::from(91));
+        assert_eq!(
+            flipped.reposted.as_ref().unwrap().remaining_amount,
+            U256::from(101)
+        );
+    }
+
+    #[test]
+    fn test_range_order_ends_when_too_small_to_flip() {
+        let (mut book, config) = setup();
+        book.set_market_config(MarketConfig::default().with_min_notional(U256::from(10_099)));
+        book.place_order(
+            range_order(1, test_trader(1), OrderSide::Sell, 101, 99),
+            &config,
+        )
+        .unwrap();
+
+        // 102 at 99 is worth less than the minimum notional, so the order is done
+        let result = book
+            .place_order(
+                market_order(2, test_trader(2), OrderSide::Buy, 100),
+                &config,
+            )
+            .unwrap();
+        let flipped = &result.flipped[0];
+        assert!(flipped.reposted.is_none());
+        assert_eq!(flipped.refund, U256::from(10_100));
+        assert!(book.get_order(OrderId(1)).is_none());
+    }
+
+    #[test]
+    fn test_invalid_range_orders() {
+        let (mut book, config) = setup();
+        let alice = test_trader(1);
+
+        // Range orders only trade as makers, flipping across the spread
+        let not_post_only = limit_order(1, alice, OrderSide::Sell, Price::from_u128(101, 1), 100)
+            .with_flip_price(Price::from_u128(99, 1));
+        let wrong_side = range_order(2, alice, OrderSide::Buy, 99, 98);
+        for order in [not_post_only, wrong_side] {
+            assert_eq!(
+                book.place_order(order, &config).unwrap_err(),
+                OrderError::InvalidRangeOrder
+            );
+        }
+
+        book.place_order(range_order(3, alice, OrderSide::Buy, 99, 101), &config)
+            .unwrap();
+        assert_eq!(
+            book.amend_order(
+                OrderId(3),
+                U256::from(50),
+                Price::from_u128(99, 1),
+                BlockContext::default(),
+                &config,
+            )
+            .unwrap_err(),
+            OrderError::RangeOrder
+        );
+        assert!(!book.can_switch_mode());
     }
 }
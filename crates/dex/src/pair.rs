@@ -24,7 +24,7 @@ impl PairId {
         let mut data = [0u8; 40];
         data[..20].copy_from_slice(first.as_slice());
         data[20..].copy_from_slice(second.as_slice());
-        let hash = keccak256(&data);
+        let hash = keccak256(data);
 
         Self(hash.0)
     }
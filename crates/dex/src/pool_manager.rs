@@ -1,13 +1,30 @@
 //! Pool manager for managing multiple orderbooks.
 
-use crate::config::DexConfig;
-use crate::order::{OrderId, OrderSide};
-use crate::orderbook::{OrderBook, OrderError, TradeResult};
+use crate::amm::{LiquidityChange, LiquidityError, LiquidityPool};
+use crate::config::{DexConfig, MarketConfig};
+use crate::history::{Candle, CandleInterval, Trade, TradeHistory};
+use crate::order::{Order, OrderId, OrderSide, SelfTradePrevention, TimeInForce};
+use crate::orderbook::{
+    AmendResult, BatchClearing, BracketExits, Fill, LinkedCancel, MarketSimulation, OrderBook,
+    OrderError, PreventedTrade, TradeResult,
+};
 use crate::pair::{Pair, PairId, PairStats};
-use crate::router::{Quote, Route, RouteHop, Router};
-use crate::types::{Address, Amount, Price, TokenId, U256};
+use crate::router::{Quote, Route, RouteHop, RouteSplit, Router};
+use crate::types::{Address, Amount, BlockContext, Price, TokenId, U256};
+use alloy::primitives::U512;
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
 
+/// Number of equal parts a split swap's input is allocated in.
+const SPLIT_STEPS: u64 = 20;
+
+/// Fixed-point scale of the rate factors price impact is worked out from.
+const WAD: u128 = 1_000_000_000_000_000_000;
+
+/// A hop of an executed swap: its trade, the input it spent and the output it
+/// received.
+type ExecutedHop = (TradeResult, Amount, Amount);
+
 /// The main DEX pool manager.
 /// Manages all trading pairs and provides routing for trades.
 #[derive(Debug)]
@@ -20,6 +37,21 @@ pub struct PoolManager {
     token_pairs: HashMap<TokenId, HashSet<PairId>>,
     /// Router for finding multi-hop paths.
     router: Router,
+    /// Next order ID. Order IDs are issued globally so they are unique across all pairs.
+    next_order_id: u64,
+    /// Reverse index from resting order ID to the pair it rests on.
+    order_index: HashMap<OrderId, PairId>,
+    /// Chain position of the transaction currently being executed.
+    /// New orders are stamped with it instead of wall-clock time.
+    block: BlockContext,
+    /// Protocol fees collected per token, net of maker rebates.
+    fee_vault: HashMap<TokenId, Amount>,
+    /// Trading balances per account and token, not counting what open orders lock.
+    balances: HashMap<(Address, TokenId), Amount>,
+    /// Groups of linked orders by the ID of their first order.
+    order_groups: HashMap<OrderId, OrderGroup>,
+    /// Reverse index from a grouped order ID to the ID of its group.
+    group_index: HashMap<OrderId, OrderId>,
 }
 
 impl PoolManager {
@@ -35,6 +67,13 @@ impl PoolManager {
             orderbooks: HashMap::new(),
             token_pairs: HashMap::new(),
             router: Router::new(),
+            next_order_id: 1,
+            order_index: HashMap::new(),
+            block: BlockContext::default(),
+            fee_vault: HashMap::new(),
+            balances: HashMap::new(),
+            order_groups: HashMap::new(),
+            group_index: HashMap::new(),
         }
     }
 
@@ -48,59 +87,204 @@ impl PoolManager {
         self.config = config;
     }
 
+    /// Get the chain position new orders are stamped with.
+    pub fn block_context(&self) -> BlockContext {
+        self.block
+    }
+
+    /// Set the chain position of the transaction about to be executed.
+    /// Must be called before each transaction so orders are stamped deterministically.
+    pub fn set_block_context(&mut self, block: BlockContext) {
+        self.block = block;
+    }
+
     /// Create a new trading pair.
     /// Returns the pair if created, or an error if it already exists.
     pub fn create_pair(&mut self, base: TokenId, quote: TokenId) -> Result<Pair, PoolError> {
-        println!("=== PoolManager::create_pair called ===");
-        println!("Input: base={:?}, quote={:?}", base, quote);
+        self.create_pair_with_config(base, quote, MarketConfig::default())
+    }
 
+    /// Create a new trading pair with custom market parameters.
+    /// Creating a pair that already exists is a no-op when the parameters match
+    /// and an error when they differ.
+    pub fn create_pair_with_config(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        market: MarketConfig,
+    ) -> Result<Pair, PoolError> {
         if base == quote {
-            println!("ERROR: base == quote, returning InvalidPair");
             return Err(PoolError::InvalidPair);
         }
 
+        if !market.is_valid(&self.config) {
+            return Err(PoolError::InvalidMarketConfig);
+        }
+
         let pair = Pair::new(base, quote);
         let pair_id = pair.id();
 
-        println!(
-            "Created Pair object: base={:?}, quote={:?}",
-            pair.base, pair.quote
-        );
-        println!("Pair ID: {:?}", pair_id);
-        println!("Current orderbooks count: {}", self.orderbooks.len());
-        println!("Checking if pair_id exists in orderbooks...");
-
-        if self.orderbooks.contains_key(&pair_id) {
-            println!("Pair already exists, returning existing pair (idempotent)");
+        if let Some(orderbook) = self.orderbooks.get(&pair_id) {
+            if *orderbook.market_config() != market {
+                return Err(PoolError::PairAlreadyExists {
+                    token0: base,
+                    token1: quote,
+                    pair_id,
+                });
+            }
+            // Re-creating a pair with the same parameters is a no-op
             return Ok(pair);
         }
 
-        println!("Pair does not exist, creating new orderbook");
-
         // Create the orderbook
-        self.orderbooks.insert(pair_id, OrderBook::new(pair));
+        self.orderbooks
+            .insert(pair_id, OrderBook::with_market_config(pair, market));
 
         // Update token index
-        println!("Updating token_pairs index");
-        self.token_pairs
-            .entry(base)
-            .or_insert_with(HashSet::new)
-            .insert(pair_id);
-        self.token_pairs
-            .entry(quote)
-            .or_insert_with(HashSet::new)
-            .insert(pair_id);
+        self.token_pairs.entry(base).or_default().insert(pair_id);
+        self.token_pairs.entry(quote).or_default().insert(pair_id);
 
         // Update router
-        println!("Updating router");
         self.router.add_pair(pair);
 
-        println!("Pair created successfully!");
-        println!("Final orderbooks count: {}", self.orderbooks.len());
-
         Ok(pair)
     }
 
+    /// Get the market parameters of a pair.
+    pub fn market_config(&self, base: TokenId, quote: TokenId) -> Option<&MarketConfig> {
+        let pair_id = PairId::from_tokens(base, quote);
+        self.orderbooks.get(&pair_id).map(|ob| ob.market_config())
+    }
+
+    /// Update the market parameters of a pair.
+    /// Orders already resting on the book are not revalidated. The market mode
+    /// only changes while no auction waits to clear and no stop or linked
+    /// order is pending.
+    pub fn set_market_config(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        market: MarketConfig,
+    ) -> Result<(), PoolError> {
+        if !market.is_valid(&self.config) {
+            return Err(PoolError::InvalidMarketConfig);
+        }
+        let pair_id = PairId::from_tokens(base, quote);
+        let orderbook = self
+            .orderbooks
+            .get_mut(&pair_id)
+            .ok_or(PoolError::PairNotFound {
+                token0: base,
+                token1: quote,
+                pair_id,
+            })?;
+        if market.mode != orderbook.market_config().mode && !orderbook.can_switch_mode() {
+            return Err(PoolError::MarketModeLocked);
+        }
+        orderbook.set_market_config(market);
+        Ok(())
+    }
+
+    /// Clear the batch auctions of all batch auction markets at the end of a
+    /// block. Markets clear in pair ID order, and those with nothing filled or
+    /// cancelled are left out.
+    ///
+    /// Collects the protocol's share of the fills' fees and forgets the orders
+    /// that were filled or cancelled.
+    pub fn clear_batch_auctions(&mut self) -> Vec<(Pair, BatchClearing)> {
+        let mut pair_ids: Vec<PairId> = self
+            .orderbooks
+            .iter()
+            .filter(|(_, orderbook)| orderbook.market_config().is_batch_auction())
+            .map(|(pair_id, _)| *pair_id)
+            .collect();
+        pair_ids.sort_by_key(|pair_id| pair_id.0);
+
+        let mut clearings = Vec::new();
+        for pair_id in pair_ids {
+            let Some(orderbook) = self.orderbooks.get_mut(&pair_id) else {
+                continue;
+            };
+            let clearing = orderbook.clear_batch(&self.config, self.block);
+            if clearing.is_empty() {
+                continue;
+            }
+
+            // The sell order of an auction fill is settled as the taker
+            let fills: Vec<Fill> = clearing.fills.iter().map(|f| f.fill.clone()).collect();
+            Self::accrue_fees(&mut self.fee_vault, orderbook.pair, OrderSide::Sell, &fills);
+
+            let touched = clearing
+                .fills
+                .iter()
+                .flat_map(|f| [f.fill.maker_order_id, f.sell_order_id])
+                .chain(clearing.cancelled.iter().map(|order| order.id));
+            for order_id in touched {
+                if orderbook.get_order(order_id).is_none() {
+                    self.order_index.remove(&order_id);
+                }
+            }
+            clearings.push((orderbook.pair, clearing));
+        }
+        clearings
+    }
+
+    /// Deposit liquidity into a pair's pool.
+    /// Returns the shares minted to the provider and the tokens the pool took,
+    /// which may be less than offered of one of them. The caller is responsible
+    /// for collecting them.
+    pub fn add_liquidity(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        provider: Address,
+        base_amount: Amount,
+        quote_amount: Amount,
+    ) -> Result<LiquidityChange, PoolError> {
+        self.orderbook_for_liquidity(base, quote)?
+            .add_liquidity(provider, base_amount, quote_amount)
+            .map_err(PoolError::LiquidityError)
+    }
+
+    /// Withdraw liquidity from a pair's pool by burning a provider's shares.
+    /// Returns the tokens released, which the caller is responsible for paying out.
+    pub fn remove_liquidity(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        provider: Address,
+        shares: Amount,
+    ) -> Result<LiquidityChange, PoolError> {
+        self.orderbook_for_liquidity(base, quote)?
+            .remove_liquidity(provider, shares)
+            .map_err(PoolError::LiquidityError)
+    }
+
+    /// The orderbook of a pair a provider adds or removes liquidity on, which
+    /// must be given as base and quote.
+    fn orderbook_for_liquidity(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+    ) -> Result<&mut OrderBook, PoolError> {
+        let pair_id = PairId::from_tokens(base, quote);
+        match self.orderbooks.get_mut(&pair_id) {
+            Some(orderbook) if orderbook.pair.base == base => Ok(orderbook),
+            Some(_) => Err(PoolError::InvalidPair),
+            None => Err(PoolError::PairNotFound {
+                token0: base,
+                token1: quote,
+                pair_id,
+            }),
+        }
+    }
+
+    /// Get the liquidity pool of a pair.
+    pub fn liquidity_pool(&self, base: TokenId, quote: TokenId) -> Option<&LiquidityPool> {
+        let pair_id = PairId::from_tokens(base, quote);
+        self.orderbooks.get(&pair_id).map(|ob| ob.pool())
+    }
+
     /// Get an orderbook by pair.
     pub fn get_orderbook(&self, pair: &Pair) -> Option<&OrderBook> {
         self.orderbooks.get(&pair.id())
@@ -150,7 +334,123 @@ impl PoolManager {
         price: Price,
         amount: Amount,
     ) -> Result<(OrderId, TradeResult), PoolError> {
+        self.place_order(
+            base,
+            quote,
+            trader,
+            side,
+            price,
+            amount,
+            TimeInForce::GoodTillCancel,
+            None,
+        )
+    }
+
+    /// Place a limit order with a time in force on a pair.
+    /// Returns the order ID and any immediate fills. The order ID is only
+    /// resolvable afterwards if part of the order rests on the book.
+    ///
+    /// `self_trade_prevention` overrides the market's self-trade prevention mode
+    /// for this order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_order(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        side: OrderSide,
+        price: Price,
+        amount: Amount,
+        time_in_force: TimeInForce,
+        self_trade_prevention: Option<SelfTradePrevention>,
+    ) -> Result<(OrderId, TradeResult), PoolError> {
+        let order_id = self.generate_order_id();
+        let mut order = Order::new_limit(order_id, trader, side, price, amount, self.block)
+            .with_time_in_force(time_in_force);
+        order.self_trade_prevention = self_trade_prevention;
+        let trade_result = self.submit_order(Pair::new(base, quote), order)?;
+
+        Ok((order_id, trade_result))
+    }
+
+    /// Place an iceberg order on a pair: a good-till-cancel limit order that shows
+    /// at most `display_amount` on the book and keeps the rest as a hidden reserve.
+    /// Each time the displayed clip is consumed, the next one is shown at the back
+    /// of the price level.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_iceberg_order(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        side: OrderSide,
+        price: Price,
+        amount: Amount,
+        display_amount: Amount,
+    ) -> Result<(OrderId, TradeResult), PoolError> {
+        let order_id = self.generate_order_id();
+        let order = Order::new_limit(order_id, trader, side, price, amount, self.block)
+            .with_display_amount(display_amount);
+        let trade_result = self.submit_order(Pair::new(base, quote), order)?;
+
+        Ok((order_id, trade_result))
+    }
+
+    /// Place a stop order on a pair.
+    /// The order waits until the last trade price on the pair reaches `stop_price`
+    /// and then becomes a limit order at `limit_price`, or a market order if no
+    /// limit price is given. It can be cancelled like a resting order until then.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_stop_order(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        side: OrderSide,
+        stop_price: Price,
+        limit_price: Option<Price>,
+        amount: Amount,
+    ) -> Result<OrderId, PoolError> {
+        let order_id = self.generate_order_id();
+        let order = match limit_price {
+            Some(price) => Order::new_limit(order_id, trader, side, price, amount, self.block),
+            None => Order::new_market(order_id, trader, side, amount, self.block),
+        };
+        self.submit_order(Pair::new(base, quote), order.with_stop_price(stop_price))?;
+
+        Ok(order_id)
+    }
+
+    /// Place a one-cancels-other pair on a pair: a limit order at `price` and a
+    /// stop order triggered at `stop_price`, both selling or buying `amount`.
+    /// The stop order becomes a limit order at `stop_limit_price` when triggered,
+    /// or a market order if no limit price is given. Filling or cancelling
+    /// either order cancels the other one.
+    ///
+    /// The limit order may not cross the book. Returns the IDs of the limit order
+    /// and the stop order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_oco_order(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        side: OrderSide,
+        amount: Amount,
+        price: Price,
+        stop_price: Price,
+        stop_limit_price: Option<Price>,
+    ) -> Result<(OrderId, OrderId), PoolError> {
         let pair = Pair::new(base, quote);
+        let limit_id = self.generate_order_id();
+        let stop_id = self.generate_order_id();
+        let limit_order = Order::new_limit(limit_id, trader, side, price, amount, self.block);
+        let stop_order = match stop_limit_price {
+            Some(limit) => Order::new_limit(stop_id, trader, side, limit, amount, self.block),
+            None => Order::new_market(stop_id, trader, side, amount, self.block),
+        }
+        .with_stop_price(stop_price);
+
         let pair_id = pair.id();
         let orderbook = self
             .orderbooks
@@ -160,10 +460,93 @@ impl PoolManager {
                 token1: quote,
                 pair_id,
             })?;
-
         orderbook
-            .place_limit_order(trader, side, price, amount, &self.config)
-            .map_err(PoolError::OrderError)
+            .place_oco_order(limit_order, stop_order, &self.config)
+            .map_err(PoolError::OrderError)?;
+
+        for order_id in [limit_id, stop_id] {
+            self.order_index.insert(order_id, pair_id);
+        }
+        self.add_order_group(
+            OrderGroupKind::OneCancelsOther,
+            pair,
+            vec![limit_id, stop_id],
+        );
+
+        Ok((limit_id, stop_id))
+    }
+
+    /// Place a bracket order on a pair: a limit entry order that, once it has
+    /// finished trading, places exit orders on the opposite side for the amount
+    /// it filled. The exits are a take-profit limit order at `take_profit` and a
+    /// stop-loss triggered at `stop_loss`, linked one-cancels-other. The
+    /// stop-loss becomes a limit order at `stop_loss_limit` when triggered, or a
+    /// market order if no limit price is given.
+    ///
+    /// Returns the entry order ID and any immediate fills, including the exits
+    /// if the entry filled right away. The exit order IDs are assigned up front
+    /// and listed in the bracket's order group.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_bracket_order(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        side: OrderSide,
+        price: Price,
+        amount: Amount,
+        take_profit: Price,
+        stop_loss: Price,
+        stop_loss_limit: Option<Price>,
+    ) -> Result<(OrderId, TradeResult), PoolError> {
+        let pair = Pair::new(base, quote);
+        let entry_id = self.generate_order_id();
+        let exits = BracketExits {
+            take_profit_id: self.generate_order_id(),
+            take_profit,
+            stop_loss_id: self.generate_order_id(),
+            stop_loss,
+            stop_loss_limit,
+        };
+        let group = vec![entry_id, exits.take_profit_id, exits.stop_loss_id];
+        let entry = Order::new_limit(entry_id, trader, side, price, amount, self.block);
+
+        let trade_result = self.submit(pair, side, |orderbook, config| {
+            orderbook.place_bracket_order(entry, exits, config)
+        })?;
+        self.add_order_group(OrderGroupKind::Bracket, pair, group);
+        self.prune_order_groups(&[entry_id]);
+
+        Ok((entry_id, trade_result))
+    }
+
+    /// Place a range order on a pair: a post-only limit order at `price` that
+    /// flips to the opposite side at `flip_price` each time it is completely
+    /// filled, for what its fills received net of maker fees. A sell flips to
+    /// a buy below its price and a buy to a sell above it, so the order keeps
+    /// earning the spread between the two prices without being placed again.
+    ///
+    /// The order keeps its ID across flips and rests until it is cancelled or
+    /// its proceeds no longer make an order the market takes. Being post-only,
+    /// it never fills on placement.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_range_order(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        side: OrderSide,
+        price: Price,
+        flip_price: Price,
+        amount: Amount,
+    ) -> Result<(OrderId, TradeResult), PoolError> {
+        let order_id = self.generate_order_id();
+        let order = Order::new_limit(order_id, trader, side, price, amount, self.block)
+            .with_time_in_force(TimeInForce::PostOnly)
+            .with_flip_price(flip_price);
+        let trade_result = self.submit_order(Pair::new(base, quote), order)?;
+
+        Ok((order_id, trade_result))
     }
 
     /// Place a market order on a pair.
@@ -175,44 +558,355 @@ impl PoolManager {
         side: OrderSide,
         amount: Amount,
     ) -> Result<TradeResult, PoolError> {
-        let pair = Pair::new(base, quote);
+        let order_id = self.generate_order_id();
+        let order = Order::new_market(order_id, trader, side, amount, self.block);
+        self.submit_order(Pair::new(base, quote), order)
+    }
+
+    /// Place a market buy on a pair sized by the quote it spends, fees included.
+    /// The order walks the asks until the budget is spent or the book runs dry.
+    pub fn place_market_buy_with_quote(
+        &mut self,
+        base: TokenId,
+        quote: TokenId,
+        trader: Address,
+        quote_amount: Amount,
+    ) -> Result<TradeResult, PoolError> {
+        let order_id = self.generate_order_id();
+        let order = Order::new_market(order_id, trader, OrderSide::Buy, U256::ZERO, self.block)
+            .with_quote_budget(quote_amount);
+        self.submit_order(Pair::new(base, quote), order)
+    }
+
+    /// Place an order on its pair's book and bring the fee vault and order index
+    /// up to date with the trade.
+    fn submit_order(&mut self, pair: Pair, order: Order) -> Result<TradeResult, PoolError> {
+        self.submit(pair, order.side, |orderbook, config| {
+            orderbook.place_order(order, config)
+        })
+    }
+
+    /// Run a placement on a pair's book and bring the fee vault, order index and
+    /// order groups up to date with the trade, including any stop orders it
+    /// triggered and bracket exits it placed.
+    fn submit(
+        &mut self,
+        pair: Pair,
+        side: OrderSide,
+        place: impl FnOnce(&mut OrderBook, &DexConfig) -> Result<TradeResult, OrderError>,
+    ) -> Result<TradeResult, PoolError> {
         let pair_id = pair.id();
         let orderbook = self
             .orderbooks
             .get_mut(&pair_id)
             .ok_or(PoolError::PairNotFound {
-                token0: base,
-                token1: quote,
+                token0: pair.base,
+                token1: pair.quote,
                 pair_id,
             })?;
 
+        let trade_result = place(orderbook, &self.config).map_err(PoolError::OrderError)?;
+
+        Self::accrue_fees(
+            &mut self.fee_vault,
+            orderbook.pair,
+            side,
+            &trade_result.fills,
+        );
+        for triggered in &trade_result.triggered {
+            Self::accrue_fees(
+                &mut self.fee_vault,
+                orderbook.pair,
+                triggered.order.side,
+                &triggered.fills,
+            );
+        }
+        for bracket in &trade_result.brackets {
+            Self::accrue_fees(
+                &mut self.fee_vault,
+                orderbook.pair,
+                bracket.take_profit.side,
+                &bracket.take_profit_fills,
+            );
+        }
+
+        self.index_orders(pair_id, &touched_orders(&trade_result));
+
+        Ok(trade_result)
+    }
+
+    /// Bring the order index and order groups up to date with orders a
+    /// placement or amendment on a pair touched: orders resting on the book or
+    /// waiting for their trigger are indexed, the rest are dropped.
+    fn index_orders(&mut self, pair_id: PairId, order_ids: &[OrderId]) {
+        let Some(orderbook) = self.orderbooks.get(&pair_id) else {
+            return;
+        };
+        for &order_id in order_ids {
+            if orderbook.get_order(order_id).is_some() {
+                self.order_index.insert(order_id, pair_id);
+            } else {
+                self.order_index.remove(&order_id);
+            }
+        }
+        self.prune_order_groups(order_ids);
+    }
+
+    /// Issue the next globally unique order ID.
+    fn generate_order_id(&mut self) -> OrderId {
+        let id = OrderId(self.next_order_id);
+        self.next_order_id += 1;
+        id
+    }
+
+    /// Register orders placed together as a group.
+    fn add_order_group(&mut self, kind: OrderGroupKind, pair: Pair, orders: Vec<OrderId>) {
+        let group_id = orders[0];
+        for &order_id in &orders {
+            self.group_index.insert(order_id, group_id);
+        }
+        self.order_groups
+            .insert(group_id, OrderGroup { kind, pair, orders });
+    }
+
+    /// Drop the groups of the given orders once none of their orders is open.
+    fn prune_order_groups(&mut self, order_ids: &[OrderId]) {
+        for order_id in order_ids {
+            let Some(group_id) = self.group_index.get(order_id).copied() else {
+                continue;
+            };
+            let Some(group) = self.order_groups.get(&group_id) else {
+                continue;
+            };
+            if group
+                .orders
+                .iter()
+                .any(|id| self.order_index.contains_key(id))
+            {
+                continue;
+            }
+            for id in &group.orders {
+                self.group_index.remove(id);
+            }
+            self.order_groups.remove(&group_id);
+        }
+    }
+
+    /// Get the group a linked order belongs to, as long as any of its orders is
+    /// open. A bracket's exit orders belong to its group before they are placed.
+    pub fn order_group(&self, order_id: OrderId) -> Option<&OrderGroup> {
+        let group_id = self.group_index.get(&order_id)?;
+        self.order_groups.get(group_id)
+    }
+
+    /// Credit the protocol's share of a trade's fees to the fee vault.
+    ///
+    /// Fees are quote-denominated, except a positive fee owed by a buying maker,
+    /// which is withheld from the base it receives.
+    fn accrue_fees(
+        fee_vault: &mut HashMap<TokenId, Amount>,
+        pair: Pair,
+        taker_side: OrderSide,
+        fills: &[Fill],
+    ) {
+        let mut quote_fees = U256::ZERO;
+        let mut base_fees = U256::ZERO;
+
+        for fill in fills {
+            if taker_side == OrderSide::Sell && fill.maker_fee.is_positive() {
+                quote_fees = quote_fees.saturating_add(fill.taker_fee);
+                base_fees = base_fees.saturating_add(fill.maker_fee_in_base());
+            } else {
+                quote_fees = quote_fees.saturating_add(fill.protocol_fee());
+            }
+        }
+
+        for (token, amount) in [(pair.quote, quote_fees), (pair.base, base_fees)] {
+            if !amount.is_zero() {
+                let balance = fee_vault.entry(token).or_default();
+                *balance = balance.saturating_add(amount);
+            }
+        }
+    }
+
+    /// Get the protocol fees collected in a token and not yet withdrawn.
+    pub fn accrued_fees(&self, token: TokenId) -> Amount {
+        self.fee_vault.get(&token).copied().unwrap_or(U256::ZERO)
+    }
+
+    /// Get all protocol fee balances.
+    pub fn fee_balances(&self) -> &HashMap<TokenId, Amount> {
+        &self.fee_vault
+    }
+
+    /// Withdraw all protocol fees collected in a token.
+    /// Returns the amount withdrawn, which the caller is responsible for paying out.
+    pub fn withdraw_fees(&mut self, token: TokenId) -> Amount {
+        self.fee_vault.remove(&token).unwrap_or(U256::ZERO)
+    }
+
+    /// Put withdrawn protocol fees whose payout failed back into the fee vault.
+    pub fn return_fees(&mut self, token: TokenId, amount: Amount) {
+        if amount.is_zero() {
+            return;
+        }
+        let balance = self.fee_vault.entry(token).or_default();
+        *balance = balance.saturating_add(amount);
+    }
+
+    /// Get an account's trading balance of a token.
+    /// Escrow locked by open orders is not part of it.
+    pub fn balance(&self, account: Address, token: TokenId) -> Amount {
+        self.balances
+            .get(&(account, token))
+            .copied()
+            .unwrap_or(U256::ZERO)
+    }
+
+    /// Add to an account's trading balance: deposits, trade proceeds and escrow refunds.
+    pub fn credit(&mut self, account: Address, token: TokenId, amount: Amount) {
+        if amount.is_zero() {
+            return;
+        }
+        let balance = self.balances.entry((account, token)).or_default();
+        *balance = balance.saturating_add(amount);
+    }
+
+    /// Take from an account's trading balance: withdrawals and escrow locked by orders.
+    /// Fails without changing the balance if it doesn't cover the amount.
+    pub fn debit(
+        &mut self,
+        account: Address,
+        token: TokenId,
+        amount: Amount,
+    ) -> Result<(), PoolError> {
+        let available = self.balance(account, token);
+        if available < amount {
+            return Err(PoolError::InsufficientBalance {
+                account,
+                token,
+                required: amount,
+                available,
+            });
+        }
+        if available == amount {
+            self.balances.remove(&(account, token));
+        } else {
+            self.balances.insert((account, token), available - amount);
+        }
+        Ok(())
+    }
+
+    /// Get the pair a resting order belongs to.
+    pub fn order_pair(&self, order_id: OrderId) -> Option<Pair> {
+        let pair_id = self.order_index.get(&order_id)?;
+        self.orderbooks.get(pair_id).map(|ob| ob.pair)
+    }
+
+    /// Find a resting order by ID.
+    /// Returns the pair the order belongs to along with the order itself.
+    pub fn get_order(&self, order_id: OrderId) -> Option<(Pair, &Order)> {
+        let pair_id = self.order_index.get(&order_id)?;
+        let orderbook = self.orderbooks.get(pair_id)?;
         orderbook
-            .place_market_order(trader, side, amount, &self.config)
-            .map_err(PoolError::OrderError)
+            .get_order(order_id)
+            .map(|order| (orderbook.pair, order))
+    }
+
+    /// Whether an order is a stop order still waiting for its trigger price.
+    pub fn is_pending_stop(&self, order_id: OrderId) -> bool {
+        self.order_index
+            .get(&order_id)
+            .and_then(|pair_id| self.orderbooks.get(pair_id))
+            .is_some_and(|orderbook| orderbook.is_pending_stop(order_id))
     }
 
-    /// Cancel an order.
+    /// Cancel an order on behalf of a trader.
+    /// The order is resolved through the order index and must be owned by `trader`.
+    /// Returns the pair, the cancelled order and the order linked to it
+    /// one-cancels-other, if any, which is cancelled along with it, so the caller
+    /// can refund their escrow.
     pub fn cancel_order(
         &mut self,
-        base: TokenId,
-        quote: TokenId,
+        trader: Address,
         order_id: OrderId,
-    ) -> Result<(), PoolError> {
-        let pair = Pair::new(base, quote);
+    ) -> Result<(Pair, Order, Option<LinkedCancel>), PoolError> {
+        let (pair, order) = self
+            .get_order(order_id)
+            .ok_or(PoolError::OrderNotFound(order_id))?;
+
+        if order.trader != trader {
+            return Err(PoolError::Unauthorized { caller: trader });
+        }
+
+        let orderbook = self
+            .orderbooks
+            .get_mut(&pair.id())
+            .ok_or(PoolError::OrderNotFound(order_id))?;
+
+        let (cancelled, linked_cancel) = orderbook
+            .cancel_order(order_id)
+            .map_err(PoolError::OrderError)?;
+        self.order_index.remove(&order_id);
+        if let Some(linked_cancel) = &linked_cancel {
+            self.order_index.remove(&linked_cancel.order.id);
+        }
+        self.prune_order_groups(&[order_id]);
+
+        Ok((pair, cancelled, linked_cancel))
+    }
+
+    /// Amend the price and unfilled amount of a resting order on behalf of a trader.
+    /// The order must be owned by `trader`, whose balance must cover any escrow
+    /// the amended order locks beyond what the order already holds. Returns the
+    /// pair and the order before and after the amendment so the caller can
+    /// settle the escrow difference.
+    pub fn amend_order(
+        &mut self,
+        trader: Address,
+        order_id: OrderId,
+        amount: Amount,
+        price: Price,
+    ) -> Result<(Pair, AmendResult), PoolError> {
+        let (pair, order) = self
+            .get_order(order_id)
+            .ok_or(PoolError::OrderNotFound(order_id))?;
+
+        if order.trader != trader {
+            return Err(PoolError::Unauthorized { caller: trader });
+        }
+
+        // Sell orders escrow base, buy orders escrow quote at the new price
+        let (token, escrow) = match order.side {
+            OrderSide::Sell => (pair.base, amount),
+            OrderSide::Buy => (
+                pair.quote,
+                price.quote_amount(amount).ok_or(PoolError::InvalidAmount)?,
+            ),
+        };
+        let required = escrow.saturating_sub(order.remaining_escrow());
+        let available = self.balance(trader, token);
+        if available < required {
+            return Err(PoolError::InsufficientBalance {
+                account: trader,
+                token,
+                required,
+                available,
+            });
+        }
+
         let pair_id = pair.id();
         let orderbook = self
             .orderbooks
             .get_mut(&pair_id)
-            .ok_or(PoolError::PairNotFound {
-                token0: base,
-                token1: quote,
-                pair_id,
-            })?;
+            .ok_or(PoolError::OrderNotFound(order_id))?;
 
-        orderbook
-            .cancel_order(order_id)
+        let amended = orderbook
+            .amend_order(order_id, amount, price, self.block, &self.config)
             .map_err(PoolError::OrderError)?;
-        Ok(())
+        self.index_orders(pair_id, &[order_id]);
+
+        Ok((pair, amended))
     }
 
     /// Get a quote for swapping tokens.
@@ -231,13 +925,32 @@ impl PoolManager {
             return Err(PoolError::InvalidAmount);
         }
 
-        // Try direct route first
-        if let Some(quote) = self.get_direct_quote(token_in, token_out, amount_in) {
-            return Ok(quote);
+        let routes = self.router.find_candidate_routes(
+            token_in,
+            token_out,
+            self.config.max_routing_hops,
+            self.config.max_route_candidates,
+            &self.orderbooks,
+        );
+
+        // Try direct route first, then multi-hop routing
+        let best = match self.get_direct_quote(token_in, token_out, amount_in) {
+            Some(quote) => Ok(quote),
+            None => self.get_routed_quote(&routes, amount_in),
+        };
+
+        // A single route has nothing to split the input with
+        if routes.len() < 2 {
+            return best;
         }
 
-        // Try multi-hop routing
-        self.get_routed_quote(token_in, token_out, amount_in)
+        // Split the input between routes when that pays out more
+        let split = self.get_split_quote(token_in, token_out, amount_in, routes);
+        match (best, split) {
+            (Ok(best), Some(split)) if best.amount_out >= split.amount_out => Ok(best),
+            (_, Some(split)) => Ok(split),
+            (best, None) => best,
+        }
     }
 
     /// Get a quote for a direct pair (no routing).
@@ -252,7 +965,7 @@ impl PoolManager {
         let orderbook = self.orderbooks.get(&pair_id)?;
 
         // Determine if we're buying or selling
-        let (amount_out, _avg_price) = if orderbook.pair.base == token_in {
+        let simulation = if orderbook.pair.base == token_in {
             // We have base, want quote -> sell
             orderbook.simulate_market_sell(amount_in, &self.config)?
         } else {
@@ -260,41 +973,37 @@ impl PoolManager {
             orderbook.simulate_market_buy(amount_in, &self.config)?
         };
 
+        let route = Route {
+            hops: vec![RouteHop {
+                pair: orderbook.pair,
+                token_in,
+                token_out,
+            }],
+        };
+
+        let amount_out = simulation.amount_out;
+        let price_impact = impact_bps(hop_rate_factor(orderbook, token_in, simulation.avg_price));
+
         Some(Quote {
             token_in,
             token_out,
             amount_in,
             amount_out,
-            route: Route {
-                hops: vec![RouteHop {
-                    pair: orderbook.pair,
-                    token_in,
-                    token_out,
-                }],
-            },
-            price_impact: self.calculate_price_impact(&orderbook, token_in, amount_in),
-            total_fee: U256::from(
-                self.config
-                    .calculate_fee(amount_out.try_into().unwrap_or(u128::MAX)),
-            ),
+            route: route.clone(),
+            price_impact,
+            hop_impacts: vec![price_impact],
+            total_fee: simulation.fee,
+            splits: vec![RouteSplit {
+                route,
+                amount_in,
+                amount_out,
+                hops: vec![simulation],
+            }],
         })
     }
 
-    /// Get a quote using multi-hop routing.
-    fn get_routed_quote(
-        &self,
-        token_in: TokenId,
-        token_out: TokenId,
-        amount_in: Amount,
-    ) -> Result<Quote, PoolError> {
-        // Find possible routes
-        let routes = self.router.find_routes(
-            token_in,
-            token_out,
-            self.config.max_routing_hops,
-            &self.orderbooks,
-        );
-
+    /// Get a quote using multi-hop routing, from the candidate routes found.
+    fn get_routed_quote(&self, routes: &[Route], amount_in: Amount) -> Result<Quote, PoolError> {
         if routes.is_empty() {
             return Err(PoolError::NoRouteFound);
         }
@@ -303,7 +1012,7 @@ impl PoolManager {
         let mut best_quote: Option<Quote> = None;
 
         for route in routes {
-            if let Some(quote) = self.evaluate_route(&route, amount_in) {
+            if let Some(quote) = self.evaluate_route(route, amount_in) {
                 match &best_quote {
                     None => best_quote = Some(quote),
                     Some(current_best) if quote.amount_out > current_best.amount_out => {
@@ -317,29 +1026,159 @@ impl PoolManager {
         best_quote.ok_or(PoolError::InsufficientLiquidity)
     }
 
+    /// Get a quote that splits the input between routes sharing no pair, so
+    /// each route trades against books the others leave untouched.
+    ///
+    /// The input is allocated in `SPLIT_STEPS` equal parts, each going to the
+    /// route that pays out the most for it on top of what it already carries.
+    /// At most `max_split_routes` of the candidate routes take part. Returns
+    /// `None` unless more than one route ends up with a share.
+    fn get_split_quote(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: Amount,
+        candidates: Vec<Route>,
+    ) -> Option<Quote> {
+        let max_routes = self.config.max_split_routes;
+        let step = amount_in / U256::from(SPLIT_STEPS);
+        if max_routes < 2 || step.is_zero() {
+            return None;
+        }
+
+        // Rank the routes by what each pays out alone and keep the best of
+        // those that share no pair with a better one
+        let mut ranked: Vec<(Route, Amount)> = candidates
+            .into_iter()
+            .filter_map(|route| {
+                let amount_out = self.evaluate_route(&route, amount_in)?.amount_out;
+                Some((route, amount_out))
+            })
+            .collect();
+        ranked.sort_by_key(|(_, amount_out)| Reverse(*amount_out));
+
+        let mut routes: Vec<Route> = Vec::new();
+        for (route, _) in ranked {
+            if routes.len() == max_routes {
+                break;
+            }
+            if routes.iter().all(|r| !r.shares_pair_with(&route)) {
+                routes.push(route);
+            }
+        }
+        if routes.len() < 2 {
+            return None;
+        }
+
+        // Give each part to the route with the best marginal output
+        let mut allocations = vec![U256::ZERO; routes.len()];
+        let mut outputs = vec![U256::ZERO; routes.len()];
+        for i in 0..SPLIT_STEPS {
+            let part = if i + 1 == SPLIT_STEPS {
+                amount_in - step * U256::from(SPLIT_STEPS - 1)
+            } else {
+                step
+            };
+
+            let mut best: Option<(usize, Amount, Amount)> = None;
+            for (j, route) in routes.iter().enumerate() {
+                let Some(quote) = self.evaluate_route(route, allocations[j] + part) else {
+                    continue;
+                };
+                let gain = quote.amount_out.saturating_sub(outputs[j]);
+                if best.is_none_or(|(_, best_gain, _)| gain > best_gain) {
+                    best = Some((j, gain, quote.amount_out));
+                }
+            }
+
+            let (j, _, amount_out) = best?;
+            allocations[j] += part;
+            outputs[j] = amount_out;
+        }
+
+        let mut quotes = Vec::new();
+        for (route, allocation) in routes.into_iter().zip(allocations) {
+            if !allocation.is_zero() {
+                quotes.push(self.evaluate_route(&route, allocation)?);
+            }
+        }
+        if quotes.len() < 2 {
+            return None;
+        }
+
+        // Each share's impact scales its output back up to what it would have
+        // received at the reference rates of its route
+        let mut total_fee = U256::ZERO;
+        let mut amount_out = U256::ZERO;
+        let mut unimpacted_out = U256::ZERO;
+        for quote in &quotes {
+            total_fee = total_fee.saturating_add(quote.total_fee);
+            amount_out = amount_out.saturating_add(quote.amount_out);
+            let kept = U256::from(10_000).saturating_sub(quote.price_impact);
+            unimpacted_out = unimpacted_out.saturating_add(
+                quote
+                    .amount_out
+                    .saturating_mul(U256::from(10_000))
+                    .checked_div(kept)
+                    .unwrap_or(quote.amount_out),
+            );
+        }
+        let price_impact = unimpacted_out
+            .saturating_sub(amount_out)
+            .saturating_mul(U256::from(10_000))
+            .checked_div(unimpacted_out)
+            .unwrap_or(U256::ZERO);
+
+        let largest = quotes.iter().max_by_key(|q| q.amount_in)?;
+        let route = largest.route.clone();
+        let hop_impacts = largest.hop_impacts.clone();
+        let splits = quotes.into_iter().flat_map(|quote| quote.splits).collect();
+
+        Some(Quote {
+            token_in,
+            token_out,
+            amount_in,
+            amount_out,
+            route,
+            price_impact,
+            hop_impacts,
+            total_fee,
+            splits,
+        })
+    }
+
     /// Evaluate a route to get the expected output.
+    /// Routes whose sell hops would place a market order below the minimum order
+    /// size can't be executed and give `None`.
     fn evaluate_route(&self, route: &Route, amount_in: Amount) -> Option<Quote> {
         let mut current_amount = amount_in;
         let mut total_fee = U256::ZERO;
+        let mut factors = Vec::with_capacity(route.hops.len());
+        let mut simulations = Vec::with_capacity(route.hops.len());
 
         for hop in &route.hops {
             let pair_id = hop.pair.id();
             let orderbook = self.orderbooks.get(&pair_id)?;
 
-            let (amount_out, _) = if orderbook.pair.base == hop.token_in {
+            let simulation = if orderbook.pair.base == hop.token_in {
                 // Selling base for quote
+                if current_amount < U256::from(self.config.min_order_size) {
+                    return None;
+                }
                 orderbook.simulate_market_sell(current_amount, &self.config)?
             } else {
                 // Buying base with quote
                 orderbook.simulate_market_buy(current_amount, &self.config)?
             };
 
-            let hop_fee = U256::from(
-                self.config
-                    .calculate_fee(amount_out.try_into().unwrap_or(u128::MAX)),
-            );
-            total_fee = total_fee.saturating_add(hop_fee);
-            current_amount = amount_out;
+            total_fee = total_fee.saturating_add(simulation.fee);
+            factors.push(hop_rate_factor(
+                orderbook,
+                hop.token_in,
+                simulation.avg_price,
+            ));
+            current_amount = simulation.amount_out;
+            simulations.push(simulation);
         }
 
         let first_hop = route.hops.first()?;
@@ -351,121 +1190,331 @@ impl PoolManager {
             amount_in,
             amount_out: current_amount,
             route: route.clone(),
-            price_impact: U256::ZERO, // TODO: Calculate cumulative price impact
+            price_impact: impact_bps(route_rate_factor(&factors)),
+            hop_impacts: factors.into_iter().map(impact_bps).collect(),
             total_fee,
+            splits: vec![RouteSplit {
+                route: route.clone(),
+                amount_in,
+                amount_out: current_amount,
+                hops: simulations,
+            }],
         })
     }
 
-    /// Calculate price impact for a trade.
-    fn calculate_price_impact(
-        &self,
-        orderbook: &OrderBook,
+    /// Execute a swap along a route.
+    /// This actually executes the trades (not just a simulation).
+    pub fn execute_swap(
+        &mut self,
+        trader: Address,
         token_in: TokenId,
+        token_out: TokenId,
+        amount_in: Amount,
+        min_amount_out: Amount,
+    ) -> Result<SwapResult, PoolError> {
+        // Get the best quote first
+        let quote = self.get_quote(token_in, token_out, amount_in)?;
+
+        if quote.amount_out < min_amount_out {
+            return Err(PoolError::SlippageExceeded);
+        }
+
+        // Dry-run every hop before any of them trades, so a hop rejected part
+        // way, or a swap paying out less than the minimum after all, leaves
+        // nothing behind
+        let mut amount_out = U256::ZERO;
+        for split in &quote.splits {
+            let (_, route_out) = self.plan_route(trader, &split.route, split.amount_in)?;
+            amount_out = amount_out.saturating_add(route_out);
+        }
+        if amount_out < min_amount_out {
+            return Err(PoolError::SlippageExceeded);
+        }
+
+        // Execute the part of the input sent down each route. The routes of a
+        // split share no pair, so each trades as it was dry-run whatever the
+        // others did
+        let mut executed = Vec::with_capacity(quote.splits.len());
+        for split in &quote.splits {
+            let hops = self.execute_route(trader, &split.route, split.amount_in)?;
+            executed.push((split.clone(), hops));
+        }
+
+        Ok(SwapResult::from_splits(quote.route, executed))
+    }
+
+    /// Dry-run each hop of a route as [`Self::execute_route`] would execute it.
+    /// Returns the input the first hop spends and the output the last receives.
+    fn plan_route(
+        &self,
+        trader: Address,
+        route: &Route,
+        amount_in: Amount,
+    ) -> Result<(Amount, Amount), PoolError> {
+        let mut spent = None;
+        let mut current_amount = amount_in;
+
+        for hop in &route.hops {
+            let order = self.route_order(OrderId(0), trader, hop, current_amount);
+            let (input, output) = self.plan_hop(hop.pair, &order)?;
+            spent.get_or_insert(input);
+            current_amount = output;
+        }
+
+        Ok((spent.unwrap_or_default(), current_amount))
+    }
+
+    /// Execute each hop of a route, passing on what one hop receives to the next.
+    fn execute_route(
+        &mut self,
+        trader: Address,
+        route: &Route,
+        amount_in: Amount,
+    ) -> Result<Vec<ExecutedHop>, PoolError> {
+        let mut current_amount = amount_in;
+        let mut hops = Vec::with_capacity(route.hops.len());
+
+        for hop in &route.hops {
+            let order_id = self.generate_order_id();
+            let order = self.route_order(order_id, trader, hop, current_amount);
+            let executed = self.execute_hop(hop.pair, order)?;
+            current_amount = executed.2;
+            hops.push(executed);
+        }
+
+        Ok(hops)
+    }
+
+    /// The market order of a hop of a route holding `amount_in` of its input
+    /// token: a sell hop is sized in the base it holds, a buy hop in the quote
+    /// it spends. Swaps trade past the trader's own resting orders.
+    fn route_order(
+        &self,
+        order_id: OrderId,
+        trader: Address,
+        hop: &RouteHop,
         amount_in: Amount,
-    ) -> U256 {
-        // Get mid price
-        let (best_bid, best_ask) = match orderbook.spread() {
-            Some(spread) => spread,
-            None => return U256::ZERO,
+    ) -> Order {
+        let order = if hop.pair.base == hop.token_in {
+            Order::new_market(order_id, trader, OrderSide::Sell, amount_in, self.block)
+        } else {
+            Order::new_market(order_id, trader, OrderSide::Buy, U256::ZERO, self.block)
+                .with_quote_budget(amount_in)
         };
+        order.with_self_trade_prevention(SelfTradePrevention::Skip)
+    }
+
+    /// Get a quote for receiving exactly `amount_out` of `token_out`.
+    /// Each route is walked backwards from the output, working out what every
+    /// hop needs to receive, and the route needing the least input wins.
+    pub fn get_quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: Amount,
+    ) -> Result<Quote, PoolError> {
+        if token_in == token_out {
+            return Err(PoolError::InvalidPair);
+        }
+
+        if amount_out.is_zero() {
+            return Err(PoolError::InvalidAmount);
+        }
+
+        let routes = self.router.find_candidate_routes(
+            token_in,
+            token_out,
+            self.config.max_routing_hops,
+            self.config.max_route_candidates,
+            &self.orderbooks,
+        );
+
+        if routes.is_empty() {
+            return Err(PoolError::NoRouteFound);
+        }
+
+        // Routes come shortest first, so ties go to the shorter route
+        let mut best_quote: Option<Quote> = None;
+
+        for route in routes {
+            if let Some(quote) = self.evaluate_route_exact_out(&route, amount_out) {
+                match &best_quote {
+                    None => best_quote = Some(quote),
+                    Some(current_best) if quote.amount_in < current_best.amount_in => {
+                        best_quote = Some(quote);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        best_quote.ok_or(PoolError::InsufficientLiquidity)
+    }
+
+    /// Evaluate a route backwards to get the input needed for `amount_out`.
+    fn evaluate_route_exact_out(&self, route: &Route, amount_out: Amount) -> Option<Quote> {
+        let simulations = self.plan_exact_out(route, amount_out)?;
+        let mut total_fee = U256::ZERO;
+        let mut factors = Vec::with_capacity(route.hops.len());
+
+        for (hop, simulation) in route.hops.iter().zip(&simulations) {
+            let orderbook = self.orderbooks.get(&hop.pair.id())?;
+            total_fee = total_fee.saturating_add(simulation.fee);
+            factors.push(hop_rate_factor(
+                orderbook,
+                hop.token_in,
+                simulation.avg_price,
+            ));
+        }
+
+        let first_hop = route.hops.first()?;
+        let last_hop = route.hops.last()?;
+        let amount_in = simulations.first()?.amount_in;
+
+        Some(Quote {
+            token_in: first_hop.token_in,
+            token_out: last_hop.token_out,
+            amount_in,
+            amount_out,
+            route: route.clone(),
+            price_impact: impact_bps(route_rate_factor(&factors)),
+            hop_impacts: factors.into_iter().map(impact_bps).collect(),
+            total_fee,
+            splits: vec![RouteSplit {
+                route: route.clone(),
+                amount_in,
+                amount_out,
+                hops: simulations,
+            }],
+        })
+    }
 
-        // Calculate mid price (average of bid and ask)
-        let mid_numerator = best_bid.numerator.saturating_add(best_ask.numerator);
-        let mid_denominator = best_bid.denominator.saturating_add(best_ask.denominator);
+    /// Work out what each hop of a route must do for the last one to output
+    /// `amount_out`, walking back from the output.
+    /// The simulations run along the route, each hop's input being what the
+    /// hop before it has to deliver.
+    fn plan_exact_out(&self, route: &Route, amount_out: Amount) -> Option<Vec<MarketSimulation>> {
+        let mut simulations = Vec::with_capacity(route.hops.len());
+        let mut needed = amount_out;
 
-        if mid_denominator.is_zero() {
-            return U256::ZERO;
-        }
+        for hop in route.hops.iter().rev() {
+            let orderbook = self.orderbooks.get(&hop.pair.id())?;
 
-        // Simulate the trade to get execution price
-        let (_amount_out, exec_price) = if orderbook.pair.base == token_in {
-            match orderbook.simulate_market_sell(amount_in, &self.config) {
-                Some(r) => r,
-                None => return U256::ZERO,
-            }
-        } else {
-            match orderbook.simulate_market_buy(amount_in, &self.config) {
-                Some(r) => r,
-                None => return U256::ZERO,
-            }
-        };
+            let simulation = if orderbook.pair.base == hop.token_in {
+                // Selling base for at least the quote needed
+                orderbook.simulate_market_sell_exact_out(needed, &self.config)?
+            } else {
+                // Buying exactly the base needed
+                orderbook.simulate_market_buy_exact_out(needed, &self.config)?
+            };
 
-        // Price impact = |exec_price - mid_price| / mid_price * 10000 (in basis points)
-        // Simplified calculation
-        let impact_bps = exec_price
-            .numerator
-            .saturating_mul(U256::from(10000))
-            .checked_div(mid_numerator)
-            .unwrap_or(U256::ZERO);
+            needed = simulation.amount_in;
+            simulations.push(simulation);
+        }
 
-        impact_bps.saturating_sub(U256::from(10000))
+        simulations.reverse();
+        Some(simulations)
     }
 
-    /// Execute a swap along a route.
-    /// This actually executes the trades (not just a simulation).
-    pub fn execute_swap(
+    /// Execute a swap that receives exactly `amount_out` of `token_out`, spending
+    /// at most `max_amount_in` of `token_in`.
+    ///
+    /// Buy hops buy exactly the base the next hop needs and sell hops sell the
+    /// base planned for them, so the swap delivers `amount_out`, or a few units
+    /// more when the last hop is a sell. The result's `amount_in` is the input
+    /// actually spent; the rest of `max_amount_in` stays with the trader.
+    pub fn execute_swap_exact_out(
         &mut self,
         trader: Address,
         token_in: TokenId,
         token_out: TokenId,
-        amount_in: Amount,
-        min_amount_out: Amount,
+        amount_out: Amount,
+        max_amount_in: Amount,
     ) -> Result<SwapResult, PoolError> {
-        // Get the best quote first
-        let quote = self.get_quote(token_in, token_out, amount_in)?;
+        let quote = self.get_quote_exact_out(token_in, token_out, amount_out)?;
 
-        if quote.amount_out < min_amount_out {
+        if quote.amount_in > max_amount_in {
             return Err(PoolError::SlippageExceeded);
         }
 
-        // Execute each hop
-        let mut current_amount = amount_in;
-        let mut all_trades = Vec::new();
+        // The quote's plan: sell hops sell the base planned, buy hops buy
+        // exactly the base the next hop needs
+        let split = quote
+            .splits
+            .first()
+            .cloned()
+            .ok_or(PoolError::InsufficientLiquidity)?;
+
+        // Dry-run every hop before any of them trades, as for exact-input swaps
+        let mut spent = None;
+        let mut received = U256::ZERO;
+        for (hop, planned) in split.route.hops.iter().zip(&split.hops) {
+            let order = self.exact_out_order(OrderId(0), trader, hop, planned);
+            let (input, output) = self.plan_hop(hop.pair, &order)?;
+            spent.get_or_insert(input);
+            received = output;
+        }
+        if spent.unwrap_or_default() > max_amount_in || received < amount_out {
+            return Err(PoolError::SlippageExceeded);
+        }
 
-        for hop in &quote.route.hops {
-            let pair_id = hop.pair.id();
-            let orderbook = self
-                .orderbooks
-                .get_mut(&pair_id)
-                .ok_or(PoolError::PairNotFound {
-                    token0: hop.pair.base,
-                    token1: hop.pair.quote,
-                    pair_id,
-                })?;
+        let mut hops = Vec::with_capacity(split.hops.len());
+        for (hop, planned) in split.route.hops.iter().zip(&split.hops) {
+            let order_id = self.generate_order_id();
+            let order = self.exact_out_order(order_id, trader, hop, planned);
+            hops.push(self.execute_hop(hop.pair, order)?);
+        }
 
-            let side = if orderbook.pair.base == hop.token_in {
-                OrderSide::Sell
-            } else {
-                OrderSide::Buy
-            };
+        Ok(SwapResult::from_splits(quote.route, vec![(split, hops)]))
+    }
 
-            let trade_result = orderbook
-                .place_market_order(trader, side, current_amount, &self.config)
-                .map_err(PoolError::OrderError)?;
+    /// The market order of a hop of an exact-output swap: a sell hop sells the
+    /// base planned for it, a buy hop buys exactly the base planned.
+    fn exact_out_order(
+        &self,
+        order_id: OrderId,
+        trader: Address,
+        hop: &RouteHop,
+        planned: &MarketSimulation,
+    ) -> Order {
+        let (side, amount) = if hop.pair.base == hop.token_in {
+            (OrderSide::Sell, planned.amount_in)
+        } else {
+            (OrderSide::Buy, planned.amount_out)
+        };
+        Order::new_market(order_id, trader, side, amount, self.block)
+            .with_self_trade_prevention(SelfTradePrevention::Skip)
+    }
 
-            // Calculate output from fills
-            let output: Amount = trade_result
-                .fills
-                .iter()
-                .map(|f| {
-                    if side == OrderSide::Sell {
-                        f.quote_amount
-                    } else {
-                        f.base_amount
-                    }
-                })
-                .fold(U256::ZERO, |acc, x| acc.saturating_add(x));
+    /// Dry-run a market order for one hop of a swap, making the same checks
+    /// and fills executing it would without changing anything.
+    /// Returns the input the hop would spend and the output it would receive.
+    fn plan_hop(&self, pair: Pair, order: &Order) -> Result<(Amount, Amount), PoolError> {
+        let pair_id = pair.id();
+        let orderbook = self
+            .orderbooks
+            .get(&pair_id)
+            .ok_or(PoolError::PairNotFound {
+                token0: pair.base,
+                token1: pair.quote,
+                pair_id,
+            })?;
 
-            current_amount = output;
-            all_trades.push(trade_result);
-        }
+        let fills = orderbook
+            .simulate_market_fills(order, &self.config)
+            .map_err(PoolError::OrderError)?;
+        Ok(hop_amounts(order.side, &fills))
+    }
 
-        Ok(SwapResult {
-            amount_in,
-            amount_out: current_amount,
-            route: quote.route,
-            trades: all_trades,
-        })
+    /// Execute a market order for one hop of a swap.
+    /// Returns the trade with the input the hop spent and the output it received.
+    /// Fees are charged in quote, so a seller receives the quote value less the
+    /// taker fee and a buyer pays the quote value plus the taker fee.
+    fn execute_hop(&mut self, pair: Pair, order: Order) -> Result<ExecutedHop, PoolError> {
+        let side = order.side;
+        let trade_result = self.submit_order(pair, order)?;
+        let (input, output) = hop_amounts(side, &trade_result.fills);
+        Ok((trade_result, input, output))
     }
 
     /// Get statistics for all pairs.
@@ -481,6 +1530,37 @@ impl PoolManager {
         let pair = Pair::new(base, quote);
         self.orderbooks.get(&pair.id()).map(|ob| ob.stats())
     }
+
+    /// Get the trade history of a pair.
+    pub fn trade_history(&self, base: TokenId, quote: TokenId) -> Option<&TradeHistory> {
+        let pair_id = PairId::from_tokens(base, quote);
+        self.orderbooks.get(&pair_id).map(|ob| ob.history())
+    }
+
+    /// Get up to `limit` of the latest trades of a pair, newest first.
+    pub fn recent_trades(&self, base: TokenId, quote: TokenId, limit: usize) -> Vec<Trade> {
+        self.trade_history(base, quote)
+            .map(|history| history.trades().iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Get up to `limit` of the latest candles of a pair at `interval`, oldest
+    /// first, as a chart draws them.
+    pub fn candles(
+        &self,
+        base: TokenId,
+        quote: TokenId,
+        interval: CandleInterval,
+        limit: usize,
+    ) -> Vec<Candle> {
+        self.trade_history(base, quote)
+            .map(|history| {
+                let candles = history.candles(interval);
+                let skip = candles.len().saturating_sub(limit);
+                candles.iter().skip(skip).copied().collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 impl Default for PoolManager {
@@ -489,17 +1569,194 @@ impl Default for PoolManager {
     }
 }
 
+/// Kind of a group of linked orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderGroupKind {
+    /// A limit order and a stop order where filling or cancelling one cancels the other.
+    OneCancelsOther,
+    /// An entry order with take-profit and stop-loss exits linked one-cancels-other.
+    Bracket,
+}
+
+/// Orders placed together and linked to each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderGroup {
+    /// Kind of the group.
+    pub kind: OrderGroupKind,
+    /// The pair all orders of the group are on.
+    pub pair: Pair,
+    /// Order IDs: the limit and stop order of a one-cancels-other pair, or the
+    /// entry, take-profit and stop-loss order of a bracket.
+    pub orders: Vec<OrderId>,
+}
+
+/// `a / b` scaled by `WAD`, saturating when it doesn't fit.
+fn price_ratio(a: Price, b: Price) -> U256 {
+    let numerator: U512 = a.numerator.widening_mul(b.denominator);
+    let denominator: U512 = a.denominator.widening_mul(b.numerator);
+    if denominator.is_zero() {
+        return U256::MAX;
+    }
+    let ratio = numerator.saturating_mul(U512::from(WAD)) / denominator;
+    U256::saturating_from(ratio)
+}
+
+/// The share of the reference rate a hop trading `token_in` on `orderbook`
+/// gets when it fills at `avg_price`, scaled by `WAD`.
+///
+/// The reference is the mid price, or the best price of the side traded
+/// against when the book is one-sided, the liquidity pool's prices included.
+/// Taker fees are left out, so only the depth the hop walks through counts.
+fn hop_rate_factor(orderbook: &OrderBook, token_in: TokenId, avg_price: Price) -> U256 {
+    let wad = U256::from(WAD);
+
+    // The reference price relative to the average fill price
+    let bid = orderbook
+        .best_bid_with_pool()
+        .map(|bid| price_ratio(bid, avg_price));
+    let ask = orderbook
+        .best_ask_with_pool()
+        .map(|ask| price_ratio(ask, avg_price));
+    let reference = match (bid, ask) {
+        (Some(bid), Some(ask)) => bid.saturating_add(ask) / U256::from(2),
+        (Some(one_side), None) | (None, Some(one_side)) => one_side,
+        (None, None) => return wad,
+    };
+
+    if orderbook.pair.base == token_in {
+        // A sell receives the average price per base instead of the reference
+        wad.saturating_mul(wad)
+            .checked_div(reference)
+            .unwrap_or(wad)
+    } else {
+        // A buy receives base at the inverse of the average price
+        reference
+    }
+}
+
+/// The rate factor of a whole route: the product of its hops' factors.
+fn route_rate_factor(factors: &[U256]) -> U256 {
+    let wad = U256::from(WAD);
+    let mut factor = wad;
+    for hop_factor in factors {
+        factor = factor.saturating_mul(*hop_factor) / wad;
+    }
+    factor
+}
+
+/// Price impact in basis points of trading at `factor` of the reference rate.
+fn impact_bps(factor: U256) -> U256 {
+    let wad = U256::from(WAD);
+    let shortfall = wad.saturating_sub(factor);
+    shortfall.saturating_mul(U256::from(10_000)) / wad
+}
+
+/// The input a swap hop's taker on `side` spends on its fills and the output
+/// it receives from them, the taker fee being charged in quote.
+fn hop_amounts(side: OrderSide, fills: &[Fill]) -> (Amount, Amount) {
+    let mut input = U256::ZERO;
+    let mut output = U256::ZERO;
+    for fill in fills {
+        let (fill_in, fill_out) = match side {
+            OrderSide::Sell => (
+                fill.base_amount,
+                fill.quote_amount.saturating_sub(fill.taker_fee),
+            ),
+            OrderSide::Buy => (
+                fill.quote_amount.saturating_add(fill.taker_fee),
+                fill.base_amount,
+            ),
+        };
+        input = input.saturating_add(fill_in);
+        output = output.saturating_add(fill_out);
+    }
+    (input, output)
+}
+
+/// Every order a trade may have placed, filled or cancelled.
+fn touched_orders(trade_result: &TradeResult) -> Vec<OrderId> {
+    let mut order_ids = vec![trade_result.taker_order_id];
+    let mut add_matches = |fills: &[Fill], prevented: &[PreventedTrade]| {
+        order_ids.extend(
+            fills
+                .iter()
+                .filter(|fill| !fill.from_pool)
+                .map(|fill| fill.maker_order_id),
+        );
+        order_ids.extend(prevented.iter().map(|prevented| prevented.maker.id));
+    };
+    add_matches(&trade_result.fills, &trade_result.prevented);
+    for triggered in &trade_result.triggered {
+        add_matches(&triggered.fills, &triggered.prevented);
+    }
+    for bracket in &trade_result.brackets {
+        add_matches(&bracket.take_profit_fills, &bracket.take_profit_prevented);
+    }
+    order_ids.extend(trade_result.triggered.iter().map(|t| t.order.id));
+    order_ids.extend(trade_result.linked_cancels.iter().map(|l| l.order.id));
+    for bracket in &trade_result.brackets {
+        order_ids.extend([bracket.take_profit.id, bracket.stop_loss.id]);
+    }
+    order_ids
+}
+
 /// Result of executing a swap.
 #[derive(Debug)]
 pub struct SwapResult {
-    /// Amount of input token.
+    /// Amount of input token spent by the first hop of each route, which can
+    /// fall short of the amount offered when the book runs dry.
     pub amount_in: Amount,
     /// Amount of output token received.
     pub amount_out: Amount,
-    /// The route taken.
+    /// The route taken, or the one taking the largest share of a split swap.
     pub route: Route,
-    /// Trade results for each hop.
+    /// Trade results for each hop, route after route when the swap was split.
     pub trades: Vec<TradeResult>,
+    /// Intermediate tokens a hop received beyond what the next hop spent,
+    /// which are owed back to the trader.
+    pub leftovers: Vec<(TokenId, Amount)>,
+    /// Input spent and output received down each route, in execution order,
+    /// with what the quote expected of each hop.
+    pub splits: Vec<RouteSplit>,
+}
+
+impl SwapResult {
+    /// Assemble a swap result from the trade, input and output of each hop of
+    /// each quoted split taken.
+    fn from_splits(route: Route, executed: Vec<(RouteSplit, Vec<ExecutedHop>)>) -> Self {
+        let mut result = Self {
+            amount_in: U256::ZERO,
+            amount_out: U256::ZERO,
+            route,
+            trades: Vec::new(),
+            leftovers: Vec::new(),
+            splits: Vec::with_capacity(executed.len()),
+        };
+
+        for (split, hops) in executed {
+            let amount_in = hops.first().map_or(U256::ZERO, |(_, input, _)| *input);
+            let amount_out = hops.last().map_or(U256::ZERO, |(_, _, output)| *output);
+            for (hop, pair) in split.route.hops.iter().skip(1).zip(hops.windows(2)) {
+                let unspent = pair[0].2.saturating_sub(pair[1].1);
+                if !unspent.is_zero() {
+                    result.leftovers.push((hop.token_in, unspent));
+                }
+            }
+
+            result.amount_in = result.amount_in.saturating_add(amount_in);
+            result.amount_out = result.amount_out.saturating_add(amount_out);
+            result
+                .trades
+                .extend(hops.into_iter().map(|(trade, _, _)| trade));
+            result.splits.push(RouteSplit {
+                amount_in,
+                amount_out,
+                ..split
+            });
+        }
+
+        result
+    }
 }
 
 /// Errors that can occur in the pool manager.
@@ -521,14 +1778,31 @@ pub enum PoolError {
     InvalidPair,
     /// Invalid amount.
     InvalidAmount,
+    /// Market parameters are unusable (e.g., zero tick or lot size).
+    InvalidMarketConfig,
+    /// The market mode can't change while orders depend on it.
+    MarketModeLocked,
     /// No route found between tokens.
     NoRouteFound,
     /// Insufficient liquidity for the trade.
     InsufficientLiquidity,
     /// Slippage tolerance exceeded.
     SlippageExceeded,
+    /// No resting order exists with the given ID.
+    OrderNotFound(OrderId),
+    /// The caller is not allowed to act on the order.
+    Unauthorized { caller: Address },
+    /// An account's trading balance doesn't cover a withdrawal or an order's escrow.
+    InsufficientBalance {
+        account: Address,
+        token: TokenId,
+        required: Amount,
+        available: Amount,
+    },
     /// Order-related error.
     OrderError(OrderError),
+    /// Liquidity pool error.
+    LiquidityError(LiquidityError),
 }
 
 impl std::fmt::Display for PoolError {
@@ -558,10 +1832,27 @@ impl std::fmt::Display for PoolError {
             }
             PoolError::InvalidPair => write!(f, "invalid pair"),
             PoolError::InvalidAmount => write!(f, "invalid amount"),
+            PoolError::InvalidMarketConfig => write!(f, "invalid market configuration"),
+            PoolError::MarketModeLocked => {
+                write!(f, "market mode can't change while orders depend on it")
+            }
             PoolError::NoRouteFound => write!(f, "no route found"),
             PoolError::InsufficientLiquidity => write!(f, "insufficient liquidity"),
             PoolError::SlippageExceeded => write!(f, "slippage tolerance exceeded"),
+            PoolError::OrderNotFound(order_id) => write!(f, "order not found: {:?}", order_id),
+            PoolError::Unauthorized { caller } => write!(f, "unauthorized caller: {:?}", caller),
+            PoolError::InsufficientBalance {
+                account,
+                token,
+                required,
+                available,
+            } => write!(
+                f,
+                "insufficient balance: account={:?}, token={:?}, required={}, available={}",
+                account, token, required, available
+            ),
             PoolError::OrderError(e) => write!(f, "order error: {}", e),
+            PoolError::LiquidityError(e) => write!(f, "liquidity error: {}", e),
         }
     }
 }
@@ -571,6 +1862,7 @@ impl std::error::Error for PoolError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MarketMode;
     use crate::types::ETH_TOKEN;
 
     fn setup_tokens() -> (TokenId, TokenId, TokenId) {
@@ -605,6 +1897,46 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_create_pair_with_market_config() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, _) = setup_tokens();
+        let market = MarketConfig::default()
+            .with_tick_size(Price::from_u128(1, 1))
+            .with_lot_size(U256::from(1_000));
+
+        pm.create_pair_with_config(eth, usdc, market.clone())
+            .unwrap();
+        assert_eq!(pm.market_config(eth, usdc), Some(&market));
+
+        // Re-creating with the same parameters is idempotent, different ones are rejected
+        pm.create_pair_with_config(eth, usdc, market.clone())
+            .unwrap();
+        assert!(matches!(
+            pm.create_pair(eth, usdc),
+            Err(PoolError::PairAlreadyExists { .. })
+        ));
+
+        // A zero lot size is unusable
+        assert!(matches!(
+            pm.set_market_config(eth, usdc, market.with_lot_size(U256::ZERO)),
+            Err(PoolError::InvalidMarketConfig)
+        ));
+
+        let result = pm.place_limit_order(
+            eth,
+            usdc,
+            test_trader(1),
+            OrderSide::Sell,
+            Price::from_u128(2000, 1),
+            U256::from(1_500),
+        );
+        assert!(matches!(
+            result,
+            Err(PoolError::OrderError(OrderError::AmountNotOnLot))
+        ));
+    }
+
     #[test]
     fn test_place_orders() {
         let mut pm = PoolManager::new();
@@ -628,6 +1960,288 @@ mod tests {
         assert!(result.fills.is_empty());
     }
 
+    #[test]
+    fn test_cancel_order_ownership() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, _) = setup_tokens();
+        let owner = test_trader(1);
+        let other = test_trader(2);
+
+        pm.create_pair(eth, usdc).unwrap();
+
+        let (order_id, _) = pm
+            .place_limit_order(
+                eth,
+                usdc,
+                owner,
+                OrderSide::Sell,
+                Price::from_u128(2000, 1),
+                U256::from(1000),
+            )
+            .unwrap();
+
+        // Someone else can't cancel the order
+        assert_eq!(
+            pm.cancel_order(other, order_id).unwrap_err(),
+            PoolError::Unauthorized { caller: other }
+        );
+        assert!(pm.get_order(order_id).is_some());
+
+        // The owner can, and gets the order back for refunding
+        let (pair, cancelled, _) = pm.cancel_order(owner, order_id).unwrap();
+        assert_eq!(pair, Pair::new(eth, usdc));
+        assert_eq!(cancelled.remaining_escrow(), U256::from(1000));
+        assert!(pm.get_order(order_id).is_none());
+
+        // Cancelling again fails
+        assert_eq!(
+            pm.cancel_order(owner, order_id).unwrap_err(),
+            PoolError::OrderNotFound(order_id)
+        );
+    }
+
+    #[test]
+    fn test_order_ids_unique_across_pairs() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, wbtc) = setup_tokens();
+        let maker = test_trader(1);
+        let taker = test_trader(2);
+
+        pm.create_pair(eth, usdc).unwrap();
+        pm.create_pair(wbtc, usdc).unwrap();
+
+        let (eth_order, _) = pm
+            .place_limit_order(
+                eth,
+                usdc,
+                maker,
+                OrderSide::Sell,
+                Price::from_u128(2000, 1),
+                U256::from(1000),
+            )
+            .unwrap();
+        let (wbtc_order, _) = pm
+            .place_limit_order(
+                wbtc,
+                usdc,
+                maker,
+                OrderSide::Sell,
+                Price::from_u128(40000, 1),
+                U256::from(1000),
+            )
+            .unwrap();
+
+        // The first order on each book no longer shares an ID
+        assert_ne!(eth_order, wbtc_order);
+        assert_eq!(pm.order_pair(eth_order), Some(Pair::new(eth, usdc)));
+        assert_eq!(pm.order_pair(wbtc_order), Some(Pair::new(wbtc, usdc)));
+
+        // Fully filled makers drop out of the index
+        let result = pm
+            .place_market_order(eth, usdc, taker, OrderSide::Buy, U256::from(1000))
+            .unwrap();
+        assert_ne!(result.taker_order_id, eth_order);
+        assert_ne!(result.taker_order_id, wbtc_order);
+        assert_eq!(pm.order_pair(eth_order), None);
+        assert!(pm.get_order(wbtc_order).is_some());
+    }
+
+    #[test]
+    fn test_fee_vault() {
+        let config = DexConfig::default().with_maker_fee_bps(10);
+        let mut pm = PoolManager::with_config(config);
+        let (eth, usdc, _) = setup_tokens();
+        pm.create_pair(eth, usdc).unwrap();
+        let price = Price::from_u128(2, 1);
+
+        // Taker buys from a selling maker: both fees are taken in quote
+        pm.place_limit_order(
+            eth,
+            usdc,
+            test_trader(1),
+            OrderSide::Sell,
+            price,
+            U256::from(5000),
+        )
+        .unwrap();
+        pm.place_market_order(eth, usdc, test_trader(2), OrderSide::Buy, U256::from(5000))
+            .unwrap();
+        // 10000 quote: 30 taker + 10 maker
+        assert_eq!(pm.accrued_fees(usdc), U256::from(40));
+        assert_eq!(pm.accrued_fees(eth), U256::ZERO);
+
+        // Taker sells to a buying maker: the maker's fee is withheld in base
+        pm.place_limit_order(
+            eth,
+            usdc,
+            test_trader(1),
+            OrderSide::Buy,
+            price,
+            U256::from(5000),
+        )
+        .unwrap();
+        pm.place_market_order(eth, usdc, test_trader(2), OrderSide::Sell, U256::from(5000))
+            .unwrap();
+        assert_eq!(pm.accrued_fees(usdc), U256::from(70));
+        // 10 quote at a price of 2 is 5 base
+        assert_eq!(pm.accrued_fees(eth), U256::from(5));
+
+        assert_eq!(pm.withdraw_fees(usdc), U256::from(70));
+        assert_eq!(pm.accrued_fees(usdc), U256::ZERO);
+        assert_eq!(pm.withdraw_fees(usdc), U256::ZERO);
+        assert_eq!(pm.fee_balances().len(), 1);
+
+        // Fees whose payout failed are withdrawn again later
+        pm.return_fees(usdc, U256::from(70));
+        assert_eq!(pm.accrued_fees(usdc), U256::from(70));
+    }
+
+    #[test]
+    fn test_balances() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, _) = setup_tokens();
+        let trader = test_trader(1);
+
+        pm.credit(trader, usdc, U256::from(1000));
+        pm.credit(trader, usdc, U256::from(500));
+        assert_eq!(pm.balance(trader, usdc), U256::from(1500));
+        assert_eq!(pm.balance(trader, eth), U256::ZERO);
+        assert_eq!(pm.balance(test_trader(2), usdc), U256::ZERO);
+
+        pm.debit(trader, usdc, U256::from(600)).unwrap();
+        assert_eq!(pm.balance(trader, usdc), U256::from(900));
+
+        // An overdraft fails and leaves the balance untouched
+        let err = pm.debit(trader, usdc, U256::from(901)).unwrap_err();
+        assert_eq!(
+            err,
+            PoolError::InsufficientBalance {
+                account: trader,
+                token: usdc,
+                required: U256::from(901),
+                available: U256::from(900),
+            }
+        );
+        assert_eq!(pm.balance(trader, usdc), U256::from(900));
+
+        pm.debit(trader, usdc, U256::from(900)).unwrap();
+        assert_eq!(pm.balance(trader, usdc), U256::ZERO);
+        assert!(pm.debit(trader, eth, U256::from(1)).is_err());
+    }
+
+    #[test]
+    fn test_orders_stamped_with_block_context() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, _) = setup_tokens();
+        let trader = test_trader(1);
+
+        pm.create_pair(eth, usdc).unwrap();
+
+        let block = BlockContext::new(42, 3, 1_700_000_000);
+        pm.set_block_context(block);
+
+        let (order_id, _) = pm
+            .place_limit_order(
+                eth,
+                usdc,
+                trader,
+                OrderSide::Sell,
+                Price::from_u128(2000, 1),
+                U256::from(1000),
+            )
+            .unwrap();
+
+        let (_, order) = pm.get_order(order_id).unwrap();
+        assert_eq!(order.placed_at, block);
+    }
+
+    #[test]
+    fn test_trade_history_records_batch_auctions() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, _) = setup_tokens();
+        let batch = MarketConfig::default().with_mode(MarketMode::BatchAuction);
+        pm.create_pair_with_config(eth, usdc, batch).unwrap();
+        let price = Price::from_u128(2000, 1);
+
+        let (sell_id, _) = pm
+            .place_limit_order(
+                eth,
+                usdc,
+                test_trader(1),
+                OrderSide::Sell,
+                price,
+                U256::from(100),
+            )
+            .unwrap();
+        pm.place_limit_order(
+            eth,
+            usdc,
+            test_trader(2),
+            OrderSide::Buy,
+            price,
+            U256::from(60),
+        )
+        .unwrap();
+        assert!(pm.recent_trades(eth, usdc, 10).is_empty());
+
+        // Auction fills are recorded when the block ends, with the sell order as taker
+        let block = BlockContext::new(7, 0, 1_700_000_000);
+        pm.set_block_context(block);
+        pm.clear_batch_auctions();
+
+        let trades = pm.recent_trades(eth, usdc, 10);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].taker_order_id, sell_id);
+        assert_eq!(trades[0].taker_side, OrderSide::Sell);
+        assert_eq!(trades[0].executed_at, block);
+
+        let candles = pm.candles(eth, usdc, CandleInterval::Block, 10);
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].start, 7);
+        assert_eq!(candles[0].close, price);
+        assert_eq!(candles[0].volume, U256::from(60));
+    }
+
+    #[test]
+    fn test_failed_swap_execution_leaves_nothing_behind() {
+        let mut pm = PoolManager::new();
+        let (eth, usdc, _) = setup_tokens();
+        let pair = pm.create_pair(eth, usdc).unwrap();
+        let trader = test_trader(1);
+        pm.place_limit_order(
+            eth,
+            usdc,
+            trader,
+            OrderSide::Sell,
+            Price::from_u128(2, 1),
+            U256::from(5000),
+        )
+        .unwrap();
+        let (maker_id, _) = pm
+            .place_limit_order(
+                eth,
+                usdc,
+                test_trader(2),
+                OrderSide::Sell,
+                Price::from_u128(3, 1),
+                U256::from(5000),
+            )
+            .unwrap();
+
+        // The quote counts the trader's own ask, which the swap trades past,
+        // so the swap pays out less than quoted and fails its dry run
+        let quote = pm.get_quote(usdc, eth, U256::from(6000)).unwrap();
+        let result = pm.execute_swap(trader, usdc, eth, U256::from(6000), quote.amount_out);
+        assert_eq!(result.unwrap_err(), PoolError::SlippageExceeded);
+
+        let (_, maker) = pm.get_order(maker_id).unwrap();
+        assert_eq!(maker.remaining_amount, U256::from(5000));
+        assert_eq!(pm.accrued_fees(usdc), U256::ZERO);
+        let orderbook = pm.get_orderbook(&pair).unwrap();
+        assert!(orderbook.history().trades().is_empty());
+        assert_eq!(pm.generate_order_id(), OrderId(maker_id.0 + 1));
+    }
+
     #[test]
     fn test_direct_quote() {
         let mut pm = PoolManager::new();
@@ -649,7 +2263,7 @@ mod tests {
 
         // Get quote for buying ETH with USDC
         let quote = pm
-            .get_quote(usdc, eth, U256::from(2000_000_000u128)) // 2000 USDC (assuming 6 decimals)
+            .get_quote(usdc, eth, U256::from(2_000_000_000u128)) // 2000 USDC (assuming 6 decimals)
             .unwrap();
 
         assert_eq!(quote.token_in, usdc);
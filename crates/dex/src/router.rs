@@ -1,9 +1,9 @@
 //! Multi-hop routing for finding the best path between tokens.
 
-use crate::orderbook::OrderBook;
+use crate::orderbook::{MarketSimulation, OrderBook};
 use crate::pair::{Pair, PairId};
 use crate::types::{Amount, TokenId, U256};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
 /// A single hop in a route.
 #[derive(Debug, Clone)]
@@ -43,6 +43,26 @@ impl Route {
     pub fn token_out(&self) -> Option<TokenId> {
         self.hops.last().map(|h| h.token_out)
     }
+
+    /// Check if this route trades on any pair that `other` trades on.
+    pub fn shares_pair_with(&self, other: &Route) -> bool {
+        self.hops
+            .iter()
+            .any(|hop| other.hops.iter().any(|o| o.pair.id() == hop.pair.id()))
+    }
+}
+
+/// The part of a swap's input sent down one route.
+#[derive(Debug, Clone)]
+pub struct RouteSplit {
+    /// The route taken by this part.
+    pub route: Route,
+    /// The input sent down the route.
+    pub amount_in: Amount,
+    /// The output received from the route.
+    pub amount_out: Amount,
+    /// What each hop of the route is expected to do, in route order.
+    pub hops: Vec<MarketSimulation>,
 }
 
 /// A quote for a swap.
@@ -56,20 +76,27 @@ pub struct Quote {
     pub amount_in: Amount,
     /// The expected output amount (after fees).
     pub amount_out: Amount,
-    /// The route to execute.
+    /// The route to execute, or the one taking the largest share of a split.
     pub route: Route,
-    /// Estimated price impact in basis points.
+    /// Price impact in basis points: how far the swap's rate falls short of
+    /// the mid prices composed across the hops, not counting fees.
     pub price_impact: U256,
+    /// Price impact of each hop of `route` in basis points.
+    pub hop_impacts: Vec<U256>,
     /// Total fees paid.
     pub total_fee: Amount,
+    /// How the input is divided between routes, one entry for `route` unless
+    /// the swap is split.
+    pub splits: Vec<RouteSplit>,
 }
 
 /// Router for finding paths between tokens.
 #[derive(Debug)]
 pub struct Router {
     /// Graph of token connections.
-    /// Maps each token to a set of tokens it can be traded with directly.
-    graph: HashMap<TokenId, HashSet<TokenId>>,
+    /// Maps each token to a set of tokens it can be traded with directly,
+    /// ordered so every node explores them in the same order.
+    graph: HashMap<TokenId, BTreeSet<TokenId>>,
     /// Maps token pairs to their pair info.
     pairs: HashMap<(TokenId, TokenId), Pair>,
 }
@@ -86,14 +113,8 @@ impl Router {
     /// Add a trading pair to the router.
     pub fn add_pair(&mut self, pair: Pair) {
         // Add bidirectional edges
-        self.graph
-            .entry(pair.base)
-            .or_insert_with(HashSet::new)
-            .insert(pair.quote);
-        self.graph
-            .entry(pair.quote)
-            .or_insert_with(HashSet::new)
-            .insert(pair.base);
+        self.graph.entry(pair.base).or_default().insert(pair.quote);
+        self.graph.entry(pair.quote).or_default().insert(pair.base);
 
         // Store pair info for both directions
         self.pairs.insert((pair.base, pair.quote), pair);
@@ -162,6 +183,116 @@ impl Router {
         routes
     }
 
+    /// Find the most promising routes between two tokens up to a maximum
+    /// number of hops, without enumerating every path.
+    ///
+    /// Each hop is weighted by the negative log of the best rate its book
+    /// offers, so the lightest route pays the most at the top of the book. At
+    /// every depth only the `max_candidates` lightest partial routes reaching
+    /// each token are extended, and the `max_candidates` lightest complete
+    /// routes are returned shortest first. Weights are fixed point and equal
+    /// weights keep the order the search found the routes in, so every node
+    /// picks the same routes. On graphs with no more than `max_candidates`
+    /// paths through any token this finds the same routes as
+    /// [`Router::find_routes`].
+    pub fn find_candidate_routes(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        max_hops: usize,
+        max_candidates: usize,
+        orderbooks: &HashMap<PairId, OrderBook>,
+    ) -> Vec<Route> {
+        // Partial routes are kept as labels pointing back at the label they
+        // extend, so extending one never copies its path
+        let mut labels = vec![SearchLabel {
+            token: token_in,
+            weight: 0,
+            parent: None,
+            hop: None,
+        }];
+        let mut frontier = vec![0];
+        let mut complete: Vec<(usize, usize)> = Vec::new();
+
+        for depth in 1..=max_hops {
+            let mut reached: BTreeMap<TokenId, Vec<usize>> = BTreeMap::new();
+
+            for &index in &frontier {
+                let token = labels[index].token;
+                let Some(neighbors) = self.graph.get(&token) else {
+                    continue;
+                };
+
+                // The last hop has to reach the output token, so there is no
+                // need to look at any other neighbor
+                let (all, last) = if depth == max_hops {
+                    (None, neighbors.get(&token_out))
+                } else {
+                    (Some(neighbors), None)
+                };
+
+                for &neighbor in all.into_iter().flatten().chain(last) {
+                    // Avoid cycles
+                    if visits(&labels, index, neighbor) {
+                        continue;
+                    }
+                    let Some(pair) = self.pairs.get(&(token, neighbor)) else {
+                        continue;
+                    };
+                    let Some(orderbook) = orderbooks.get(&pair.id()) else {
+                        continue;
+                    };
+
+                    labels.push(SearchLabel {
+                        token: neighbor,
+                        weight: labels[index]
+                            .weight
+                            .saturating_add(hop_weight(pair, token, orderbook)),
+                        parent: Some(index),
+                        hop: Some(RouteHop {
+                            pair: *pair,
+                            token_in: token,
+                            token_out: neighbor,
+                        }),
+                    });
+
+                    let label = labels.len() - 1;
+                    if neighbor == token_out {
+                        complete.push((label, depth));
+                    } else {
+                        reached.entry(neighbor).or_default().push(label);
+                    }
+                }
+            }
+
+            // Only the lightest partial routes into each token go on
+            frontier.clear();
+            for mut into in reached.into_values() {
+                into.sort_by_key(|&label| labels[label].weight);
+                into.truncate(max_candidates);
+                frontier.extend(into);
+            }
+        }
+
+        complete.sort_by_key(|&(label, _)| labels[label].weight);
+        complete.truncate(max_candidates);
+        complete.sort_by_key(|&(_, depth)| depth);
+
+        complete
+            .into_iter()
+            .map(|(label, depth)| {
+                let mut hops = Vec::with_capacity(depth);
+                let mut current = Some(label);
+                while let Some(index) = current {
+                    hops.extend(labels[index].hop.clone());
+                    current = labels[index].parent;
+                }
+                hops.reverse();
+                Route { hops }
+            })
+            .collect()
+    }
+
     /// Convert a path of tokens to a Route.
     fn path_to_route(
         &self,
@@ -240,7 +371,7 @@ impl Router {
             let orderbook = orderbooks.get(&pair_id)?;
 
             // Determine direction
-            let (output, _) = if orderbook.pair.base == hop.token_in {
+            let simulation = if orderbook.pair.base == hop.token_in {
                 // Selling base for quote
                 orderbook.simulate_market_sell(current_amount, config)?
             } else {
@@ -248,7 +379,7 @@ impl Router {
                 orderbook.simulate_market_buy(current_amount, config)?
             };
 
-            current_amount = output;
+            current_amount = simulation.amount_out;
         }
 
         Some(current_amount)
@@ -293,6 +424,76 @@ impl Router {
     }
 }
 
+/// A partial route found by [`Router::find_candidate_routes`].
+#[derive(Debug)]
+struct SearchLabel {
+    /// The token the partial route ends at.
+    token: TokenId,
+    /// Sum of the hop weights so far.
+    weight: i64,
+    /// The label this one extends, `None` at the input token.
+    parent: Option<usize>,
+    /// The hop taken from the parent label.
+    hop: Option<RouteHop>,
+}
+
+/// Whether the partial route ending at `index` passes through `token`.
+fn visits(labels: &[SearchLabel], index: usize, token: TokenId) -> bool {
+    let mut current = Some(index);
+    while let Some(index) = current {
+        if labels[index].token == token {
+            return true;
+        }
+        current = labels[index].parent;
+    }
+    false
+}
+
+/// Fractional bits of the fixed-point hop weights.
+const WEIGHT_FRACTION_BITS: u32 = 32;
+
+/// Weight of trading `token_in` across `pair`: the negative base 2 log of the
+/// best rate its book or liquidity pool offers, in fixed point, or `i64::MAX`
+/// when neither has anything to trade against.
+fn hop_weight(pair: &Pair, token_in: TokenId, orderbook: &OrderBook) -> i64 {
+    let rate = if pair.base == token_in {
+        orderbook.best_bid_with_pool()
+    } else {
+        orderbook.best_ask_with_pool().map(|ask| ask.invert())
+    };
+
+    match rate {
+        Some(rate) if !rate.numerator.is_zero() && !rate.denominator.is_zero() => {
+            log2_fixed(rate.denominator) - log2_fixed(rate.numerator)
+        }
+        _ => i64::MAX,
+    }
+}
+
+/// Base 2 log of a non-zero `value` with [`WEIGHT_FRACTION_BITS`] fractional
+/// bits, rounded down. Integer only, so it is the same on every node.
+fn log2_fixed(value: U256) -> i64 {
+    let msb = 255 - value.leading_zeros();
+    let mut log = (msb as i64) << WEIGHT_FRACTION_BITS;
+
+    // The value scaled into [1, 2) with 127 fractional bits, so squaring it
+    // stays within 256 bits
+    let mut mantissa = if msb > 127 {
+        value >> (msb - 127)
+    } else {
+        value << (127 - msb)
+    };
+    let two = U256::from(1) << 128;
+    for bit in (0..WEIGHT_FRACTION_BITS).rev() {
+        mantissa = (mantissa * mantissa) >> 127;
+        if mantissa >= two {
+            mantissa >>= 1;
+            log |= 1 << bit;
+        }
+    }
+    log
+}
+
 impl Default for Router {
     fn default() -> Self {
         Self::new()
@@ -302,7 +503,9 @@ impl Default for Router {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::ETH_TOKEN;
+    use crate::config::DexConfig;
+    use crate::order::{Order, OrderId, OrderSide};
+    use crate::types::{BlockContext, Price, ETH_TOKEN};
 
     fn setup_tokens() -> (TokenId, TokenId, TokenId, TokenId) {
         let eth = ETH_TOKEN;
@@ -369,6 +572,132 @@ mod tests {
         assert_eq!(routes[0].len(), 1);
     }
 
+    fn path(route: &Route) -> Vec<TokenId> {
+        let mut tokens: Vec<TokenId> = route.hops.iter().map(|h| h.token_in).collect();
+        tokens.extend(route.token_out());
+        tokens
+    }
+
+    fn place_bid(book: &mut OrderBook, id: u64, price: u128) {
+        let order = Order::new_limit(
+            OrderId(id),
+            Address::repeat_byte(0xAA),
+            OrderSide::Buy,
+            Price::from_u128(price, 1),
+            U256::from(1000),
+            BlockContext::default(),
+        );
+        book.place_order(order, &DexConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn test_find_candidate_routes_matches_find_routes() {
+        let mut router = Router::new();
+        let (eth, usdc, wbtc, dai) = setup_tokens();
+
+        // Every pair between the four tokens, only some of them with bids
+        let pairs = [
+            Pair::new(eth, usdc),
+            Pair::new(eth, wbtc),
+            Pair::new(eth, dai),
+            Pair::new(wbtc, usdc),
+            Pair::new(wbtc, dai),
+            Pair::new(dai, usdc),
+        ];
+        let mut orderbooks = HashMap::new();
+        for (i, pair) in pairs.into_iter().enumerate() {
+            router.add_pair(pair);
+            let mut book = OrderBook::new(pair);
+            if i % 2 == 0 {
+                place_bid(&mut book, i as u64, 10 + i as u128);
+            }
+            orderbooks.insert(pair.id(), book);
+        }
+
+        for max_hops in 1..=3 {
+            let mut expected: Vec<_> = router
+                .find_routes(eth, usdc, max_hops, &orderbooks)
+                .iter()
+                .map(path)
+                .collect();
+            let candidates = router.find_candidate_routes(eth, usdc, max_hops, 8, &orderbooks);
+            assert!(candidates.windows(2).all(|w| w[0].len() <= w[1].len()));
+
+            let mut found: Vec<_> = candidates.iter().map(path).collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_find_candidate_routes_keeps_best_priced() {
+        let mut router = Router::new();
+        let (eth, usdc, _, _) = setup_tokens();
+
+        // ETH -> X -> USDC through three intermediaries, paying
+        // 2 * 100, 2 * 150 and 3 * 50 USDC per ETH at the top of the book
+        let mut orderbooks = HashMap::new();
+        for (i, (first, second)) in [(2, 100), (2, 150), (3, 50)].into_iter().enumerate() {
+            let token = Address::repeat_byte(0x10 + i as u8);
+            for (pair, price) in [
+                (Pair::new(eth, token), first),
+                (Pair::new(token, usdc), second),
+            ] {
+                router.add_pair(pair);
+                let mut book = OrderBook::new(pair);
+                place_bid(&mut book, i as u64, price);
+                orderbooks.insert(pair.id(), book);
+            }
+        }
+
+        assert_eq!(router.find_routes(eth, usdc, 2, &orderbooks).len(), 3);
+
+        let routes = router.find_candidate_routes(eth, usdc, 2, 1, &orderbooks);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(
+            path(&routes[0]),
+            vec![eth, Address::repeat_byte(0x11), usdc]
+        );
+    }
+
+    #[test]
+    fn test_find_candidate_routes_breaks_ties_deterministically() {
+        let (eth, usdc, _, _) = setup_tokens();
+        let intermediaries = [Address::repeat_byte(0x10), Address::repeat_byte(0x11)];
+
+        // Two ETH -> X -> USDC routes paying the same at the top of the book,
+        // added to the router in either order
+        for order in [[0, 1], [1, 0]] {
+            let mut router = Router::new();
+            let mut orderbooks = HashMap::new();
+            for i in order {
+                let token = intermediaries[i];
+                for pair in [Pair::new(eth, token), Pair::new(token, usdc)] {
+                    router.add_pair(pair);
+                    let mut book = OrderBook::new(pair);
+                    place_bid(&mut book, i as u64, 10);
+                    orderbooks.insert(pair.id(), book);
+                }
+            }
+
+            let routes = router.find_candidate_routes(eth, usdc, 2, 1, &orderbooks);
+            assert_eq!(path(&routes[0]), vec![eth, intermediaries[0], usdc]);
+        }
+    }
+
+    #[test]
+    fn test_log2_fixed() {
+        let one = 1i64 << WEIGHT_FRACTION_BITS;
+        assert_eq!(log2_fixed(U256::from(1)), 0);
+        assert_eq!(log2_fixed(U256::from(1024)), 10 * one);
+        assert_eq!(log2_fixed(U256::MAX) >> WEIGHT_FRACTION_BITS, 255);
+
+        // log2(3) = 1.5849625007...
+        let log3 = log2_fixed(U256::from(3));
+        assert_eq!(log3 * 10_000 / one, 15_849);
+    }
+
     #[test]
     fn test_reachable_tokens() {
         let mut router = Router::new();
@@ -2,7 +2,11 @@
 //!
 //! Re-exports from alloy-primitives for Ethereum-compatible types.
 
-pub use alloy::primitives::{Address, U256};
+pub use alloy::primitives::{Address, I256, U256};
+
+use alloy::primitives::U512;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
 /// Unique identifier for a token (contract address).
 /// For ETH, use `Address::ZERO`.
@@ -15,9 +19,39 @@ pub const ETH_TOKEN: TokenId = Address::ZERO;
 /// This is in the smallest unit (e.g., wei for ETH, smallest decimal for ERC-20).
 pub type Amount = U256;
 
+/// Position of a transaction on chain.
+///
+/// Orders are stamped with the context of the transaction that placed them so that
+/// replaying the same block always produces the same order state. The derived ordering
+/// follows chain position (block number, then transaction index) and defines time priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockContext {
+    /// Number of the block containing the transaction.
+    pub block_number: u64,
+    /// Index of the transaction within the block.
+    pub tx_index: u64,
+    /// Timestamp of the block (unix seconds).
+    pub timestamp: u64,
+}
+
+impl BlockContext {
+    /// Create a new block context.
+    pub fn new(block_number: u64, tx_index: u64, timestamp: u64) -> Self {
+        Self {
+            block_number,
+            tx_index,
+            timestamp,
+        }
+    }
+}
+
 /// Price represented as a rational number (numerator/denominator) for precision.
 /// Price is expressed as: how much quote token per 1 unit of base token.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+///
+/// Prices are kept in canonical form (numerator and denominator reduced by their
+/// greatest common divisor), so equal ratios such as 1/2 and 2/4 are the same value.
+/// Equality and hashing compare the ratio, not the raw fields.
+#[derive(Debug, Clone, Copy)]
 pub struct Price {
     /// Numerator of the price ratio.
     pub numerator: U256,
@@ -26,13 +60,32 @@ pub struct Price {
 }
 
 impl Price {
-    /// Create a new price. Panics if denominator is zero.
+    /// Create a new price in canonical form. Panics if denominator is zero.
     pub fn new(numerator: U256, denominator: U256) -> Self {
         assert!(!denominator.is_zero(), "price denominator cannot be zero");
         Self {
             numerator,
             denominator,
         }
+        .normalized()
+    }
+
+    /// Reduce the price to canonical form by dividing out the greatest common divisor.
+    /// A zero price is represented as 0/1.
+    pub fn normalized(&self) -> Self {
+        let gcd = self.numerator.gcd(self.denominator);
+        if gcd.is_zero() {
+            return *self;
+        }
+        Self {
+            numerator: self.numerator / gcd,
+            denominator: self.denominator / gcd,
+        }
+    }
+
+    /// Check whether the price is already in canonical form.
+    pub fn is_canonical(&self) -> bool {
+        self.numerator.gcd(self.denominator) == U256::from(1)
     }
 
     /// Create a price from u128 values for convenience.
@@ -65,11 +118,11 @@ impl Price {
     }
 
     /// Compare two prices. Returns ordering based on their ratio values.
-    pub fn cmp_value(&self, other: &Self) -> std::cmp::Ordering {
-        // Compare a/b vs c/d by comparing a*d vs c*b
-        // Use checked_mul to avoid overflow, fall back to comparison if overflow
-        let lhs = self.numerator.saturating_mul(other.denominator);
-        let rhs = other.numerator.saturating_mul(self.denominator);
+    pub fn cmp_value(&self, other: &Self) -> Ordering {
+        // Compare a/b vs c/d by comparing a*d vs c*b.
+        // Multiply into 512 bits so the comparison is exact for any U256 inputs.
+        let lhs: U512 = self.numerator.widening_mul(other.denominator);
+        let rhs: U512 = other.numerator.widening_mul(self.denominator);
         lhs.cmp(&rhs)
     }
 
@@ -90,14 +143,31 @@ impl Price {
     }
 }
 
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp_value(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl Hash for Price {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Hash the canonical form so equal ratios hash identically
+        let canonical = self.normalized();
+        canonical.numerator.hash(state);
+        canonical.denominator.hash(state);
+    }
+}
+
 impl PartialOrd for Price {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp_value(other))
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Price {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    fn cmp(&self, other: &Self) -> Ordering {
         self.cmp_value(other)
     }
 }
@@ -125,6 +195,16 @@ mod tests {
         assert!(!usdc.is_zero());
     }
 
+    #[test]
+    fn test_block_context_ordering() {
+        let early = BlockContext::new(10, 5, 1_000);
+        let same_block_later = BlockContext::new(10, 6, 1_000);
+        let next_block = BlockContext::new(11, 0, 1_002);
+
+        assert!(early < same_block_later);
+        assert!(same_block_later < next_block);
+    }
+
     #[test]
     fn test_price_calculations() {
         // Price of 2 (2 quote per 1 base)
@@ -161,6 +241,44 @@ mod tests {
         assert!(p1 < p3);
     }
 
+    #[test]
+    fn test_price_canonical_form() {
+        let half = Price::from_u128(1, 2);
+        let two_quarters = Price::from_u128(2, 4);
+
+        // Constructors reduce to lowest terms
+        assert_eq!(two_quarters.numerator, U256::from(1));
+        assert_eq!(two_quarters.denominator, U256::from(2));
+        assert!(two_quarters.is_canonical());
+        assert_eq!(half, two_quarters);
+
+        // Raw field construction compares and hashes by value too
+        let raw = Price {
+            numerator: U256::from(3),
+            denominator: U256::from(6),
+        };
+        assert!(!raw.is_canonical());
+        assert_eq!(raw, half);
+        assert_eq!(raw.normalized().numerator, U256::from(1));
+
+        let mut set = std::collections::HashSet::new();
+        set.insert(half);
+        assert!(set.contains(&raw));
+
+        // Zero is 0/1
+        let zero = Price::from_u128(0, 7);
+        assert_eq!(zero.denominator, U256::from(1));
+    }
+
+    #[test]
+    fn test_price_ordering_large_values() {
+        // Cross products overflow U256 but must still compare exactly
+        let a = Price::new(U256::MAX, U256::MAX - U256::from(1));
+        let b = Price::new(U256::MAX - U256::from(1), U256::MAX - U256::from(2));
+        assert!(a < b);
+        assert_ne!(a, b);
+    }
+
     #[test]
     fn test_price_invert() {
         let price = Price::from_u128(3, 4); // 0.75
@@ -0,0 +1,258 @@
+//! Differential tests: a quote must promise exactly what executing it does.
+//!
+//! Each case builds a random market over a few tokens, with resting orders at
+//! several price levels on every pair, icebergs and one-cancels-other orders
+//! among them, and liquidity pools trading alongside some of the books. It
+//! then swaps random amounts between random tokens, comparing every quote with
+//! the swap that executes it straight after.
+//!
+//! `DEX_DIFFERENTIAL_CASES` sets how many random markets are tried, for a
+//! longer run than the default, and `DEX_DIFFERENTIAL_SEED` replays the single
+//! case a failure names.
+
+use dex::pool_manager::SwapResult;
+use dex::{Address, OrderSide, PoolManager, Price, Quote, U256};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+
+const CASES: u64 = 25;
+const TOKENS: u64 = 5;
+const SWAPS: usize = 10;
+
+/// Deterministic generator so a failing case can be replayed from its seed.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok()?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} must be a number, got {value:?}")),
+    )
+}
+
+/// Seeds of the cases to run: the one in `DEX_DIFFERENTIAL_SEED`, otherwise
+/// the first `DEX_DIFFERENTIAL_CASES`, `CASES` by default.
+fn seeds() -> Range<u64> {
+    match env_u64("DEX_DIFFERENTIAL_SEED") {
+        Some(seed) => seed..seed + 1,
+        None => 0..env_u64("DEX_DIFFERENTIAL_CASES").unwrap_or(CASES),
+    }
+}
+
+/// Run `case` with a generator for every seed, naming the seed to replay when
+/// one fails, including on panics from inside the DEX. Returns the number of
+/// cases run.
+fn for_each_seed(mut case: impl FnMut(u64, &mut Lcg)) -> usize {
+    let seeds = seeds();
+    for seed in seeds.clone() {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| case(seed, &mut Lcg(seed))));
+        if outcome.is_err() {
+            panic!("case failed, replay it with DEX_DIFFERENTIAL_SEED={seed}");
+        }
+    }
+    seeds.count()
+}
+
+fn token(index: u64) -> Address {
+    Address::left_padding_from(&(index + 1).to_be_bytes())
+}
+
+fn maker(index: u64) -> Address {
+    Address::repeat_byte(0xA0 + index as u8)
+}
+
+fn provider() -> Address {
+    Address::repeat_byte(0xB0)
+}
+
+fn swapper() -> Address {
+    Address::repeat_byte(0xEE)
+}
+
+/// A market where some token pairs trade and others are only reachable
+/// through intermediate tokens.
+fn random_market(rng: &mut Lcg) -> PoolManager {
+    let mut pm = PoolManager::new();
+
+    for quote in 0..TOKENS {
+        for base in quote + 1..TOKENS {
+            if rng.next(3) == 0 {
+                continue;
+            }
+            pm.create_pair(token(base), token(quote)).unwrap();
+            let mid = 200 + rng.next(2000);
+            for _ in 0..1 + rng.next(8) {
+                place_random_order(&mut pm, rng, base, quote, mid);
+            }
+            if rng.next(2) == 0 {
+                let base_amount = 10_000 + rng.next(1_000_000);
+                pm.add_liquidity(
+                    token(base),
+                    token(quote),
+                    provider(),
+                    U256::from(base_amount),
+                    U256::from(base_amount * mid / 8),
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    pm
+}
+
+/// Place a resting order a few ticks off `mid`, in eighths of a quote unit
+/// so fills round.
+fn place_random_order(pm: &mut PoolManager, rng: &mut Lcg, base: u64, quote: u64, mid: u64) {
+    let maker = maker(rng.next(3));
+    let offset = 1 + rng.next(40);
+    let (side, price) = match rng.next(2) {
+        0 => (OrderSide::Buy, mid - offset),
+        _ => (OrderSide::Sell, mid + offset),
+    };
+    let price = Price::from_u128(price as u128, 8);
+    let amount = U256::from(1 + rng.next(100_000));
+    let (base, quote) = (token(base), token(quote));
+
+    match rng.next(4) {
+        0 => {
+            let display_amount = amount / U256::from(2 + rng.next(10)) + U256::from(1);
+            pm.place_iceberg_order(base, quote, maker, side, price, amount, display_amount)
+                .unwrap();
+        }
+        1 => {
+            // A stop further out than the limit, on the side it protects
+            let stop_price = match side {
+                OrderSide::Buy => Price::from_u128((mid + 2 * offset) as u128, 8),
+                OrderSide::Sell => Price::from_u128((mid - 2 * offset) as u128, 8),
+            };
+            // Stops the last trade has already passed are skipped
+            let _ = pm.place_oco_order(base, quote, maker, side, amount, price, stop_price, None);
+        }
+        _ => {
+            pm.place_limit_order(base, quote, maker, side, price, amount)
+                .unwrap();
+        }
+    }
+}
+
+/// A swap amount of up to `digits` digits, small ones as likely as large ones.
+fn random_amount(rng: &mut Lcg, digits: u64) -> U256 {
+    let bound = 10u64.pow(1 + rng.next(digits) as u32);
+    U256::from(1 + rng.next(bound))
+}
+
+/// Check that a swap did what its quote promised, hop by hop.
+fn assert_executed_as_quoted(seed: u64, quote: &Quote, result: &SwapResult) {
+    assert_eq!(result.splits.len(), quote.splits.len(), "seed {seed}");
+    for (executed, quoted) in result.splits.iter().zip(&quote.splits) {
+        // A split can be offered more than its book takes, and an exact output
+        // comes out over the amount asked for by whatever the last hop rounds up
+        let (first, last) = (&quoted.hops[0], quoted.hops.last().unwrap());
+        assert_eq!(executed.amount_in, first.amount_in, "seed {seed}");
+        assert_eq!(executed.amount_out, last.amount_out, "seed {seed}");
+    }
+
+    let hops: Vec<_> = quote.splits.iter().flat_map(|split| &split.hops).collect();
+    assert_eq!(result.trades.len(), hops.len(), "seed {seed}");
+    for (trade, hop) in result.trades.iter().zip(hops) {
+        let fee = trade
+            .fills
+            .iter()
+            .fold(U256::ZERO, |acc, fill| acc + fill.taker_fee);
+        assert_eq!(fee, hop.fee, "seed {seed}");
+        assert_eq!(trade.fills.len(), hop.orders_consumed, "seed {seed}");
+    }
+}
+
+#[test]
+fn test_quote_matches_execution() {
+    let mut swaps = 0;
+    let mut multi_hop = 0;
+
+    let cases = for_each_seed(|seed, rng| {
+        let mut pm = random_market(rng);
+
+        for _ in 0..SWAPS {
+            let token_in = rng.next(TOKENS);
+            let token_out = (token_in + 1 + rng.next(TOKENS - 1)) % TOKENS;
+            let amount_in = random_amount(rng, 8);
+
+            let Ok(quote) = pm.get_quote(token(token_in), token(token_out), amount_in) else {
+                continue;
+            };
+            let result = pm
+                .execute_swap(
+                    swapper(),
+                    token(token_in),
+                    token(token_out),
+                    amount_in,
+                    quote.amount_out,
+                )
+                .unwrap_or_else(|err| panic!("seed {seed}: {err:?}"));
+
+            assert_eq!(result.amount_out, quote.amount_out, "seed {seed}");
+            assert_executed_as_quoted(seed, &quote, &result);
+            swaps += 1;
+            if quote.splits.iter().any(|split| split.route.hops.len() > 1) {
+                multi_hop += 1;
+            }
+        }
+    });
+
+    // The random markets exercise both direct and routed swaps, which a single
+    // replayed case needn't
+    if cases > 1 {
+        assert!(swaps > cases, "only {swaps} swaps quoted");
+        assert!(multi_hop > cases / 4, "only {multi_hop} routed swaps");
+    }
+}
+
+#[test]
+fn test_exact_out_quote_matches_execution() {
+    let mut swaps = 0;
+
+    let cases = for_each_seed(|seed, rng| {
+        let mut pm = random_market(rng);
+
+        for _ in 0..SWAPS {
+            let token_in = rng.next(TOKENS);
+            let token_out = (token_in + 1 + rng.next(TOKENS - 1)) % TOKENS;
+            let amount_out = random_amount(rng, 7);
+
+            let Ok(quote) = pm.get_quote_exact_out(token(token_in), token(token_out), amount_out)
+            else {
+                continue;
+            };
+            let result = pm
+                .execute_swap_exact_out(
+                    swapper(),
+                    token(token_in),
+                    token(token_out),
+                    amount_out,
+                    quote.amount_in,
+                )
+                .unwrap_or_else(|err| panic!("seed {seed}: {err:?}"));
+
+            assert_eq!(result.amount_in, quote.amount_in, "seed {seed}");
+            assert!(result.amount_out >= amount_out, "seed {seed}");
+            assert_executed_as_quoted(seed, &quote, &result);
+            swaps += 1;
+        }
+    });
+
+    if cases > 1 {
+        assert!(swaps > cases, "only {swaps} swaps quoted");
+    }
+}
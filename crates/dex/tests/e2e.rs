@@ -1,7 +1,9 @@
 //! End-to-end tests for the DEX orderbook.
 
 use dex::{
-    Address, DexConfig, OrderSide, Pair, PoolManager, Price, U256,
+    Address, BlockContext, CandleInterval, DexConfig, LiquidityError, MarketConfig, MarketMode,
+    OrderError, OrderGroupKind, OrderSide, OrderStatus, Pair, PoolError, PoolManager, Price,
+    SelfTradePrevention, TimeInForce, I256, U256,
 };
 
 // Token addresses for testing
@@ -272,8 +274,10 @@ fn test_limit_order_full_match_multiple_levels() {
 
     // Verify price-time priority (cheapest first)
     // First fill should be at $2000
-    let first_fill_price: u128 = result.fills[0].price.numerator.try_into().unwrap();
-    assert_eq!(first_fill_price, 2000 * 10u128.pow(6));
+    assert_eq!(
+        result.fills[0].price,
+        Price::from_u128(2000 * 10u128.pow(6), 10u128.pow(18))
+    );
 }
 
 #[test]
@@ -403,6 +407,88 @@ fn test_multi_hop_routing() {
     assert_eq!(quote.route.hops[1].token_out, usdc());
 }
 
+#[test]
+fn test_quote_price_impact() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    // ETH/USDC trades around a mid of $1995: selling 5 ETH at $1990 gives up
+    // 25 bps, and selling 15 ETH reaches the $1980 bid for an average of ~$1986.67
+    let quote = pm.get_quote(eth(), usdc(), eth_amount(5)).unwrap();
+    assert_eq!(quote.price_impact, U256::from(25));
+    assert_eq!(quote.hop_impacts, vec![U256::from(25)]);
+
+    let quote = pm.get_quote(eth(), usdc(), eth_amount(15)).unwrap();
+    assert_eq!(quote.price_impact, U256::from(41));
+
+    // Buying at the $2000 ask is 25 bps above the mid
+    let quote = pm.get_quote(usdc(), eth(), usdc_amount(10000)).unwrap();
+    assert_eq!(quote.price_impact, U256::from(25));
+
+    // LINK/ETH trades around a mid of 0.0095 ETH, so selling LINK at 0.009
+    // gives up ~526 bps before the ETH hop gives up another 25
+    pm.place_limit_order(
+        link(),
+        eth(),
+        alice(),
+        OrderSide::Sell,
+        Price::from_u128(10u128.pow(16), 10u128.pow(18)),
+        U256::from(10000) * U256::from(10u64.pow(18)),
+    )
+    .unwrap();
+    pm.place_limit_order(
+        link(),
+        eth(),
+        alice(),
+        OrderSide::Buy,
+        Price::from_u128(9 * 10u128.pow(15), 10u128.pow(18)),
+        U256::from(10000) * U256::from(10u64.pow(18)),
+    )
+    .unwrap();
+
+    let link_amount = U256::from(100) * U256::from(10u64.pow(18));
+    let quote = pm.get_quote(link(), usdc(), link_amount).unwrap();
+    assert_eq!(quote.route.hops.len(), 2);
+    assert_eq!(quote.hop_impacts, vec![U256::from(526), U256::from(25)]);
+
+    // 1 - (18 / 19) * (1990 / 1995) of the mid rate is lost across both hops
+    assert_eq!(quote.price_impact, U256::from(550));
+}
+
+#[test]
+fn test_quote_detail() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    // 15 ETH fills the $1990 bid and half the $1980 one, paying 0.30% on the
+    // $29,800 they fetch
+    let quote = pm.get_quote(eth(), usdc(), eth_amount(15)).unwrap();
+    assert_eq!(quote.splits.len(), 1);
+    let hop = &quote.splits[0].hops[0];
+    assert_eq!(hop.amount_in, eth_amount(15));
+    assert_eq!(
+        hop.fee,
+        usdc_amount(29800) * U256::from(3) / U256::from(1000)
+    );
+    assert_eq!(hop.amount_out, usdc_amount(29800) - hop.fee);
+    assert_eq!(hop.amount_out, quote.amount_out);
+    assert_eq!(hop.fee, quote.total_fee);
+    assert_eq!(
+        hop.worst_price,
+        Price::from_u128(1980 * 10u128.pow(6), 10u128.pow(18))
+    );
+    assert_eq!(hop.levels_consumed, 2);
+    assert_eq!(hop.orders_consumed, 2);
+    assert!(!hop.liquidity_exhausted);
+
+    // The bids only hold 40 ETH
+    let quote = pm.get_quote(eth(), usdc(), eth_amount(50)).unwrap();
+    let hop = &quote.splits[0].hops[0];
+    assert_eq!(hop.amount_in, eth_amount(40));
+    assert_eq!(hop.levels_consumed, 5);
+    assert!(hop.liquidity_exhausted);
+}
+
 #[test]
 fn test_execute_swap_direct() {
     let mut pm = setup_market();
@@ -446,6 +532,187 @@ fn test_execute_swap_with_slippage_protection() {
     );
 }
 
+#[test]
+fn test_execute_swap_buy_spends_quote() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    // Spend 30,000 USDC on ETH: all 10 ETH at $2000, then part of the $2010 level
+    let quote = pm.get_quote(usdc(), eth(), usdc_amount(30000)).unwrap();
+    let result = pm
+        .execute_swap(bob(), usdc(), eth(), usdc_amount(30000), U256::ZERO)
+        .unwrap();
+
+    assert_eq!(result.amount_out, quote.amount_out);
+    assert!(result.amount_out > eth_amount(14) && result.amount_out < eth_amount(15));
+
+    // Fills and fees use up the budget, short of the dust too small to buy more
+    let fills = &result.trades[0].fills;
+    let spent = fills
+        .iter()
+        .fold(U256::ZERO, |acc, f| acc + f.quote_amount + f.taker_fee);
+    assert_eq!(result.amount_in, spent);
+    assert!(spent <= usdc_amount(30000));
+    assert!(usdc_amount(30000) - spent < U256::from(10u64.pow(6)));
+}
+
+#[test]
+fn test_execute_multi_hop_swap_matches_quote() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    pm.place_limit_order(
+        link(),
+        eth(),
+        alice(),
+        OrderSide::Sell,
+        Price::from_u128(10u128.pow(18), 100 * 10u128.pow(18)), // 0.01 ETH per LINK
+        U256::from(10000) * U256::from(10u64.pow(18)),
+    )
+    .unwrap();
+
+    // USDC -> ETH -> LINK buys on both hops, each sized by what the last one bought
+    let quote = pm.get_quote(usdc(), link(), usdc_amount(10000)).unwrap();
+    assert_eq!(quote.route.hops.len(), 2);
+
+    let result = pm
+        .execute_swap(bob(), usdc(), link(), usdc_amount(10000), U256::ZERO)
+        .unwrap();
+
+    assert_eq!(result.amount_out, quote.amount_out);
+    // About 4.98 ETH after fees, which buys about 497 LINK
+    assert!(result.amount_out > U256::from(490) * U256::from(10u64.pow(18)));
+}
+
+#[test]
+fn test_execute_swap_exact_out_direct() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    // 15 ETH: 10 at $2000 and 5 at $2010, plus the 0.30% taker fee on each
+    let quote = pm
+        .get_quote_exact_out(usdc(), eth(), eth_amount(15))
+        .unwrap();
+    assert_eq!(quote.amount_in, U256::from(30_140_150_000u64));
+    assert_eq!(quote.route.hops.len(), 1);
+
+    // Averaging ~$2003.33 is ~41 bps above the $1995 mid
+    assert_eq!(quote.price_impact, U256::from(41));
+
+    // Too little input allowed for the quote
+    let result =
+        pm.execute_swap_exact_out(bob(), usdc(), eth(), eth_amount(15), usdc_amount(30000));
+    assert_eq!(result.unwrap_err(), PoolError::SlippageExceeded);
+
+    let result = pm
+        .execute_swap_exact_out(bob(), usdc(), eth(), eth_amount(15), usdc_amount(40000))
+        .unwrap();
+    assert_eq!(result.amount_out, eth_amount(15));
+    assert_eq!(result.amount_in, quote.amount_in);
+
+    // Selling for an exact amount of USDC receives at least that much
+    let quote = pm
+        .get_quote_exact_out(eth(), usdc(), usdc_amount(10000))
+        .unwrap();
+    let result = pm
+        .execute_swap_exact_out(bob(), eth(), usdc(), usdc_amount(10000), eth_amount(10))
+        .unwrap();
+    assert!(result.amount_out >= usdc_amount(10000));
+    assert_eq!(result.amount_in, quote.amount_in);
+}
+
+#[test]
+fn test_execute_multi_hop_swap_exact_out() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    pm.place_limit_order(
+        link(),
+        eth(),
+        alice(),
+        OrderSide::Sell,
+        Price::from_u128(10u128.pow(18), 100 * 10u128.pow(18)), // 0.01 ETH per LINK
+        U256::from(10000) * U256::from(10u64.pow(18)),
+    )
+    .unwrap();
+
+    // Walking back from 500 LINK: 5 ETH plus fees on LINK/ETH, bought with USDC
+    let link_out = U256::from(500) * U256::from(10u64.pow(18));
+    let quote = pm.get_quote_exact_out(usdc(), link(), link_out).unwrap();
+    assert_eq!(quote.route.hops.len(), 2);
+    assert!(quote.amount_in > usdc_amount(10000));
+
+    let result = pm
+        .execute_swap_exact_out(bob(), usdc(), link(), link_out, usdc_amount(11000))
+        .unwrap();
+    assert_eq!(result.amount_out, link_out);
+    assert_eq!(result.amount_in, quote.amount_in);
+
+    // The first hop buys exactly the ETH the second spends
+    assert!(result.leftovers.is_empty());
+}
+
+/// Set up ETH/USDC bids alongside an ETH -> DAI -> USDC path paying slightly less.
+fn split_route_market(config: DexConfig) -> PoolManager {
+    let mut pm = PoolManager::with_config(config);
+    pm.create_pair(eth(), usdc()).unwrap();
+    pm.create_pair(eth(), dai()).unwrap();
+    pm.create_pair(dai(), usdc()).unwrap();
+    add_eth_usdc_liquidity(&mut pm);
+
+    // 20 ETH bid at 1995 DAI, and DAI bid at 1 USDC
+    pm.place_limit_order(
+        eth(),
+        dai(),
+        charlie(),
+        OrderSide::Buy,
+        Price::from_u128(1995, 1),
+        eth_amount(20),
+    )
+    .unwrap();
+    pm.place_limit_order(
+        dai(),
+        usdc(),
+        charlie(),
+        OrderSide::Buy,
+        Price::from_u128(10u128.pow(6), 10u128.pow(18)),
+        U256::from(100_000) * U256::from(10u64.pow(18)),
+    )
+    .unwrap();
+    pm
+}
+
+#[test]
+fn test_split_route_swap() {
+    // Alone, the direct route pays 39,580.9 USDC for 20 ETH and the DAI route
+    // 39,660.96. Split, the first 10 ETH take the $1990 bids and the other 10
+    // go through DAI, where they pay more than the $1980 bids
+    let single = split_route_market(DexConfig::default().with_max_split_routes(1))
+        .get_quote(eth(), usdc(), eth_amount(20))
+        .unwrap();
+    assert_eq!(single.splits.len(), 1);
+
+    let mut pm = split_route_market(DexConfig::default());
+    let quote = pm.get_quote(eth(), usdc(), eth_amount(20)).unwrap();
+    assert!(quote.amount_out > single.amount_out);
+    assert_eq!(quote.splits.len(), 2);
+    assert_eq!(quote.splits[0].amount_in, eth_amount(10));
+    assert_eq!(quote.splits[1].amount_in, eth_amount(10));
+
+    let result = pm
+        .execute_swap(bob(), eth(), usdc(), eth_amount(20), U256::ZERO)
+        .unwrap();
+    assert_eq!(result.amount_in, eth_amount(20));
+    assert_eq!(result.amount_out, quote.amount_out);
+    assert_eq!(result.splits.len(), 2);
+    for (executed, quoted) in result.splits.iter().zip(&quote.splits) {
+        assert_eq!(executed.route.len(), quoted.route.len());
+        assert_eq!(executed.amount_out, quoted.amount_out);
+    }
+    // One trade for the direct route and two for the route through DAI
+    assert_eq!(result.trades.len(), 3);
+}
+
 #[test]
 fn test_orderbook_depth() {
     let mut pm = setup_market();
@@ -489,14 +756,253 @@ fn test_cancel_order() {
     let orderbook = pm.get_orderbook(&pair).unwrap();
     assert!(orderbook.get_order(order_id).is_some());
 
+    // Only the owner can cancel it
+    assert_eq!(
+        pm.cancel_order(alice(), order_id).unwrap_err(),
+        dex::pool_manager::PoolError::Unauthorized { caller: alice() }
+    );
+
     // Cancel it
-    pm.cancel_order(eth(), usdc(), order_id).unwrap();
+    let (cancelled_pair, cancelled, linked_cancel) = pm.cancel_order(bob(), order_id).unwrap();
+    assert_eq!(cancelled_pair, pair);
+    assert_eq!(cancelled.remaining_amount, eth_amount(10));
+    assert!(linked_cancel.is_none());
 
     // Verify it's gone
     let orderbook = pm.get_orderbook(&pair).unwrap();
     assert!(orderbook.get_order(order_id).is_none());
 }
 
+#[test]
+fn test_amend_order() {
+    let mut pm = setup_market();
+    let usd_price = |usd: u128| Price::from_u128(usd * 10u128.pow(6), 10u128.pow(18));
+
+    let (order_id, _) = pm
+        .place_limit_order(
+            eth(),
+            usdc(),
+            bob(),
+            OrderSide::Buy,
+            usd_price(1800),
+            eth_amount(10),
+        )
+        .unwrap();
+
+    // Only the owner can amend it
+    assert_eq!(
+        pm.amend_order(alice(), order_id, eth_amount(5), usd_price(1800))
+            .unwrap_err(),
+        PoolError::Unauthorized { caller: alice() }
+    );
+
+    // Halving the size keeps priority and frees half of the escrow
+    pm.set_block_context(BlockContext::new(2, 0, 0));
+    let (pair, amended) = pm
+        .amend_order(bob(), order_id, eth_amount(5), usd_price(1800))
+        .unwrap();
+    assert_eq!(pair, Pair::new(eth(), usdc()));
+    assert!(amended.kept_priority);
+    assert_eq!(amended.previous.remaining_escrow(), usdc_amount(18_000));
+    assert_eq!(amended.order.remaining_escrow(), usdc_amount(9_000));
+
+    // Raising the price needs more escrow, which the balance must cover
+    assert_eq!(
+        pm.amend_order(bob(), order_id, eth_amount(5), usd_price(1850))
+            .unwrap_err(),
+        PoolError::InsufficientBalance {
+            account: bob(),
+            token: usdc(),
+            required: usdc_amount(250),
+            available: U256::ZERO,
+        }
+    );
+    let (_, order) = pm.get_order(order_id).unwrap();
+    assert_eq!(order.price, usd_price(1800));
+
+    // and loses priority
+    pm.credit(bob(), usdc(), usdc_amount(250));
+    let (_, amended) = pm
+        .amend_order(bob(), order_id, eth_amount(5), usd_price(1850))
+        .unwrap();
+    assert!(!amended.kept_priority);
+    assert_eq!(amended.order.placed_at, BlockContext::new(2, 0, 0));
+    assert_eq!(amended.order.remaining_escrow(), usdc_amount(9_250));
+
+    let (_, order) = pm.get_order(order_id).unwrap();
+    assert_eq!(order.price, usd_price(1850));
+    assert_eq!(order.remaining_amount, eth_amount(5));
+}
+
+#[test]
+fn test_iceberg_order_depth() {
+    let mut pm = setup_market();
+    let price = Price::from_u128(2100 * 10u128.pow(6), 10u128.pow(18));
+
+    let (order_id, _) = pm
+        .place_iceberg_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Sell,
+            price,
+            eth_amount(10),
+            eth_amount(2),
+        )
+        .unwrap();
+
+    // The depth view only shows the clip
+    let pair = Pair::new(eth(), usdc());
+    let orderbook = pm.get_orderbook(&pair).unwrap();
+    assert_eq!(orderbook.ask_liquidity(5), vec![(price, eth_amount(2))]);
+
+    // The hidden reserve still fills, one clip at a time
+    let result = pm
+        .place_market_order(eth(), usdc(), bob(), OrderSide::Buy, eth_amount(3))
+        .unwrap();
+    assert!(result.fully_filled);
+    assert_eq!(result.fills.len(), 2);
+
+    let (_, order) = pm.get_order(order_id).unwrap();
+    assert_eq!(order.remaining_amount, eth_amount(7));
+    assert_eq!(order.visible_amount, eth_amount(1));
+
+    let orderbook = pm.get_orderbook(&pair).unwrap();
+    assert_eq!(orderbook.ask_liquidity(5), vec![(price, eth_amount(1))]);
+}
+
+#[test]
+fn test_oco_order() {
+    let mut pm = setup_market();
+    let usd = |price: u128| Price::from_u128(price * 10u128.pow(6), 10u128.pow(18));
+
+    // Bob takes profit at $2100 or stops out at $1900, whichever comes first
+    let (limit_id, stop_id) = pm
+        .place_oco_order(
+            eth(),
+            usdc(),
+            bob(),
+            OrderSide::Sell,
+            eth_amount(5),
+            usd(2100),
+            usd(1900),
+            None,
+        )
+        .unwrap();
+    let group = pm.order_group(stop_id).unwrap();
+    assert_eq!(group.kind, OrderGroupKind::OneCancelsOther);
+    assert_eq!(group.orders, vec![limit_id, stop_id]);
+    assert!(pm.get_order(stop_id).is_some());
+
+    // A partial fill of the limit order cancels the stop order
+    let result = pm
+        .place_market_order(eth(), usdc(), charlie(), OrderSide::Buy, eth_amount(2))
+        .unwrap();
+    assert_eq!(result.linked_cancels.len(), 1);
+    assert_eq!(result.linked_cancels[0].order.id, stop_id);
+    assert!(pm.get_order(stop_id).is_none());
+
+    // The group is dropped once none of its orders is open
+    let (_, cancelled, linked_cancel) = pm.cancel_order(bob(), limit_id).unwrap();
+    assert_eq!(cancelled.remaining_amount, eth_amount(3));
+    assert!(linked_cancel.is_none());
+    assert!(pm.order_group(limit_id).is_none());
+}
+
+#[test]
+fn test_bracket_order() {
+    let mut pm = setup_market();
+    let usd = |price: u128| Price::from_u128(price * 10u128.pow(6), 10u128.pow(18));
+
+    let (entry_id, result) = pm
+        .place_bracket_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Buy,
+            usd(2000),
+            eth_amount(2),
+            usd(2200),
+            usd(1800),
+            Some(usd(1790)),
+        )
+        .unwrap();
+    assert!(result.brackets.is_empty());
+    let group = pm.order_group(entry_id).unwrap().clone();
+    assert_eq!(group.kind, OrderGroupKind::Bracket);
+    let (take_profit_id, stop_loss_id) = (group.orders[1], group.orders[2]);
+    assert!(pm.get_order(take_profit_id).is_none());
+
+    // Filling the entry places both exits
+    let result = pm
+        .place_market_order(eth(), usdc(), bob(), OrderSide::Sell, eth_amount(2))
+        .unwrap();
+    assert_eq!(result.brackets.len(), 1);
+    assert_eq!(result.brackets[0].entry_id, entry_id);
+    let (_, take_profit) = pm.get_order(take_profit_id).unwrap();
+    assert_eq!(take_profit.side, OrderSide::Sell);
+    assert_eq!(take_profit.remaining_amount, eth_amount(2));
+    let (_, stop_loss) = pm.get_order(stop_loss_id).unwrap();
+    assert_eq!(stop_loss.stop_price, Some(usd(1800)));
+
+    // Taking profit cancels the stop-loss and closes the bracket
+    let result = pm
+        .place_market_order(eth(), usdc(), charlie(), OrderSide::Buy, eth_amount(2))
+        .unwrap();
+    assert_eq!(result.fills[0].maker_order_id, take_profit_id);
+    assert_eq!(result.linked_cancels[0].order.id, stop_loss_id);
+    assert!(pm.get_order(stop_loss_id).is_none());
+    assert!(pm.order_group(entry_id).is_none());
+}
+
+#[test]
+fn test_range_order() {
+    let mut pm = setup_market();
+    let usd = |price: u128| Price::from_u128(price * 10u128.pow(6), 10u128.pow(18));
+
+    let (order_id, _) = pm
+        .place_range_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Sell,
+            usd(2010),
+            usd(2000),
+            eth_amount(1),
+        )
+        .unwrap();
+
+    // Filling the sell re-posts the $2010 it received as a buy at $2000
+    let bought = eth_amount(1) * U256::from(1005) / U256::from(1000);
+    let result = pm
+        .place_market_order(eth(), usdc(), bob(), OrderSide::Buy, eth_amount(1))
+        .unwrap();
+    assert_eq!(result.flipped.len(), 1);
+    assert_eq!(result.flipped[0].refund, U256::ZERO);
+    let (_, order) = pm.get_order(order_id).unwrap();
+    assert_eq!(order.side, OrderSide::Buy);
+    assert_eq!(order.price, usd(2000));
+    assert_eq!(order.remaining_amount, bought);
+
+    // Filling the buy flips it back, now selling the 1.005 ETH it bought
+    let result = pm
+        .place_market_order(eth(), usdc(), charlie(), OrderSide::Sell, bought)
+        .unwrap();
+    assert_eq!(result.flipped.len(), 1);
+    let (_, order) = pm.get_order(order_id).unwrap();
+    assert_eq!(order.side, OrderSide::Sell);
+    assert_eq!(order.price, usd(2010));
+
+    // Range orders can't be amended, only cancelled
+    let err = pm
+        .amend_order(alice(), order_id, eth_amount(1), usd(2010))
+        .unwrap_err();
+    assert!(matches!(err, PoolError::OrderError(OrderError::RangeOrder)));
+    let (_, cancelled, _) = pm.cancel_order(alice(), order_id).unwrap();
+    assert_eq!(cancelled.status, OrderStatus::Cancelled);
+    assert!(pm.get_order(order_id).is_none());
+}
+
 #[test]
 fn test_multiple_traders() {
     let mut pm = setup_market();
@@ -539,8 +1045,10 @@ fn test_multiple_traders() {
     assert!(result.fully_filled);
 
     // The fill should be at Bob's price ($1990)
-    let fill_price: u128 = result.fills[0].price.numerator.try_into().unwrap();
-    assert_eq!(fill_price, 1990 * 10u128.pow(6));
+    assert_eq!(
+        result.fills[0].price,
+        Price::from_u128(1990 * 10u128.pow(6), 10u128.pow(18))
+    );
 }
 
 #[test]
@@ -594,6 +1102,71 @@ fn test_all_stats() {
     assert_eq!(all_stats.len(), 5);
 }
 
+#[test]
+fn test_trade_history_and_candles() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+    let usdc_per_eth = |price: u128| Price::from_u128(price * 10u128.pow(6), 10u128.pow(18));
+
+    // Two blocks in the same minute, then one in the next minute
+    pm.set_block_context(BlockContext::new(10, 0, 1_700_000_000));
+    pm.place_market_order(eth(), usdc(), bob(), OrderSide::Buy, eth_amount(15))
+        .unwrap();
+    pm.set_block_context(BlockContext::new(11, 0, 1_700_000_012));
+    pm.place_market_order(eth(), usdc(), charlie(), OrderSide::Sell, eth_amount(12))
+        .unwrap();
+    pm.set_block_context(BlockContext::new(12, 0, 1_700_000_090));
+    pm.place_market_order(eth(), usdc(), bob(), OrderSide::Buy, eth_amount(1))
+        .unwrap();
+
+    // The tape lists the latest trades first
+    let trades = pm.recent_trades(eth(), usdc(), 10);
+    assert_eq!(trades.len(), 5);
+    assert_eq!(trades[0].sequence, 4);
+    assert_eq!(trades[0].taker_side, OrderSide::Buy);
+    assert_eq!(trades[0].fill.price, usdc_per_eth(2010));
+    assert_eq!(trades[0].executed_at.block_number, 12);
+    assert_eq!(trades[2].taker_side, OrderSide::Sell);
+    assert_eq!(trades[4].fill.price, usdc_per_eth(2000));
+    assert_eq!(pm.recent_trades(eth(), usdc(), 2).len(), 2);
+
+    let blocks = pm.candles(eth(), usdc(), CandleInterval::Block, 10);
+    let starts: Vec<u64> = blocks.iter().map(|c| c.start).collect();
+    assert_eq!(starts, vec![10, 11, 12]);
+    assert_eq!(blocks[0].open, usdc_per_eth(2000));
+    assert_eq!(blocks[0].close, usdc_per_eth(2010));
+    assert_eq!(blocks[0].volume, eth_amount(15));
+    assert_eq!(blocks[0].quote_volume, usdc_amount(20_000 + 10_050));
+
+    let minutes = pm.candles(eth(), usdc(), CandleInterval::Minute, 10);
+    assert_eq!(minutes.len(), 2);
+    assert_eq!(minutes[0].start, 1_699_999_980);
+    assert_eq!(minutes[0].open, usdc_per_eth(2000));
+    assert_eq!(minutes[0].high, usdc_per_eth(2010));
+    assert_eq!(minutes[0].low, usdc_per_eth(1980));
+    assert_eq!(minutes[0].close, usdc_per_eth(1980));
+    assert_eq!(minutes[0].volume, eth_amount(27));
+    assert_eq!(minutes[0].trade_count, 4);
+
+    // A limit keeps the latest candles
+    let latest = pm.candles(eth(), usdc(), CandleInterval::Minute, 1);
+    assert_eq!(latest, vec![minutes[1]]);
+    assert_eq!(latest[0].start, 1_700_000_040);
+
+    let days = pm.candles(eth(), usdc(), CandleInterval::Day, 10);
+    assert_eq!(days.len(), 1);
+    assert_eq!(days[0].volume, eth_amount(28));
+    assert_eq!(days[0].trade_count, 5);
+
+    // Pairs without trades have an empty history
+    assert!(pm.recent_trades(wbtc(), usdc(), 10).is_empty());
+    assert!(pm
+        .trade_history(wbtc(), usdc())
+        .unwrap()
+        .last_trade()
+        .is_none());
+}
+
 #[test]
 fn test_no_self_trade_default() {
     let mut pm = setup_market();
@@ -627,7 +1200,7 @@ fn test_no_self_trade_default() {
 
 #[test]
 fn test_self_trade_allowed() {
-    let config = DexConfig::default().with_self_trade(true);
+    let config = DexConfig::default().with_self_trade_prevention(SelfTradePrevention::Allow);
     let mut pm = PoolManager::with_config(config);
 
     pm.create_pair(eth(), usdc()).unwrap();
@@ -660,6 +1233,45 @@ fn test_self_trade_allowed() {
     assert!(result.fully_filled);
 }
 
+#[test]
+fn test_swap_skips_own_orders() {
+    let mut pm = setup_market();
+
+    // Alice's ask is the best on the book, Bob's the next one up
+    let (alice_order, _) = pm
+        .place_limit_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Sell,
+            Price::from_u128(2000 * 10u128.pow(6), 10u128.pow(18)),
+            eth_amount(1),
+        )
+        .unwrap();
+    pm.place_limit_order(
+        eth(),
+        usdc(),
+        bob(),
+        OrderSide::Sell,
+        Price::from_u128(2100 * 10u128.pow(6), 10u128.pow(18)),
+        eth_amount(1),
+    )
+    .unwrap();
+
+    // Alice's swap trades past her own ask with Bob's and leaves hers resting
+    let result = pm
+        .execute_swap(alice(), usdc(), eth(), usdc_amount(1000), U256::ZERO)
+        .unwrap();
+
+    assert!(result.amount_out > U256::ZERO);
+    let fills = &result.trades[0].fills;
+    assert!(fills.iter().all(|fill| fill.maker == bob()));
+    assert!(result.trades[0].prevented.is_empty());
+
+    let (_, order) = pm.get_order(alice_order).unwrap();
+    assert_eq!(order.remaining_amount, eth_amount(1));
+}
+
 #[test]
 fn test_wbtc_usdc_trading() {
     let mut pm = setup_market();
@@ -747,6 +1359,48 @@ fn test_empty_orderbook_quote() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_equivalent_price_submissions_share_level() {
+    let mut pm = setup_market();
+
+    // $2000 expressed three different ways
+    for (num, denom) in [
+        (2000 * 10u128.pow(6), 10u128.pow(18)),
+        (2 * 10u128.pow(9), 10u128.pow(18)),
+        (1, 5 * 10u128.pow(8)),
+    ] {
+        pm.place_limit_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Sell,
+            Price::from_u128(num, denom),
+            eth_amount(1),
+        )
+        .unwrap();
+    }
+
+    let pair = Pair::new(eth(), usdc());
+    let orderbook = pm.get_orderbook(&pair).unwrap();
+    let asks = orderbook.ask_liquidity(10);
+    assert_eq!(asks.len(), 1);
+    assert_eq!(asks[0].1, eth_amount(3));
+
+    // A buyer at the same price clears the single level
+    let (_, result) = pm
+        .place_limit_order(
+            eth(),
+            usdc(),
+            bob(),
+            OrderSide::Buy,
+            Price::from_u128(4000 * 10u128.pow(6), 2 * 10u128.pow(18)),
+            eth_amount(3),
+        )
+        .unwrap();
+    assert!(result.fully_filled);
+    assert_eq!(result.fills.len(), 3);
+}
+
 #[test]
 fn test_min_order_size() {
     let config = DexConfig::default().with_min_order_size(1000);
@@ -766,3 +1420,427 @@ fn test_min_order_size() {
 
     assert!(result.is_err());
 }
+
+#[test]
+fn test_market_parameters() {
+    let mut pm = PoolManager::new();
+    let wei_per_eth = 10u128.pow(18);
+
+    // $0.01 ticks, 0.001 ETH lots and a $10 minimum order
+    let market = MarketConfig::default()
+        .with_tick_size(Price::from_u128(10_000, wei_per_eth))
+        .with_lot_size(eth_amount(1) / U256::from(1000))
+        .with_min_notional(usdc_amount(10));
+    pm.create_pair_with_config(eth(), usdc(), market).unwrap();
+
+    let place = |pm: &mut PoolManager, price: Price, amount: U256| {
+        pm.place_limit_order(eth(), usdc(), alice(), OrderSide::Sell, price, amount)
+            .map(|(order_id, _)| order_id)
+    };
+
+    // $2000.005 is between ticks
+    let result = place(
+        &mut pm,
+        Price::from_u128(2_000_005_000, wei_per_eth),
+        eth_amount(1),
+    );
+    assert!(matches!(
+        result,
+        Err(PoolError::OrderError(OrderError::PriceNotOnTick))
+    ));
+
+    // 1.0005 ETH is not a whole number of lots
+    let odd_amount = eth_amount(1) + eth_amount(1) / U256::from(2000);
+    let result = place(
+        &mut pm,
+        Price::from_u128(2_000_010_000, wei_per_eth),
+        odd_amount,
+    );
+    assert!(matches!(
+        result,
+        Err(PoolError::OrderError(OrderError::AmountNotOnLot))
+    ));
+
+    // 0.001 ETH at $2000 is worth $2
+    let result = place(
+        &mut pm,
+        Price::from_u128(2_000_000_000, wei_per_eth),
+        eth_amount(1) / U256::from(1000),
+    );
+    assert!(matches!(
+        result,
+        Err(PoolError::OrderError(OrderError::BelowMinimumNotional))
+    ));
+
+    // 1 ETH at $2000.01 satisfies every constraint
+    let order_id = place(
+        &mut pm,
+        Price::from_u128(2_000_010_000, wei_per_eth),
+        eth_amount(1),
+    )
+    .unwrap();
+    assert!(pm.get_order(order_id).is_some());
+
+    // Swaps are not bound by the lot size
+    let result = pm
+        .execute_swap(bob(), usdc(), eth(), usdc_amount(1000), U256::ZERO)
+        .unwrap();
+    assert!(result.amount_out > U256::ZERO);
+}
+
+#[test]
+fn test_maker_rebate_on_swap() {
+    // 30 bps taker fee, 10 bps maker rebate
+    let config = DexConfig::default().with_maker_fee_bps(-10);
+    let mut pm = PoolManager::with_config(config);
+    pm.create_pair(eth(), usdc()).unwrap();
+
+    // Bob bids for 1 ETH at $2000
+    let price = Price::new(usdc_amount(2000), eth_amount(1));
+    pm.place_limit_order(eth(), usdc(), bob(), OrderSide::Buy, price, eth_amount(1))
+        .unwrap();
+
+    // Alice sells 1 ETH into the bid
+    let result = pm
+        .execute_swap(alice(), eth(), usdc(), eth_amount(1), U256::ZERO)
+        .unwrap();
+
+    let fill = &result.trades[0].fills[0];
+    assert_eq!(fill.quote_amount, usdc_amount(2000));
+    assert_eq!(fill.taker_fee, usdc_amount(6)); // 0.30% of $2000
+    assert_eq!(fill.maker_fee, -I256::from_raw(usdc_amount(2))); // 0.10% rebate
+    assert_eq!(fill.protocol_fee(), usdc_amount(4));
+
+    // The taker fee is withheld from the taker's quote output
+    assert_eq!(result.amount_out, usdc_amount(1994));
+}
+
+#[test]
+fn test_swap_fees_collected_in_vault() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+
+    // Sell 1 ETH into the $1990 bid: 0.30% of $1990 is withheld from the output
+    let result = pm
+        .execute_swap(charlie(), eth(), usdc(), eth_amount(1), U256::ZERO)
+        .unwrap();
+    let taker_fee = result.trades[0].fills[0].taker_fee;
+    assert_eq!(taker_fee, U256::from(5_970_000u64));
+    assert_eq!(result.amount_out, usdc_amount(1990) - taker_fee);
+
+    // The fee lands in the vault, and withdrawing empties it
+    assert_eq!(pm.accrued_fees(usdc()), taker_fee);
+    assert_eq!(pm.withdraw_fees(usdc()), taker_fee);
+    assert_eq!(pm.accrued_fees(usdc()), U256::ZERO);
+}
+
+#[test]
+fn test_time_in_force() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+    let ask = Price::from_u128(2000 * 10u128.pow(6), 10u128.pow(18));
+
+    // Post-only bid at the best ask would take liquidity
+    let result = pm.place_order(
+        eth(),
+        usdc(),
+        bob(),
+        OrderSide::Buy,
+        ask,
+        eth_amount(1),
+        TimeInForce::PostOnly,
+        None,
+    );
+    assert!(matches!(
+        result,
+        Err(PoolError::OrderError(OrderError::PostOnlyWouldCross))
+    ));
+
+    // Only 10 ETH is offered at $2000, so a 15 ETH fill-or-kill fails untouched
+    let result = pm.place_order(
+        eth(),
+        usdc(),
+        bob(),
+        OrderSide::Buy,
+        ask,
+        eth_amount(15),
+        TimeInForce::FillOrKill,
+        None,
+    );
+    assert!(matches!(
+        result,
+        Err(PoolError::OrderError(OrderError::FillOrKillNotFilled))
+    ));
+
+    // Immediate-or-cancel takes the 10 ETH and drops the rest
+    let (order_id, result) = pm
+        .place_order(
+            eth(),
+            usdc(),
+            bob(),
+            OrderSide::Buy,
+            ask,
+            eth_amount(15),
+            TimeInForce::ImmediateOrCancel,
+            None,
+        )
+        .unwrap();
+    assert_eq!(result.remaining_amount, eth_amount(5));
+    assert!(pm.get_order(order_id).is_none());
+    assert_eq!(
+        pm.get_orderbook(&Pair::new(eth(), usdc()))
+            .unwrap()
+            .best_bid(),
+        Some(Price::from_u128(1990 * 10u128.pow(6), 10u128.pow(18)))
+    );
+}
+
+#[test]
+fn test_stop_orders_cascade() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+    let usd_price = |usd: u128| Price::from_u128(usd * 10u128.pow(6), 10u128.pow(18));
+
+    let stop_market = pm
+        .place_stop_order(
+            eth(),
+            usdc(),
+            bob(),
+            OrderSide::Sell,
+            usd_price(1980),
+            None,
+            eth_amount(10),
+        )
+        .unwrap();
+    let stop_limit = pm
+        .place_stop_order(
+            eth(),
+            usdc(),
+            charlie(),
+            OrderSide::Sell,
+            usd_price(1970),
+            Some(usd_price(1950)),
+            eth_amount(10),
+        )
+        .unwrap();
+    let stop_buy = pm
+        .place_stop_order(
+            eth(),
+            usdc(),
+            charlie(),
+            OrderSide::Buy,
+            usd_price(2100),
+            None,
+            eth_amount(1),
+        )
+        .unwrap();
+
+    // Pending stops are cancelled like resting orders
+    assert!(matches!(
+        pm.cancel_order(bob(), stop_buy),
+        Err(PoolError::Unauthorized { .. })
+    ));
+    pm.cancel_order(charlie(), stop_buy).unwrap();
+
+    // Selling 11 ETH trades down to $1980, triggering the stop market order, whose
+    // fills at $1970 trigger the stop limit order in turn
+    let result = pm
+        .execute_swap(bob(), eth(), usdc(), eth_amount(11), U256::ZERO)
+        .unwrap();
+    let trade = &result.trades[0];
+    let triggered: Vec<_> = trade.triggered.iter().map(|t| t.order.id).collect();
+    assert_eq!(triggered, vec![stop_market, stop_limit]);
+    assert!(trade
+        .triggered
+        .iter()
+        .all(|t| t.order.remaining_amount.is_zero()));
+    assert!(pm.get_order(stop_market).is_none());
+    assert!(pm.get_order(stop_limit).is_none());
+
+    let book = pm.get_orderbook(&Pair::new(eth(), usdc())).unwrap();
+    assert_eq!(book.last_trade_price(), Some(usd_price(1950)));
+    assert_eq!(book.best_bid(), Some(usd_price(1950)));
+
+    // Fees of the activated orders are collected too
+    let fees = trade
+        .fills
+        .iter()
+        .chain(trade.triggered.iter().flat_map(|t| &t.fills))
+        .fold(U256::ZERO, |acc, fill| acc + fill.taker_fee);
+    assert_eq!(pm.accrued_fees(usdc()), fees);
+}
+
+#[test]
+fn test_self_trade_prevention_per_market_and_order() {
+    let mut pm = PoolManager::new();
+    let market =
+        MarketConfig::default().with_self_trade_prevention(SelfTradePrevention::CancelOldest);
+    pm.create_pair_with_config(eth(), usdc(), market).unwrap();
+    let price = Price::from_u128(2000 * 10u128.pow(6), 10u128.pow(18));
+
+    let (ask_id, _) = pm
+        .place_limit_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Sell,
+            price,
+            eth_amount(1),
+        )
+        .unwrap();
+
+    // The market cancels Alice's resting ask, so her bid rests uncrossed
+    let (bid_id, result) = pm
+        .place_limit_order(eth(), usdc(), alice(), OrderSide::Buy, price, eth_amount(2))
+        .unwrap();
+    assert_eq!(result.prevented[0].maker.id, ask_id);
+    assert_eq!(result.prevented[0].maker.status, OrderStatus::Cancelled);
+    assert!(pm.get_order(ask_id).is_none());
+    assert!(pm.get_order(bid_id).is_some());
+
+    // An order can override the market and cancel itself instead
+    let (sell_id, result) = pm
+        .place_order(
+            eth(),
+            usdc(),
+            alice(),
+            OrderSide::Sell,
+            price,
+            eth_amount(1),
+            TimeInForce::GoodTillCancel,
+            Some(SelfTradePrevention::CancelNewest),
+        )
+        .unwrap();
+    assert_eq!(result.prevented[0].maker.id, bid_id);
+    assert!(pm.get_order(sell_id).is_none());
+    assert_eq!(
+        pm.get_order(bid_id).unwrap().1.remaining_amount,
+        eth_amount(2)
+    );
+}
+
+#[test]
+fn test_batch_auction_market() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+    let batch = MarketConfig::default().with_mode(MarketMode::BatchAuction);
+    let usdc_per_eth = |price: u128| Price::from_u128(price * 10u128.pow(6), 10u128.pow(18));
+
+    // A continuous market with resting orders switches as long as nothing is pending
+    pm.set_market_config(eth(), usdc(), batch).unwrap();
+    assert!(pm.get_quote(usdc(), eth(), usdc_amount(1000)).is_err());
+
+    // Orders crossing the book wait for the end of the block
+    let (bid_id, result) = pm
+        .place_limit_order(
+            eth(),
+            usdc(),
+            bob(),
+            OrderSide::Buy,
+            usdc_per_eth(2010),
+            eth_amount(15),
+        )
+        .unwrap();
+    assert!(result.fills.is_empty());
+    assert!(matches!(
+        pm.set_market_config(eth(), usdc(), MarketConfig::default()),
+        Err(PoolError::MarketModeLocked)
+    ));
+    assert!(pm
+        .execute_swap(charlie(), usdc(), eth(), usdc_amount(1000), U256::ZERO)
+        .is_err());
+
+    // Bob's bid meets the asks at 2000 and 2010; 2010 matches all 15 ETH
+    let clearings = pm.clear_batch_auctions();
+    assert_eq!(clearings.len(), 1);
+    let (pair, clearing) = &clearings[0];
+    assert_eq!(*pair, Pair::new(eth(), usdc()));
+    assert_eq!(clearing.price, Some(usdc_per_eth(2010)));
+    assert_eq!(clearing.volume, eth_amount(15));
+    assert!(clearing.fills.iter().all(|f| f.fill.maker_order_id == bid_id));
+    assert!(pm.get_order(bid_id).is_none());
+    assert!(!pm.accrued_fees(usdc()).is_zero());
+
+    // The uncrossed book has nothing left to clear and can switch back
+    assert!(pm.clear_batch_auctions().is_empty());
+    pm.set_market_config(eth(), usdc(), MarketConfig::default())
+        .unwrap();
+    assert!(pm.get_quote(usdc(), eth(), usdc_amount(1000)).is_ok());
+}
+
+#[test]
+fn test_liquidity_pool_market() {
+    let mut pm = setup_market();
+    add_eth_usdc_liquidity(&mut pm);
+    let dai_amount = |dai: u64| U256::from(dai) * U256::from(10u64.pow(18));
+
+    // DAI/USDC has no orders, only a pool at 1 USDC per DAI
+    let deposit = pm
+        .add_liquidity(
+            dai(),
+            usdc(),
+            bob(),
+            dai_amount(1_000_000),
+            usdc_amount(1_000_000),
+        )
+        .unwrap();
+    assert_eq!(deposit.base, dai_amount(1_000_000));
+    assert_eq!(deposit.quote, usdc_amount(1_000_000));
+    let pool = pm.liquidity_pool(dai(), usdc()).unwrap();
+    assert_eq!(pool.shares_of(bob()), deposit.shares);
+
+    // Swaps trade with the pool, and the quote holds
+    let quote = pm.get_quote(usdc(), dai(), usdc_amount(1000)).unwrap();
+    assert!(quote.amount_out < dai_amount(1000) && quote.amount_out > dai_amount(990));
+    let result = pm
+        .execute_swap(
+            charlie(),
+            usdc(),
+            dai(),
+            usdc_amount(1000),
+            quote.amount_out,
+        )
+        .unwrap();
+    assert_eq!(result.amount_out, quote.amount_out);
+    assert!(result.trades[0].fills.iter().all(|f| f.from_pool));
+    assert!(!pm.accrued_fees(usdc()).is_zero());
+
+    // and routes reach through it: ETH sells on the book, the USDC buys DAI
+    let quote = pm.get_quote(eth(), dai(), eth_amount(1)).unwrap();
+    assert_eq!(quote.route.hops.len(), 2);
+    assert!(quote.amount_out > dai_amount(1900));
+
+    // Withdrawing everything returns more than the DAI given up was worth,
+    // the pool's fee included
+    let withdrawal = pm
+        .remove_liquidity(dai(), usdc(), bob(), deposit.shares)
+        .unwrap();
+    let dai_given_up = (deposit.base - withdrawal.base) / U256::from(10u64.pow(12));
+    assert!(withdrawal.quote - deposit.quote > dai_given_up);
+
+    assert!(matches!(
+        pm.remove_liquidity(dai(), usdc(), bob(), U256::from(1)),
+        Err(PoolError::LiquidityError(
+            LiquidityError::InsufficientShares { .. }
+        ))
+    ));
+    assert_eq!(
+        pm.add_liquidity(usdc(), dai(), bob(), usdc_amount(1), dai_amount(1)),
+        Err(PoolError::InvalidPair)
+    );
+
+    // A market with pool liquidity doesn't switch to batch auctions, and a
+    // batch auction market takes none
+    let batch = MarketConfig::default().with_mode(MarketMode::BatchAuction);
+    assert_eq!(
+        pm.set_market_config(dai(), usdc(), batch.clone()),
+        Err(PoolError::MarketModeLocked)
+    );
+    pm.set_market_config(wbtc(), usdc(), batch).unwrap();
+    assert_eq!(
+        pm.add_liquidity(wbtc(), usdc(), bob(), btc_amount(1), usdc_amount(60_000)),
+        Err(PoolError::LiquidityError(
+            LiquidityError::BatchAuctionMarket
+        ))
+    );
+}
@@ -8,6 +8,7 @@ use alloy_eips::Encodable2718;
 use alloy_evm::{Database, EvmError};
 use alloy_op_evm::block::receipt_builder::OpReceiptBuilder;
 use alloy_primitives::{Bytes, U256};
+use dex::BlockContext;
 use op_alloy_consensus::OpDepositReceipt;
 use op_revm::transaction::abstraction::OpTransactionBuilder;
 use op_revm::OpSpecId;
@@ -108,12 +109,13 @@ impl DexPayloadBuilderCtx {
 
     fn build_dex_receipt(
         &self,
+        success: bool,
         cumulative_gas_used: u64,
         logs: Vec<alloy_primitives::Log>,
         deposit_nonce: Option<u64>,
     ) -> OpReceipt {
         let receipt = alloy_consensus::Receipt {
-            status: Eip658Value::Eip658(true),
+            status: Eip658Value::Eip658(success),
             cumulative_gas_used,
             logs,
         };
@@ -141,14 +143,41 @@ impl DexPayloadBuilderCtx {
         let calldata: Bytes = tx.input().clone();
         let value: U256 = tx.value();
 
-        let dex_result = self
+        // Stamp orders with the transaction's chain position so replays are deterministic
+        let block = BlockContext::new(
+            self.block_number(),
+            info.executed_transactions.len() as u64,
+            self.attributes().timestamp(),
+        );
+
+        let dex_result = match self
             .dex_handler
-            .handle_transaction(sender, &calldata, value)
-            .map_err(|e| PayloadBuilderError::Other(Box::new(DexError(e.to_string()))))?;
+            .handle_transaction(sender, &calldata, value, block)
+        {
+            Ok(dex_result) => dex_result,
+            Err(err) => {
+                // A call the caller isn't allowed to make, like cancelling someone
+                // else's order, is included as a reverted transaction
+                let Some(revert_data) = err.revert_data() else {
+                    return Err(PayloadBuilderError::Other(Box::new(DexError(
+                        err.to_string(),
+                    ))));
+                };
+                debug!(target: "payload_builder",
+                    sender = ?sender,
+                    error = %err,
+                    revert_data = %revert_data,
+                    "DEX transaction reverted"
+                );
+                self.record_dex_transaction(tx, info, gas_used, false, Vec::new(), deposit_nonce);
+                return Ok(());
+            }
+        };
 
-        // Execute token transfers via protocolTransfer calls
-        let transfers = self.get_transfers(&dex_result);
+        // Execute deposits and withdrawals on the token via protocolTransfer calls
+        let transfers = self.external_transfers(&dex_result);
         let mut all_logs = Vec::new();
+        let mut transfers_succeeded = true;
 
         // Create EVM instance for executing token transfers
         let mut evm = self.evm_config.evm_with_env(&mut *db, self.evm_env.clone());
@@ -189,18 +218,20 @@ impl DexPayloadBuilderCtx {
                     );
 
                     match tx_result {
-                        Ok(result_and_state) => {
+                        Ok(result_and_state) if result_and_state.result.is_success() => {
                             // Commit the ETH transfer state changes
                             evm.db_mut().commit(result_and_state.state);
                             debug!(target: "payload_builder", "ETH transfer succeeded");
                         }
-                        Err(err) => {
+                        result => {
                             warn!(target: "payload_builder",
-                                ?err,
+                                result = ?result.map(|r| r.result),
                                 to = ?transfer.to,
                                 amount = ?transfer.amount,
                                 "ETH transfer failed"
                             );
+                            transfers_succeeded = false;
+                            break;
                         }
                     }
                 }
@@ -220,47 +251,79 @@ impl DexPayloadBuilderCtx {
             let result = evm.transact_system_call(DEX_PREDEPLOY_ADDRESS, transfer.token, calldata);
 
             match result {
-                Ok(result_and_state) => {
+                Ok(result_and_state) if result_and_state.result.is_success() => {
                     // Commit the state changes
                     evm.db_mut().commit(result_and_state.state);
                     // Collect logs (Transfer events)
                     all_logs.extend(result_and_state.result.into_logs());
                     debug!(target: "payload_builder", "protocolTransfer succeeded");
                 }
-                Err(err) => {
+                result => {
                     warn!(target: "payload_builder",
                         token = ?transfer.token,
                         from = ?transfer.from,
                         to = ?transfer.to,
-                        ?err,
+                        result = ?result.map(|r| r.result),
                         "protocolTransfer failed"
                     );
-                    // Continue with other transfers even if one fails
+                    transfers_succeeded = false;
+                    break;
                 }
             }
         }
 
-        // Add DEX-specific logs
-        let dex_logs = self.dex_handler.create_logs(&dex_result);
-        all_logs.extend(dex_logs);
+        // A deposit is credited once its tokens arrived, a failed withdrawal is credited back
+        self.dex_handler
+            .complete_transfer(&dex_result, transfers_succeeded);
 
+        // Add DEX-specific logs; a failed transfer reverts the call, so it has none
+        if transfers_succeeded {
+            all_logs.extend(self.dex_handler.create_logs(&dex_result));
+        } else {
+            all_logs.clear();
+        }
+
+        self.record_dex_transaction(
+            tx,
+            info,
+            gas_used,
+            transfers_succeeded,
+            all_logs,
+            deposit_nonce,
+        );
+
+        Ok(())
+    }
+
+    /// Add an executed DEX transaction and its receipt to the block.
+    fn record_dex_transaction(
+        &self,
+        tx: &Recovered<OpTransactionSigned>,
+        info: &mut ExecutionInfo,
+        gas_used: u64,
+        success: bool,
+        logs: Vec<alloy_primitives::Log>,
+        deposit_nonce: Option<u64>,
+    ) {
         info.cumulative_gas_used += gas_used;
         info.cumulative_da_bytes_used +=
             op_alloy_flz::tx_estimated_size_fjord(tx.encoded_2718().as_slice());
 
-        let receipt = self.build_dex_receipt(info.cumulative_gas_used, all_logs, deposit_nonce);
+        let receipt =
+            self.build_dex_receipt(success, info.cumulative_gas_used, logs, deposit_nonce);
         info.receipts.push(receipt);
-        info.executed_senders.push(sender);
+        info.executed_senders.push(tx.signer());
         info.executed_transactions.push(tx.clone().into_inner());
-
-        Ok(())
     }
 
-    /// Extract token transfers from a DexResult.
-    fn get_transfers(&self, result: &DexResult) -> Vec<TokenTransfer> {
+    /// Extract the token transfers to execute on chain from a DexResult.
+    /// Trades settle against DEX balances, so only deposits, withdrawals and fee
+    /// payouts move tokens.
+    fn external_transfers(&self, result: &DexResult) -> Vec<TokenTransfer> {
         match result {
-            DexResult::OrderPlaced { transfers, .. } => transfers.clone(),
-            DexResult::SwapExecuted { transfers, .. } => transfers.clone(),
+            DexResult::Deposited { transfers, .. } => transfers.clone(),
+            DexResult::Withdrawn { transfers, .. } => transfers.clone(),
+            DexResult::FeesWithdrawn { transfers, .. } => transfers.clone(),
             _ => Vec::new(),
         }
     }
@@ -363,6 +426,12 @@ impl DexPayloadBuilderCtx {
             evm.db_mut().commit(state);
             info.executed_senders.push(sequencer_tx.signer());
             info.executed_transactions.push(sequencer_tx.into_inner());
+
+            // Auctions clear on the L1 attributes deposit, ahead of anything that
+            // could add orders to them
+            if info.executed_transactions.len() == 1 {
+                self.clear_batch_auctions(&mut info);
+            }
         }
 
         Ok(info)
@@ -457,6 +526,28 @@ impl DexPayloadBuilderCtx {
 
         Ok(())
     }
+
+    /// Clear the batch auctions of batch auction markets on the orders the
+    /// previous block collected, before any other transaction of this block.
+    ///
+    /// No transaction carries the clearing itself, so its logs are added to the
+    /// receipt of the block's first transaction: the L1 attributes deposit every
+    /// OP block starts with, which always succeeds.
+    fn clear_batch_auctions(&self, info: &mut ExecutionInfo) {
+        let block = BlockContext::new(self.block_number(), 0, self.attributes().timestamp());
+        let results = self.dex_handler.clear_batch_auctions(block);
+        if results.is_empty() {
+            return;
+        }
+        let logs = results
+            .iter()
+            .flat_map(|result| self.dex_handler.create_logs(result));
+        if let Some(receipt) = info.receipts.first_mut() {
+            receipt.as_receipt_mut().logs.extend(logs);
+        }
+
+        info!(target: "payload_builder", markets = results.len(), "Batch auctions cleared");
+    }
 }
 
 #[derive(Debug)]
@@ -1,26 +1,81 @@
 //! DEX transaction handler.
 
-use super::types::{DexError, DexResult, TokenTransfer};
+use super::types::{
+    market_mode_from_u8, order_id_from_b256, order_id_to_b256, self_trade_prevention_from_u8,
+    time_in_force_from_u8, DexError, DexResult, TokenTransfer,
+};
 use crate::selectors::{selectors, EnshrinedDEX};
 use crate::DEX_PREDEPLOY_ADDRESS;
-use alloy_primitives::{Address, Bytes, Log, B256, U256};
+use alloy_primitives::{Address, Bytes, Log, B256, I256, U256};
 use alloy_sol_types::{SolEvent, SolValue};
-use dex::{OrderSide, PoolManager, Price};
+use dex::orderbook::{
+    ActivatedBracket, BatchClearing, BatchFill, Fill, FlippedOrder, LinkedCancel, PreventedTrade,
+    TradeResult, TriggeredOrder,
+};
+use dex::pool_manager::SwapResult;
+use dex::{
+    BlockContext, MarketConfig, Order, OrderGroupKind, OrderId, OrderSide, OrderStatus, Pair,
+    PairId, PoolError, PoolManager, Price, RouteHop, SelfTradePrevention, TimeInForce,
+};
 use parking_lot::RwLock;
 use tracing::{debug, info};
 
+/// Decoded placeLimitOrder arguments:
+/// (tokenIn, tokenOut, isBuy, amount, priceNum, priceDenom)
+type LimitOrderArgs = (Address, Address, bool, U256, U256, U256);
+
+/// Decoded placeBracketOrder exit prices, over the entry's price denominator:
+/// (takeProfitNum, stopLossNum, stopLossLimitNum)
+type BracketPrices = (U256, U256, U256);
+
+/// An order that traded as a taker: (trader, side, limit price)
+type Taker = (Address, OrderSide, Price);
+
+/// How the shared limit order path places an order.
+#[derive(Debug, Clone, Copy)]
+enum OrderOptions {
+    /// A limit order with a time in force and an optional self-trade prevention mode.
+    Limit(TimeInForce, Option<SelfTradePrevention>),
+    /// An iceberg order showing clips of this size, in tokenIn like the order amount.
+    Iceberg(U256),
+    /// A bracket entry with exits at these prices.
+    Bracket(BracketPrices),
+    /// A range order flipping at this price numerator, over the order's price denominator.
+    Range(U256),
+}
+
 /// Handler for enshrined DEX operations.
 #[derive(Debug)]
 pub struct DexHandler {
     pool_manager: RwLock<PoolManager>,
+    /// Address allowed to call restricted entrypoints. Without one they are
+    /// rejected for every caller.
+    admin: Option<Address>,
 }
 
 impl DexHandler {
-    /// Create a new DexHandler.
+    /// Create a new DexHandler without an admin.
     pub fn new() -> Self {
         Self {
             pool_manager: RwLock::new(PoolManager::new()),
+            admin: None,
+        }
+    }
+
+    /// Create a new DexHandler with a custom admin.
+    pub fn with_admin(admin: Address) -> Self {
+        Self {
+            admin: Some(admin),
+            ..Self::new()
+        }
+    }
+
+    /// Reject a call to a restricted entrypoint unless it comes from the admin.
+    fn ensure_admin(&self, caller: Address) -> Result<(), DexError> {
+        if self.admin != Some(caller) {
+            return Err(DexError::Unauthorized(caller));
         }
+        Ok(())
     }
 
     /// Handle a transaction to the DEX predeploy.
@@ -29,6 +84,7 @@ impl DexHandler {
     /// * `caller` - The address calling the DEX
     /// * `calldata` - The transaction calldata
     /// * `value` - ETH value sent with the transaction
+    /// * `block` - Chain position of the transaction, used to stamp new orders
     ///
     /// # Returns
     /// * `Ok(DexResult)` - The result of the operation
@@ -38,6 +94,7 @@ impl DexHandler {
         caller: Address,
         calldata: &Bytes,
         value: U256,
+        block: BlockContext,
     ) -> Result<DexResult, DexError> {
         if calldata.len() < 4 {
             return Err(DexError::InvalidCalldata(
@@ -47,18 +104,83 @@ impl DexHandler {
 
         let selector = &calldata[0..4];
 
+        // Trading settles against DEX balances, so ETH only comes in as a deposit
+        if !value.is_zero() && selector != selectors::DEPOSIT.as_slice() {
+            return Err(DexError::InvalidCalldata(format!(
+                "Non-zero ETH value sent with a call other than deposit: tx.value={}",
+                value
+            )));
+        }
+
+        self.pool_manager.write().set_block_context(block);
+
         match selector {
             s if s == selectors::CREATE_PAIR.as_slice() => {
                 self.handle_create_pair(caller, &calldata[4..])
             }
+            s if s == selectors::CREATE_PAIR_WITH_PARAMS.as_slice() => {
+                self.handle_create_pair_with_params(caller, &calldata[4..])
+            }
+            s if s == selectors::DEPOSIT.as_slice() => {
+                self.handle_deposit(caller, &calldata[4..], value)
+            }
+            s if s == selectors::WITHDRAW.as_slice() => {
+                self.handle_withdraw(caller, &calldata[4..])
+            }
+            s if s == selectors::GET_BALANCE.as_slice() => self.handle_get_balance(&calldata[4..]),
             s if s == selectors::PLACE_LIMIT_ORDER.as_slice() => {
-                self.handle_place_limit_order(caller, &calldata[4..], value)
+                self.handle_place_limit_order(caller, &calldata[4..])
+            }
+            s if s == selectors::PLACE_ORDER.as_slice() => {
+                self.handle_place_order(caller, &calldata[4..])
+            }
+            s if s == selectors::PLACE_ICEBERG_ORDER.as_slice() => {
+                self.handle_place_iceberg_order(caller, &calldata[4..])
+            }
+            s if s == selectors::PLACE_STOP_ORDER.as_slice() => {
+                self.handle_place_stop_order(caller, &calldata[4..])
+            }
+            s if s == selectors::PLACE_OCO_ORDER.as_slice() => {
+                self.handle_place_oco_order(caller, &calldata[4..])
+            }
+            s if s == selectors::PLACE_BRACKET_ORDER.as_slice() => {
+                self.handle_place_bracket_order(caller, &calldata[4..])
+            }
+            s if s == selectors::PLACE_RANGE_ORDER.as_slice() => {
+                self.handle_place_range_order(caller, &calldata[4..])
             }
             s if s == selectors::CANCEL_ORDER.as_slice() => {
                 self.handle_cancel_order(caller, &calldata[4..])
             }
-            s if s == selectors::SWAP.as_slice() => self.handle_swap(caller, &calldata[4..], value),
+            s if s == selectors::AMEND_ORDER.as_slice() => {
+                self.handle_amend_order(caller, &calldata[4..])
+            }
+            s if s == selectors::SWAP.as_slice() => self.handle_swap(caller, &calldata[4..]),
             s if s == selectors::GET_QUOTE.as_slice() => self.handle_get_quote(&calldata[4..]),
+            s if s == selectors::GET_QUOTE_DETAIL.as_slice() => {
+                self.handle_get_quote_detail(&calldata[4..])
+            }
+            s if s == selectors::SWAP_EXACT_OUT.as_slice() => {
+                self.handle_swap_exact_out(caller, &calldata[4..])
+            }
+            s if s == selectors::GET_QUOTE_EXACT_OUT.as_slice() => {
+                self.handle_get_quote_exact_out(&calldata[4..])
+            }
+            s if s == selectors::SET_SELF_TRADE_PREVENTION.as_slice() => {
+                self.handle_set_self_trade_prevention(caller, &calldata[4..])
+            }
+            s if s == selectors::SET_MARKET_MODE.as_slice() => {
+                self.handle_set_market_mode(caller, &calldata[4..])
+            }
+            s if s == selectors::WITHDRAW_FEES.as_slice() => {
+                self.handle_withdraw_fees(caller, &calldata[4..])
+            }
+            s if s == selectors::ADD_LIQUIDITY.as_slice() => {
+                self.handle_add_liquidity(caller, &calldata[4..])
+            }
+            s if s == selectors::REMOVE_LIQUIDITY.as_slice() => {
+                self.handle_remove_liquidity(caller, &calldata[4..])
+            }
             _ => Err(DexError::InvalidCalldata(format!(
                 "unknown function selector: 0x{}",
                 hex::encode(selector)
@@ -92,76 +214,360 @@ impl DexHandler {
             token0,
             token1,
             pair_id: pair_id_bytes,
+            market: None,
+        })
+    }
+
+    /// Handle createPairWithParams(address,address,uint256,uint256,uint256,uint256,bool,uint256,int256)
+    fn handle_create_pair_with_params(
+        &self,
+        caller: Address,
+        data: &[u8],
+    ) -> Result<DexResult, DexError> {
+        info!("DEX Handler: createPairWithParams called");
+
+        let (
+            token0,
+            token1,
+            tick_num,
+            tick_denom,
+            lot_size,
+            min_notional,
+            override_fee,
+            fee_bps,
+            maker_fee_bps,
+        ): (Address, Address, U256, U256, U256, U256, bool, U256, I256) =
+            <(Address, Address, U256, U256, U256, U256, bool, U256, I256)>::abi_decode(data)
+                .map_err(|e| {
+                    DexError::InvalidCalldata(format!(
+                        "failed to decode createPairWithParams: {}",
+                        e
+                    ))
+                })?;
+
+        let mut market = MarketConfig::default()
+            .with_lot_size(lot_size)
+            .with_min_notional(min_notional);
+
+        if !tick_num.is_zero() {
+            if tick_denom.is_zero() {
+                return Err(DexError::InvalidPrice {
+                    num: tick_num,
+                    denom: tick_denom,
+                });
+            }
+            market = market.with_tick_size(Price::new(tick_num, tick_denom));
+        }
+
+        if override_fee {
+            let fee_bps = u32::try_from(fee_bps)
+                .ok()
+                .filter(|bps| *bps <= 10_000)
+                .ok_or_else(|| {
+                    DexError::InvalidCalldata(format!("invalid fee: {} bps", fee_bps))
+                })?;
+            let maker_fee_bps = i32::try_from(maker_fee_bps)
+                .ok()
+                .filter(|bps| bps.unsigned_abs() <= 10_000)
+                .ok_or_else(|| {
+                    DexError::InvalidCalldata(format!("invalid maker fee: {} bps", maker_fee_bps))
+                })?;
+            market = market
+                .with_fee_bps(fee_bps)
+                .with_maker_fee_bps(maker_fee_bps);
+        }
+
+        // A pair's market can't be changed once it exists, so only the admin picks
+        // anything but the defaults anyone gets from createPair
+        if market != MarketConfig::default() {
+            self.ensure_admin(caller)?;
+        }
+
+        debug!(
+            "createPairWithParams: token0={:?}, token1={:?}, market={:?}",
+            token0, token1, market
+        );
+
+        let mut pm = self.pool_manager.write();
+        let pair = pm
+            .create_pair_with_config(token0, token1, market.clone())
+            .map_err(DexError::from)?;
+
+        let pair_id_bytes = B256::from_slice(&pair.id().0);
+
+        info!(
+            "Pair created: token0={:?}, token1={:?}, pair_id={:?}, market={:?}",
+            token0, token1, pair_id_bytes, market
+        );
+
+        Ok(DexResult::PairCreated {
+            token0,
+            token1,
+            pair_id: pair_id_bytes,
+            market: Some(market),
+        })
+    }
+
+    /// Handle deposit(address,uint256)
+    ///
+    /// The balance is only credited by [`Self::complete_transfer`] once the tokens
+    /// have arrived.
+    fn handle_deposit(
+        &self,
+        caller: Address,
+        data: &[u8],
+        value: U256,
+    ) -> Result<DexResult, DexError> {
+        let (token, amount): (Address, U256) = <(Address, U256)>::abi_decode(data)
+            .map_err(|e| DexError::InvalidCalldata(format!("failed to decode deposit: {}", e)))?;
+
+        if amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount));
+        }
+
+        // ETH arrives as the transaction value, tokens are pulled with protocolTransfer
+        let expected_value = if token == Address::ZERO {
+            amount
+        } else {
+            U256::ZERO
+        };
+        if value != expected_value {
+            return Err(DexError::InvalidCalldata(format!(
+                "ETH value mismatch for deposit: tx.value={}, expected={}",
+                value, expected_value
+            )));
+        }
+
+        info!(
+            account = ?caller,
+            token = ?token,
+            amount = ?amount,
+            "Deposit requested"
+        );
+
+        Ok(DexResult::Deposited {
+            account: caller,
+            token,
+            amount,
+            transfers: vec![TokenTransfer {
+                token,
+                from: caller,
+                to: DEX_PREDEPLOY_ADDRESS,
+                amount,
+            }],
         })
     }
 
+    /// Handle withdraw(address,uint256)
+    ///
+    /// The balance is debited right away and credited back by
+    /// [`Self::complete_transfer`] if the payout fails.
+    fn handle_withdraw(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token, amount): (Address, U256) = <(Address, U256)>::abi_decode(data)
+            .map_err(|e| DexError::InvalidCalldata(format!("failed to decode withdraw: {}", e)))?;
+
+        if amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount));
+        }
+
+        self.pool_manager
+            .write()
+            .debit(caller, token, amount)
+            .map_err(DexError::from)?;
+
+        info!(
+            account = ?caller,
+            token = ?token,
+            amount = ?amount,
+            "Withdrawal requested"
+        );
+
+        Ok(DexResult::Withdrawn {
+            account: caller,
+            token,
+            amount,
+            transfers: vec![TokenTransfer {
+                token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount,
+            }],
+        })
+    }
+
+    /// Handle getBalance(address,address)
+    fn handle_get_balance(&self, data: &[u8]) -> Result<DexResult, DexError> {
+        let (account, token): (Address, Address) =
+            <(Address, Address)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode getBalance: {}", e))
+            })?;
+
+        Ok(DexResult::Balance {
+            amount: self.pool_manager.read().balance(account, token),
+        })
+    }
+
+    /// Finish a deposit or withdrawal once its token transfer has run.
+    ///
+    /// A deposit is credited only if the tokens arrived, a withdrawal whose
+    /// payout failed is credited back and protocol fees whose payout failed go
+    /// back into the fee vault. Other results settle against DEX balances while
+    /// they are handled, so there is nothing left to do for them.
+    pub fn complete_transfer(&self, result: &DexResult, succeeded: bool) {
+        match result {
+            DexResult::Deposited {
+                account,
+                token,
+                amount,
+                ..
+            } if succeeded => self.pool_manager.write().credit(*account, *token, *amount),
+            DexResult::Withdrawn {
+                account,
+                token,
+                amount,
+                ..
+            } if !succeeded => self.pool_manager.write().credit(*account, *token, *amount),
+            DexResult::FeesWithdrawn { token, amount, .. } if !succeeded => {
+                self.pool_manager.write().return_fees(*token, *amount)
+            }
+            _ => {}
+        }
+    }
+
     /// Handle placeLimitOrder(address,address,bool,uint256,uint256,uint256)
     fn handle_place_limit_order(
         &self,
         caller: Address,
         data: &[u8],
-        value: U256,
     ) -> Result<DexResult, DexError> {
-        let (token_in, token_out, is_buy, amount, price_num, price_denom): (
+        let order = <LimitOrderArgs>::abi_decode(data).map_err(|e| {
+            DexError::InvalidCalldata(format!("failed to decode placeLimitOrder: {}", e))
+        })?;
+
+        let options = OrderOptions::Limit(TimeInForce::GoodTillCancel, None);
+        self.place_order(caller, order, options)
+    }
+
+    /// Handle placeOrder(address,address,bool,uint256,uint256,uint256,uint8,uint8)
+    fn handle_place_order(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (
+            token_in,
+            token_out,
+            is_buy,
+            amount,
+            price_num,
+            price_denom,
+            time_in_force,
+            self_trade_prevention,
+        ): (Address, Address, bool, U256, U256, U256, U256, U256) =
+            <(Address, Address, bool, U256, U256, U256, U256, U256)>::abi_decode(data).map_err(
+                |e| DexError::InvalidCalldata(format!("failed to decode placeOrder: {}", e)),
+            )?;
+
+        // The uint8 enum is decoded as a full word, so out of range values are rejected here
+        let time_in_force = u8::try_from(time_in_force)
+            .ok()
+            .and_then(time_in_force_from_u8)
+            .ok_or_else(|| {
+                DexError::InvalidCalldata(format!("invalid time in force: {}", time_in_force))
+            })?;
+        let self_trade_prevention = decode_self_trade_prevention(self_trade_prevention)?;
+
+        let order = (token_in, token_out, is_buy, amount, price_num, price_denom);
+        let options = OrderOptions::Limit(time_in_force, self_trade_prevention);
+        self.place_order(caller, order, options)
+    }
+
+    /// Handle placeIcebergOrder(address,address,bool,uint256,uint256,uint256,uint256)
+    fn handle_place_iceberg_order(
+        &self,
+        caller: Address,
+        data: &[u8],
+    ) -> Result<DexResult, DexError> {
+        let (token_in, token_out, is_buy, amount, price_num, price_denom, display_amount): (
             Address,
             Address,
             bool,
             U256,
             U256,
             U256,
-        ) = <(Address, Address, bool, U256, U256, U256)>::abi_decode(data).map_err(|e| {
-            DexError::InvalidCalldata(format!("failed to decode placeLimitOrder: {}", e))
+            U256,
+        ) = <(Address, Address, bool, U256, U256, U256, U256)>::abi_decode(data).map_err(|e| {
+            DexError::InvalidCalldata(format!("failed to decode placeIcebergOrder: {}", e))
         })?;
 
-        if amount == U256::ZERO {
-            return Err(DexError::InvalidAmount(amount));
+        if display_amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(display_amount));
         }
 
-        if price_num == U256::ZERO || price_denom == U256::ZERO {
-            return Err(DexError::InvalidPrice {
-                num: price_num,
-                denom: price_denom,
-            });
-        }
+        let order = (token_in, token_out, is_buy, amount, price_num, price_denom);
+        self.place_order(caller, order, OrderOptions::Iceberg(display_amount))
+    }
 
-        // Validate ETH value for limit orders
-        // If selling ETH (isBuy=false, tokenIn=ETH), must send ETH value
-        // If buying with ETH (isBuy=true, tokenOut=ETH), must NOT send ETH value (escrow tokenIn instead)
-        if !is_buy && token_in == Address::ZERO {
-            // Selling ETH - must send value equal to amount
-            if value != amount {
-                return Err(DexError::InvalidCalldata(format!(
-                    "ETH value mismatch for sell order: tx.value={}, amount={}",
-                    value, amount
-                )));
-            }
-        } else if is_buy && token_out == Address::ZERO {
-            // Buying ETH - must NOT send value
-            if !value.is_zero() {
-                return Err(DexError::InvalidCalldata(format!(
-                    "Non-zero ETH value sent with buy ETH order: tx.value={}",
-                    value
-                )));
-            }
-        } else if !value.is_zero() {
-            // No ETH involved - must NOT send value
-            return Err(DexError::InvalidCalldata(format!(
-                "Non-zero ETH value sent with non-ETH order: tx.value={}",
-                value
-            )));
-        }
+    /// Handle placeBracketOrder(address,address,uint256,uint256,uint256,uint256,uint256,uint256)
+    fn handle_place_bracket_order(
+        &self,
+        caller: Address,
+        data: &[u8],
+    ) -> Result<DexResult, DexError> {
+        let (
+            token_in,
+            token_out,
+            amount,
+            price_num,
+            take_profit_num,
+            stop_loss_num,
+            stop_loss_limit_num,
+            price_denom,
+        ): (Address, Address, U256, U256, U256, U256, U256, U256) =
+            <(Address, Address, U256, U256, U256, U256, U256, U256)>::abi_decode(data).map_err(
+                |e| DexError::InvalidCalldata(format!("failed to decode placeBracketOrder: {}", e)),
+            )?;
+
+        // Brackets are long only: the entry buys tokenOut and the exits sell what it bought,
+        // while a short bracket would have to escrow the cost of its stop-loss up front
+        let order = (token_in, token_out, true, amount, price_num, price_denom);
+        let exits = (take_profit_num, stop_loss_num, stop_loss_limit_num);
+        self.place_order(caller, order, OrderOptions::Bracket(exits))
+    }
 
-        let price_num_u128: u128 = price_num.try_into().map_err(|_| DexError::InvalidPrice {
-            num: price_num,
-            denom: price_denom,
+    /// Handle placeRangeOrder(address,address,bool,uint256,uint256,uint256,uint256)
+    fn handle_place_range_order(
+        &self,
+        caller: Address,
+        data: &[u8],
+    ) -> Result<DexResult, DexError> {
+        let (token_in, token_out, is_buy, amount, price_num, flip_price_num, price_denom): (
+            Address,
+            Address,
+            bool,
+            U256,
+            U256,
+            U256,
+            U256,
+        ) = <(Address, Address, bool, U256, U256, U256, U256)>::abi_decode(data).map_err(|e| {
+            DexError::InvalidCalldata(format!("failed to decode placeRangeOrder: {}", e))
         })?;
-        let price_denom_u128: u128 =
-            price_denom.try_into().map_err(|_| DexError::InvalidPrice {
-                num: price_num,
-                denom: price_denom,
-            })?;
 
-        let price = Price::from_u128(price_num_u128, price_denom_u128);
+        let order = (token_in, token_out, is_buy, amount, price_num, price_denom);
+        self.place_order(caller, order, OrderOptions::Range(flip_price_num))
+    }
+
+    /// Place a limit order with escrow, shared by placeLimitOrder, placeOrder,
+    /// placeIcebergOrder, placeBracketOrder and placeRangeOrder.
+    fn place_order(
+        &self,
+        caller: Address,
+        order: LimitOrderArgs,
+        options: OrderOptions,
+    ) -> Result<DexResult, DexError> {
+        let (token_in, token_out, is_buy, amount, price_num, price_denom) = order;
+
+        if amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount));
+        }
+
+        let price = price_from_parts(price_num, price_denom)?;
 
         // Map Solidity semantics to DEX library semantics:
         // - Solidity: tokenIn = what caller pays, tokenOut = what caller receives, amount = tokenIn amount
@@ -175,42 +581,164 @@ impl DexHandler {
         //   -> amount is already in tokenIn (base) units
         let (base, quote, side, base_amount) = if is_buy {
             // User provides quote amount (tokenIn), convert to base amount (tokenOut)
-            // base_amount = quote_amount / price = quote_amount * price.denominator / price.numerator
-            let base_amt = price.base_amount(amount).ok_or(DexError::InvalidAmount(amount))?;
+            let base_amt = self.buy_base_amount(token_out, token_in, price, amount)?;
             (token_out, token_in, OrderSide::Buy, base_amt)
         } else {
             // User provides base amount (tokenIn) directly
             (token_in, token_out, OrderSide::Sell, amount)
         };
 
+        // The iceberg clip is sized like the order amount
+        let options = match options {
+            OrderOptions::Iceberg(display) if is_buy => {
+                OrderOptions::Iceberg(self.buy_base_amount(base, quote, price, display)?)
+            }
+            options => options,
+        };
+
+        let pair = Pair::new(base, quote);
+        let escrow = match side {
+            OrderSide::Sell => amount,
+            OrderSide::Buy => buy_escrow(price, base_amount)?,
+        };
+
+        // A buy that crosses pays the taker fee on top of its fills, which the
+        // escrow at its limit price may not cover: require the fee on the whole
+        // escrow to be available as well
         let mut pm = self.pool_manager.write();
-        let (order_id, trade_result) = pm
-            .place_limit_order(base, quote, caller, side, price, base_amount)
-            .map_err(DexError::from)?;
+        let allowance = fee_allowance(&pm, pair, side, escrow);
+        check_balance(&pm, caller, token_in, escrow.saturating_add(allowance))?;
+        let (order_id, trade_result) = match options {
+            OrderOptions::Limit(time_in_force, self_trade_prevention) => pm.place_order(
+                base,
+                quote,
+                caller,
+                side,
+                price,
+                base_amount,
+                time_in_force,
+                self_trade_prevention,
+            ),
+            OrderOptions::Iceberg(display) => {
+                pm.place_iceberg_order(base, quote, caller, side, price, base_amount, display)
+            }
+            OrderOptions::Bracket((take_profit_num, stop_loss_num, stop_loss_limit_num)) => {
+                // A zero stop-loss limit makes the stop-loss a stop-market order
+                let stop_loss_limit = if stop_loss_limit_num.is_zero() {
+                    None
+                } else {
+                    Some(price_from_parts(stop_loss_limit_num, price_denom)?)
+                };
+                pm.place_bracket_order(
+                    base,
+                    quote,
+                    caller,
+                    side,
+                    price,
+                    base_amount,
+                    price_from_parts(take_profit_num, price_denom)?,
+                    price_from_parts(stop_loss_num, price_denom)?,
+                    stop_loss_limit,
+                )
+            }
+            OrderOptions::Range(flip_price_num) => pm.place_range_order(
+                base,
+                quote,
+                caller,
+                side,
+                price,
+                price_from_parts(flip_price_num, price_denom)?,
+                base_amount,
+            ),
+        }
+        .map_err(DexError::from)?;
 
-        let mut bytes = [0u8; 32];
-        bytes[24..32].copy_from_slice(&order_id.0.to_be_bytes());
-        let order_id_bytes = B256::from(bytes);
+        let order_id_bytes = order_id_to_b256(order_id);
 
         // Escrow model: transfer collateral from caller to DEX
         // For limit orders, the caller escrows token_in
-        let transfers = vec![TokenTransfer {
+        let mut transfers = vec![TokenTransfer {
             token: token_in,
             from: caller,
             to: DEX_PREDEPLOY_ADDRESS,
-            amount,
+            amount: escrow,
         }];
 
+        // An unfilled remainder that doesn't rest (IOC, or cancelled by self-trade
+        // prevention) is cancelled: refund its escrow
+        let remainder_cancelled =
+            !trade_result.remaining_amount.is_zero() && pm.get_order(order_id).is_none();
+        if remainder_cancelled {
+            transfers.extend(escrow_refund(
+                pair,
+                caller,
+                side,
+                price,
+                trade_result.remaining_amount,
+            ));
+        }
+
+        // Self-trade prevention hands back the escrow of whatever it cancelled or reduced
+        transfers.extend(prevented_refunds(pair, &trade_result.prevented));
+        transfers.extend(escrow_refund(
+            pair,
+            caller,
+            side,
+            price,
+            taker_reduced(&trade_result.prevented),
+        ));
+
+        // Stop orders activated by the fills get back the escrow of any dropped remainder
+        transfers.extend(triggered_refunds(pair, &trade_result.triggered));
+        transfers.extend(bracket_refunds(pair, &trade_result.brackets));
+        transfers.extend(linked_cancel_refunds(
+            &pm,
+            pair,
+            &trade_result.linked_cancels,
+        ));
+        transfers.extend(flipped_refunds(pair, &trade_result.flipped));
+        settle_transfers(&mut pm, &transfers)?;
+
+        // The fills pay the order and its makers; a bracket entry keeps what it
+        // buys for its exits
+        let keeps_base = matches!(options, OrderOptions::Bracket(_));
+        transfers.extend(settle_taker_fills(
+            &mut pm,
+            pair,
+            &trade_result,
+            (caller, side, price),
+            &trade_result.fills,
+            keeps_base,
+            U256::ZERO,
+        )?);
+        transfers.extend(settle_activated_fills(&mut pm, pair, &trade_result)?);
+
         info!(
             trader = ?caller,
             token_in = ?token_in,
             token_out = ?token_out,
             is_buy = is_buy,
             amount = ?amount,
+            options = ?options,
             fills = trade_result.fills.len(),
+            triggered = trade_result.triggered.len(),
+            prevented = trade_result.prevented.len(),
+            linked_cancels = trade_result.linked_cancels.len(),
+            brackets = trade_result.brackets.len(),
+            flipped = trade_result.flipped.len(),
+            remainder_cancelled = remainder_cancelled,
             "Limit order placed with escrow"
         );
 
+        let bracket = match options {
+            OrderOptions::Bracket(exits) => Some(exits),
+            _ => None,
+        };
+        let flip_price_num = match options {
+            OrderOptions::Range(flip_price_num) => Some(flip_price_num),
+            _ => None,
+        };
+
         Ok(DexResult::OrderPlaced {
             order_id: order_id_bytes,
             trader: caller,
@@ -222,53 +750,408 @@ impl DexHandler {
             price_denom,
             transfers,
             fills: trade_result.fills,
+            remainder_cancelled,
+            prevented: trade_result.prevented,
+            triggered: trade_result.triggered,
+            linked_cancels: trade_result.linked_cancels,
+            bracket,
+            brackets: trade_result.brackets,
+            flip_price_num,
+            flipped: trade_result.flipped,
         })
     }
 
-    /// Handle cancelOrder(bytes32)
-    fn handle_cancel_order(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
-        let order_id: B256 = <B256>::abi_decode(data).map_err(|e| {
-            DexError::InvalidCalldata(format!("failed to decode cancelOrder: {}", e))
-        })?;
-
-        // TODO: Implement proper order cancellation with order tracking
-        Ok(DexResult::OrderCancelled {
-            order_id,
-            trader: caller,
-        })
+    /// Convert the quote amount of a buy order to base at `price`.
+    /// The result is rounded down to whole lots so quote-sized buys remain placeable.
+    fn buy_base_amount(
+        &self,
+        base: Address,
+        quote: Address,
+        price: Price,
+        amount: U256,
+    ) -> Result<U256, DexError> {
+        // base_amount = quote_amount / price = quote_amount * price.denominator / price.numerator
+        let base_amt = price
+            .base_amount(amount)
+            .ok_or(DexError::InvalidAmount(amount))?;
+        Ok(self
+            .pool_manager
+            .read()
+            .market_config(base, quote)
+            .map_or(base_amt, |market| market.round_down_to_lot(base_amt)))
     }
 
-    /// Handle swap(address,address,uint256,uint256)
-    fn handle_swap(
-        &self,
-        caller: Address,
-        data: &[u8],
-        value: U256,
-    ) -> Result<DexResult, DexError> {
-        let (token_in, token_out, amount_in, min_amount_out): (Address, Address, U256, U256) =
-            <(Address, Address, U256, U256)>::abi_decode(data)
-                .map_err(|e| DexError::InvalidCalldata(format!("failed to decode swap: {}", e)))?;
+    /// Handle placeStopOrder(address,address,bool,uint256,uint256,uint256,uint256,uint256)
+    fn handle_place_stop_order(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token_in, token_out, is_buy, amount, stop_num, stop_denom, limit_num, limit_denom): (
+            Address,
+            Address,
+            bool,
+            U256,
+            U256,
+            U256,
+            U256,
+            U256,
+        ) = <(Address, Address, bool, U256, U256, U256, U256, U256)>::abi_decode(data).map_err(
+            |e| DexError::InvalidCalldata(format!("failed to decode placeStopOrder: {}", e)),
+        )?;
 
-        if amount_in == U256::ZERO {
-            return Err(DexError::InvalidAmount(amount_in));
+        if amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount));
         }
 
-        // Validate ETH value matches amount_in if swapping ETH
-        if token_in == Address::ZERO {
-            if value != amount_in {
-                return Err(DexError::InvalidCalldata(format!(
-                    "ETH value mismatch: tx.value={}, amount_in={}",
-                    value, amount_in
-                )));
-            }
-        } else if !value.is_zero() {
-            return Err(DexError::InvalidCalldata(format!(
-                "Non-zero ETH value sent with non-ETH swap: tx.value={}",
-                value
-            )));
-        }
+        let stop_price = price_from_parts(stop_num, stop_denom)?;
+        // A zero limit price places a stop market order
+        let limit_price = if limit_num.is_zero() {
+            None
+        } else {
+            Some(price_from_parts(limit_num, limit_denom)?)
+        };
 
-        debug!(
+        // Same token mapping as limit orders. Buy stops escrow quote for the base they
+        // buy at their limit price; a stop market buy could cost any amount of quote.
+        let (base, quote, side, base_amount, escrow) = if is_buy {
+            let price = limit_price.ok_or(DexError::InvalidPrice {
+                num: limit_num,
+                denom: limit_denom,
+            })?;
+            let base_amt = self.buy_base_amount(token_out, token_in, price, amount)?;
+            (
+                token_out,
+                token_in,
+                OrderSide::Buy,
+                base_amt,
+                buy_escrow(price, base_amt)?,
+            )
+        } else {
+            (token_in, token_out, OrderSide::Sell, amount, amount)
+        };
+
+        // A buy stop trades as a taker once triggered, long after this transaction,
+        // so the taker fee it may owe beyond its escrow is locked along with it
+        let mut pm = self.pool_manager.write();
+        let escrow =
+            escrow.saturating_add(fee_allowance(&pm, Pair::new(base, quote), side, escrow));
+        check_balance(&pm, caller, token_in, escrow)?;
+        let order_id = pm
+            .place_stop_order(
+                base,
+                quote,
+                caller,
+                side,
+                stop_price,
+                limit_price,
+                base_amount,
+            )
+            .map_err(DexError::from)?;
+
+        let transfers = vec![TokenTransfer {
+            token: token_in,
+            from: caller,
+            to: DEX_PREDEPLOY_ADDRESS,
+            amount: escrow,
+        }];
+        settle_transfers(&mut pm, &transfers)?;
+
+        info!(
+            trader = ?caller,
+            token_in = ?token_in,
+            token_out = ?token_out,
+            is_buy = is_buy,
+            amount = ?amount,
+            stop_price = ?stop_price,
+            limit_price = ?limit_price,
+            "Stop order placed with escrow"
+        );
+
+        Ok(DexResult::StopOrderPlaced {
+            order_id: order_id_to_b256(order_id),
+            trader: caller,
+            token_in,
+            token_out,
+            is_buy,
+            amount,
+            stop_num,
+            stop_denom,
+            limit_num,
+            limit_denom,
+            transfers,
+        })
+    }
+
+    /// Handle placeOcoOrder(address,address,bool,uint256,uint256,uint256,uint256,uint256)
+    fn handle_place_oco_order(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (
+            token_in,
+            token_out,
+            is_buy,
+            amount,
+            price_num,
+            stop_num,
+            stop_limit_num,
+            price_denom,
+        ): (Address, Address, bool, U256, U256, U256, U256, U256) =
+            <(Address, Address, bool, U256, U256, U256, U256, U256)>::abi_decode(data).map_err(
+                |e| DexError::InvalidCalldata(format!("failed to decode placeOcoOrder: {}", e)),
+            )?;
+
+        if amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount));
+        }
+
+        let price = price_from_parts(price_num, price_denom)?;
+        let stop_price = price_from_parts(stop_num, price_denom)?;
+        // A zero stop limit price makes the stop leg a stop market order
+        let stop_limit_price = if stop_limit_num.is_zero() {
+            None
+        } else {
+            Some(price_from_parts(stop_limit_num, price_denom)?)
+        };
+
+        // Both legs trade the same base amount out of one escrow. Buys size it at the
+        // higher of the two limit prices so the escrow covers either leg, which is
+        // why a buy needs a stop limit price like a buy stop order.
+        let (base, quote, side, base_amount, escrow) = if is_buy {
+            let stop_limit = stop_limit_price.ok_or(DexError::InvalidPrice {
+                num: stop_limit_num,
+                denom: price_denom,
+            })?;
+            let escrow_price = price.max(stop_limit);
+            let base_amt = self.buy_base_amount(token_out, token_in, escrow_price, amount)?;
+            let escrow = buy_escrow(escrow_price, base_amt)?;
+            (token_out, token_in, OrderSide::Buy, base_amt, escrow)
+        } else {
+            (token_in, token_out, OrderSide::Sell, amount, amount)
+        };
+
+        // The stop leg trades as a taker once triggered, so the taker fee it may owe
+        // beyond its escrow is locked along with the shared escrow
+        let stop_escrow = match stop_limit_price {
+            Some(stop_limit) if is_buy => buy_escrow(stop_limit, base_amount)?,
+            _ => U256::ZERO,
+        };
+        let mut pm = self.pool_manager.write();
+        let fee_reserve = fee_allowance(&pm, Pair::new(base, quote), side, stop_escrow);
+        let escrow = escrow.saturating_add(fee_reserve);
+        check_balance(&pm, caller, token_in, escrow)?;
+        let (limit_order_id, stop_order_id) = pm
+            .place_oco_order(
+                base,
+                quote,
+                caller,
+                side,
+                base_amount,
+                price,
+                stop_price,
+                stop_limit_price,
+            )
+            .map_err(DexError::from)?;
+
+        let transfers = vec![TokenTransfer {
+            token: token_in,
+            from: caller,
+            to: DEX_PREDEPLOY_ADDRESS,
+            amount: escrow,
+        }];
+        settle_transfers(&mut pm, &transfers)?;
+
+        info!(
+            trader = ?caller,
+            token_in = ?token_in,
+            token_out = ?token_out,
+            is_buy = is_buy,
+            amount = ?amount,
+            price = ?price,
+            stop_price = ?stop_price,
+            stop_limit_price = ?stop_limit_price,
+            "One-cancels-other order placed with escrow"
+        );
+
+        Ok(DexResult::OcoOrderPlaced {
+            limit_order_id: order_id_to_b256(limit_order_id),
+            stop_order_id: order_id_to_b256(stop_order_id),
+            trader: caller,
+            token_in,
+            token_out,
+            is_buy,
+            amount,
+            price_num,
+            stop_num,
+            stop_limit_num,
+            price_denom,
+            transfers,
+        })
+    }
+
+    /// Handle cancelOrder(bytes32)
+    fn handle_cancel_order(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let order_id: B256 = <B256>::abi_decode(data).map_err(|e| {
+            DexError::InvalidCalldata(format!("failed to decode cancelOrder: {}", e))
+        })?;
+
+        let id = order_id_from_b256(order_id).ok_or(DexError::OrderNotFound(order_id))?;
+
+        let mut pm = self.pool_manager.write();
+        // A bracket entry keeps what it bought as the escrow of its exits until it
+        // is done, so cancelling it early pays that out
+        let is_bracket_entry = pm
+            .order_group(id)
+            .is_some_and(|g| g.kind == OrderGroupKind::Bracket && g.orders[0] == id);
+        let pending_stop = pm.is_pending_stop(id);
+        let (pair, order, linked_cancel) = pm.cancel_order(caller, id).map_err(DexError::from)?;
+
+        // Return the unfilled escrow: sell orders locked base, buy orders locked quote.
+        // A buy stop that never triggered also gets back its taker fee reserve.
+        let refund_token = match order.side {
+            OrderSide::Sell => pair.base,
+            OrderSide::Buy => pair.quote,
+        };
+        let mut refund_amount = order.remaining_escrow();
+        if pending_stop {
+            refund_amount += fee_allowance(&pm, pair, order.side, order.original_escrow());
+        }
+
+        let mut transfers = Vec::new();
+        if !refund_amount.is_zero() {
+            transfers.push(TokenTransfer {
+                token: refund_token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount: refund_amount,
+            });
+        }
+        if is_bracket_entry && !order.filled_amount().is_zero() {
+            transfers.push(TokenTransfer {
+                token: pair.base,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount: order.filled_amount(),
+            });
+        }
+        // A range order holds its proceeds until it flips, so they're paid out too
+        transfers.extend(range_proceeds_refund(pair, &order));
+        transfers.extend(linked_cancel_refunds(&pm, pair, linked_cancel.as_slice()));
+        settle_transfers(&mut pm, &transfers)?;
+
+        info!(
+            trader = ?caller,
+            order_id = ?order_id,
+            refund_token = ?refund_token,
+            refund_amount = ?refund_amount,
+            linked_cancelled = linked_cancel.is_some(),
+            "Order cancelled with escrow refund"
+        );
+
+        Ok(DexResult::OrderCancelled {
+            order_id,
+            trader: caller,
+            transfers,
+            linked_order_id: linked_cancel.map(|l| order_id_to_b256(l.order.id)),
+        })
+    }
+
+    /// Handle amendOrder(bytes32,uint256,uint256,uint256)
+    fn handle_amend_order(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (order_id, amount, price_num, price_denom): (B256, U256, U256, U256) =
+            <(B256, U256, U256, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode amendOrder: {}", e))
+            })?;
+
+        let id = order_id_from_b256(order_id).ok_or(DexError::OrderNotFound(order_id))?;
+
+        if amount == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount));
+        }
+
+        let price = price_from_parts(price_num, price_denom)?;
+
+        let (pair, side, previous_escrow) = self
+            .pool_manager
+            .read()
+            .get_order(id)
+            .map(|(pair, order)| (pair, order.side, order.remaining_escrow()))
+            .ok_or(DexError::OrderNotFound(order_id))?;
+
+        // Like placeOrder, sell orders are sized in base and buy orders in quote,
+        // which is converted to base at the new price
+        let (escrow_token, base_amount) = match side {
+            OrderSide::Sell => (pair.base, amount),
+            OrderSide::Buy => (
+                pair.quote,
+                self.buy_base_amount(pair.base, pair.quote, price, amount)?,
+            ),
+        };
+        let new_escrow = match side {
+            OrderSide::Sell => base_amount,
+            OrderSide::Buy => price
+                .quote_amount(base_amount)
+                .ok_or(DexError::InvalidAmount(amount))?,
+        };
+
+        // Extra escrow comes out of the trader's balance, checked before the book changes
+        let mut pm = self.pool_manager.write();
+        check_balance(
+            &pm,
+            caller,
+            escrow_token,
+            new_escrow.saturating_sub(previous_escrow),
+        )?;
+        let (_, amended) = pm
+            .amend_order(caller, id, base_amount, price)
+            .map_err(DexError::from)?;
+
+        // Settle the escrow difference: sell orders lock base, buy orders lock quote
+        let previous_escrow = amended.previous.remaining_escrow();
+        let new_escrow = amended.order.remaining_escrow();
+        let mut transfers = Vec::new();
+        if new_escrow > previous_escrow {
+            transfers.push(TokenTransfer {
+                token: escrow_token,
+                from: caller,
+                to: DEX_PREDEPLOY_ADDRESS,
+                amount: new_escrow - previous_escrow,
+            });
+        } else if new_escrow < previous_escrow {
+            transfers.push(TokenTransfer {
+                token: escrow_token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount: previous_escrow - new_escrow,
+            });
+        }
+        settle_transfers(&mut pm, &transfers)?;
+
+        info!(
+            trader = ?caller,
+            order_id = ?order_id,
+            amount = ?amount,
+            kept_priority = amended.kept_priority,
+            transfers = transfers.len(),
+            "Order amended"
+        );
+
+        Ok(DexResult::OrderAmended {
+            order_id,
+            trader: caller,
+            amount,
+            price_num,
+            price_denom,
+            kept_priority: amended.kept_priority,
+            transfers,
+        })
+    }
+
+    /// Handle swap(address,address,uint256,uint256)
+    fn handle_swap(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token_in, token_out, amount_in, min_amount_out): (Address, Address, U256, U256) =
+            <(Address, Address, U256, U256)>::abi_decode(data)
+                .map_err(|e| DexError::InvalidCalldata(format!("failed to decode swap: {}", e)))?;
+
+        if amount_in == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount_in));
+        }
+
+        debug!(
             caller = ?caller,
             token_in = ?token_in,
             token_out = ?token_out,
@@ -278,97 +1161,66 @@ impl DexHandler {
         );
 
         let mut pm = self.pool_manager.write();
+        check_balance(&pm, caller, token_in, amount_in)?;
         let result = pm
             .execute_swap(caller, token_in, token_out, amount_in, min_amount_out)
             .map_err(DexError::from)?;
 
-        // Convert route to Vec<B256>
-        let route: Vec<B256> = result
-            .route
-            .hops
-            .iter()
-            .map(|hop| {
-                let pair_id = hop.pair.id();
-                B256::from_slice(&pair_id.0)
-            })
-            .collect();
-
-        // Build token transfers based on fills
-        // Escrow model:
-        // 1. Taker (caller) sends token_in to DEX (or directly to makers)
-        // 2. DEX sends token_out to taker (from maker's escrow)
-        // 3. DEX sends token_in to each maker (their share)
-        let mut transfers = Vec::new();
-
-        // Taker sends token_in to DEX
-        transfers.push(TokenTransfer {
+        // Taker sends the token_in the swap spent to DEX
+        let transfers = vec![TokenTransfer {
             token: token_in,
             from: caller,
             to: DEX_PREDEPLOY_ADDRESS,
-            amount: amount_in,
-        });
-
-        // DEX sends token_out to taker (total amount_out)
-        transfers.push(TokenTransfer {
-            token: token_out,
-            from: DEX_PREDEPLOY_ADDRESS,
-            to: caller,
-            amount: result.amount_out,
-        });
-
-        // Collect all fills for OrderFilled events
-        let mut all_fills = Vec::new();
-
-        // For each hop and fill, determine the correct amount to send to maker
-        for (hop, trade) in result.route.hops.iter().zip(&result.trades) {
-            // Determine which side the taker was on
-            // If pair.base == token_in at this hop, taker is selling base
-            let taker_is_selling_base = hop.pair.base == hop.token_in;
+            amount: result.amount_in,
+        }];
 
-            for fill in &trade.fills {
-                // Store fills with their taker order IDs for OrderFilled events
-                all_fills.push((trade.taker_order_id, fill.clone()));
+        swap_executed(&mut pm, caller, token_in, token_out, result, transfers)
+    }
 
-                // Maker receives the opposite of what taker is doing:
-                // - If taker sells base (base=token_in), maker receives base_amount (token_in)
-                // - If taker buys base (base=token_out, quote=token_in), maker receives quote_amount (token_in)
-                let amount_to_maker = if taker_is_selling_base {
-                    fill.base_amount  // token_in is base
-                } else {
-                    fill.quote_amount // token_in is quote
-                };
+    /// Handle swapExactOut(address,address,uint256,uint256)
+    fn handle_swap_exact_out(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token_in, token_out, amount_out, max_amount_in): (Address, Address, U256, U256) =
+            <(Address, Address, U256, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode swapExactOut: {}", e))
+            })?;
 
-                transfers.push(TokenTransfer {
-                    token: hop.token_in,
-                    from: DEX_PREDEPLOY_ADDRESS,
-                    to: fill.maker,
-                    amount: amount_to_maker,
-                });
-            }
+        if amount_out == U256::ZERO {
+            return Err(DexError::InvalidAmount(amount_out));
         }
 
-        info!(
+        debug!(
             caller = ?caller,
             token_in = ?token_in,
             token_out = ?token_out,
-            amount_in = ?amount_in,
-            amount_out = ?result.amount_out,
-            hops = route.len(),
-            fills = all_fills.len(),
-            transfers = transfers.len(),
-            "Swap executed successfully"
+            amount_out = ?amount_out,
+            max_amount_in = ?max_amount_in,
+            "Executing exact-output swap"
         );
 
-        Ok(DexResult::SwapExecuted {
-            trader: caller,
-            token_in,
-            token_out,
-            amount_in,
-            amount_out: result.amount_out,
-            route,
-            transfers,
-            all_fills,
-        })
+        let mut pm = self.pool_manager.write();
+        check_balance(&pm, caller, token_in, max_amount_in)?;
+        let result = pm
+            .execute_swap_exact_out(caller, token_in, token_out, amount_out, max_amount_in)
+            .map_err(DexError::from)?;
+
+        // Taker sends up to maxAmountIn to DEX and gets back what the swap didn't spend
+        let mut transfers = vec![TokenTransfer {
+            token: token_in,
+            from: caller,
+            to: DEX_PREDEPLOY_ADDRESS,
+            amount: max_amount_in,
+        }];
+        let unspent = max_amount_in.saturating_sub(result.amount_in);
+        if !unspent.is_zero() {
+            transfers.push(TokenTransfer {
+                token: token_in,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount: unspent,
+            });
+        }
+
+        swap_executed(&mut pm, caller, token_in, token_out, result, transfers)
     }
 
     /// Handle getQuote(address,address,uint256)
@@ -399,166 +1251,2057 @@ impl DexHandler {
         })
     }
 
-    /// Create logs for a DEX operation result.
-    pub fn create_logs(&self, result: &DexResult) -> Vec<Log> {
-        let mut logs = Vec::new();
+    /// Handle getQuoteDetail(address,address,uint256)
+    fn handle_get_quote_detail(&self, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token_in, token_out, amount_in): (Address, Address, U256) =
+            <(Address, Address, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode getQuoteDetail: {}", e))
+            })?;
 
-        match result {
-            DexResult::PairCreated {
-                token0,
-                token1,
-                pair_id,
-            } => {
-                logs.push(Log {
-                    address: DEX_PREDEPLOY_ADDRESS,
-                    data: alloy_primitives::LogData::new_unchecked(
-                        vec![
-                            EnshrinedDEX::PairCreated::SIGNATURE_HASH.into(),
-                            B256::left_padding_from(token0.as_slice()),
-                            B256::left_padding_from(token1.as_slice()),
-                            *pair_id,
-                        ],
-                        Bytes::new(),
-                    ),
-                });
-            }
-            DexResult::OrderPlaced {
-                order_id,
-                trader,
-                token_in,
-                token_out,
-                is_buy,
-                amount,
-                price_num,
-                price_denom,
-                transfers: _,
-                fills,
-            } => {
-                // Non-indexed params: (address tokenOut, bool isBuy, uint256 amount, uint256 priceNum, uint256 priceDenom)
-                let data = (*token_out, *is_buy, *amount, *price_num, *price_denom).abi_encode();
-                logs.push(Log {
-                    address: DEX_PREDEPLOY_ADDRESS,
-                    data: alloy_primitives::LogData::new_unchecked(
-                        vec![
-                            EnshrinedDEX::LimitOrderPlaced::SIGNATURE_HASH.into(),
-                            *order_id,
-                            B256::left_padding_from(trader.as_slice()),
-                            B256::left_padding_from(token_in.as_slice()),
-                        ],
-                        data.into(),
-                    ),
-                });
+        let pm = self.pool_manager.read();
+        let result = pm
+            .get_quote(token_in, token_out, amount_in)
+            .map_err(DexError::from)?;
 
-                // Emit OrderFilled events for any fills that occurred
-                for fill in fills {
-                    let mut maker_order_bytes = [0u8; 32];
-                    maker_order_bytes[24..32].copy_from_slice(&fill.maker_order_id.0.to_be_bytes());
-                    let maker_order_id = B256::from(maker_order_bytes);
+        // Every hop of every route the input is split across, in order
+        let hops = result
+            .splits
+            .into_iter()
+            .flat_map(|split| split.route.hops.into_iter().zip(split.hops))
+            .collect();
+
+        Ok(DexResult::QuoteDetail {
+            amount_out: result.amount_out,
+            price_impact: result.price_impact,
+            hops,
+        })
+    }
+
+    /// Handle getQuoteExactOut(address,address,uint256)
+    fn handle_get_quote_exact_out(&self, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token_in, token_out, amount_out): (Address, Address, U256) =
+            <(Address, Address, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode getQuoteExactOut: {}", e))
+            })?;
+
+        let pm = self.pool_manager.read();
+        let result = pm
+            .get_quote_exact_out(token_in, token_out, amount_out)
+            .map_err(DexError::from)?;
+
+        let route: Vec<B256> = result
+            .route
+            .hops
+            .iter()
+            .map(|hop| {
+                let pair_id = hop.pair.id();
+                B256::from_slice(&pair_id.0)
+            })
+            .collect();
+
+        Ok(DexResult::QuoteExactOut {
+            amount_in: result.amount_in,
+            route,
+        })
+    }
+
+    /// Handle setSelfTradePrevention(address,address,uint8)
+    fn handle_set_self_trade_prevention(
+        &self,
+        caller: Address,
+        data: &[u8],
+    ) -> Result<DexResult, DexError> {
+        self.ensure_admin(caller)?;
+
+        let (token0, token1, mode): (Address, Address, U256) =
+            <(Address, Address, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode setSelfTradePrevention: {}", e))
+            })?;
+        let self_trade_prevention = decode_self_trade_prevention(mode)?;
+
+        let pair_id = PairId::from_tokens(token0, token1);
+        let mut pm = self.pool_manager.write();
+        let not_found = PoolError::PairNotFound {
+            token0,
+            token1,
+            pair_id,
+        };
+        let mut market = pm.market_config(token0, token1).cloned().ok_or(not_found)?;
+        market.self_trade_prevention = self_trade_prevention;
+        pm.set_market_config(token0, token1, market)?;
+
+        info!(
+            token0 = ?token0,
+            token1 = ?token1,
+            mode = ?self_trade_prevention,
+            "Self-trade prevention updated"
+        );
+
+        Ok(DexResult::SelfTradePreventionSet {
+            pair_id: B256::from_slice(&pair_id.0),
+            mode: u8::try_from(mode).unwrap_or_default(),
+        })
+    }
+
+    /// Handle setMarketMode(address,address,uint8)
+    fn handle_set_market_mode(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        self.ensure_admin(caller)?;
+
+        let (token0, token1, mode): (Address, Address, U256) =
+            <(Address, Address, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode setMarketMode: {}", e))
+            })?;
+        // The uint8 enum is decoded as a full word, so out of range values are rejected here
+        let market_mode = u8::try_from(mode)
+            .ok()
+            .and_then(market_mode_from_u8)
+            .ok_or_else(|| DexError::InvalidCalldata(format!("invalid market mode: {}", mode)))?;
+
+        let pair_id = PairId::from_tokens(token0, token1);
+        let mut pm = self.pool_manager.write();
+        let not_found = PoolError::PairNotFound {
+            token0,
+            token1,
+            pair_id,
+        };
+        let market = pm
+            .market_config(token0, token1)
+            .cloned()
+            .ok_or(not_found)?
+            .with_mode(market_mode);
+        pm.set_market_config(token0, token1, market)?;
+
+        info!(
+            token0 = ?token0,
+            token1 = ?token1,
+            mode = ?market_mode,
+            "Market mode updated"
+        );
+
+        Ok(DexResult::MarketModeSet {
+            pair_id: B256::from_slice(&pair_id.0),
+            mode: u8::try_from(mode).unwrap_or_default(),
+        })
+    }
+
+    /// Clear the batch auctions of batch auction markets at the chain position
+    /// `block` and settle them against DEX balances. Returns a result for each
+    /// market whose auction filled or cancelled anything.
+    ///
+    /// Sellers are paid the clearing price net of the taker fee and buyers get
+    /// base net of the maker fee. Buyers escrowed quote at their limit price,
+    /// so the difference to the clearing price is refunded, as is the escrow
+    /// of immediate-or-cancel orders left unfilled.
+    pub fn clear_batch_auctions(&self, block: BlockContext) -> Vec<DexResult> {
+        let mut pm = self.pool_manager.write();
+        pm.set_block_context(block);
+        let clearings = pm.clear_batch_auctions();
+
+        let mut results = Vec::new();
+        for (pair, clearing) in clearings {
+            let hop = RouteHop {
+                pair,
+                token_in: pair.base,
+                token_out: pair.quote,
+            };
+            let mut transfers = Vec::new();
+            for batch_fill in &clearing.fills {
+                transfers.extend(batch_fill_transfers(&hop, batch_fill));
+            }
+            for order in &clearing.cancelled {
+                transfers.extend(escrow_refund(
+                    pair,
+                    order.trader,
+                    order.side,
+                    order.price,
+                    order.remaining_amount,
+                ));
+            }
+            // Every transfer pays out escrow the orders locked when placed, so
+            // settling the clearing can't fail
+            credit_payouts(&mut pm, &transfers);
+
+            info!(
+                base = ?pair.base,
+                quote = ?pair.quote,
+                price = ?clearing.price,
+                volume = ?clearing.volume,
+                fills = clearing.fills.len(),
+                cancelled = clearing.cancelled.len(),
+                "Batch auction cleared"
+            );
+
+            results.push(DexResult::BatchCleared {
+                pair_id: B256::from_slice(&pair.id().0),
+                quote: pair.quote,
+                clearing,
+                transfers,
+            });
+        }
+        results
+    }
+
+    /// Handle withdrawFees(address,address)
+    fn handle_withdraw_fees(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        self.ensure_admin(caller)?;
+
+        let (token, to): (Address, Address) =
+            <(Address, Address)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode withdrawFees: {}", e))
+            })?;
+
+        let amount = self.pool_manager.write().withdraw_fees(token);
+        if amount.is_zero() {
+            return Err(DexError::InvalidAmount(amount));
+        }
+
+        info!(
+            token = ?token,
+            to = ?to,
+            amount = ?amount,
+            "Protocol fees withdrawn"
+        );
+
+        Ok(DexResult::FeesWithdrawn {
+            token,
+            to,
+            amount,
+            transfers: vec![TokenTransfer {
+                token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to,
+                amount,
+            }],
+        })
+    }
+
+    /// Handle addLiquidity(address,address,uint256,uint256)
+    ///
+    /// The pool takes what it needs of the two amounts out of the provider's
+    /// DEX balance, which must cover both of them.
+    fn handle_add_liquidity(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token0, token1, amount0, amount1): (Address, Address, U256, U256) =
+            <(Address, Address, U256, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode addLiquidity: {}", e))
+            })?;
+
+        let mut pm = self.pool_manager.write();
+        let pair = liquidity_pair(&pm, token0, token1)?;
+        check_balance(&pm, caller, token0, amount0)?;
+        check_balance(&pm, caller, token1, amount1)?;
+
+        let (base_amount, quote_amount) = if token0 == pair.base {
+            (amount0, amount1)
+        } else {
+            (amount1, amount0)
+        };
+        let change = pm
+            .add_liquidity(pair.base, pair.quote, caller, base_amount, quote_amount)
+            .map_err(DexError::from)?;
+        let (amount0, amount1) = if token0 == pair.base {
+            (change.base, change.quote)
+        } else {
+            (change.quote, change.base)
+        };
+
+        // The deposit stays with the DEX as pool reserves
+        let transfers = vec![
+            TokenTransfer {
+                token: token0,
+                from: caller,
+                to: DEX_PREDEPLOY_ADDRESS,
+                amount: amount0,
+            },
+            TokenTransfer {
+                token: token1,
+                from: caller,
+                to: DEX_PREDEPLOY_ADDRESS,
+                amount: amount1,
+            },
+        ];
+        settle_transfers(&mut pm, &transfers)?;
+
+        info!(
+            provider = ?caller,
+            token0 = ?token0,
+            token1 = ?token1,
+            amount0 = ?amount0,
+            amount1 = ?amount1,
+            shares = ?change.shares,
+            "Liquidity added"
+        );
+
+        Ok(DexResult::LiquidityAdded {
+            provider: caller,
+            token0,
+            token1,
+            amount0,
+            amount1,
+            shares: change.shares,
+            transfers,
+        })
+    }
+
+    /// Handle removeLiquidity(address,address,uint256)
+    fn handle_remove_liquidity(&self, caller: Address, data: &[u8]) -> Result<DexResult, DexError> {
+        let (token0, token1, shares): (Address, Address, U256) =
+            <(Address, Address, U256)>::abi_decode(data).map_err(|e| {
+                DexError::InvalidCalldata(format!("failed to decode removeLiquidity: {}", e))
+            })?;
+
+        let mut pm = self.pool_manager.write();
+        let pair = liquidity_pair(&pm, token0, token1)?;
+        let change = pm
+            .remove_liquidity(pair.base, pair.quote, caller, shares)
+            .map_err(DexError::from)?;
+        let (amount0, amount1) = if token0 == pair.base {
+            (change.base, change.quote)
+        } else {
+            (change.quote, change.base)
+        };
+
+        let transfers = vec![
+            TokenTransfer {
+                token: token0,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount: amount0,
+            },
+            TokenTransfer {
+                token: token1,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount: amount1,
+            },
+        ];
+        settle_transfers(&mut pm, &transfers)?;
+
+        info!(
+            provider = ?caller,
+            token0 = ?token0,
+            token1 = ?token1,
+            amount0 = ?amount0,
+            amount1 = ?amount1,
+            shares = ?shares,
+            "Liquidity removed"
+        );
+
+        Ok(DexResult::LiquidityRemoved {
+            provider: caller,
+            token0,
+            token1,
+            amount0,
+            amount1,
+            transfers,
+        })
+    }
 
-                    let data = (fill.base_amount,).abi_encode();
+    /// Create logs for a DEX operation result.
+    pub fn create_logs(&self, result: &DexResult) -> Vec<Log> {
+        let mut logs = Vec::new();
+
+        match result {
+            DexResult::PairCreated {
+                token0,
+                token1,
+                pair_id,
+                market,
+            } => {
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::PairCreated::SIGNATURE_HASH.into(),
+                            B256::left_padding_from(token0.as_slice()),
+                            B256::left_padding_from(token1.as_slice()),
+                            *pair_id,
+                        ],
+                        Bytes::new(),
+                    ),
+                });
+
+                if let Some(market) = market {
+                    let (tick_num, tick_denom) =
+                        market.tick_size.map_or((U256::ZERO, U256::ZERO), |tick| {
+                            (tick.numerator, tick.denominator)
+                        });
+                    // Non-indexed params: (uint256 tickNum, uint256 tickDenom, uint256 lotSize,
+                    // uint256 minNotional, bool overrideFee, uint256 feeBps, int256 makerFeeBps)
+                    let data = (
+                        tick_num,
+                        tick_denom,
+                        market.lot_size,
+                        market.min_notional,
+                        market.fee_bps.is_some(),
+                        U256::from(market.fee_bps.unwrap_or_default()),
+                        I256::try_from(market.maker_fee_bps.unwrap_or_default())
+                            .unwrap_or_default(),
+                    )
+                        .abi_encode();
                     logs.push(Log {
                         address: DEX_PREDEPLOY_ADDRESS,
                         data: alloy_primitives::LogData::new_unchecked(
                             vec![
-                                EnshrinedDEX::OrderFilled::SIGNATURE_HASH.into(),
-                                maker_order_id,
-                                *order_id, // taker order ID
+                                EnshrinedDEX::MarketConfigured::SIGNATURE_HASH.into(),
+                                *pair_id,
                             ],
                             data.into(),
                         ),
                     });
                 }
             }
-            DexResult::OrderCancelled { order_id, trader } => {
+            DexResult::OrderPlaced {
+                order_id,
+                trader,
+                token_in,
+                token_out,
+                is_buy,
+                amount,
+                price_num,
+                price_denom,
+                transfers: _,
+                fills,
+                remainder_cancelled,
+                prevented,
+                triggered,
+                linked_cancels,
+                bracket,
+                brackets,
+                flip_price_num,
+                flipped,
+            } => {
+                // Non-indexed params: (address tokenOut, bool isBuy, uint256 amount, uint256 priceNum, uint256 priceDenom)
+                let data = (*token_out, *is_buy, *amount, *price_num, *price_denom).abi_encode();
                 logs.push(Log {
                     address: DEX_PREDEPLOY_ADDRESS,
                     data: alloy_primitives::LogData::new_unchecked(
                         vec![
-                            EnshrinedDEX::OrderCancelled::SIGNATURE_HASH.into(),
+                            EnshrinedDEX::LimitOrderPlaced::SIGNATURE_HASH.into(),
                             *order_id,
                             B256::left_padding_from(trader.as_slice()),
+                            B256::left_padding_from(token_in.as_slice()),
                         ],
-                        Bytes::new(),
+                        data.into(),
                     ),
                 });
-            }
-            DexResult::SwapExecuted {
-                trader,
-                token_in,
-                token_out,
-                amount_in,
-                amount_out,
-                route,
-                transfers: _,
-                all_fills,
-            } => {
-                // Emit OrderFilled events for all fills
-                for (taker_order_id, fill) in all_fills {
-                    let mut maker_order_bytes = [0u8; 32];
-                    maker_order_bytes[24..32].copy_from_slice(&fill.maker_order_id.0.to_be_bytes());
-                    let maker_order_id_b256 = B256::from(maker_order_bytes);
 
-                    let mut taker_order_bytes = [0u8; 32];
-                    taker_order_bytes[24..32].copy_from_slice(&taker_order_id.0.to_be_bytes());
-                    let taker_order_id_b256 = B256::from(taker_order_bytes);
+                if let Some((take_profit_num, stop_loss_num, stop_loss_limit_num)) = bracket {
+                    // Non-indexed params: (uint256 takeProfitNum, uint256 stopLossNum,
+                    // uint256 stopLossLimitNum, uint256 priceDenom)
+                    let data = (
+                        *take_profit_num,
+                        *stop_loss_num,
+                        *stop_loss_limit_num,
+                        *price_denom,
+                    )
+                        .abi_encode();
+                    logs.push(Log {
+                        address: DEX_PREDEPLOY_ADDRESS,
+                        data: alloy_primitives::LogData::new_unchecked(
+                            vec![
+                                EnshrinedDEX::BracketOrderPlaced::SIGNATURE_HASH.into(),
+                                *order_id,
+                                B256::left_padding_from(trader.as_slice()),
+                            ],
+                            data.into(),
+                        ),
+                    });
+                }
 
-                    let data = (fill.base_amount,).abi_encode();
+                if let Some(flip_price_num) = flip_price_num {
+                    // Non-indexed params: (uint256 flipPriceNum, uint256 priceDenom)
+                    let data = (*flip_price_num, *price_denom).abi_encode();
                     logs.push(Log {
                         address: DEX_PREDEPLOY_ADDRESS,
                         data: alloy_primitives::LogData::new_unchecked(
                             vec![
-                                EnshrinedDEX::OrderFilled::SIGNATURE_HASH.into(),
-                                maker_order_id_b256,
-                                taker_order_id_b256,
+                                EnshrinedDEX::RangeOrderPlaced::SIGNATURE_HASH.into(),
+                                *order_id,
                             ],
                             data.into(),
                         ),
                     });
                 }
 
-                // Non-indexed params: (uint256 amountIn, uint256 amountOut, bytes32[] route)
-                // Manually encode to avoid tuple wrapper offset
-                let mut data = Vec::new();
+                // Fees are charged in the quote token, which the buyer pays
+                let fee_token = if *is_buy { *token_in } else { *token_out };
+
+                // Emit OrderFilled events for any fills that occurred
+                for fill in fills {
+                    let maker_order_id = order_id_to_b256(fill.maker_order_id);
 
-                // amountIn (32 bytes)
-                data.extend_from_slice(&amount_in.to_be_bytes::<32>());
+                    // taker order ID is the placed order
+                    logs.push(order_filled_log(maker_order_id, *order_id, fill));
+
+                    if let Some(log) = trade_fees_log(maker_order_id, *order_id, fee_token, fill) {
+                        logs.push(log);
+                    }
+                }
 
-                // amountOut (32 bytes)
-                data.extend_from_slice(&amount_out.to_be_bytes::<32>());
+                for prevented in prevented {
+                    logs.extend(prevented_logs(*order_id, prevented));
+                }
 
-                // offset to array = 0x60 (96 bytes, after amountIn + amountOut + this offset)
-                data.extend_from_slice(&U256::from(0x60).to_be_bytes::<32>());
+                // Orders linked one-cancels-other to the makers it traded with
+                logs.extend(linked_cancels.iter().map(linked_cancel_log));
 
-                // array length
-                data.extend_from_slice(&U256::from(route.len()).to_be_bytes::<32>());
+                // Stop orders activated by the fills are matched right after them
+                for triggered in triggered {
+                    logs.extend(triggered_logs(fee_token, triggered));
+                }
 
-                // array elements
-                for pair_id in route {
-                    data.extend_from_slice(pair_id.as_slice());
+                // Then the exits of brackets whose entry finished trading
+                for bracket in brackets {
+                    logs.extend(bracket_logs(fee_token, bracket));
                 }
 
+                // Range orders filled by the order re-post on the other side
+                logs.extend(flipped.iter().map(range_flipped_log));
+
+                // An immediate-or-cancel remainder is cancelled in the same transaction
+                if *remainder_cancelled {
+                    logs.push(order_cancelled_log(*order_id, *trader));
+                }
+            }
+            DexResult::StopOrderPlaced {
+                order_id,
+                trader,
+                token_in,
+                token_out,
+                is_buy,
+                amount,
+                stop_num,
+                stop_denom,
+                limit_num,
+                limit_denom,
+                transfers: _,
+            } => {
+                // Non-indexed params: (address tokenOut, bool isBuy, uint256 amount, uint256 stopNum,
+                // uint256 stopDenom, uint256 limitNum, uint256 limitDenom)
+                let data = (
+                    *token_out,
+                    *is_buy,
+                    *amount,
+                    *stop_num,
+                    *stop_denom,
+                    *limit_num,
+                    *limit_denom,
+                )
+                    .abi_encode();
                 logs.push(Log {
                     address: DEX_PREDEPLOY_ADDRESS,
                     data: alloy_primitives::LogData::new_unchecked(
                         vec![
-                            EnshrinedDEX::Swap::SIGNATURE_HASH.into(),
+                            EnshrinedDEX::StopOrderPlaced::SIGNATURE_HASH.into(),
+                            *order_id,
                             B256::left_padding_from(trader.as_slice()),
                             B256::left_padding_from(token_in.as_slice()),
-                            B256::left_padding_from(token_out.as_slice()),
                         ],
                         data.into(),
                     ),
                 });
             }
-            DexResult::Quote { .. } => {}
-        }
+            DexResult::OcoOrderPlaced {
+                limit_order_id,
+                stop_order_id,
+                trader,
+                token_in,
+                token_out,
+                is_buy,
+                amount,
+                price_num,
+                stop_num,
+                stop_limit_num,
+                price_denom,
+                transfers: _,
+            } => {
+                // Each leg is logged like a standalone order, then the link between them
+                // Non-indexed params: (address tokenOut, bool isBuy, uint256 amount, uint256 priceNum, uint256 priceDenom)
+                let data = (*token_out, *is_buy, *amount, *price_num, *price_denom).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::LimitOrderPlaced::SIGNATURE_HASH.into(),
+                            *limit_order_id,
+                            B256::left_padding_from(trader.as_slice()),
+                            B256::left_padding_from(token_in.as_slice()),
+                        ],
+                        data.into(),
+                    ),
+                });
 
-        logs
-    }
-}
+                // Non-indexed params: (address tokenOut, bool isBuy, uint256 amount, uint256 stopNum,
+                // uint256 stopDenom, uint256 limitNum, uint256 limitDenom)
+                let data = (
+                    *token_out,
+                    *is_buy,
+                    *amount,
+                    *stop_num,
+                    *price_denom,
+                    *stop_limit_num,
+                    *price_denom,
+                )
+                    .abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::StopOrderPlaced::SIGNATURE_HASH.into(),
+                            *stop_order_id,
+                            B256::left_padding_from(trader.as_slice()),
+                            B256::left_padding_from(token_in.as_slice()),
+                        ],
+                        data.into(),
+                    ),
+                });
 
-impl Default for DexHandler {
-    fn default() -> Self {
-        Self::new()
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::OcoOrderPlaced::SIGNATURE_HASH.into(),
+                            *limit_order_id,
+                            *stop_order_id,
+                            B256::left_padding_from(trader.as_slice()),
+                        ],
+                        Bytes::new(),
+                    ),
+                });
+            }
+            DexResult::OrderCancelled {
+                order_id,
+                trader,
+                linked_order_id,
+                ..
+            } => {
+                logs.push(order_cancelled_log(*order_id, *trader));
+                // Linked orders always belong to the same trader
+                if let Some(linked_order_id) = linked_order_id {
+                    logs.push(order_cancelled_log(*linked_order_id, *trader));
+                }
+            }
+            DexResult::OrderAmended {
+                order_id,
+                trader,
+                amount,
+                price_num,
+                price_denom,
+                kept_priority,
+                transfers: _,
+            } => {
+                // Non-indexed params: (uint256 amount, uint256 priceNum, uint256 priceDenom, bool keptPriority)
+                let data = (*amount, *price_num, *price_denom, *kept_priority).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::OrderAmended::SIGNATURE_HASH.into(),
+                            *order_id,
+                            B256::left_padding_from(trader.as_slice()),
+                        ],
+                        data.into(),
+                    ),
+                });
+            }
+            DexResult::SwapExecuted {
+                trader,
+                token_in,
+                token_out,
+                splits,
+                transfers: _,
+                all_fills,
+                prevented,
+                triggered,
+                linked_cancels,
+                brackets,
+                flipped,
+            } => {
+                // Emit OrderFilled events for all fills
+                for (taker_order_id, fee_token, fill) in all_fills {
+                    let maker_order_id_b256 = order_id_to_b256(fill.maker_order_id);
+                    let taker_order_id_b256 = order_id_to_b256(*taker_order_id);
+
+                    logs.push(order_filled_log(
+                        maker_order_id_b256,
+                        taker_order_id_b256,
+                        fill,
+                    ));
+
+                    if let Some(log) =
+                        trade_fees_log(maker_order_id_b256, taker_order_id_b256, *fee_token, fill)
+                    {
+                        logs.push(log);
+                    }
+                }
+
+                for (taker_order_id, prevented) in prevented {
+                    logs.extend(prevented_logs(order_id_to_b256(*taker_order_id), prevented));
+                }
+
+                logs.extend(linked_cancels.iter().map(linked_cancel_log));
+
+                for (fee_token, triggered) in triggered {
+                    logs.extend(triggered_logs(*fee_token, triggered));
+                }
+
+                for (fee_token, bracket) in brackets {
+                    logs.extend(bracket_logs(*fee_token, bracket));
+                }
+
+                logs.extend(flipped.iter().map(range_flipped_log));
+
+                // One Swap event for each route the input was split across
+                for (amount_in, amount_out, route) in splits {
+                    // Non-indexed params: (uint256 amountIn, uint256 amountOut, bytes32[] route)
+                    // Manually encode to avoid tuple wrapper offset
+                    let mut data = Vec::new();
+
+                    // amountIn (32 bytes)
+                    data.extend_from_slice(&amount_in.to_be_bytes::<32>());
+
+                    // amountOut (32 bytes)
+                    data.extend_from_slice(&amount_out.to_be_bytes::<32>());
+
+                    // offset to array = 0x60 (96 bytes, after amountIn + amountOut + this offset)
+                    data.extend_from_slice(&U256::from(0x60).to_be_bytes::<32>());
+
+                    // array length
+                    data.extend_from_slice(&U256::from(route.len()).to_be_bytes::<32>());
+
+                    // array elements
+                    for pair_id in route {
+                        data.extend_from_slice(pair_id.as_slice());
+                    }
+
+                    logs.push(Log {
+                        address: DEX_PREDEPLOY_ADDRESS,
+                        data: alloy_primitives::LogData::new_unchecked(
+                            vec![
+                                EnshrinedDEX::Swap::SIGNATURE_HASH.into(),
+                                B256::left_padding_from(trader.as_slice()),
+                                B256::left_padding_from(token_in.as_slice()),
+                                B256::left_padding_from(token_out.as_slice()),
+                            ],
+                            data.into(),
+                        ),
+                    });
+                }
+            }
+            DexResult::SelfTradePreventionSet { pair_id, mode } => {
+                // Non-indexed params: (uint8 mode)
+                let data = (U256::from(*mode),).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::SelfTradePreventionSet::SIGNATURE_HASH.into(),
+                            *pair_id,
+                        ],
+                        data.into(),
+                    ),
+                });
+            }
+            DexResult::MarketModeSet { pair_id, mode } => {
+                // Non-indexed params: (uint8 mode)
+                let data = (U256::from(*mode),).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![EnshrinedDEX::MarketModeSet::SIGNATURE_HASH.into(), *pair_id],
+                        data.into(),
+                    ),
+                });
+            }
+            DexResult::BatchCleared {
+                pair_id,
+                quote,
+                clearing,
+                transfers: _,
+            } => {
+                logs.extend(batch_cleared_logs(*pair_id, *quote, clearing));
+            }
+            DexResult::FeesWithdrawn {
+                token, to, amount, ..
+            } => {
+                // Non-indexed params: (uint256 amount)
+                let data = (*amount,).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::FeesWithdrawn::SIGNATURE_HASH.into(),
+                            B256::left_padding_from(token.as_slice()),
+                            B256::left_padding_from(to.as_slice()),
+                        ],
+                        data.into(),
+                    ),
+                });
+            }
+            DexResult::LiquidityAdded {
+                provider,
+                token0,
+                token1,
+                amount0,
+                amount1,
+                ..
+            } => {
+                logs.push(liquidity_log(
+                    EnshrinedDEX::LiquidityAdded::SIGNATURE_HASH,
+                    *provider,
+                    *token0,
+                    *token1,
+                    *amount0,
+                    *amount1,
+                ));
+            }
+            DexResult::LiquidityRemoved {
+                provider,
+                token0,
+                token1,
+                amount0,
+                amount1,
+                ..
+            } => {
+                logs.push(liquidity_log(
+                    EnshrinedDEX::LiquidityRemoved::SIGNATURE_HASH,
+                    *provider,
+                    *token0,
+                    *token1,
+                    *amount0,
+                    *amount1,
+                ));
+            }
+            DexResult::Deposited {
+                account,
+                token,
+                amount,
+                transfers: _,
+            } => {
+                // Non-indexed params: (uint256 amount)
+                let data = (*amount,).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::Deposited::SIGNATURE_HASH.into(),
+                            B256::left_padding_from(account.as_slice()),
+                            B256::left_padding_from(token.as_slice()),
+                        ],
+                        data.into(),
+                    ),
+                });
+            }
+            DexResult::Withdrawn {
+                account,
+                token,
+                amount,
+                transfers: _,
+            } => {
+                // Non-indexed params: (uint256 amount)
+                let data = (*amount,).abi_encode();
+                logs.push(Log {
+                    address: DEX_PREDEPLOY_ADDRESS,
+                    data: alloy_primitives::LogData::new_unchecked(
+                        vec![
+                            EnshrinedDEX::Withdrawn::SIGNATURE_HASH.into(),
+                            B256::left_padding_from(account.as_slice()),
+                            B256::left_padding_from(token.as_slice()),
+                        ],
+                        data.into(),
+                    ),
+                });
+            }
+            DexResult::Quote { .. }
+            | DexResult::QuoteDetail { .. }
+            | DexResult::QuoteExactOut { .. }
+            | DexResult::Balance { .. } => {}
+        }
+
+        logs
+    }
+}
+
+/// Build a price from the numerator and denominator of an order ABI.
+fn price_from_parts(num: U256, denom: U256) -> Result<Price, DexError> {
+    if num == U256::ZERO || denom == U256::ZERO {
+        return Err(DexError::InvalidPrice { num, denom });
+    }
+
+    let num_u128: u128 = num
+        .try_into()
+        .map_err(|_| DexError::InvalidPrice { num, denom })?;
+    let denom_u128: u128 = denom
+        .try_into()
+        .map_err(|_| DexError::InvalidPrice { num, denom })?;
+
+    Ok(Price::from_u128(num_u128, denom_u128))
+}
+
+/// Decode a `SelfTradePrevention` ABI argument, `None` meaning the market's mode.
+fn decode_self_trade_prevention(mode: U256) -> Result<Option<SelfTradePrevention>, DexError> {
+    // The uint8 enum is decoded as a full word, so out of range values are rejected here
+    u8::try_from(mode)
+        .ok()
+        .and_then(self_trade_prevention_from_u8)
+        .ok_or_else(|| {
+            DexError::InvalidCalldata(format!("invalid self-trade prevention: {}", mode))
+        })
+}
+
+/// The pair a provider adds or removes liquidity on, given its tokens in either order.
+fn liquidity_pair(pm: &PoolManager, token0: Address, token1: Address) -> Result<Pair, DexError> {
+    let pair_id = PairId::from_tokens(token0, token1);
+    pm.get_orderbook_by_id(&pair_id)
+        .map(|ob| ob.pair)
+        .ok_or_else(|| {
+            PoolError::PairNotFound {
+                token0,
+                token1,
+                pair_id,
+            }
+            .into()
+        })
+}
+
+/// Create a LiquidityAdded or LiquidityRemoved log.
+fn liquidity_log(
+    signature: B256,
+    provider: Address,
+    token0: Address,
+    token1: Address,
+    amount0: U256,
+    amount1: U256,
+) -> Log {
+    // Non-indexed params: (uint256 amount0, uint256 amount1)
+    let data = (amount0, amount1).abi_encode();
+    Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                signature,
+                B256::left_padding_from(provider.as_slice()),
+                B256::left_padding_from(token0.as_slice()),
+                B256::left_padding_from(token1.as_slice()),
+            ],
+            data.into(),
+        ),
+    }
+}
+
+/// Quote a buy of `base_amount` escrows at its limit price. Buys are sized in
+/// quote and rounded down to whole lots, so this is what the trader is debited
+/// rather than the quote the order was sized with.
+fn buy_escrow(price: Price, base_amount: U256) -> Result<U256, DexError> {
+    price
+        .quote_amount(base_amount)
+        .ok_or(DexError::InvalidAmount(base_amount))
+}
+
+/// Taker fee allowance of an order escrowing `escrow` on `pair`: the most a buy
+/// can owe in taker fees beyond its escrow when it crosses. Sells pay the fee out
+/// of their proceeds and need none.
+fn fee_allowance(pm: &PoolManager, pair: Pair, side: OrderSide, escrow: U256) -> U256 {
+    match (side, pm.market_config(pair.base, pair.quote)) {
+        (OrderSide::Buy, Some(market)) => {
+            let fee_basis = escrow.try_into().unwrap_or(u128::MAX);
+            U256::from(market.calculate_fee(fee_basis, pm.config()))
+        }
+        _ => U256::ZERO,
+    }
+}
+
+/// Check that an account's DEX balance covers the escrow an operation locks,
+/// before the book changes.
+fn check_balance(
+    pm: &PoolManager,
+    account: Address,
+    token: Address,
+    amount: U256,
+) -> Result<(), DexError> {
+    let available = pm.balance(account, token);
+    if available < amount {
+        return Err(PoolError::InsufficientBalance {
+            account,
+            token,
+            required: amount,
+            available,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Settle an operation's transfers against DEX balances: transfers into the DEX
+/// lock escrow out of the sender's balance, transfers out of it credit the
+/// recipient's balance.
+fn settle_transfers(pm: &mut PoolManager, transfers: &[TokenTransfer]) -> Result<(), DexError> {
+    for transfer in transfers {
+        if transfer.from != DEX_PREDEPLOY_ADDRESS {
+            pm.debit(transfer.from, transfer.token, transfer.amount)
+                .map_err(DexError::from)?;
+        }
+        if transfer.to != DEX_PREDEPLOY_ADDRESS {
+            pm.credit(transfer.to, transfer.token, transfer.amount);
+        }
+    }
+    Ok(())
+}
+
+/// Settle transfers that all pay out of the DEX by crediting their recipients.
+/// Unlike `settle_transfers` this can't fail, as nothing is debited.
+fn credit_payouts(pm: &mut PoolManager, transfers: &[TokenTransfer]) {
+    for transfer in transfers {
+        debug_assert_eq!(transfer.from, DEX_PREDEPLOY_ADDRESS);
+        pm.credit(transfer.to, transfer.token, transfer.amount);
+    }
+}
+
+/// Settle an executed swap and build its result. `transfers` holds what the
+/// taker paid in; the output, any intermediate tokens left over between hops and
+/// every hop's fills and refunds are added to it before it's settled.
+fn swap_executed(
+    pm: &mut PoolManager,
+    caller: Address,
+    token_in: Address,
+    token_out: Address,
+    result: SwapResult,
+    mut transfers: Vec<TokenTransfer>,
+) -> Result<DexResult, DexError> {
+    // Convert each route taken to Vec<B256>
+    let splits: Vec<(U256, U256, Vec<B256>)> = result
+        .splits
+        .iter()
+        .map(|split| {
+            let route = split
+                .route
+                .hops
+                .iter()
+                .map(|hop| {
+                    let pair_id = hop.pair.id();
+                    B256::from_slice(&pair_id.0)
+                })
+                .collect();
+            (split.amount_in, split.amount_out, route)
+        })
+        .collect();
+
+    // Build token transfers based on fills
+    // Escrow model:
+    // 1. Taker (caller) sends token_in to DEX (or directly to makers)
+    // 2. DEX sends token_out to taker (from maker's escrow)
+    // 3. DEX sends token_in to each maker (their share)
+
+    // DEX sends token_out to taker (total amount_out)
+    transfers.push(TokenTransfer {
+        token: token_out,
+        from: DEX_PREDEPLOY_ADDRESS,
+        to: caller,
+        amount: result.amount_out,
+    });
+
+    // Intermediate tokens a hop didn't pass on go back to the taker
+    transfers.extend(
+        result
+            .leftovers
+            .iter()
+            .map(|&(token, amount)| TokenTransfer {
+                token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: caller,
+                amount,
+            }),
+    );
+
+    // Collect all fills for OrderFilled events
+    let mut all_fills = Vec::new();
+    let mut prevented = Vec::new();
+    let mut triggered = Vec::new();
+    let mut linked_cancels = Vec::new();
+    let mut brackets = Vec::new();
+    let mut flipped = Vec::new();
+
+    // For each hop and fill, determine the correct amount to send to maker
+    let hops = result.splits.iter().flat_map(|split| &split.route.hops);
+    for (hop, trade) in hops.zip(&result.trades) {
+        for fill in &trade.fills {
+            // Store fills with their taker order IDs for OrderFilled and TradeFees events
+            all_fills.push((trade.taker_order_id, hop.pair.quote, fill.clone()));
+
+            transfers.extend(maker_payouts(pm, hop, trade, fill));
+        }
+
+        // Own resting orders cancelled or reduced by self-trade prevention are refunded
+        transfers.extend(prevented_refunds(hop.pair, &trade.prevented));
+        prevented.extend(
+            trade
+                .prevented
+                .iter()
+                .map(|p| (trade.taker_order_id, p.clone())),
+        );
+
+        transfers.extend(triggered_refunds(hop.pair, &trade.triggered));
+        triggered.extend(trade.triggered.iter().map(|t| (hop.pair.quote, t.clone())));
+
+        transfers.extend(bracket_refunds(hop.pair, &trade.brackets));
+        brackets.extend(trade.brackets.iter().map(|b| (hop.pair.quote, b.clone())));
+
+        transfers.extend(linked_cancel_refunds(pm, hop.pair, &trade.linked_cancels));
+        linked_cancels.extend(trade.linked_cancels.iter().cloned());
+
+        transfers.extend(flipped_refunds(hop.pair, &trade.flipped));
+        flipped.extend(trade.flipped.iter().cloned());
+    }
+    settle_transfers(pm, &transfers)?;
+
+    let hops = result.splits.iter().flat_map(|split| &split.route.hops);
+    for (hop, trade) in hops.zip(&result.trades) {
+        transfers.extend(settle_activated_fills(pm, hop.pair, trade)?);
+    }
+
+    info!(
+        caller = ?caller,
+        token_in = ?token_in,
+        token_out = ?token_out,
+        amount_in = ?result.amount_in,
+        amount_out = ?result.amount_out,
+        routes = splits.len(),
+        hops = result.trades.len(),
+        fills = all_fills.len(),
+        prevented = prevented.len(),
+        triggered = triggered.len(),
+        linked_cancels = linked_cancels.len(),
+        brackets = brackets.len(),
+        flipped = flipped.len(),
+        transfers = transfers.len(),
+        "Swap executed successfully"
+    );
+
+    Ok(DexResult::SwapExecuted {
+        trader: caller,
+        token_in,
+        token_out,
+        splits,
+        transfers,
+        all_fills,
+        prevented,
+        triggered,
+        linked_cancels,
+        brackets,
+        flipped,
+    })
+}
+
+/// Settle the fills of an order that traded as a taker on `pair` against DEX
+/// balances, after the rest of the operation, and return the transfers made.
+///
+/// The taker is paid net of the taker fee, unless it keeps the base it buys,
+/// and each maker through `maker_payouts`. A buy pays for each fill and its
+/// taker fee out of the escrow its limit price locked and gets back the rest.
+/// What that escrow doesn't cover comes out of `fee_reserve`, the allowance a
+/// triggered stop locked when placed, whose unused part is paid back, and
+/// otherwise out of the balance the placement checked for it.
+fn settle_taker_fills(
+    pm: &mut PoolManager,
+    pair: Pair,
+    trade: &TradeResult,
+    taker: Taker,
+    fills: &[Fill],
+    keeps_base: bool,
+    mut fee_reserve: U256,
+) -> Result<Vec<TokenTransfer>, DexError> {
+    let (trader, side, limit_price) = taker;
+    let hop = match side {
+        OrderSide::Sell => RouteHop {
+            pair,
+            token_in: pair.base,
+            token_out: pair.quote,
+        },
+        OrderSide::Buy => RouteHop {
+            pair,
+            token_in: pair.quote,
+            token_out: pair.base,
+        },
+    };
+    let payout = |token, amount| TokenTransfer {
+        token,
+        from: DEX_PREDEPLOY_ADDRESS,
+        to: trader,
+        amount,
+    };
+
+    let mut transfers = Vec::new();
+    for fill in fills {
+        let mut fill_transfers = maker_payouts(pm, &hop, trade, fill);
+        match side {
+            OrderSide::Sell => fill_transfers.push(payout(
+                pair.quote,
+                fill.quote_amount.saturating_sub(fill.taker_fee),
+            )),
+            OrderSide::Buy => {
+                if !keeps_base {
+                    fill_transfers.push(payout(pair.base, fill.base_amount));
+                }
+                let escrowed = limit_price
+                    .quote_amount(fill.base_amount)
+                    .unwrap_or(U256::ZERO);
+                let cost = fill.quote_amount.saturating_add(fill.taker_fee);
+                if escrowed >= cost {
+                    fill_transfers.push(payout(pair.quote, escrowed - cost));
+                } else {
+                    let shortfall = cost - escrowed;
+                    let from_reserve = shortfall.min(fee_reserve);
+                    fee_reserve -= from_reserve;
+                    fill_transfers.push(TokenTransfer {
+                        token: pair.quote,
+                        from: trader,
+                        to: DEX_PREDEPLOY_ADDRESS,
+                        amount: shortfall - from_reserve,
+                    });
+                }
+            }
+        }
+        fill_transfers.retain(|t| !t.amount.is_zero());
+        settle_transfers(pm, &fill_transfers)?;
+        transfers.extend(fill_transfers);
+    }
+
+    if !fee_reserve.is_zero() {
+        let refund = payout(pair.quote, fee_reserve);
+        settle_transfers(pm, std::slice::from_ref(&refund))?;
+        transfers.push(refund);
+    }
+    Ok(transfers)
+}
+
+/// Settle the fills of the orders a trade activated that went on to trade as
+/// takers: the stop orders it triggered and the take-profit exits of the
+/// brackets it filled.
+fn settle_activated_fills(
+    pm: &mut PoolManager,
+    pair: Pair,
+    trade: &TradeResult,
+) -> Result<Vec<TokenTransfer>, DexError> {
+    let takers = trade.triggered.iter().map(|t| (&t.order, &t.fills)).chain(
+        trade
+            .brackets
+            .iter()
+            .map(|b| (&b.take_profit, &b.take_profit_fills)),
+    );
+
+    // Only a triggered buy stop locked a fee reserve: take-profit exits sell
+    let mut transfers = Vec::new();
+    for (order, fills) in takers {
+        let taker = (order.trader, order.side, order.price);
+        let fee_reserve = fee_allowance(pm, pair, order.side, order.original_escrow());
+        transfers.extend(settle_taker_fills(
+            pm,
+            pair,
+            trade,
+            taker,
+            fills,
+            false,
+            fee_reserve,
+        )?);
+    }
+    Ok(transfers)
+}
+
+/// Transfers paying the maker of one fill, see `maker_fill_transfers`.
+///
+/// What the taker pays the pool stays with the DEX as reserves. A bracket entry
+/// keeps the base it buys as the escrow of its exits, and a range order keeps
+/// its proceeds until it flips.
+fn maker_payouts(
+    pm: &PoolManager,
+    hop: &RouteHop,
+    trade: &TradeResult,
+    fill: &Fill,
+) -> Vec<TokenTransfer> {
+    if fill.from_pool {
+        return Vec::new();
+    }
+
+    // If pair.base == token_in at this hop, the taker is selling base
+    let taker_is_selling_base = hop.pair.base == hop.token_in;
+    let transfers = maker_fill_transfers(hop, fill, taker_is_selling_base);
+    if is_bracket_entry(pm, trade, fill.maker_order_id) {
+        transfers
+            .into_iter()
+            .filter(|t| t.token != hop.pair.base)
+            .collect()
+    } else if is_range_order(pm, trade, fill.maker_order_id) {
+        transfers
+            .into_iter()
+            .filter(|t| t.token != hop.token_in)
+            .collect()
+    } else {
+        transfers
+    }
+}
+
+/// Refund of the escrow held for `amount` of base by an order on `pair`: sell
+/// orders escrowed base, buy orders escrowed quote at their limit price.
+fn escrow_refund(
+    pair: Pair,
+    trader: Address,
+    side: OrderSide,
+    price: Price,
+    amount: U256,
+) -> Option<TokenTransfer> {
+    let (token, amount) = match side {
+        OrderSide::Sell => (pair.base, amount),
+        OrderSide::Buy => (pair.quote, price.quote_amount(amount).unwrap_or(U256::ZERO)),
+    };
+    if amount.is_zero() {
+        return None;
+    }
+    Some(TokenTransfer {
+        token,
+        from: DEX_PREDEPLOY_ADDRESS,
+        to: trader,
+        amount,
+    })
+}
+
+/// Refunds for resting orders cancelled or reduced by self-trade prevention.
+fn prevented_refunds(pair: Pair, prevented: &[PreventedTrade]) -> Vec<TokenTransfer> {
+    prevented
+        .iter()
+        .flat_map(|p| {
+            // A cancelled range order also pays out the proceeds it was holding
+            let (amount, proceeds) = if p.maker.status == OrderStatus::Cancelled {
+                (
+                    p.maker.remaining_amount,
+                    range_proceeds_refund(pair, &p.maker),
+                )
+            } else {
+                (p.maker_reduced, None)
+            };
+            let refund = escrow_refund(pair, p.maker.trader, p.maker.side, p.maker.price, amount);
+            [refund, proceeds]
+        })
+        .flatten()
+        .collect()
+}
+
+/// Total base amount self-trade prevention cut from the incoming order.
+fn taker_reduced(prevented: &[PreventedTrade]) -> U256 {
+    prevented
+        .iter()
+        .fold(U256::ZERO, |total, p| total.saturating_add(p.taker_reduced))
+}
+
+/// Whether a maker order is the entry of a bracket, either activated by this
+/// trade or still waiting to fill.
+fn is_bracket_entry(pm: &PoolManager, trade: &TradeResult, maker_order_id: OrderId) -> bool {
+    trade.brackets.iter().any(|b| b.entry_id == maker_order_id)
+        || pm
+            .order_group(maker_order_id)
+            .is_some_and(|g| g.kind == OrderGroupKind::Bracket && g.orders[0] == maker_order_id)
+}
+
+/// Whether a maker order is a range order, either flipped by this trade or
+/// still resting.
+fn is_range_order(pm: &PoolManager, trade: &TradeResult, maker_order_id: OrderId) -> bool {
+    trade.flipped.iter().any(|f| f.filled.id == maker_order_id)
+        || pm
+            .get_order(maker_order_id)
+            .is_some_and(|(_, order)| order.flip_price.is_some())
+}
+
+/// Payout of the proceeds a range order collected since it last flipped: quote
+/// for a sell order, base for a buy order.
+fn range_proceeds_refund(pair: Pair, order: &Order) -> Option<TokenTransfer> {
+    if order.range_proceeds.is_zero() {
+        return None;
+    }
+    let token = match order.side {
+        OrderSide::Sell => pair.quote,
+        OrderSide::Buy => pair.base,
+    };
+    Some(TokenTransfer {
+        token,
+        from: DEX_PREDEPLOY_ADDRESS,
+        to: order.trader,
+        amount: order.range_proceeds,
+    })
+}
+
+/// Payouts for range orders that flipped: whatever of their proceeds the
+/// re-posted order doesn't escrow, or all of it if the order wasn't re-posted.
+fn flipped_refunds(pair: Pair, flipped: &[FlippedOrder]) -> Vec<TokenTransfer> {
+    flipped
+        .iter()
+        .filter(|f| !f.refund.is_zero())
+        .map(|f| {
+            let token = match f.filled.side {
+                OrderSide::Sell => pair.quote,
+                OrderSide::Buy => pair.base,
+            };
+            TokenTransfer {
+                token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: f.filled.trader,
+                amount: f.refund,
+            }
+        })
+        .collect()
+}
+
+/// Refunds for stop orders activated by a trade: the escrow of a cancelled
+/// remainder and of anything self-trade prevention cancelled or reduced.
+fn triggered_refunds(pair: Pair, triggered: &[TriggeredOrder]) -> Vec<TokenTransfer> {
+    triggered
+        .iter()
+        .flat_map(|t| taker_refunds(pair, &t.order, &t.prevented))
+        .collect()
+}
+
+/// Refunds for an order that traded as a taker outside its own placement: the
+/// escrow of a cancelled remainder and of anything self-trade prevention
+/// cancelled or reduced.
+fn taker_refunds(pair: Pair, order: &Order, prevented: &[PreventedTrade]) -> Vec<TokenTransfer> {
+    let mut transfers = Vec::new();
+    if order.status == OrderStatus::Cancelled {
+        transfers.extend(escrow_refund(
+            pair,
+            order.trader,
+            order.side,
+            order.price,
+            order.remaining_amount,
+        ));
+    }
+    transfers.extend(prevented_refunds(pair, prevented));
+    transfers.extend(escrow_refund(
+        pair,
+        order.trader,
+        order.side,
+        order.price,
+        taker_reduced(prevented),
+    ));
+    transfers
+}
+
+/// Refunds for the take-profit exits of brackets activated by a trade.
+fn bracket_refunds(pair: Pair, brackets: &[ActivatedBracket]) -> Vec<TokenTransfer> {
+    brackets
+        .iter()
+        .flat_map(|b| taker_refunds(pair, &b.take_profit, &b.take_profit_prevented))
+        .collect()
+}
+
+/// Refunds for orders cancelled because their linked order traded or was
+/// cancelled.
+///
+/// Linked orders share a single escrow sized for the larger of the two, so the
+/// cancelled order only hands back what it held beyond the escrow of the order
+/// that cancelled it, along with the taker fee reserve of a buy stop that never
+/// triggered.
+fn linked_cancel_refunds(
+    pm: &PoolManager,
+    pair: Pair,
+    linked_cancels: &[LinkedCancel],
+) -> Vec<TokenTransfer> {
+    linked_cancels
+        .iter()
+        .filter_map(|l| {
+            let mut amount = l
+                .order
+                .remaining_escrow()
+                .saturating_sub(l.cancelled_by.original_escrow());
+            if l.pending_stop {
+                amount += fee_allowance(pm, pair, l.order.side, l.order.original_escrow());
+            }
+            if amount.is_zero() {
+                return None;
+            }
+            let token = match l.order.side {
+                OrderSide::Sell => pair.base,
+                OrderSide::Buy => pair.quote,
+            };
+            Some(TokenTransfer {
+                token,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to: l.order.trader,
+                amount,
+            })
+        })
+        .collect()
+}
+
+/// Transfers paying a maker for one swap fill, net of the maker fee.
+///
+/// The maker receives the token the taker pays in. Fees are quote-denominated:
+/// a maker selling base is paid in quote, so the fee is netted directly. A maker
+/// buying base is paid in base, so a positive fee is withheld in base at the fill
+/// price, while a rebate is paid in quote out of the taker fee withheld from the
+/// taker's quote output.
+fn maker_fill_transfers(
+    hop: &RouteHop,
+    fill: &Fill,
+    taker_is_selling_base: bool,
+) -> Vec<TokenTransfer> {
+    let fee = fill.maker_fee.unsigned_abs();
+    let is_rebate = fill.maker_fee.is_negative();
+
+    let transfer = |token, amount| TokenTransfer {
+        token,
+        from: DEX_PREDEPLOY_ADDRESS,
+        to: fill.maker,
+        amount,
+    };
+
+    if !taker_is_selling_base {
+        // token_in is quote
+        let amount = if is_rebate {
+            fill.quote_amount.saturating_add(fee)
+        } else {
+            fill.quote_amount.saturating_sub(fee)
+        };
+        return vec![transfer(hop.token_in, amount)];
+    }
+
+    // token_in is base, token_out is quote
+    if is_rebate {
+        let mut transfers = vec![transfer(hop.token_in, fill.base_amount)];
+        if !fee.is_zero() {
+            transfers.push(transfer(hop.token_out, fee));
+        }
+        return transfers;
+    }
+
+    vec![transfer(
+        hop.token_in,
+        fill.base_amount.saturating_sub(fill.maker_fee_in_base()),
+    )]
+}
+
+/// Transfers settling one fill of a batch auction. The buy order is settled as
+/// the maker of a swap selling base and the sell order as its taker, and the
+/// buyer gets back the escrow its limit price locked above the clearing price.
+fn batch_fill_transfers(hop: &RouteHop, batch_fill: &BatchFill) -> Vec<TokenTransfer> {
+    let fill = &batch_fill.fill;
+    let mut transfers = maker_fill_transfers(hop, fill, true);
+
+    let proceeds = fill.quote_amount.saturating_sub(fill.taker_fee);
+    let escrowed = batch_fill
+        .buy_price
+        .quote_amount(fill.base_amount)
+        .unwrap_or(U256::ZERO);
+    let improvement = escrowed.saturating_sub(fill.quote_amount);
+    for (to, amount) in [(batch_fill.seller, proceeds), (fill.maker, improvement)] {
+        if !amount.is_zero() {
+            transfers.push(TokenTransfer {
+                token: hop.token_out,
+                from: DEX_PREDEPLOY_ADDRESS,
+                to,
+                amount,
+            });
+        }
+    }
+    transfers
+}
+
+/// Create the logs for a cleared batch auction: the clearing price and volume,
+/// each fill with its fees, and the immediate-or-cancel orders cancelled.
+fn batch_cleared_logs(pair_id: B256, fee_token: Address, clearing: &BatchClearing) -> Vec<Log> {
+    let mut logs = Vec::new();
+
+    if let Some(price) = clearing.price {
+        // Non-indexed params: (uint256 priceNum, uint256 priceDenom, uint256 volume)
+        let data = (price.numerator, price.denominator, clearing.volume).abi_encode();
+        logs.push(Log {
+            address: DEX_PREDEPLOY_ADDRESS,
+            data: alloy_primitives::LogData::new_unchecked(
+                vec![EnshrinedDEX::BatchCleared::SIGNATURE_HASH.into(), pair_id],
+                data.into(),
+            ),
+        });
+    }
+
+    for batch_fill in &clearing.fills {
+        let buy_order_id = order_id_to_b256(batch_fill.fill.maker_order_id);
+        let sell_order_id = order_id_to_b256(batch_fill.sell_order_id);
+        logs.push(order_filled_log(
+            buy_order_id,
+            sell_order_id,
+            &batch_fill.fill,
+        ));
+        logs.extend(trade_fees_log(
+            buy_order_id,
+            sell_order_id,
+            fee_token,
+            &batch_fill.fill,
+        ));
+    }
+
+    for order in &clearing.cancelled {
+        logs.push(order_cancelled_log(
+            order_id_to_b256(order.id),
+            order.trader,
+        ));
+    }
+
+    logs
+}
+
+/// Create an OrderFilled log for a fill.
+fn order_filled_log(maker_order_id: B256, taker_order_id: B256, fill: &Fill) -> Log {
+    // Non-indexed params: (uint256 amount)
+    let data = (fill.base_amount,).abi_encode();
+    Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::OrderFilled::SIGNATURE_HASH.into(),
+                maker_order_id,
+                taker_order_id,
+            ],
+            data.into(),
+        ),
+    }
+}
+
+/// Create an OrderCancelled log.
+fn order_cancelled_log(order_id: B256, trader: Address) -> Log {
+    Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::OrderCancelled::SIGNATURE_HASH.into(),
+                order_id,
+                B256::left_padding_from(trader.as_slice()),
+            ],
+            Bytes::new(),
+        ),
+    }
+}
+
+/// Create the logs for a stop order activated by a trade: the trigger, the fills
+/// of the activated order and the cancellation of a remainder that didn't rest.
+fn triggered_logs(fee_token: Address, triggered: &TriggeredOrder) -> Vec<Log> {
+    let order_id = order_id_to_b256(triggered.order.id);
+
+    // Non-indexed params: (uint256 priceNum, uint256 priceDenom)
+    let data = (
+        triggered.trigger_price.numerator,
+        triggered.trigger_price.denominator,
+    )
+        .abi_encode();
+    let mut logs = vec![Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::StopOrderTriggered::SIGNATURE_HASH.into(),
+                order_id,
+            ],
+            data.into(),
+        ),
+    }];
+
+    for fill in &triggered.fills {
+        let maker_order_id = order_id_to_b256(fill.maker_order_id);
+        logs.push(order_filled_log(maker_order_id, order_id, fill));
+        if let Some(log) = trade_fees_log(maker_order_id, order_id, fee_token, fill) {
+            logs.push(log);
+        }
+    }
+
+    for prevented in &triggered.prevented {
+        logs.extend(prevented_logs(order_id, prevented));
+    }
+
+    if triggered.order.status == OrderStatus::Cancelled {
+        logs.push(order_cancelled_log(order_id, triggered.order.trader));
+    }
+
+    logs
+}
+
+/// Create the log for a range order that flipped, with the re-posted order's
+/// amount in its tokenIn, or zero if the order wasn't re-posted.
+fn range_flipped_log(flipped: &FlippedOrder) -> Log {
+    let (is_buy, amount, price) = match &flipped.reposted {
+        Some(order) => (
+            order.side == OrderSide::Buy,
+            order.original_escrow(),
+            order.price,
+        ),
+        None => (
+            flipped.filled.side == OrderSide::Sell,
+            U256::ZERO,
+            flipped.filled.flip_price.unwrap_or(flipped.filled.price),
+        ),
+    };
+
+    // Non-indexed params: (bool isBuy, uint256 amount, uint256 priceNum, uint256 priceDenom)
+    let data = (is_buy, amount, price.numerator, price.denominator).abi_encode();
+    Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::RangeOrderFlipped::SIGNATURE_HASH.into(),
+                order_id_to_b256(flipped.filled.id),
+            ],
+            data.into(),
+        ),
+    }
+}
+
+/// Create the OrderCancelled log for an order cancelled by its linked order.
+fn linked_cancel_log(linked_cancel: &LinkedCancel) -> Log {
+    order_cancelled_log(
+        order_id_to_b256(linked_cancel.order.id),
+        linked_cancel.order.trader,
+    )
+}
+
+/// Create the logs for a bracket whose entry finished trading: the activation,
+/// the fills of the take-profit and the cancellation of a take-profit remainder
+/// that didn't rest.
+fn bracket_logs(fee_token: Address, bracket: &ActivatedBracket) -> Vec<Log> {
+    let take_profit_id = order_id_to_b256(bracket.take_profit.id);
+
+    // Non-indexed params: (uint256 amount)
+    let data = (bracket.take_profit.original_amount,).abi_encode();
+    let mut logs = vec![Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::BracketActivated::SIGNATURE_HASH.into(),
+                order_id_to_b256(bracket.entry_id),
+                take_profit_id,
+                order_id_to_b256(bracket.stop_loss.id),
+            ],
+            data.into(),
+        ),
+    }];
+
+    for fill in &bracket.take_profit_fills {
+        let maker_order_id = order_id_to_b256(fill.maker_order_id);
+        logs.push(order_filled_log(maker_order_id, take_profit_id, fill));
+        if let Some(log) = trade_fees_log(maker_order_id, take_profit_id, fee_token, fill) {
+            logs.push(log);
+        }
+    }
+
+    for prevented in &bracket.take_profit_prevented {
+        logs.extend(prevented_logs(take_profit_id, prevented));
+    }
+
+    if bracket.take_profit.status == OrderStatus::Cancelled {
+        logs.push(order_cancelled_log(
+            take_profit_id,
+            bracket.take_profit.trader,
+        ));
+    }
+
+    logs
+}
+
+/// Create the logs for a match stopped by self-trade prevention, including the
+/// cancellation of the resting order if it was cancelled.
+fn prevented_logs(taker_order_id: B256, prevented: &PreventedTrade) -> Vec<Log> {
+    let maker_order_id = order_id_to_b256(prevented.maker.id);
+
+    // Non-indexed params: (uint256 makerReduced, uint256 takerReduced)
+    let data = (prevented.maker_reduced, prevented.taker_reduced).abi_encode();
+    let mut logs = vec![Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::SelfTradePrevented::SIGNATURE_HASH.into(),
+                maker_order_id,
+                taker_order_id,
+            ],
+            data.into(),
+        ),
+    }];
+
+    if prevented.maker.status == OrderStatus::Cancelled {
+        logs.push(order_cancelled_log(maker_order_id, prevented.maker.trader));
+    }
+
+    logs
+}
+
+/// Create a TradeFees log for a fill, or `None` if it was fee-free.
+fn trade_fees_log(
+    maker_order_id: B256,
+    taker_order_id: B256,
+    token: Address,
+    fill: &Fill,
+) -> Option<Log> {
+    if fill.taker_fee.is_zero() && fill.maker_fee.is_zero() {
+        return None;
+    }
+
+    // Non-indexed params: (uint256 takerFee, int256 makerFee)
+    let data = (fill.taker_fee, fill.maker_fee).abi_encode();
+    Some(Log {
+        address: DEX_PREDEPLOY_ADDRESS,
+        data: alloy_primitives::LogData::new_unchecked(
+            vec![
+                EnshrinedDEX::TradeFees::SIGNATURE_HASH.into(),
+                maker_order_id,
+                taker_order_id,
+                B256::left_padding_from(token.as_slice()),
+            ],
+            data.into(),
+        ),
+    })
+}
+
+impl Default for DexHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_sol_types::SolCall;
+
+    fn weth() -> Address {
+        Address::repeat_byte(0x01)
+    }
+
+    fn usdc() -> Address {
+        Address::repeat_byte(0x02)
+    }
+
+    fn alice() -> Address {
+        Address::repeat_byte(0xa1)
+    }
+
+    fn bob() -> Address {
+        Address::repeat_byte(0xb0)
+    }
+
+    fn carol() -> Address {
+        Address::repeat_byte(0xc0)
+    }
+
+    /// Handle a call and complete its token transfers as the builder would.
+    fn call(handler: &DexHandler, caller: Address, call: impl SolCall) -> DexResult {
+        let calldata = Bytes::from(call.abi_encode());
+        let block = BlockContext::new(1, 0, 12);
+        let result = handler
+            .handle_transaction(caller, &calldata, U256::ZERO, block)
+            .unwrap();
+        handler.complete_transfer(&result, true);
+        result
+    }
+
+    fn deposit(handler: &DexHandler, account: Address, token: Address, amount: u64) {
+        let amount = U256::from(amount);
+        call(
+            handler,
+            account,
+            EnshrinedDEX::depositCall { token, amount },
+        );
+    }
+
+    fn place_limit_order(
+        handler: &DexHandler,
+        trader: Address,
+        (token_in, token_out): (Address, Address),
+        amount: u64,
+        price: u64,
+    ) -> DexResult {
+        let order = EnshrinedDEX::placeLimitOrderCall {
+            tokenIn: token_in,
+            tokenOut: token_out,
+            isBuy: token_in == usdc(),
+            amount: U256::from(amount),
+            priceNum: U256::from(price),
+            priceDenom: U256::from(1),
+        };
+        call(handler, trader, order)
+    }
+
+    /// What the DEX holds of a token for its users: their balances, the escrow
+    /// of their open orders and the protocol fees.
+    fn ledger_total(handler: &DexHandler, token: Address, open_orders: &[OrderId]) -> U256 {
+        let pm = handler.pool_manager.read();
+        let balances = [alice(), bob(), carol()]
+            .iter()
+            .fold(U256::ZERO, |total, &account| {
+                total + pm.balance(account, token)
+            });
+        let escrow = open_orders
+            .iter()
+            .filter_map(|&id| pm.get_order(id))
+            .filter(|(pair, order)| match order.side {
+                OrderSide::Sell => pair.base == token,
+                OrderSide::Buy => pair.quote == token,
+            })
+            .fold(U256::ZERO, |total, (_, order)| {
+                total + order.remaining_escrow()
+            });
+        balances + escrow + pm.accrued_fees(token)
+    }
+
+    fn create_pair_with_params(lot_size: u64) -> EnshrinedDEX::createPairWithParamsCall {
+        EnshrinedDEX::createPairWithParamsCall {
+            token0: weth(),
+            token1: usdc(),
+            tickNum: U256::ZERO,
+            tickDenom: U256::ZERO,
+            lotSize: U256::from(lot_size),
+            minNotional: U256::ZERO,
+            overrideFee: false,
+            feeBps: U256::ZERO,
+            makerFeeBps: I256::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_create_pair_with_params_requires_admin() {
+        let handler = DexHandler::with_admin(alice());
+        let calldata = Bytes::from(create_pair_with_params(1_000).abi_encode());
+        let block = BlockContext::new(1, 0, 12);
+
+        // Only the admin picks market parameters
+        let err = handler
+            .handle_transaction(bob(), &calldata, U256::ZERO, block)
+            .unwrap_err();
+        assert!(matches!(err, DexError::Unauthorized(caller) if caller == bob()));
+
+        // Anyone can create a pair with the defaults
+        call(&handler, bob(), create_pair_with_params(1));
+        let pm = handler.pool_manager.read();
+        assert_eq!(
+            pm.market_config(weth(), usdc()),
+            Some(&MarketConfig::default())
+        );
+    }
+
+    #[test]
+    fn test_rounded_buy_refunds_in_full_on_cancel() {
+        let handler = DexHandler::with_admin(alice());
+        call(&handler, alice(), create_pair_with_params(1_000));
+        deposit(&handler, bob(), usdc(), 10_000);
+
+        // 10k quote at 3 is 3333 base, rounded down to 3000: only 9k is escrowed
+        let DexResult::OrderPlaced { order_id, .. } =
+            place_limit_order(&handler, bob(), (usdc(), weth()), 10_000, 3)
+        else {
+            panic!("expected OrderPlaced");
+        };
+        let balance = || handler.pool_manager.read().balance(bob(), usdc());
+        assert_eq!(balance(), U256::from(1_000));
+
+        call(
+            &handler,
+            bob(),
+            EnshrinedDEX::cancelOrderCall { orderId: order_id },
+        );
+        assert_eq!(balance(), U256::from(10_000));
+    }
+
+    #[test]
+    fn test_crossing_order_settles_fills() {
+        let handler = DexHandler::new();
+        let pair_call = EnshrinedDEX::createPairCall {
+            token0: weth(),
+            token1: usdc(),
+        };
+        call(&handler, alice(), pair_call);
+        deposit(&handler, alice(), weth(), 1_000_000);
+        deposit(&handler, bob(), usdc(), 2_000_000);
+
+        let DexResult::OrderPlaced { order_id, .. } =
+            place_limit_order(&handler, alice(), (weth(), usdc()), 1_000_000, 2)
+        else {
+            panic!("expected OrderPlaced");
+        };
+        let ask = order_id_from_b256(order_id).unwrap();
+
+        // Buy 500k at up to 3 against the ask at 2: 1M quote plus a 3k taker
+        // fee, the rest of the 1.5M escrow comes back
+        let DexResult::OrderPlaced { fills, .. } =
+            place_limit_order(&handler, bob(), (usdc(), weth()), 1_500_000, 3)
+        else {
+            panic!("expected OrderPlaced");
+        };
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].taker_fee, U256::from(3_000));
+        {
+            let pm = handler.pool_manager.read();
+            assert_eq!(pm.balance(bob(), weth()), U256::from(500_000));
+            assert_eq!(pm.balance(bob(), usdc()), U256::from(997_000));
+            assert_eq!(pm.balance(alice(), usdc()), U256::from(1_000_000));
+        }
+
+        // A buy at the ask's price pays its taker fee out of its balance
+        place_limit_order(&handler, bob(), (usdc(), weth()), 200_000, 2);
+        {
+            let pm = handler.pool_manager.read();
+            assert_eq!(pm.balance(bob(), weth()), U256::from(600_000));
+            assert_eq!(pm.balance(bob(), usdc()), U256::from(796_400));
+        }
+
+        // Nothing was created or lost along the way
+        assert_eq!(
+            ledger_total(&handler, weth(), &[ask]),
+            U256::from(1_000_000)
+        );
+        assert_eq!(
+            ledger_total(&handler, usdc(), &[ask]),
+            U256::from(2_000_000)
+        );
+    }
+
+    #[test]
+    fn test_triggered_buy_stop_pays_fee_from_reserve() {
+        let handler = DexHandler::new();
+        let pair_call = EnshrinedDEX::createPairCall {
+            token0: weth(),
+            token1: usdc(),
+        };
+        call(&handler, alice(), pair_call);
+        deposit(&handler, alice(), weth(), 1_000_000);
+        deposit(&handler, bob(), usdc(), 200_600);
+        deposit(&handler, carol(), usdc(), 20_060);
+
+        let DexResult::OrderPlaced { order_id, .. } =
+            place_limit_order(&handler, alice(), (weth(), usdc()), 1_000_000, 2)
+        else {
+            panic!("expected OrderPlaced");
+        };
+        let ask = order_id_from_b256(order_id).unwrap();
+
+        // A buy stop at its limit locks the 600 taker fee on its 200k escrow
+        let stop = EnshrinedDEX::placeStopOrderCall {
+            tokenIn: usdc(),
+            tokenOut: weth(),
+            isBuy: true,
+            amount: U256::from(200_000),
+            stopNum: U256::from(2),
+            stopDenom: U256::from(1),
+            limitNum: U256::from(2),
+            limitDenom: U256::from(1),
+        };
+        call(&handler, bob(), stop);
+        let balance = |account, token| handler.pool_manager.read().balance(account, token);
+        assert_eq!(balance(bob(), usdc()), U256::ZERO);
+
+        // A trade at 2 triggers it, and the fill's fee comes out of the reserve
+        place_limit_order(&handler, carol(), (usdc(), weth()), 20_000, 2);
+        assert_eq!(balance(bob(), weth()), U256::from(100_000));
+        assert_eq!(balance(bob(), usdc()), U256::ZERO);
+        assert_eq!(
+            handler.pool_manager.read().accrued_fees(usdc()),
+            U256::from(660)
+        );
+
+        assert_eq!(
+            ledger_total(&handler, weth(), &[ask]),
+            U256::from(1_000_000)
+        );
+        assert_eq!(ledger_total(&handler, usdc(), &[ask]), U256::from(220_660));
     }
 }
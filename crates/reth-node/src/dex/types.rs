@@ -1,11 +1,21 @@
 //! Type definitions for DEX operations.
 
-use crate::selectors::DexToken;
+use crate::selectors::{DexToken, EnshrinedDEX};
 use alloy_primitives::{Address, Bytes, B256, U256};
-use alloy_sol_types::SolCall;
-use dex::orderbook::Fill;
+use alloy_sol_types::{SolCall, SolError};
+use dex::orderbook::{
+    ActivatedBracket, BatchClearing, Fill, FlippedOrder, LinkedCancel, PreventedTrade,
+    TriggeredOrder,
+};
+use dex::{
+    MarketConfig, MarketMode, MarketSimulation, OrderId, RouteHop, SelfTradePrevention, TimeInForce,
+};
 
-/// A token transfer to be executed via protocolTransfer.
+/// A token transfer between an account and the DEX.
+///
+/// Deposits and withdrawals are executed on the token via protocolTransfer.
+/// Trades only settle against DEX balances: transfers into the DEX lock escrow
+/// and transfers out of it credit the recipient's balance.
 #[derive(Debug, Clone)]
 pub struct TokenTransfer {
     /// The token contract address (address(0) for ETH).
@@ -38,6 +48,22 @@ pub enum DexResult {
         token0: Address,
         token1: Address,
         pair_id: B256,
+        /// Market parameters, if the pair was created with custom ones.
+        market: Option<MarketConfig>,
+    },
+    Deposited {
+        account: Address,
+        token: Address,
+        amount: U256,
+        /// Token transfer pulling the deposit into the DEX.
+        transfers: Vec<TokenTransfer>,
+    },
+    Withdrawn {
+        account: Address,
+        token: Address,
+        amount: U256,
+        /// Token transfer paying out the withdrawal.
+        transfers: Vec<TokenTransfer>,
     },
     OrderPlaced {
         order_id: B256,
@@ -52,28 +78,213 @@ pub enum DexResult {
         transfers: Vec<TokenTransfer>,
         /// Fills that occurred immediately when placing this order
         fills: Vec<Fill>,
+        /// Whether an unfilled remainder was cancelled instead of resting on the book.
+        remainder_cancelled: bool,
+        /// Matches against the trader's own orders stopped by self-trade prevention.
+        prevented: Vec<PreventedTrade>,
+        /// Stop orders activated by this order's fills.
+        triggered: Vec<TriggeredOrder>,
+        /// Orders cancelled because the order linked to them one-cancels-other traded.
+        linked_cancels: Vec<LinkedCancel>,
+        /// Exit price numerators over `price_denom` if the order is a bracket entry:
+        /// take-profit, stop-loss and stop-loss limit.
+        bracket: Option<(U256, U256, U256)>,
+        /// Brackets whose exits were placed, including this order's own.
+        brackets: Vec<ActivatedBracket>,
+        /// Flip price numerator over `price_denom` if the order is a range order.
+        flip_price_num: Option<U256>,
+        /// Range orders that flipped because this order filled them.
+        flipped: Vec<FlippedOrder>,
+    },
+    StopOrderPlaced {
+        order_id: B256,
+        trader: Address,
+        token_in: Address,
+        token_out: Address,
+        is_buy: bool,
+        amount: U256,
+        stop_num: U256,
+        stop_denom: U256,
+        limit_num: U256,
+        limit_denom: U256,
+        /// Token transfers for escrowing collateral.
+        transfers: Vec<TokenTransfer>,
+    },
+    OcoOrderPlaced {
+        limit_order_id: B256,
+        stop_order_id: B256,
+        trader: Address,
+        token_in: Address,
+        token_out: Address,
+        is_buy: bool,
+        amount: U256,
+        price_num: U256,
+        stop_num: U256,
+        stop_limit_num: U256,
+        price_denom: U256,
+        /// Token transfers for escrowing collateral shared by both orders.
+        transfers: Vec<TokenTransfer>,
     },
     OrderCancelled {
         order_id: B256,
         trader: Address,
+        /// Token transfers refunding the unfilled escrow.
+        transfers: Vec<TokenTransfer>,
+        /// ID of the order linked one-cancels-other that was cancelled along with it.
+        linked_order_id: Option<B256>,
+    },
+    OrderAmended {
+        order_id: B256,
+        trader: Address,
+        amount: U256,
+        price_num: U256,
+        price_denom: U256,
+        kept_priority: bool,
+        /// Token transfers settling the escrow difference.
+        transfers: Vec<TokenTransfer>,
     },
     SwapExecuted {
         trader: Address,
         token_in: Address,
         token_out: Address,
-        amount_in: U256,
-        amount_out: U256,
-        route: Vec<B256>,
+        /// Input spent, output received and pair IDs of each route the swap
+        /// took, one `Swap` event each
+        splits: Vec<(U256, U256, Vec<B256>)>,
         /// Token transfers for the swap.
         transfers: Vec<TokenTransfer>,
-        /// All fills from all hops
-        all_fills: Vec<(dex::OrderId, Fill)>,
+        /// All fills from all hops, with their taker order ID and fee (quote) token
+        all_fills: Vec<(dex::OrderId, Address, Fill)>,
+        /// Matches against the trader's own orders stopped by self-trade prevention,
+        /// with their taker order ID
+        prevented: Vec<(dex::OrderId, PreventedTrade)>,
+        /// Stop orders activated by the swap, with their fee (quote) token
+        triggered: Vec<(Address, TriggeredOrder)>,
+        /// Orders cancelled because the order linked to them one-cancels-other traded
+        linked_cancels: Vec<LinkedCancel>,
+        /// Brackets whose exits were placed by the swap, with their fee (quote) token
+        brackets: Vec<(Address, ActivatedBracket)>,
+        /// Range orders that flipped because the swap filled them
+        flipped: Vec<FlippedOrder>,
+    },
+    SelfTradePreventionSet {
+        pair_id: B256,
+        /// The `SelfTradePrevention` ABI value.
+        mode: u8,
+    },
+    MarketModeSet {
+        pair_id: B256,
+        /// The `MarketMode` ABI value.
+        mode: u8,
+    },
+    BatchCleared {
+        pair_id: B256,
+        /// Quote token of the pair, which fees are charged in.
+        quote: Address,
+        /// Fills and cancellations of the auction.
+        clearing: BatchClearing,
+        /// Token transfers paying out the fills and refunding the escrow of
+        /// price improvement and cancelled orders.
+        transfers: Vec<TokenTransfer>,
+    },
+    FeesWithdrawn {
+        token: Address,
+        to: Address,
+        amount: U256,
+        /// Token transfer paying out the fees.
+        transfers: Vec<TokenTransfer>,
+    },
+    LiquidityAdded {
+        provider: Address,
+        token0: Address,
+        token1: Address,
+        amount0: U256,
+        amount1: U256,
+        shares: U256,
+        /// Token transfers moving the deposit into the pool.
+        transfers: Vec<TokenTransfer>,
+    },
+    LiquidityRemoved {
+        provider: Address,
+        token0: Address,
+        token1: Address,
+        amount0: U256,
+        amount1: U256,
+        /// Token transfers paying out of the pool.
+        transfers: Vec<TokenTransfer>,
     },
     #[allow(dead_code)]
     Quote {
         amount_out: U256,
         route: Vec<B256>,
     },
+    #[allow(dead_code)]
+    QuoteDetail {
+        amount_out: U256,
+        /// Price impact in basis points.
+        price_impact: U256,
+        /// Each hop quoted with what it is expected to do, route after route.
+        hops: Vec<(RouteHop, MarketSimulation)>,
+    },
+    #[allow(dead_code)]
+    QuoteExactOut {
+        amount_in: U256,
+        route: Vec<B256>,
+    },
+    #[allow(dead_code)]
+    Balance { amount: U256 },
+}
+
+/// Encode an order ID as the `bytes32` used in the DEX ABI.
+pub fn order_id_to_b256(order_id: OrderId) -> B256 {
+    let mut bytes = [0u8; 32];
+    bytes[24..32].copy_from_slice(&order_id.0.to_be_bytes());
+    B256::from(bytes)
+}
+
+/// Decode a `bytes32` order ID from the DEX ABI.
+/// Returns `None` if the value doesn't fit an order ID.
+pub fn order_id_from_b256(order_id: B256) -> Option<OrderId> {
+    if order_id[..24].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&order_id[24..32]);
+    Some(OrderId(u64::from_be_bytes(bytes)))
+}
+
+/// Decode the `TimeInForce` enum of the placeOrder ABI.
+/// Returns `None` for values outside the enum.
+pub fn time_in_force_from_u8(value: u8) -> Option<TimeInForce> {
+    match value {
+        0 => Some(TimeInForce::GoodTillCancel),
+        1 => Some(TimeInForce::ImmediateOrCancel),
+        2 => Some(TimeInForce::FillOrKill),
+        3 => Some(TimeInForce::PostOnly),
+        _ => None,
+    }
+}
+
+/// Decode the `SelfTradePrevention` enum of the DEX ABI.
+/// `MARKET_DEFAULT` decodes to `Some(None)`, values outside the enum to `None`.
+pub fn self_trade_prevention_from_u8(value: u8) -> Option<Option<SelfTradePrevention>> {
+    match value {
+        0 => Some(None),
+        1 => Some(Some(SelfTradePrevention::CancelNewest)),
+        2 => Some(Some(SelfTradePrevention::CancelOldest)),
+        3 => Some(Some(SelfTradePrevention::CancelBoth)),
+        4 => Some(Some(SelfTradePrevention::DecrementAndCancel)),
+        _ => None,
+    }
+}
+
+/// Decode the `MarketMode` enum of the DEX ABI.
+/// Returns `None` for values outside the enum.
+pub fn market_mode_from_u8(value: u8) -> Option<MarketMode> {
+    match value {
+        0 => Some(MarketMode::Continuous),
+        1 => Some(MarketMode::BatchAuction),
+        _ => None,
+    }
 }
 
 /// Errors that can occur during DEX operations.
@@ -88,12 +299,39 @@ pub enum DexError {
     #[error("Invalid price: num={num}, denom={denom}")]
     InvalidPrice { num: U256, denom: U256 },
 
+    #[error("Order not found: {0}")]
+    OrderNotFound(B256),
+
+    #[error("Unauthorized caller: {0}")]
+    Unauthorized(Address),
+
     #[error("DEX error: {0}")]
     DexLibrary(String),
 }
 
+impl DexError {
+    /// ABI-encoded `IEnshrinedDEX` error for failures the caller is to blame
+    /// for that revert the transaction rather than drop it, `None` for others.
+    pub fn revert_data(&self) -> Option<Bytes> {
+        match self {
+            DexError::Unauthorized(caller) => Some(
+                EnshrinedDEX::Unauthorized { caller: *caller }
+                    .abi_encode()
+                    .into(),
+            ),
+            _ => None,
+        }
+    }
+}
+
 impl From<dex::PoolError> for DexError {
     fn from(err: dex::PoolError) -> Self {
-        DexError::DexLibrary(err.to_string())
+        match err {
+            dex::PoolError::OrderNotFound(order_id) => {
+                DexError::OrderNotFound(order_id_to_b256(order_id))
+            }
+            dex::PoolError::Unauthorized { caller } => DexError::Unauthorized(caller),
+            err => DexError::DexLibrary(err.to_string()),
+        }
     }
 }
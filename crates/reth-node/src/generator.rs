@@ -4,6 +4,7 @@ use crate::dex::DexHandler;
 use crate::job::DexPayloadJob;
 use crate::payload::DexOpPayloadBuilder;
 use alloy_eips::BlockNumberOrTag;
+use alloy_primitives::Address;
 use reth_basic_payload_builder::PayloadConfig;
 use reth_node_api::PayloadBuilderAttributes;
 use reth_optimism_evm::OpEvmConfig;
@@ -26,13 +27,26 @@ pub struct DexPayloadJobGenerator<Pool, Client> {
 }
 
 impl<Pool, Client> DexPayloadJobGenerator<Pool, Client> {
-    pub fn new(client: Client, pool: Pool, evm_config: OpEvmConfig) -> Self {
-        info!(target: "payload_builder", "Creating DEX payload job generator");
+    /// Create a generator whose DEX takes restricted calls from `dex_admin` only.
+    pub fn new(
+        client: Client,
+        pool: Pool,
+        evm_config: OpEvmConfig,
+        dex_admin: Option<Address>,
+    ) -> Self {
+        info!(target: "payload_builder",
+            dex_admin = ?dex_admin,
+            "Creating DEX payload job generator"
+        );
+        let dex_handler = match dex_admin {
+            Some(admin) => DexHandler::with_admin(admin),
+            None => DexHandler::new(),
+        };
         Self {
             client,
             pool,
             evm_config,
-            dex_handler: Arc::new(DexHandler::new()),
+            dex_handler: Arc::new(dex_handler),
         }
     }
 }
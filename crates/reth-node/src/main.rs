@@ -15,13 +15,14 @@ mod selectors;
 
 use crate::generator::DexPayloadJobGenerator;
 use alloy_primitives::{address, Address};
+use clap::Parser;
 use reth_chain_state::CanonStateSubscriptions;
 use reth_node_api::{NodeTypes, TxTy};
 use reth_node_builder::{components::PayloadServiceBuilder, node::FullNodeTypes, BuilderContext};
 use reth_optimism_chainspec::OpChainSpec;
-use reth_optimism_cli::Cli;
+use reth_optimism_cli::{chainspec::OpChainSpecParser, Cli};
 use reth_optimism_evm::OpEvmConfig;
-use reth_optimism_node::{node::OpAddOns, OpEngineTypes, OpNode};
+use reth_optimism_node::{args::RollupArgs, node::OpAddOns, OpEngineTypes, OpNode};
 use reth_optimism_primitives::OpPrimitives;
 use reth_optimism_txpool::OpPooledTx;
 use reth_payload_builder::{PayloadBuilderHandle, PayloadBuilderService};
@@ -30,10 +31,33 @@ use reth_transaction_pool::TransactionPool;
 /// DEX predeploy address (same as in op-rbuilder)
 pub const DEX_PREDEPLOY_ADDRESS: Address = address!("4200000000000000000000000000000000000042");
 
+/// Node arguments: the rollup arguments and the DEX admin.
+#[derive(Debug, Clone, clap::Args)]
+struct DexArgs {
+    #[command(flatten)]
+    rollup: RollupArgs,
+
+    /// Address allowed to call the DEX's restricted entrypoints: withdrawFees,
+    /// setSelfTradePrevention and setMarketMode. They are rejected when unset.
+    #[arg(long = "dex.admin", value_name = "ADDRESS", env = "DEX_ADMIN")]
+    dex_admin: Option<Address>,
+}
+
 /// Custom payload builder service that uses DEX-aware payload generation
 #[derive(Debug, Clone, Copy, Default)]
 #[non_exhaustive]
-pub struct DexPayloadServiceBuilder;
+pub struct DexPayloadServiceBuilder {
+    /// Address allowed to call the DEX's restricted entrypoints, if any.
+    dex_admin: Option<Address>,
+}
+
+impl DexPayloadServiceBuilder {
+    /// Create a payload builder service whose DEX takes restricted calls from
+    /// `dex_admin` only, or from no one when it is `None`.
+    pub fn new(dex_admin: Option<Address>) -> Self {
+        Self { dex_admin }
+    }
+}
 
 impl<Node, Pool> PayloadServiceBuilder<Node, Pool, OpEvmConfig> for DexPayloadServiceBuilder
 where
@@ -55,7 +79,7 @@ where
         tracing::info!("Spawning DEX-aware Optimism payload builder");
 
         let payload_generator =
-            DexPayloadJobGenerator::new(ctx.provider().clone(), pool, evm_config);
+            DexPayloadJobGenerator::new(ctx.provider().clone(), pool, evm_config, self.dex_admin);
 
         let (payload_service, payload_builder) =
             PayloadBuilderService::new(payload_generator, ctx.provider().canonical_state_stream());
@@ -68,16 +92,20 @@ where
 }
 
 fn main() {
-    Cli::parse_args()
-        .run(|builder, _| async move {
-            let op_node = OpNode::default();
+    Cli::<OpChainSpecParser, DexArgs>::parse()
+        .run(|builder, args| async move {
+            if args.dex_admin.is_none() {
+                tracing::warn!("No --dex.admin set, restricted DEX calls will be rejected");
+            }
+
+            let op_node = OpNode::new(args.rollup);
             let handle = builder
                 .with_types_and_provider::<OpNode, _>()
                 // Use custom DEX-aware payload builder
                 .with_components(
                     op_node
                         .components()
-                        .payload(DexPayloadServiceBuilder::default()),
+                        .payload(DexPayloadServiceBuilder::new(args.dex_admin)),
                 )
                 .with_add_ons(OpAddOns::default())
                 .launch_with_debug_capabilities()
@@ -23,8 +23,28 @@ pub mod selectors {
     use alloy_sol_types::SolCall;
 
     pub const CREATE_PAIR: [u8; 4] = EnshrinedDEX::createPairCall::SELECTOR;
+    pub const CREATE_PAIR_WITH_PARAMS: [u8; 4] = EnshrinedDEX::createPairWithParamsCall::SELECTOR;
+    pub const DEPOSIT: [u8; 4] = EnshrinedDEX::depositCall::SELECTOR;
+    pub const WITHDRAW: [u8; 4] = EnshrinedDEX::withdrawCall::SELECTOR;
+    pub const GET_BALANCE: [u8; 4] = EnshrinedDEX::getBalanceCall::SELECTOR;
     pub const PLACE_LIMIT_ORDER: [u8; 4] = EnshrinedDEX::placeLimitOrderCall::SELECTOR;
+    pub const PLACE_ORDER: [u8; 4] = EnshrinedDEX::placeOrderCall::SELECTOR;
+    pub const PLACE_ICEBERG_ORDER: [u8; 4] = EnshrinedDEX::placeIcebergOrderCall::SELECTOR;
+    pub const PLACE_STOP_ORDER: [u8; 4] = EnshrinedDEX::placeStopOrderCall::SELECTOR;
+    pub const PLACE_OCO_ORDER: [u8; 4] = EnshrinedDEX::placeOcoOrderCall::SELECTOR;
+    pub const PLACE_BRACKET_ORDER: [u8; 4] = EnshrinedDEX::placeBracketOrderCall::SELECTOR;
+    pub const PLACE_RANGE_ORDER: [u8; 4] = EnshrinedDEX::placeRangeOrderCall::SELECTOR;
     pub const CANCEL_ORDER: [u8; 4] = EnshrinedDEX::cancelOrderCall::SELECTOR;
+    pub const AMEND_ORDER: [u8; 4] = EnshrinedDEX::amendOrderCall::SELECTOR;
     pub const SWAP: [u8; 4] = EnshrinedDEX::swapCall::SELECTOR;
     pub const GET_QUOTE: [u8; 4] = EnshrinedDEX::getQuoteCall::SELECTOR;
+    pub const GET_QUOTE_DETAIL: [u8; 4] = EnshrinedDEX::getQuoteDetailCall::SELECTOR;
+    pub const SWAP_EXACT_OUT: [u8; 4] = EnshrinedDEX::swapExactOutCall::SELECTOR;
+    pub const GET_QUOTE_EXACT_OUT: [u8; 4] = EnshrinedDEX::getQuoteExactOutCall::SELECTOR;
+    pub const SET_SELF_TRADE_PREVENTION: [u8; 4] =
+        EnshrinedDEX::setSelfTradePreventionCall::SELECTOR;
+    pub const SET_MARKET_MODE: [u8; 4] = EnshrinedDEX::setMarketModeCall::SELECTOR;
+    pub const WITHDRAW_FEES: [u8; 4] = EnshrinedDEX::withdrawFeesCall::SELECTOR;
+    pub const ADD_LIQUIDITY: [u8; 4] = EnshrinedDEX::addLiquidityCall::SELECTOR;
+    pub const REMOVE_LIQUIDITY: [u8; 4] = EnshrinedDEX::removeLiquidityCall::SELECTOR;
 }